@@ -393,6 +393,10 @@ impl PageServerNode {
                 .remove("compaction_target_size")
                 .map(|x| x.parse::<u64>())
                 .transpose()?,
+            compaction_max_size: settings
+                .remove("compaction_max_size")
+                .map(|x| x.parse::<u64>())
+                .transpose()?,
             compaction_period: settings.remove("compaction_period").map(|x| x.to_string()),
             compaction_threshold: settings
                 .remove("compaction_threshold")
@@ -408,6 +412,7 @@ impl PageServerNode {
                 .map(|x| x.parse::<usize>())
                 .transpose()?,
             pitr_interval: settings.remove("pitr_interval").map(|x| x.to_string()),
+            gc_cutoff_policy: settings.remove("gc_cutoff_policy").map(|x| x.to_string()),
             walreceiver_connect_timeout: settings
                 .remove("walreceiver_connect_timeout")
                 .map(|x| x.to_string()),
@@ -419,6 +424,34 @@ impl PageServerNode {
                 .map(|x| x.parse::<NonZeroU64>())
                 .transpose()
                 .context("Failed to parse 'max_lsn_wal_lag' as non zero integer")?,
+            compaction_concurrency: settings
+                .remove("compaction_concurrency")
+                .map(|x| x.parse::<usize>())
+                .transpose()?,
+            max_ancestor_depth: settings
+                .remove("max_ancestor_depth")
+                .map(|x| x.parse::<usize>())
+                .transpose()?,
+            compaction_batch_size: settings
+                .remove("compaction_batch_size")
+                .map(|x| x.parse::<usize>())
+                .transpose()?,
+            max_layers_per_get: settings
+                .remove("max_layers_per_get")
+                .map(|x| x.parse::<usize>())
+                .transpose()?,
+            max_upload_bytes_per_sec: settings
+                .remove("max_upload_bytes_per_sec")
+                .map(|x| x.parse::<u64>())
+                .transpose()?,
+            gc_respect_remote_branchpoints: settings
+                .remove("gc_respect_remote_branchpoints")
+                .map(|x| x.parse::<bool>())
+                .transpose()?,
+            checkpoint_max_inmem_bytes: settings
+                .remove("checkpoint_max_inmem_bytes")
+                .map(|x| x.parse::<u64>())
+                .transpose()?,
         };
         if !settings.is_empty() {
             bail!("Unrecognized tenant settings: {settings:?}")
@@ -454,6 +487,11 @@ impl PageServerNode {
                     .map(|x| x.parse::<u64>())
                     .transpose()
                     .context("Failed to parse 'compaction_target_size' as an integer")?,
+                compaction_max_size: settings
+                    .get("compaction_max_size")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()
+                    .context("Failed to parse 'compaction_max_size' as an integer")?,
                 compaction_period: settings.get("compaction_period").map(|x| x.to_string()),
                 compaction_threshold: settings
                     .get("compaction_threshold")
@@ -472,6 +510,7 @@ impl PageServerNode {
                     .transpose()
                     .context("Failed to parse 'image_creation_threshold' as non zero integer")?,
                 pitr_interval: settings.get("pitr_interval").map(|x| x.to_string()),
+                gc_cutoff_policy: settings.get("gc_cutoff_policy").map(|x| x.to_string()),
                 walreceiver_connect_timeout: settings
                     .get("walreceiver_connect_timeout")
                     .map(|x| x.to_string()),
@@ -481,6 +520,41 @@ impl PageServerNode {
                     .map(|x| x.parse::<NonZeroU64>())
                     .transpose()
                     .context("Failed to parse 'max_lsn_wal_lag' as non zero integer")?,
+                compaction_concurrency: settings
+                    .get("compaction_concurrency")
+                    .map(|x| x.parse::<usize>())
+                    .transpose()
+                    .context("Failed to parse 'compaction_concurrency' as an integer")?,
+                max_ancestor_depth: settings
+                    .get("max_ancestor_depth")
+                    .map(|x| x.parse::<usize>())
+                    .transpose()
+                    .context("Failed to parse 'max_ancestor_depth' as an integer")?,
+                compaction_batch_size: settings
+                    .get("compaction_batch_size")
+                    .map(|x| x.parse::<usize>())
+                    .transpose()
+                    .context("Failed to parse 'compaction_batch_size' as an integer")?,
+                max_layers_per_get: settings
+                    .get("max_layers_per_get")
+                    .map(|x| x.parse::<usize>())
+                    .transpose()
+                    .context("Failed to parse 'max_layers_per_get' as an integer")?,
+                max_upload_bytes_per_sec: settings
+                    .get("max_upload_bytes_per_sec")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()
+                    .context("Failed to parse 'max_upload_bytes_per_sec' as an integer")?,
+                gc_respect_remote_branchpoints: settings
+                    .get("gc_respect_remote_branchpoints")
+                    .map(|x| x.parse::<bool>())
+                    .transpose()
+                    .context("Failed to parse 'gc_respect_remote_branchpoints' as a boolean")?,
+                checkpoint_max_inmem_bytes: settings
+                    .get("checkpoint_max_inmem_bytes")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()
+                    .context("Failed to parse 'checkpoint_max_inmem_bytes' as an integer")?,
             })
             .send()?
             .error_from_body()?;
@@ -518,6 +592,9 @@ impl PageServerNode {
             ancestor_start_lsn,
             ancestor_timeline_id,
             pg_version,
+            initdb_encoding: None,
+            initdb_locale: None,
+            created_by: None,
         })
         .send()?
         .error_from_body()?
@@ -10,8 +10,11 @@ use utils::{
 /// A state of a tenant in pageserver's memory.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum TenantState {
-    /// Tenant is fully operational, its background jobs might be running or not.
-    Active { background_jobs_running: bool },
+    /// Tenant is fully operational, its GC and compaction jobs might be running or not.
+    Active {
+        gc_enabled: bool,
+        compaction_enabled: bool,
+    },
     /// A tenant is recognized by pageserver, but not yet ready to operate:
     /// e.g. not present locally and being downloaded or being read into memory from the file system.
     Paused,
@@ -48,6 +51,15 @@ pub struct TimelineCreateRequest {
     #[serde_as(as = "Option<DisplayFromStr>")]
     pub ancestor_start_lsn: Option<Lsn>,
     pub pg_version: Option<u32>,
+    /// Encoding of a timeline bootstrapped without an ancestor, `utf8` by default.
+    #[serde(default)]
+    pub initdb_encoding: Option<String>,
+    /// Locale of a timeline bootstrapped without an ancestor, `C` by default.
+    #[serde(default)]
+    pub initdb_locale: Option<String>,
+    /// Free-form description of who creates the timeline, reported in the timeline info.
+    #[serde(default)]
+    pub created_by: Option<String>,
 }
 
 #[serde_as]
@@ -59,15 +71,24 @@ pub struct TenantCreateRequest {
     pub checkpoint_distance: Option<u64>,
     pub checkpoint_timeout: Option<String>,
     pub compaction_target_size: Option<u64>,
+    pub compaction_max_size: Option<u64>,
     pub compaction_period: Option<String>,
     pub compaction_threshold: Option<usize>,
     pub gc_horizon: Option<u64>,
     pub gc_period: Option<String>,
     pub image_creation_threshold: Option<usize>,
     pub pitr_interval: Option<String>,
+    pub gc_cutoff_policy: Option<String>,
     pub walreceiver_connect_timeout: Option<String>,
     pub lagging_wal_timeout: Option<String>,
     pub max_lsn_wal_lag: Option<NonZeroU64>,
+    pub compaction_concurrency: Option<usize>,
+    pub max_ancestor_depth: Option<usize>,
+    pub compaction_batch_size: Option<usize>,
+    pub max_layers_per_get: Option<usize>,
+    pub max_upload_bytes_per_sec: Option<u64>,
+    pub gc_respect_remote_branchpoints: Option<bool>,
+    pub checkpoint_max_inmem_bytes: Option<u64>,
 }
 
 #[serde_as]
@@ -98,15 +119,24 @@ pub struct TenantConfigRequest {
     pub checkpoint_distance: Option<u64>,
     pub checkpoint_timeout: Option<String>,
     pub compaction_target_size: Option<u64>,
+    pub compaction_max_size: Option<u64>,
     pub compaction_period: Option<String>,
     pub compaction_threshold: Option<usize>,
     pub gc_horizon: Option<u64>,
     pub gc_period: Option<String>,
     pub image_creation_threshold: Option<usize>,
     pub pitr_interval: Option<String>,
+    pub gc_cutoff_policy: Option<String>,
     pub walreceiver_connect_timeout: Option<String>,
     pub lagging_wal_timeout: Option<String>,
     pub max_lsn_wal_lag: Option<NonZeroU64>,
+    pub compaction_concurrency: Option<usize>,
+    pub max_ancestor_depth: Option<usize>,
+    pub compaction_batch_size: Option<usize>,
+    pub max_layers_per_get: Option<usize>,
+    pub max_upload_bytes_per_sec: Option<u64>,
+    pub gc_respect_remote_branchpoints: Option<bool>,
+    pub checkpoint_max_inmem_bytes: Option<u64>,
 }
 
 impl TenantConfigRequest {
@@ -116,15 +146,24 @@ impl TenantConfigRequest {
             checkpoint_distance: None,
             checkpoint_timeout: None,
             compaction_target_size: None,
+            compaction_max_size: None,
             compaction_period: None,
             compaction_threshold: None,
             gc_horizon: None,
             gc_period: None,
             image_creation_threshold: None,
             pitr_interval: None,
+            gc_cutoff_policy: None,
             walreceiver_connect_timeout: None,
             lagging_wal_timeout: None,
             max_lsn_wal_lag: None,
+            compaction_concurrency: None,
+            max_ancestor_depth: None,
+            compaction_batch_size: None,
+            max_layers_per_get: None,
+            max_upload_bytes_per_sec: None,
+            gc_respect_remote_branchpoints: None,
+            checkpoint_max_inmem_bytes: None,
         }
     }
 }
@@ -158,6 +197,11 @@ pub struct TimelineInfo {
     pub prev_record_lsn: Option<Lsn>,
     #[serde_as(as = "DisplayFromStr")]
     pub latest_gc_cutoff_lsn: Lsn,
+    /// GC keeps everything newer than both cutoffs, branches can't start below them.
+    #[serde_as(as = "DisplayFromStr")]
+    pub gc_horizon_cutoff: Lsn,
+    #[serde_as(as = "DisplayFromStr")]
+    pub gc_pitr_cutoff: Lsn,
     #[serde_as(as = "DisplayFromStr")]
     pub disk_consistent_lsn: Lsn,
     pub current_logical_size: Option<u64>, // is None when timeline is Unloaded
@@ -171,6 +215,11 @@ pub struct TimelineInfo {
     /// the timestamp (in microseconds) of the last received message
     pub last_received_msg_ts: Option<u128>,
     pub pg_version: u32,
+    pub label: Option<String>,
+    /// the timestamp (in microseconds) of the timeline creation, None if it was not recorded
+    pub created_at: Option<u128>,
+    pub created_by: Option<String>,
+    pub read_only: bool,
 
     #[serde_as(as = "Option<DisplayFromStr>")]
     pub remote_consistent_lsn: Option<Lsn>,
@@ -202,6 +251,15 @@ pub struct RemoteTimelineInfo {
     pub remote_consistent_lsn: Option<Lsn>,
 }
 
+/// Error body of a timeline deletion refused because other timelines are branched off it.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineHasChildrenError {
+    pub msg: String,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub child_timeline_ids: Vec<TimelineId>,
+}
+
 pub type ConfigureFailpointsRequest = Vec<FailpointConfig>;
 
 /// Information for configuring a single fail point
@@ -218,4 +276,7 @@ pub struct FailpointConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TimelineGcRequest {
     pub gc_horizon: Option<u64>,
+    /// Overrides the tenant's `pitr_interval` for this GC iteration, in humantime format.
+    #[serde(default)]
+    pub pitr_interval: Option<String>,
 }
@@ -1,5 +1,6 @@
 use std::{
     borrow::Cow,
+    collections::HashSet,
     ffi::OsStr,
     fs::{self, File},
     io,
@@ -85,18 +86,86 @@ pub fn path_with_suffix_extension(original_path: impl AsRef<Path>, suffix: &str)
 }
 
 pub fn fsync_file_and_parent(file_path: &Path) -> io::Result<()> {
-    let parent = file_path.parent().ok_or_else(|| {
+    let parent = parent(file_path)?;
+
+    fsync(file_path)?;
+    fsync(parent)?;
+    Ok(())
+}
+
+/// Same as calling [`fsync_file_and_parent`] for every path, but fsyncs each parent
+/// directory once, after all the files are fsynced.
+pub fn fsync_files_and_parents(paths: &[PathBuf]) -> io::Result<()> {
+    fsync_files_and_parents_with(paths, fsync)
+}
+
+fn fsync_files_and_parents_with(
+    paths: &[PathBuf],
+    mut fsync: impl FnMut(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut seen_parents = HashSet::new();
+    let mut parents = Vec::new();
+    for path in paths {
+        let parent = parent(path)?;
+        if seen_parents.insert(parent) {
+            parents.push(parent);
+        }
+    }
+
+    for path in paths {
+        fsync(path)?;
+    }
+    for parent in parents {
+        fsync(parent)?;
+    }
+    Ok(())
+}
+
+/// Renames a fully built file or directory into its final location, fsyncing the
+/// parent directories so that the new entry is durable. Fails with
+/// [`io::ErrorKind::AlreadyExists`] if `to` exists, instead of replacing it.
+///
+/// The rename is atomic, so after a crash either `from` or the complete `to` exists.
+/// The contents of `from` are expected to be fsynced by the caller.
+pub fn rename_and_fsync(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(to) {
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Failed to rename {from:?}: {to:?} already exists"),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let from_parent = parent(from)?;
+    let to_parent = parent(to)?;
+
+    fs::rename(from, to).map_err(|e| {
         io::Error::new(
-            io::ErrorKind::Other,
-            format!("File {file_path:?} has no parent"),
+            e.kind(),
+            format!("Failed to rename {from:?} to {to:?}: {e}"),
         )
     })?;
+    fail::fail_point!("rename-and-fsync-before-parent-fsync", |_| {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "failpoint rename-and-fsync-before-parent-fsync",
+        ))
+    });
 
-    fsync(file_path)?;
-    fsync(parent)?;
+    fsync(to_parent)?;
+    if from_parent != to_parent {
+        fsync(from_parent)?;
+    }
     Ok(())
 }
 
+fn parent(path: &Path) -> io::Result<&Path> {
+    path.parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, format!("File {path:?} has no parent")))
+}
+
 pub fn fsync(path: &Path) -> io::Result<()> {
     File::open(path)
         .map_err(|e| io::Error::new(e.kind(), format!("Failed to open the file {path:?}: {e}")))
@@ -160,6 +229,89 @@ mod tests {
         create_dir_all(&invalid_dir_path).unwrap_err();
     }
 
+    #[test]
+    fn test_fsync_files_and_parents() {
+        let dir = tempdir().unwrap();
+        let dirs = (0..10)
+            .map(|i| {
+                let path = dir.path().join(format!("dir{i}"));
+                create_dir(&path).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let files = (0..1000)
+            .map(|i| {
+                let path = dirs[i % dirs.len()].join(format!("file{i}"));
+                std::fs::write(&path, b"").unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+
+        fsync_files_and_parents(&files).unwrap();
+
+        let mut fsynced = Vec::new();
+        fsync_files_and_parents_with(&files, |path| {
+            fsynced.push(path.to_path_buf());
+            fsync(path)
+        })
+        .unwrap();
+        // One fsync per file and per directory, instead of 1000 directory fsyncs
+        assert_eq!(fsynced.len(), files.len() + dirs.len());
+        // All files are durable before the directory entries
+        assert_eq!(fsynced[..files.len()], files);
+        assert_eq!(
+            fsynced[files.len()..].iter().collect::<HashSet<_>>(),
+            dirs.iter().collect::<HashSet<_>>()
+        );
+
+        let err = fsync_files_and_parents(&[dir.path().join("missing")]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_rename_and_fsync() {
+        let dir = tempdir().unwrap();
+        let build_dir = |name: &str| {
+            let path = dir.path().join(name);
+            create_dir(&path).unwrap();
+            std::fs::write(path.join("file"), name).unwrap();
+            fsync_file_and_parent(&path.join("file")).unwrap();
+            path
+        };
+
+        let temp_dir = build_dir("dir.___temp");
+        let final_dir = dir.path().join("dir");
+        rename_and_fsync(&temp_dir, &final_dir).unwrap();
+        assert!(!temp_dir.exists());
+        assert_eq!(
+            std::fs::read_to_string(final_dir.join("file")).unwrap(),
+            "dir.___temp"
+        );
+
+        // The published directory is never replaced
+        let other_temp_dir = build_dir("other.___temp");
+        let err = rename_and_fsync(&other_temp_dir, &final_dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(other_temp_dir.exists());
+        assert_eq!(
+            std::fs::read_to_string(final_dir.join("file")).unwrap(),
+            "dir.___temp"
+        );
+
+        // Crash right after the rename: the entry is either at the old or at the new
+        // location, never half-published
+        let scenario = fail::FailScenario::setup();
+        fail::cfg("rename-and-fsync-before-parent-fsync", "return").unwrap();
+        let crashed_dir = dir.path().join("crashed");
+        rename_and_fsync(&other_temp_dir, &crashed_dir).unwrap_err();
+        scenario.teardown();
+        assert!(!other_temp_dir.exists());
+        assert_eq!(
+            std::fs::read_to_string(crashed_dir.join("file")).unwrap(),
+            "other.___temp"
+        );
+    }
+
     #[test]
     fn test_path_with_suffix_extension() {
         let p = PathBuf::from("/foo/bar");
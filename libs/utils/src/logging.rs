@@ -1,17 +1,41 @@
 use std::{
+    fmt::{self, Write as _},
     fs::{File, OpenOptions},
     path::Path,
     str::FromStr,
 };
 
 use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use once_cell::sync::OnceCell;
 use strum_macros::{EnumString, EnumVariantNames};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::{Format, Json, Writer},
+        time::FormatTime,
+        FmtContext, FormatEvent, FormatFields, FormattedFields,
+    },
+    prelude::*,
+    registry::LookupSpan,
+    reload, EnvFilter, Registry,
+};
+
+/// Allows to replace the log filter of the global logger, set up by [`init`].
+static LOG_FILTER_RELOAD_HANDLE: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
 
 #[derive(EnumString, EnumVariantNames, Eq, PartialEq, Debug, Clone, Copy)]
 #[strum(serialize_all = "snake_case")]
 pub enum LogFormat {
     Plain,
     Json,
+    /// Multi-line human-readable output.
+    Pretty,
+    /// Single-line `key=value` output, see [`LogfmtFormat`].
+    Logfmt,
 }
 
 impl LogFormat {
@@ -25,10 +49,176 @@ impl LogFormat {
         })
     }
 }
+
+/// RFC3339 UTC timestamps with millisecond precision, e.g. `2022-10-16T10:00:00.000Z`.
+pub struct UtcMillisTime;
+
+impl FormatTime for UtcMillisTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        write!(
+            w,
+            "{}",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+        )
+    }
+}
+
+/// Formats events as single logfmt lines, e.g.
+/// `ts=2022-10-16T10:00:00.000Z level=info tenant=.. custom=hi message="test log message"`.
+///
+/// Fields of the enclosing spans go before the event fields, the message is always the last one.
+pub struct LogfmtFormat;
+
+impl<S, N> FormatEvent<S, N> for LogfmtFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        write!(writer, "ts=")?;
+        UtcMillisTime.format_time(&mut writer)?;
+        write!(
+            writer,
+            " level={}",
+            event.metadata().level().to_string().to_lowercase()
+        )?;
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, " {fields}")?;
+                    }
+                }
+            }
+        }
+
+        let mut visitor = LogfmtVisitor::default();
+        event.record(&mut visitor);
+        write!(writer, "{}", visitor.fields)?;
+        if let Some(message) = visitor.message {
+            write!(writer, " message={}", logfmt_value(&message))?;
+        }
+        writeln!(writer)
+    }
+}
+
+#[derive(Default)]
+struct LogfmtVisitor {
+    fields: String,
+    message: Option<String>,
+}
+
+impl Visit for LogfmtVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            // Writing into a String can't fail
+            let _ = write!(self.fields, " {}={}", field.name(), logfmt_value(value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"))
+    }
+}
+
+/// Formats events as JSON objects, one per line, or pretty-printed over multiple lines
+/// for reading the logs locally.
+///
+/// The fields of all the spans the event is in are flattened into the `spans` object,
+/// e.g. `"spans":{"tenant":"..","timeline":".."}`, the inner spans overriding the outer ones.
+struct JsonFormat {
+    inner: Format<Json, UtcMillisTime>,
+    pretty: bool,
+}
+
+impl JsonFormat {
+    fn new(pretty: bool) -> Self {
+        Self {
+            // Log parsers rely on the timestamp and target being always present
+            inner: tracing_subscriber::fmt::format()
+                .json()
+                .with_timer(UtcMillisTime)
+                .with_target(true)
+                .with_current_span(false)
+                .with_span_list(false),
+            pretty,
+        }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        let mut object = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(serde_json::Value::Object(object)) => object,
+            // Should not happen, but better write the event as is than lose it
+            _ => return write!(writer, "{line}"),
+        };
+
+        let mut span_fields = serde_json::Map::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                // Recorded by the JSON fields formatter as a JSON object
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(fields) {
+                        span_fields.extend(fields);
+                    }
+                }
+            }
+        }
+        if !span_fields.is_empty() {
+            object.insert("spans".to_string(), serde_json::Value::Object(span_fields));
+        }
+
+        let object = serde_json::Value::Object(object);
+        let formatted = if self.pretty {
+            serde_json::to_string_pretty(&object)
+        } else {
+            serde_json::to_string(&object)
+        };
+        writeln!(writer, "{}", formatted.map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Quotes the value if it can't be written in logfmt as is.
+fn logfmt_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{value:?}")
+    } else {
+        value.to_string()
+    }
+}
+
+/// Sets up the global logger, writing into the given file if `daemonize` is set, to stdout otherwise.
+///
+/// `pretty_json` only affects [`LogFormat::Json`]: the objects are pretty-printed over
+/// multiple lines instead of one object per line, which log aggregators expect.
 pub fn init(
     log_filename: impl AsRef<Path>,
     daemonize: bool,
     log_format: LogFormat,
+    pretty_json: bool,
 ) -> Result<File> {
     // Don't open the same file for output multiple times;
     // the different fds could overwrite each other's output.
@@ -42,12 +232,14 @@ pub fn init(
 
     // We fall back to printing all spans at info-level or above if
     // the RUST_LOG environment variable is not set.
-    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_filter_str));
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter_str));
+    // The filter is reloadable, to change the log level at runtime with [`set_log_filter`]
+    let (env_filter, reload_handle) = reload::Layer::new(env_filter);
+    let base_subscriber = tracing_subscriber::registry().with(env_filter);
 
     let x: File = log_file.try_clone().unwrap();
-    let base_logger = tracing_subscriber::fmt()
-        .with_env_filter(env_filter)
+    let base_logger = tracing_subscriber::fmt::layer()
         .with_target(false)
         .with_ansi(false)
         .with_writer(move || -> Box<dyn std::io::Write> {
@@ -62,19 +254,46 @@ pub fn init(
         });
 
     match log_format {
-        LogFormat::Json => base_logger.json().init(),
-        LogFormat::Plain => base_logger.init(),
+        LogFormat::Json => base_subscriber
+            .with(
+                base_logger
+                    .json()
+                    .event_format(JsonFormat::new(pretty_json)),
+            )
+            .init(),
+        LogFormat::Plain => base_subscriber.with(base_logger).init(),
+        LogFormat::Pretty => base_subscriber.with(base_logger.pretty()).init(),
+        LogFormat::Logfmt => base_subscriber
+            .with(base_logger.event_format(LogfmtFormat))
+            .init(),
     }
+    // `init` panics if the global logger was already set, so the handle is never set twice
+    let _ = LOG_FILTER_RELOAD_HANDLE.set(reload_handle);
 
     Ok(log_file)
 }
 
+/// Replaces the log filter of the logger set up by [`init`], taking the directives in
+/// the `RUST_LOG` format, e.g. `info,pageserver::walreceiver=debug`.
+pub fn set_log_filter(directives: &str) -> Result<()> {
+    let env_filter = EnvFilter::try_new(directives)
+        .with_context(|| format!("invalid log filter directives {directives:?}"))?;
+    LOG_FILTER_RELOAD_HANDLE
+        .get()
+        .context("logging is not initialized")?
+        .reload(env_filter)
+        .context("failed to reload the log filter")
+}
+
 // #[cfg(test)]
 // Due to global logger, can't run tests in same process.
 // So until there's a non-global one, the tests are in ../tests/ as separate files.
 #[macro_export(local_inner_macros)]
 macro_rules! test_init_file_logger {
     ($log_level:expr, $log_format:expr) => {{
+        test_init_file_logger!($log_level, $log_format, false)
+    }};
+    ($log_level:expr, $log_format:expr, $pretty_json:expr) => {{
         use std::str::FromStr;
         std::env::set_var("RUST_LOG", $log_level);
 
@@ -82,7 +301,8 @@ macro_rules! test_init_file_logger {
         let log_file_path = tmp_dir.path().join("logfile");
 
         let log_format = $crate::logging::LogFormat::from_str($log_format).unwrap();
-        let _log_file = $crate::logging::init(&log_file_path, true, log_format).unwrap();
+        let _log_file =
+            $crate::logging::init(&log_file_path, true, log_format, $pretty_json).unwrap();
 
         let log_file = std::fs::OpenOptions::new()
             .read(true)
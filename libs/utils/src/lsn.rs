@@ -2,7 +2,7 @@
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::ops::{Add, AddAssign};
+use std::ops::{Add, AddAssign, Range};
 use std::path::Path;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -41,6 +41,16 @@ impl Lsn {
         i128::from(self.0) - i128::from(other)
     }
 
+    /// Number of bytes from this LSN forward to `other`, returning None if `other` is behind.
+    pub fn distance_to(self, other: Lsn) -> Option<u64> {
+        other.0.checked_sub(self.0)
+    }
+
+    /// Number of bytes from this LSN forward to `other`, or 0 if `other` is behind.
+    pub fn saturating_distance(self, other: Lsn) -> u64 {
+        other.0.saturating_sub(self.0)
+    }
+
     /// Parse an LSN from a filename in the form `0000000000000000`
     pub fn from_filename<F>(filename: F) -> Result<Self, LsnParseError>
     where
@@ -107,6 +117,13 @@ impl Lsn {
         Lsn((self.0 + 7) & !7)
     }
 
+    /// Align LSN down to the start of the WAL segment containing it.
+    ///
+    /// Unlike [`Lsn::align`], rounds down: an LSN is never moved past its segment.
+    pub fn align_to_segment(self, wal_seg_size: usize) -> Lsn {
+        self.segment_lsn(wal_seg_size)
+    }
+
     /// Align LSN on 8-byte boundary (alignment of WAL records).
     pub fn is_aligned(&self) -> bool {
         *self == self.align()
@@ -223,6 +240,68 @@ impl From<Lsn> for AtomicLsn {
     }
 }
 
+/// A half-open range of LSNs, `start` inclusive and `end` exclusive, like the LSN ranges of layers.
+/// The range is empty if `start >= end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LsnRange {
+    /// First LSN of the range
+    pub start: Lsn,
+    /// LSN right after the last one of the range
+    pub end: Lsn,
+}
+
+impl LsnRange {
+    /// The range of all the LSNs, but `Lsn::MAX`
+    pub const FULL: LsnRange = LsnRange {
+        start: Lsn(0),
+        end: Lsn::MAX,
+    };
+
+    /// Returns true if there are no LSNs in the range
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Returns true if the LSN is in the range
+    pub fn contains(&self, lsn: Lsn) -> bool {
+        self.start <= lsn && lsn < self.end
+    }
+
+    /// Returns true if the ranges have LSNs in common. Adjacent ranges do not overlap.
+    pub fn overlaps(&self, other: &LsnRange) -> bool {
+        !self.intersect(other).is_empty()
+    }
+
+    /// The LSNs that are in both ranges, an empty range if there are none.
+    pub fn intersect(&self, other: &LsnRange) -> LsnRange {
+        LsnRange {
+            start: self.start.max(other.start),
+            end: self.end.min(other.end),
+        }
+    }
+}
+
+impl From<Range<Lsn>> for LsnRange {
+    fn from(range: Range<Lsn>) -> Self {
+        LsnRange {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
+impl From<LsnRange> for Range<Lsn> {
+    fn from(range: LsnRange) -> Self {
+        range.start..range.end
+    }
+}
+
+impl fmt::Display for LsnRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
 /// Pair of LSN's pointing to the end of the last valid record and previous one
 #[derive(Debug, Clone, Copy)]
 pub struct RecordLsn {
@@ -308,6 +387,59 @@ mod tests {
         assert_eq!(Lsn(0xffff00).calc_padding(8u32), 0u64);
     }
 
+    #[test]
+    fn test_lsn_distance() {
+        assert_eq!(Lsn(1234).distance_to(Lsn(1245)), Some(11));
+        assert_eq!(Lsn(1234).distance_to(Lsn(1234)), Some(0));
+        assert_eq!(Lsn(1245).distance_to(Lsn(1234)), None);
+        assert_eq!(Lsn(1245).saturating_distance(Lsn(1234)), 0);
+        assert_eq!(Lsn(0).distance_to(Lsn::MAX), Some(u64::MAX));
+
+        let values = [
+            0,
+            1,
+            7,
+            8,
+            0x1000000,
+            0x12345678AAAA5555,
+            u64::MAX - 1,
+            u64::MAX,
+        ];
+        for a in values.map(Lsn) {
+            for b in values.map(Lsn) {
+                let distance = a.distance_to(b);
+                assert_eq!(distance.is_some(), a <= b, "{a} -> {b}");
+                assert_eq!(a.saturating_distance(b), distance.unwrap_or(0));
+                assert_eq!(i128::from(distance.unwrap_or(0)), b.widening_sub(a).max(0));
+                if let Some(distance) = distance {
+                    assert_eq!(a + distance, b);
+                    assert_eq!(b.checked_sub(distance), Some(a));
+                    // The reverse distance only exists between equal LSNs
+                    assert_eq!(b.distance_to(a), (a == b).then(|| 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lsn_segment_alignment() {
+        let seg_sz: usize = 16 * 1024 * 1024;
+
+        // Exactly on the segment start
+        assert_eq!(Lsn(0x2000000).align_to_segment(seg_sz), Lsn(0x2000000));
+        assert_eq!(Lsn(0x2000000).segment_number(seg_sz), 2);
+        // One byte before it
+        assert_eq!(Lsn(0x1ffffff).align_to_segment(seg_sz), Lsn(0x1000000));
+        assert_eq!(Lsn(0x1ffffff).segment_number(seg_sz), 1);
+
+        assert_eq!(Lsn(0).align_to_segment(seg_sz), Lsn(0));
+        assert_eq!(Lsn(0x1000007).align_to_segment(seg_sz), Lsn(0x1000000));
+        assert_eq!(
+            Lsn(u64::MAX).align_to_segment(seg_sz),
+            Lsn(u64::MAX - (seg_sz as u64 - 1))
+        );
+    }
+
     #[test]
     fn test_atomic_lsn() {
         let lsn = AtomicLsn::new(0);
@@ -319,4 +451,50 @@ mod tests {
         assert_eq!(lsn.fetch_max(Lsn(6000)), Lsn(5678));
         assert_eq!(lsn.fetch_max(Lsn(5000)), Lsn(6000));
     }
+
+    #[test]
+    fn test_lsn_range() {
+        let range = |start, end| LsnRange {
+            start: Lsn(start),
+            end: Lsn(end),
+        };
+
+        // Empty ranges contain and overlap nothing, not even themselves
+        for empty in [range(0x10, 0x10), range(0x20, 0x10)] {
+            assert!(empty.is_empty());
+            assert!(!empty.contains(Lsn(0x10)));
+            assert!(!empty.overlaps(&empty));
+            assert!(!empty.overlaps(&LsnRange::FULL));
+            assert!(!LsnRange::FULL.overlaps(&empty));
+            assert!(empty.intersect(&LsnRange::FULL).is_empty());
+        }
+
+        let r = range(0x10, 0x20);
+        assert!(!r.is_empty());
+        assert!(!r.contains(Lsn(0xF)));
+        assert!(r.contains(Lsn(0x10)));
+        assert!(r.contains(Lsn(0x1F)));
+        assert!(!r.contains(Lsn(0x20)));
+
+        // Adjacent ranges don't overlap
+        assert!(!r.overlaps(&range(0x20, 0x30)));
+        assert!(!range(0x0, 0x10).overlaps(&r));
+        assert!(r.intersect(&range(0x20, 0x30)).is_empty());
+
+        // Partial overlap
+        assert!(r.overlaps(&range(0x18, 0x30)));
+        assert_eq!(r.intersect(&range(0x18, 0x30)), range(0x18, 0x20));
+        assert_eq!(range(0x0, 0x11).intersect(&r), range(0x10, 0x11));
+
+        // Full overlap, in both directions
+        assert!(r.overlaps(&r));
+        assert_eq!(r.intersect(&r), r);
+        assert!(LsnRange::FULL.overlaps(&r));
+        assert_eq!(LsnRange::FULL.intersect(&r), r);
+        assert_eq!(r.intersect(&range(0x12, 0x14)), range(0x12, 0x14));
+
+        assert_eq!(LsnRange::from(Lsn(0x10)..Lsn(0x20)), r);
+        assert_eq!(Range::from(r), Lsn(0x10)..Lsn(0x20));
+        assert_eq!(r.to_string(), "0/10..0/20");
+    }
 }
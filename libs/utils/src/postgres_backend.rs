@@ -210,6 +210,33 @@ impl PostgresBackend {
         &self.peer_addr
     }
 
+    /// Returns another handle to the connection socket, must be called before the read stream is taken.
+    pub fn try_clone_socket(&mut self) -> Result<TcpStream> {
+        Ok(self.get_stream_in()?.try_clone_socket()?)
+    }
+
+    /// Makes writes to the connection fail if the peer does not accept any data for `timeout`,
+    /// instead of blocking until it does. `None` disables the timeout.
+    /// Must be called before the read stream is taken.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.try_clone_socket()?
+            .set_write_timeout(timeout)
+            .context("failed to set the socket write timeout")
+    }
+
+    /// Enables TCP keepalive probes on the connection, sent once it's idle for `idle`,
+    /// so that a vanished peer is detected even without any traffic. `None` disables them.
+    /// Must be called before the read stream is taken.
+    pub fn set_keepalive(&mut self, idle: Option<Duration>) -> Result<()> {
+        let socket = self.try_clone_socket()?;
+        let socket = socket2::SockRef::from(&socket);
+        match idle {
+            Some(idle) => socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle)),
+            None => socket.set_keepalive(false),
+        }
+        .context("failed to configure TCP keepalive")
+    }
+
     pub fn take_stream_in(&mut self) -> Option<ReadStream> {
         let stream = self.stream.take();
         match stream {
@@ -243,9 +270,22 @@ impl PostgresBackend {
     }
 
     /// Flush output buffer into the socket.
+    ///
+    /// Fails with [`io::ErrorKind::TimedOut`] if the write timeout is set and expires.
     pub fn flush(&mut self) -> io::Result<&mut Self> {
         let stream = self.stream.as_mut().unwrap();
-        stream.write_all(&self.buf_out)?;
+        stream.write_all(&self.buf_out).map_err(|e| {
+            // An expired socket timeout is reported as `WouldBlock`, which the read loops
+            // treat as a read timeout to retry.
+            if e.kind() == io::ErrorKind::WouldBlock {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("timed out writing to {}", self.peer_addr),
+                )
+            } else {
+                e
+            }
+        })?;
         self.buf_out.clear();
         Ok(self)
     }
@@ -503,3 +543,37 @@ impl PostgresBackend {
         Ok(ProcessMsgResult::Continue)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    #[test]
+    fn write_to_stalled_peer_times_out() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        // Never reads, so the socket buffers eventually fill up
+        let _peer = TcpStream::connect(listener.local_addr()?)?;
+        let (socket, _) = listener.accept()?;
+
+        let mut pgb = PostgresBackend::new(socket, AuthType::Trust, None, false)?;
+        pgb.set_keepalive(Some(Duration::from_secs(60)))?;
+        pgb.set_write_timeout(Some(Duration::from_millis(100)))?;
+
+        let started = Instant::now();
+        let chunk = vec![0; 64 * 1024];
+        let err = loop {
+            if let Err(e) = pgb.write_message(&BeMessage::CopyData(&chunk)) {
+                break e;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(60),
+                "writes to a stalled peer never time out"
+            );
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut, "{err}");
+
+        Ok(())
+    }
+}
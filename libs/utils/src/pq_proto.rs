@@ -26,6 +26,10 @@ pub const INT8_OID: Oid = 20;
 pub const INT4_OID: Oid = 23;
 pub const TEXT_OID: Oid = 25;
 
+/// Maximum message body size accepted by [`FeMessage::read`], the same as the limit
+/// Postgres applies to large messages (`PQ_LARGE_MESSAGE_LIMIT`).
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 0x3fff_ffff;
+
 #[derive(Debug)]
 pub enum FeMessage {
     StartupPacket(FeStartupPacket),
@@ -212,9 +216,20 @@ impl FeMessage {
     ///     Ok(())
     /// }
     /// ```
+    ///
+    /// Messages with a body larger than [`DEFAULT_MAX_MESSAGE_SIZE`] are rejected.
     #[inline(never)]
     pub fn read(stream: &mut (impl io::Read + Unpin)) -> anyhow::Result<Option<FeMessage>> {
-        Self::read_fut(&mut AsyncishRead(stream)).wait()
+        Self::read_with_max_size(stream, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Same as [`Self::read`], but fails on messages with a body larger than `max_message_size`,
+    /// before allocating the buffer for it.
+    pub fn read_with_max_size(
+        stream: &mut (impl io::Read + Unpin),
+        max_message_size: usize,
+    ) -> anyhow::Result<Option<FeMessage>> {
+        Self::read_fut_with_max_size(&mut AsyncishRead(stream), max_message_size).wait()
     }
 
     /// Read one message from the stream.
@@ -222,6 +237,18 @@ impl FeMessage {
     pub fn read_fut<Reader>(
         stream: &mut Reader,
     ) -> SyncFuture<Reader, impl Future<Output = anyhow::Result<Option<FeMessage>>> + '_>
+    where
+        Reader: tokio::io::AsyncRead + Unpin,
+    {
+        Self::read_fut_with_max_size(stream, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Read one message from the stream.
+    /// See documentation for `Self::read_with_max_size`.
+    pub fn read_fut_with_max_size<Reader>(
+        stream: &mut Reader,
+        max_message_size: usize,
+    ) -> SyncFuture<Reader, impl Future<Output = anyhow::Result<Option<FeMessage>>> + '_>
     where
         Reader: tokio::io::AsyncRead + Unpin,
     {
@@ -242,6 +269,11 @@ impl FeMessage {
             let len = retry_read!(stream.read_u32().await)?
                 .checked_sub(4)
                 .context("invalid message length")?;
+            ensure!(
+                len as usize <= max_message_size,
+                "message length {len} exceeds the maximum of {max_message_size} bytes, tag: {:?}",
+                tag as char
+            );
 
             let body = {
                 let mut buffer = vec![0u8; len as usize];
@@ -1066,10 +1098,29 @@ mod tests {
         assert_eq!(split_options(&params), ["foo bar", " \\", "baz ", "lol"]);
     }
 
+    #[test]
+    fn test_fe_message_max_size() {
+        // A `CopyData` message that declares an almost 4 GiB body
+        let oversized = [b'd', 0xff, 0xff, 0xff, 0xff];
+        let err = FeMessage::read(&mut oversized.as_ref()).unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum"), "{err}");
+
+        let mut message = vec![b'd'];
+        message.extend_from_slice(&(4 + 10_u32).to_be_bytes());
+        message.extend_from_slice(&[0; 10]);
+        let err = FeMessage::read_with_max_size(&mut message.as_slice(), 9).unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum"), "{err}");
+        match FeMessage::read_with_max_size(&mut message.as_slice(), 10) {
+            Ok(Some(FeMessage::CopyData(body))) => assert_eq!(body.len(), 10),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
     // Make sure that `read` is sync/async callable
     async fn _assert(stream: &mut (impl tokio::io::AsyncRead + Unpin)) {
         let _ = FeMessage::read(&mut [].as_ref());
         let _ = FeMessage::read_fut(stream).await;
+        let _ = FeMessage::read_fut_with_max_size(stream, 0).await;
 
         let _ = FeStartupPacket::read(&mut [].as_ref());
         let _ = FeStartupPacket::read_fut(stream).await;
@@ -136,6 +136,14 @@ impl BidiStream {
         }
     }
 
+    /// Returns another handle to the underlying socket, e.g. to shut it down from a different thread.
+    pub fn try_clone_socket(&self) -> io::Result<TcpStream> {
+        match self {
+            Self::Tcp(stream) => stream.get_ref().try_clone(),
+            Self::Tls(tls_boxed) => tls_boxed.sock.get_ref().try_clone(),
+        }
+    }
+
     /// Split the bi-directional stream into two owned read and write halves.
     pub fn split(self) -> (ReadStream, WriteStream) {
         match self {
@@ -0,0 +1,40 @@
+// This could be in ../src/logging.rs but since the logger is global, these
+// can't be run in threads of the same process
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use tracing::*;
+use utils::test_init_file_logger;
+
+fn read_lines(file: File) -> Lines<BufReader<File>> {
+    BufReader::new(file).lines()
+}
+
+#[test]
+fn test_json_format_has_span_fields() {
+    std::env::set_var("RUST_LOG", "info");
+
+    let log_file = test_init_file_logger!("info", "json");
+
+    info!("outside of spans");
+    {
+        let _tenant_span = info_span!("tenant", tenant = "some_tenant").entered();
+        let _timeline_span = info_span!("compact_timeline", timeline = "some_timeline").entered();
+        info!(custom = "hi", "inside of spans");
+    }
+
+    let lines = read_lines(log_file).map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+
+    let outside = serde_json::from_str::<serde_json::Value>(&lines[0]).unwrap();
+    assert_eq!(outside["fields"]["message"], "outside of spans");
+    assert!(outside.get("spans").is_none(), "{outside}");
+
+    let inside = serde_json::from_str::<serde_json::Value>(&lines[1]).unwrap();
+    assert_eq!(inside["fields"]["message"], "inside of spans");
+    assert_eq!(inside["fields"]["custom"], "hi");
+    // The span fields are not event fields, but are present all the same
+    assert!(inside["fields"].get("timeline").is_none(), "{inside}");
+    assert_eq!(inside["spans"]["tenant"], "some_tenant");
+    assert_eq!(inside["spans"]["timeline"], "some_timeline");
+    assert_eq!(inside["target"], "logger_json_spans_test");
+}
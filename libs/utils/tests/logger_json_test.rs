@@ -29,6 +29,13 @@ fn test_json_format_has_message_and_custom_field() {
 
         assert_eq!(json_object["fields"]["custom"], "hi");
         assert_eq!(json_object["fields"]["message"], "test log message");
+        assert_eq!(json_object["target"], "logger_json_test");
+
+        let timestamp = json_object["timestamp"].as_str().unwrap();
+        chrono::DateTime::parse_from_rfc3339(timestamp).unwrap();
+        // Milliseconds precision, e.g. 2022-10-16T10:00:00.000Z
+        assert_eq!(timestamp.len(), 24, "{timestamp}");
+        assert!(timestamp.ends_with('Z'), "{timestamp}");
 
         assert_ne!(json_object["level"], "TRACE");
         assert_ne!(json_object["level"], "DEBUG");
@@ -0,0 +1,37 @@
+// This could be in ../src/logging.rs but since the logger is global, these
+// can't be run in threads of the same process
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use tracing::*;
+use utils::test_init_file_logger;
+
+fn read_lines(file: File) -> Lines<BufReader<File>> {
+    BufReader::new(file).lines()
+}
+
+#[test]
+fn test_logfmt_format_has_message_and_custom_field() {
+    std::env::set_var("RUST_LOG", "info");
+
+    let log_file = test_init_file_logger!("info", "logfmt");
+
+    let custom_field: &str = "hi";
+    trace!(custom = %custom_field, "test log message");
+    debug!(custom = %custom_field, "test log message");
+    info!(custom = %custom_field, "test log message");
+    warn!(custom = %custom_field, "test log message");
+    error!(custom = %custom_field, "test log message");
+
+    let lines = read_lines(log_file).map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    for (content, level) in lines.iter().zip(["info", "warn", "error"]) {
+        serde_json::from_str::<serde_json::Value>(content).unwrap_err();
+        assert!(content.starts_with("ts="), "{content}");
+        assert!(
+            content.ends_with(&format!(
+                " level={level} custom=hi message=\"test log message\""
+            )),
+            "{content}"
+        );
+    }
+}
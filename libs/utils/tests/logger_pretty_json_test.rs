@@ -0,0 +1,44 @@
+// This could be in ../src/logging.rs but since the logger is global, these
+// can't be run in threads of the same process
+use std::io::Read;
+use tracing::*;
+use utils::test_init_file_logger;
+
+#[test]
+fn test_pretty_json_format_spans_multiple_lines() {
+    std::env::set_var("RUST_LOG", "info");
+
+    let mut log_file = test_init_file_logger!("info", "json", true);
+
+    let custom_field: &str = "hi";
+    debug!(custom = %custom_field, "test log message");
+    info!(custom = %custom_field, "test log message");
+    warn!(custom = %custom_field, "test log message");
+
+    let mut content = String::new();
+    log_file.read_to_string(&mut content).unwrap();
+
+    let objects = serde_json::Deserializer::from_str(&content)
+        .into_iter::<serde_json::Value>()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert_eq!(objects.len(), 2);
+    assert!(
+        content.lines().count() > objects.len(),
+        "objects should be pretty-printed over multiple lines: {content}"
+    );
+    // A single line is not a complete object anymore
+    for line in content.lines() {
+        serde_json::from_str::<serde_json::Value>(line).unwrap_err();
+    }
+
+    for (json_object, level) in objects.iter().zip(["INFO", "WARN"]) {
+        assert_eq!(json_object["fields"]["custom"], "hi");
+        assert_eq!(json_object["fields"]["message"], "test log message");
+        assert_eq!(json_object["target"], "logger_pretty_json_test");
+        assert_eq!(json_object["level"], level);
+
+        let timestamp = json_object["timestamp"].as_str().unwrap();
+        chrono::DateTime::parse_from_rfc3339(timestamp).unwrap();
+    }
+}
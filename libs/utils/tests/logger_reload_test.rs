@@ -0,0 +1,32 @@
+// This could be in ../src/logging.rs but since the logger is global, these
+// can't be run in threads of the same process
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use tracing::*;
+use utils::{logging, test_init_file_logger};
+
+fn read_lines(file: File) -> Lines<BufReader<File>> {
+    BufReader::new(file).lines()
+}
+
+#[test]
+fn test_log_filter_reload() {
+    std::env::set_var("RUST_LOG", "info");
+
+    let log_file = test_init_file_logger!("info", "plain");
+
+    info!("info before reload");
+    debug!("debug before reload");
+
+    logging::set_log_filter("utils=not_a_level").unwrap_err();
+    logging::set_log_filter("debug").unwrap();
+
+    info!("info after reload");
+    debug!("debug after reload");
+
+    let lines = read_lines(log_file).map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{lines:?}");
+    assert!(lines[0].contains("INFO") && lines[0].contains("info before reload"));
+    assert!(lines[1].contains("INFO") && lines[1].contains("info after reload"));
+    assert!(lines[2].contains("DEBUG") && lines[2].contains("debug after reload"));
+}
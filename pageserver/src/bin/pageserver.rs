@@ -199,7 +199,12 @@ fn initialize_config(
 
 fn start_pageserver(conf: &'static PageServerConf, daemonize: bool) -> Result<()> {
     // Initialize logger
-    let log_file = logging::init(LOG_FILE_NAME, daemonize, conf.log_format)?;
+    let log_file = logging::init(
+        LOG_FILE_NAME,
+        daemonize,
+        conf.log_format,
+        conf.log_pretty_json,
+    )?;
 
     info!("version: {}", version());
 
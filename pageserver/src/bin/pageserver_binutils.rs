@@ -4,16 +4,18 @@
 //!
 //! Separate, `metadata` subcommand allows to print and update pageserver's metadata file.
 use std::{
+    ops::Range,
     path::{Path, PathBuf},
     str::FromStr,
 };
 
 use anyhow::Context;
-use clap::{value_parser, Arg, Command};
+use clap::{value_parser, Arg, ArgAction, Command};
 
 use pageserver::{
     page_cache,
-    tenant::{dump_layerfile_from_path, metadata::TimelineMetadata},
+    repository::Key,
+    tenant::{dump_layerfile_as_json, dump_layerfile_from_path, metadata::TimelineMetadata},
     virtual_file,
 };
 use postgres_ffi::ControlFileData;
@@ -52,7 +54,8 @@ fn main() -> anyhow::Result<()> {
                     "Failed to read input file as a pg control one: {e:#}\n\
                     Attempting to read it as layer file"
                 );
-                print_layerfile(&path)?;
+                let key_range = parse_key_range(&arg_matches)?;
+                print_layerfile(&path, arg_matches.get_flag("json"), key_range)?;
             }
         }
     };
@@ -71,11 +74,34 @@ fn read_pg_control_file(control_file_path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn print_layerfile(path: &Path) -> anyhow::Result<()> {
+fn parse_key_range(arg_matches: &clap::ArgMatches) -> anyhow::Result<Option<Range<Key>>> {
+    let key_start = arg_matches.get_one::<String>("key_start");
+    let key_end = arg_matches.get_one::<String>("key_end");
+    if key_start.is_none() && key_end.is_none() {
+        return Ok(None);
+    }
+    let start = match key_start {
+        Some(key) => Key::from_hex(key).context("Failed to parse --key_start")?,
+        None => Key::MIN,
+    };
+    let end = match key_end {
+        Some(key) => Key::from_hex(key).context("Failed to parse --key_end")?,
+        None => Key::MAX,
+    };
+    Ok(Some(start..end))
+}
+
+fn print_layerfile(path: &Path, json: bool, key_range: Option<Range<Key>>) -> anyhow::Result<()> {
     // Basic initialization of things that don't change after startup
     virtual_file::init(10);
     page_cache::init(100);
-    dump_layerfile_from_path(path, true)
+    if json {
+        let dump = dump_layerfile_as_json(path, true, key_range.as_ref())?;
+        println!("{}", serde_json::to_string_pretty(&dump)?);
+        Ok(())
+    } else {
+        dump_layerfile_from_path(path, true, key_range.as_ref())
+    }
 }
 
 fn handle_metadata(path: &Path, arg_matches: &clap::ArgMatches) -> Result<(), anyhow::Error> {
@@ -126,6 +152,22 @@ fn cli() -> Command {
                 .value_parser(value_parser!(PathBuf))
                 .required(false),
         )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print layer file contents as JSON"),
+        )
+        .arg(
+            Arg::new("key_start")
+                .long("key_start")
+                .help("Print only the layer entries with keys starting from this one, in hex"),
+        )
+        .arg(
+            Arg::new("key_end")
+                .long("key_end")
+                .help("Print only the layer entries with keys before this one, in hex"),
+        )
         .subcommand(
             Command::new(METADATA_SUBCOMMAND)
                 .about("Read and update pageserver metadata file")
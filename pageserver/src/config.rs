@@ -6,7 +6,9 @@
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use remote_storage::RemoteStorageConfig;
+use std::collections::BTreeMap;
 use std::env;
+use std::os::unix::fs::PermissionsExt;
 use utils::crashsafe::path_with_suffix_extension;
 
 use std::path::{Path, PathBuf};
@@ -25,10 +27,16 @@ use crate::tenant::TIMELINES_SEGMENT_NAME;
 use crate::tenant_config::{TenantConf, TenantConfOpt};
 
 /// The name of the metadata file pageserver creates per timeline.
+/// Every save of the metadata adds a generation suffix to it, see [`crate::tenant::metadata::save_metadata`].
 pub const METADATA_FILE_NAME: &str = "metadata";
+/// The name of the file with the timeline settings, like its label.
+pub const TIMELINE_SETTINGS_FILE_NAME: &str = "settings.json";
 pub const TIMELINE_UNINIT_MARK_SUFFIX: &str = "___uninit";
 const TENANT_CONFIG_NAME: &str = "config";
 
+/// Postgres versions the pageserver knows how to handle, see [`PageServerConf::pg_distrib_dir`].
+pub const SUPPORTED_PG_VERSIONS: &[u32] = &[14, 15];
+
 pub mod defaults {
     use crate::tenant_config::defaults::*;
     use const_format::formatcp;
@@ -67,10 +75,12 @@ pub mod defaults {
 #initial_superuser_name = '{DEFAULT_SUPERUSER}'
 
 #log_format = '{DEFAULT_LOG_FORMAT}'
+#log_pretty_json = false
 # [tenant_config]
 #checkpoint_distance = {DEFAULT_CHECKPOINT_DISTANCE} # in bytes
 #checkpoint_timeout = {DEFAULT_CHECKPOINT_TIMEOUT}
 #compaction_target_size = {DEFAULT_COMPACTION_TARGET_SIZE} # in bytes
+#compaction_max_size = {DEFAULT_COMPACTION_MAX_SIZE} # in bytes
 #compaction_period = '{DEFAULT_COMPACTION_PERIOD}'
 #compaction_threshold = '{DEFAULT_COMPACTION_THRESHOLD}'
 
@@ -78,6 +88,14 @@ pub mod defaults {
 #gc_horizon = {DEFAULT_GC_HORIZON}
 #image_creation_threshold = {DEFAULT_IMAGE_CREATION_THRESHOLD}
 #pitr_interval = '{DEFAULT_PITR_INTERVAL}'
+#gc_cutoff_policy = '{DEFAULT_GC_CUTOFF_POLICY}'
+#compaction_concurrency = {DEFAULT_COMPACTION_CONCURRENCY}
+#max_ancestor_depth = {DEFAULT_MAX_ANCESTOR_DEPTH}
+#compaction_batch_size = {DEFAULT_COMPACTION_BATCH_SIZE}
+#max_layers_per_get = {DEFAULT_MAX_LAYERS_PER_GET}
+#max_upload_bytes_per_sec = {DEFAULT_MAX_UPLOAD_BYTES_PER_SEC}
+#gc_respect_remote_branchpoints = {DEFAULT_GC_RESPECT_REMOTE_BRANCHPOINTS}
+#checkpoint_max_inmem_bytes = {DEFAULT_CHECKPOINT_MAX_INMEM_BYTES}
 
 # [remote_storage]
 
@@ -115,6 +133,9 @@ pub struct PageServerConf {
     pub workdir: PathBuf,
 
     pub pg_distrib_dir: PathBuf,
+    /// Explicit `initdb` binary paths per postgres major version,
+    /// used instead of the one from `pg_distrib_dir`.
+    pub initdb_bin_overrides: BTreeMap<u32, PathBuf>,
 
     pub auth_type: AuthType,
 
@@ -132,6 +153,8 @@ pub struct PageServerConf {
     pub broker_endpoints: Vec<Url>,
 
     pub log_format: LogFormat,
+    /// Pretty-print the JSON logs over multiple lines, for reading them locally.
+    pub log_pretty_json: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -186,6 +209,7 @@ struct PageServerConfigBuilder {
     workdir: BuilderValue<PathBuf>,
 
     pg_distrib_dir: BuilderValue<PathBuf>,
+    initdb_bin_overrides: BuilderValue<BTreeMap<u32, PathBuf>>,
 
     auth_type: BuilderValue<AuthType>,
 
@@ -200,6 +224,7 @@ struct PageServerConfigBuilder {
     broker_endpoints: BuilderValue<Vec<Url>>,
 
     log_format: BuilderValue<LogFormat>,
+    log_pretty_json: BuilderValue<bool>,
 }
 
 impl Default for PageServerConfigBuilder {
@@ -220,6 +245,7 @@ impl Default for PageServerConfigBuilder {
             pg_distrib_dir: Set(env::current_dir()
                 .expect("cannot access current directory")
                 .join("pg_install")),
+            initdb_bin_overrides: Set(BTreeMap::new()),
             auth_type: Set(AuthType::Trust),
             auth_validation_public_key_path: Set(None),
             remote_storage_config: Set(None),
@@ -228,6 +254,7 @@ impl Default for PageServerConfigBuilder {
             broker_etcd_prefix: Set(etcd_broker::DEFAULT_NEON_BROKER_ETCD_PREFIX.to_string()),
             broker_endpoints: Set(Vec::new()),
             log_format: Set(LogFormat::from_str(DEFAULT_LOG_FORMAT).unwrap()),
+            log_pretty_json: Set(false),
         }
     }
 }
@@ -269,6 +296,10 @@ impl PageServerConfigBuilder {
         self.pg_distrib_dir = BuilderValue::Set(pg_distrib_dir)
     }
 
+    pub fn initdb_bin_overrides(&mut self, initdb_bin_overrides: BTreeMap<u32, PathBuf>) {
+        self.initdb_bin_overrides = BuilderValue::Set(initdb_bin_overrides)
+    }
+
     pub fn auth_type(&mut self, auth_type: AuthType) {
         self.auth_type = BuilderValue::Set(auth_type)
     }
@@ -304,6 +335,10 @@ impl PageServerConfigBuilder {
         self.log_format = BuilderValue::Set(log_format)
     }
 
+    pub fn log_pretty_json(&mut self, log_pretty_json: bool) {
+        self.log_pretty_json = BuilderValue::Set(log_pretty_json)
+    }
+
     pub fn build(self) -> anyhow::Result<PageServerConf> {
         let broker_endpoints = self
             .broker_endpoints
@@ -333,6 +368,9 @@ impl PageServerConfigBuilder {
             pg_distrib_dir: self
                 .pg_distrib_dir
                 .ok_or(anyhow!("missing pg_distrib_dir"))?,
+            initdb_bin_overrides: self
+                .initdb_bin_overrides
+                .ok_or(anyhow!("missing initdb_bin_overrides"))?,
             auth_type: self.auth_type.ok_or(anyhow!("missing auth_type"))?,
             auth_validation_public_key_path: self
                 .auth_validation_public_key_path
@@ -349,6 +387,9 @@ impl PageServerConfigBuilder {
                 .broker_etcd_prefix
                 .ok_or(anyhow!("missing broker_etcd_prefix"))?,
             log_format: self.log_format.ok_or(anyhow!("missing log_format"))?,
+            log_pretty_json: self
+                .log_pretty_json
+                .ok_or(anyhow!("missing log_pretty_json"))?,
         })
     }
 }
@@ -362,6 +403,11 @@ impl PageServerConf {
         self.workdir.join("tenants")
     }
 
+    /// Directory with the cached initdb outputs, see `Tenant::bootstrap_timeline`.
+    pub fn initdb_cache_path(&self) -> PathBuf {
+        self.workdir.join("initdb_cache")
+    }
+
     pub fn tenant_path(&self, tenant_id: &TenantId) -> PathBuf {
         self.tenants_path().join(tenant_id.to_string())
     }
@@ -392,12 +438,19 @@ impl PageServerConf {
     }
 
     /// Points to a place in pageserver's local directory,
-    /// where certain timeline's metadata file should be located.
+    /// where certain timeline's metadata file without the generation suffix should be located.
+    /// Use [`crate::tenant::metadata::latest_metadata_path`] for the current metadata file.
     pub fn metadata_path(&self, timeline_id: TimelineId, tenant_id: TenantId) -> PathBuf {
         self.timeline_path(&timeline_id, &tenant_id)
             .join(METADATA_FILE_NAME)
     }
 
+    /// Points to the timeline's settings file.
+    pub fn timeline_settings_path(&self, timeline_id: TimelineId, tenant_id: TenantId) -> PathBuf {
+        self.timeline_path(&timeline_id, &tenant_id)
+            .join(TIMELINE_SETTINGS_FILE_NAME)
+    }
+
     //
     // Postgres distribution paths
     //
@@ -426,6 +479,29 @@ impl PageServerConf {
         }
     }
 
+    /// Postgres versions that new timelines can be bootstrapped with: the supported versions
+    /// that have an `initdb` binary, see [`PageServerConf::initdb_bin_path`].
+    pub fn available_pg_versions(&self) -> Vec<u32> {
+        SUPPORTED_PG_VERSIONS
+            .iter()
+            .copied()
+            .filter(|&pg_version| {
+                self.initdb_bin_path(pg_version)
+                    .map(|initdb_bin_path| initdb_bin_path.is_file())
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    /// The `initdb` binary to bootstrap timelines of the given postgres version with:
+    /// the override from `initdb_bin_overrides` if there's one, `pg_bin_dir/initdb` otherwise.
+    pub fn initdb_bin_path(&self, pg_version: u32) -> anyhow::Result<PathBuf> {
+        match self.initdb_bin_overrides.get(&pg_version) {
+            Some(initdb_bin_path) => Ok(initdb_bin_path.clone()),
+            None => Ok(self.pg_bin_dir(pg_version)?.join("initdb")),
+        }
+    }
+
     /// Parse a configuration file (pageserver.toml) into a PageServerConf struct,
     /// validating the input and failing on errors.
     ///
@@ -450,6 +526,9 @@ impl PageServerConf {
                 "pg_distrib_dir" => {
                     builder.pg_distrib_dir(PathBuf::from(parse_toml_string(key, item)?))
                 }
+                "initdb_bin_overrides" => {
+                    builder.initdb_bin_overrides(parse_toml_initdb_bin_overrides(key, item)?)
+                }
                 "auth_validation_public_key_path" => builder.auth_validation_public_key_path(Some(
                     PathBuf::from(parse_toml_string(key, item)?),
                 )),
@@ -476,6 +555,7 @@ impl PageServerConf {
                 "log_format" => builder.log_format(
                     LogFormat::from_config(&parse_toml_string(key, item)?)?
                 ),
+                "log_pretty_json" => builder.log_pretty_json(parse_toml_bool(key, item)?),
                 _ => bail!("unrecognized pageserver option '{key}'"),
             }
         }
@@ -495,6 +575,23 @@ impl PageServerConf {
             );
         }
 
+        for (pg_version, initdb_bin_path) in &conf.initdb_bin_overrides {
+            conf.pg_bin_dir(*pg_version).with_context(|| {
+                format!("Invalid initdb_bin_overrides entry for version {pg_version}")
+            })?;
+            let initdb_metadata = initdb_bin_path.metadata().with_context(|| {
+                format!(
+                    "Can't access initdb binary override for postgres {pg_version} at '{}'",
+                    initdb_bin_path.display()
+                )
+            })?;
+            ensure!(
+                initdb_metadata.is_file() && initdb_metadata.permissions().mode() & 0o111 != 0,
+                "initdb binary override for postgres {pg_version} at '{}' is not an executable file",
+                initdb_bin_path.display()
+            );
+        }
+
         conf.default_tenant_conf = t_conf.merge(TenantConf::default());
 
         Ok(conf)
@@ -523,6 +620,11 @@ impl PageServerConf {
             )?);
         }
 
+        if let Some(compaction_max_size) = item.get("compaction_max_size") {
+            t_conf.compaction_max_size =
+                Some(parse_toml_u64("compaction_max_size", compaction_max_size)?);
+        }
+
         if let Some(compaction_period) = item.get("compaction_period") {
             t_conf.compaction_period =
                 Some(parse_toml_duration("compaction_period", compaction_period)?);
@@ -544,6 +646,10 @@ impl PageServerConf {
         if let Some(pitr_interval) = item.get("pitr_interval") {
             t_conf.pitr_interval = Some(parse_toml_duration("pitr_interval", pitr_interval)?);
         }
+        if let Some(gc_cutoff_policy) = item.get("gc_cutoff_policy") {
+            t_conf.gc_cutoff_policy =
+                Some(parse_toml_from_str("gc_cutoff_policy", gc_cutoff_policy)?);
+        }
         if let Some(walreceiver_connect_timeout) = item.get("walreceiver_connect_timeout") {
             t_conf.walreceiver_connect_timeout = Some(parse_toml_duration(
                 "walreceiver_connect_timeout",
@@ -559,7 +665,41 @@ impl PageServerConf {
         if let Some(max_lsn_wal_lag) = item.get("max_lsn_wal_lag") {
             t_conf.max_lsn_wal_lag = Some(parse_toml_from_str("max_lsn_wal_lag", max_lsn_wal_lag)?);
         }
+        if let Some(compaction_concurrency) = item.get("compaction_concurrency") {
+            t_conf.compaction_concurrency =
+                Some(parse_toml_u64("compaction_concurrency", compaction_concurrency)?.try_into()?);
+        }
+        if let Some(max_ancestor_depth) = item.get("max_ancestor_depth") {
+            t_conf.max_ancestor_depth =
+                Some(parse_toml_u64("max_ancestor_depth", max_ancestor_depth)?.try_into()?);
+        }
+        if let Some(compaction_batch_size) = item.get("compaction_batch_size") {
+            t_conf.compaction_batch_size =
+                Some(parse_toml_u64("compaction_batch_size", compaction_batch_size)?.try_into()?);
+        }
+        if let Some(max_layers_per_get) = item.get("max_layers_per_get") {
+            t_conf.max_layers_per_get =
+                Some(parse_toml_u64("max_layers_per_get", max_layers_per_get)?.try_into()?);
+        }
+        if let Some(max_upload_bytes_per_sec) = item.get("max_upload_bytes_per_sec") {
+            t_conf.max_upload_bytes_per_sec = Some(parse_toml_u64(
+                "max_upload_bytes_per_sec",
+                max_upload_bytes_per_sec,
+            )?);
+        }
+        if let Some(gc_respect_remote_branchpoints) = item.get("gc_respect_remote_branchpoints") {
+            t_conf.gc_respect_remote_branchpoints = Some(parse_toml_bool(
+                "gc_respect_remote_branchpoints",
+                gc_respect_remote_branchpoints,
+            )?);
+        }
 
+        if let Some(checkpoint_max_inmem_bytes) = item.get("checkpoint_max_inmem_bytes") {
+            t_conf.checkpoint_max_inmem_bytes = Some(parse_toml_u64(
+                "checkpoint_max_inmem_bytes",
+                checkpoint_max_inmem_bytes,
+            )?);
+        }
         Ok(t_conf)
     }
 
@@ -581,6 +721,7 @@ impl PageServerConf {
             superuser: "cloud_admin".to_string(),
             workdir: repo_dir,
             pg_distrib_dir: PathBuf::new(),
+            initdb_bin_overrides: BTreeMap::new(),
             auth_type: AuthType::Trust,
             auth_validation_public_key_path: None,
             remote_storage_config: None,
@@ -589,6 +730,7 @@ impl PageServerConf {
             broker_endpoints: Vec::new(),
             broker_etcd_prefix: etcd_broker::DEFAULT_NEON_BROKER_ETCD_PREFIX.to_string(),
             log_format: LogFormat::from_str(defaults::DEFAULT_LOG_FORMAT).unwrap(),
+            log_pretty_json: false,
         }
     }
 }
@@ -614,6 +756,11 @@ fn parse_toml_u64(name: &str, item: &Item) -> Result<u64> {
     Ok(i as u64)
 }
 
+fn parse_toml_bool(name: &str, item: &Item) -> Result<bool> {
+    item.as_bool()
+        .with_context(|| format!("configure option {name} is not a boolean"))
+}
+
 fn parse_toml_duration(name: &str, item: &Item) -> Result<Duration> {
     let s = item
         .as_str()
@@ -638,6 +785,28 @@ where
     })
 }
 
+fn parse_toml_initdb_bin_overrides(
+    name: &str,
+    item: &Item,
+) -> anyhow::Result<BTreeMap<u32, PathBuf>> {
+    let table = item
+        .as_table_like()
+        .with_context(|| format!("configure option {name} is not a table"))?;
+
+    table
+        .iter()
+        .map(|(pg_version, path)| {
+            let pg_version = pg_version.parse::<u32>().with_context(|| {
+                format!("Key {pg_version} for {name} is not a postgres major version")
+            })?;
+            let path = path
+                .as_str()
+                .with_context(|| format!("Value for key {pg_version} in {name} is not a string"))?;
+            Ok((pg_version, PathBuf::from(path)))
+        })
+        .collect()
+}
+
 fn parse_toml_array(name: &str, item: &Item) -> anyhow::Result<Vec<String>> {
     let array = item
         .as_array()
@@ -715,6 +884,7 @@ log_format = 'json'
                 max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
                 workdir,
                 pg_distrib_dir,
+                initdb_bin_overrides: BTreeMap::new(),
                 auth_type: AuthType::Trust,
                 auth_validation_public_key_path: None,
                 remote_storage_config: None,
@@ -725,6 +895,7 @@ log_format = 'json'
                     .expect("Failed to parse a valid broker endpoint URL")],
                 broker_etcd_prefix: etcd_broker::DEFAULT_NEON_BROKER_ETCD_PREFIX.to_string(),
                 log_format: LogFormat::from_str(defaults::DEFAULT_LOG_FORMAT).unwrap(),
+                log_pretty_json: false,
             },
             "Correct defaults should be used when no config values are provided"
         );
@@ -760,6 +931,7 @@ log_format = 'json'
                 max_file_descriptors: 333,
                 workdir,
                 pg_distrib_dir,
+                initdb_bin_overrides: BTreeMap::new(),
                 auth_type: AuthType::Trust,
                 auth_validation_public_key_path: None,
                 remote_storage_config: None,
@@ -770,6 +942,7 @@ log_format = 'json'
                     .expect("Failed to parse a valid broker endpoint URL")],
                 broker_etcd_prefix: etcd_broker::DEFAULT_NEON_BROKER_ETCD_PREFIX.to_string(),
                 log_format: LogFormat::Json,
+                log_pretty_json: false,
             },
             "Should be able to parse all basic config values correctly"
         );
@@ -902,6 +1075,92 @@ broker_endpoints = ['{broker_endpoint}']
         Ok(())
     }
 
+    #[test]
+    fn parse_initdb_bin_overrides() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+        let broker_endpoint = "http://127.0.0.1:7777";
+
+        let initdb_bin_path = tempdir.path().join("custom_initdb");
+        fs::write(&initdb_bin_path, "#!/bin/sh\n")?;
+        let parse_with_override = |initdb_bin_path: &Path| {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoints = ['{broker_endpoint}']
+
+[initdb_bin_overrides]
+{DEFAULT_PG_VERSION} = '{}'"#,
+                pg_distrib_dir.display(),
+                initdb_bin_path.display(),
+            );
+            PageServerConf::parse_and_validate(&config_string.parse()?, &workdir)
+        };
+
+        let err = parse_with_override(&initdb_bin_path)
+            .expect_err("Should not accept a non-executable initdb override");
+        assert!(
+            err.to_string().contains("is not an executable file"),
+            "Unexpected error: {err:?}"
+        );
+
+        fs::set_permissions(&initdb_bin_path, fs::Permissions::from_mode(0o755))?;
+        let parsed_config = parse_with_override(&initdb_bin_path)?;
+        assert_eq!(
+            parsed_config.initdb_bin_path(DEFAULT_PG_VERSION)?,
+            initdb_bin_path
+        );
+        // Versions without an override still use the binary from pg_distrib_dir
+        assert_eq!(
+            parsed_config.initdb_bin_path(15)?,
+            pg_distrib_dir.join("v15").join("bin").join("initdb")
+        );
+        // There's no initdb in pg_distrib_dir, only the overridden version is available
+        assert_eq!(
+            parsed_config.available_pg_versions(),
+            vec![DEFAULT_PG_VERSION]
+        );
+
+        let missing_bin_path = tempdir.path().join("missing_initdb");
+        let err = parse_with_override(&missing_bin_path)
+            .expect_err("Should not accept a missing initdb override");
+        assert!(
+            err.to_string()
+                .contains("Can't access initdb binary override"),
+            "Unexpected error: {err:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tenant_conf_round_trip() -> anyhow::Result<()> {
+        let toml = r#"[tenant_config]
+compaction_batch_size = 3
+compaction_concurrency = 2
+compaction_target_size = 1024
+compaction_max_size = 4096"#
+            .parse::<toml_edit::Document>()?;
+        let parsed = PageServerConf::parse_toml_tenant_conf(&toml["tenant_config"])?;
+        assert_eq!(
+            parsed,
+            TenantConfOpt {
+                compaction_batch_size: Some(3),
+                compaction_concurrency: Some(2),
+                compaction_target_size: Some(1024),
+                compaction_max_size: Some(4096),
+                ..TenantConfOpt::default()
+            }
+        );
+
+        // Tenant configs are persisted with serde, the result should parse back the same way
+        let persisted = toml_edit::easy::to_string(&parsed)?.parse::<toml_edit::Document>()?;
+        let reparsed = PageServerConf::parse_toml_tenant_conf(persisted.as_item())?;
+        assert_eq!(reparsed, parsed);
+
+        Ok(())
+    }
+
     fn prepare_fs(tempdir: &TempDir) -> anyhow::Result<(PathBuf, PathBuf)> {
         let tempdir_path = tempdir.path();
 
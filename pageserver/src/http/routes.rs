@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{anyhow, Context, Result};
 use hyper::StatusCode;
@@ -10,12 +11,15 @@ use tracing::*;
 use super::models::{LocalTimelineInfo, RemoteTimelineInfo, TimelineInfo};
 use super::models::{
     StatusResponse, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse, TenantInfo,
-    TimelineCreateRequest,
+    TimelineCreateRequest, TimelineHasChildrenError,
 };
 use crate::pgdatadir_mapping::LsnForTimestamp;
 use crate::storage_sync;
 use crate::storage_sync::index::{RemoteIndex, RemoteTimeline};
-use crate::tenant::{TenantState, Timeline};
+use crate::tenant::timeline_settings::TIMELINE_CREATOR_MAX_LEN;
+use crate::tenant::{
+    AncestorStart, InitdbParams, TenantError, TenantState, Timeline, TimelineCreateResult,
+};
 use crate::tenant_config::TenantConfOpt;
 use crate::{config::PageServerConf, tenant_mgr};
 use utils::{
@@ -35,7 +39,11 @@ use utils::{
 #[cfg(feature = "testing")]
 use super::models::{ConfigureFailpointsRequest, TimelineGcRequest};
 #[cfg(feature = "testing")]
+use crate::tenant::GcRequest;
+#[cfg(feature = "testing")]
 use crate::CheckpointConfig;
+#[cfg(feature = "testing")]
+use tokio_util::sync::CancellationToken;
 
 struct State {
     conf: &'static PageServerConf,
@@ -79,6 +87,20 @@ fn get_config(request: &Request<Body>) -> &'static PageServerConf {
     get_state(request).conf
 }
 
+impl From<TenantError> for ApiError {
+    fn from(e: TenantError) -> ApiError {
+        match e {
+            TenantError::NotFound(_) => ApiError::NotFound(anyhow!(e)),
+            TenantError::NotActive(_)
+            | TenantError::AlreadyExists(_)
+            | TenantError::HasChildren(_)
+            | TenantError::Busy(_) => ApiError::Conflict(e.to_string()),
+            TenantError::UnsupportedPgVersion { .. } => ApiError::BadRequest(anyhow!(e)),
+            TenantError::Other(e) => ApiError::InternalServerError(e),
+        }
+    }
+}
+
 // Helper function to construct a TimelineInfo struct for a timeline
 async fn build_timeline_info(
     state: &State,
@@ -131,6 +153,14 @@ async fn build_timeline_info(
     let current_physical_size = Some(timeline.get_physical_size());
     let state = timeline.current_state();
 
+    let created_at = timeline
+        .get_created_at()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .filter(|since_epoch| !since_epoch.is_zero())
+        .map(|since_epoch| since_epoch.as_micros());
+
+    let gc_info = timeline.gc_info_snapshot();
     let info = TimelineInfo {
         tenant_id: timeline.tenant_id,
         timeline_id: timeline.timeline_id,
@@ -140,6 +170,8 @@ async fn build_timeline_info(
         last_record_lsn,
         prev_record_lsn: Some(timeline.get_prev_record_lsn()),
         latest_gc_cutoff_lsn: *timeline.get_latest_gc_cutoff_lsn(),
+        gc_horizon_cutoff: gc_info.horizon_cutoff,
+        gc_pitr_cutoff: gc_info.pitr_cutoff,
         current_logical_size,
         current_physical_size,
         current_logical_size_non_incremental: if include_non_incremental_logical_size {
@@ -156,6 +188,10 @@ async fn build_timeline_info(
         last_received_msg_lsn,
         last_received_msg_ts,
         pg_version: timeline.pg_version,
+        label: timeline.get_label(),
+        created_at,
+        created_by: timeline.get_created_by(),
+        read_only: timeline.is_read_only(),
 
         remote_consistent_lsn,
         awaits_download,
@@ -189,30 +225,48 @@ async fn timeline_create_handler(mut request: Request<Body>) -> Result<Response<
 
     let state = get_state(&request);
 
+    let initdb_params = InitdbParams {
+        encoding: request_data.initdb_encoding.clone(),
+        locale: request_data.initdb_locale.clone(),
+        superuser_override: None,
+    };
+    initdb_params.validate().map_err(ApiError::BadRequest)?;
+    if let Some(created_by) = &request_data.created_by {
+        if created_by.len() > TIMELINE_CREATOR_MAX_LEN {
+            return Err(ApiError::BadRequest(anyhow!(
+                "created_by is {} bytes long, the limit is {TIMELINE_CREATOR_MAX_LEN} bytes",
+                created_by.len()
+            )));
+        }
+    }
+
     let tenant = tenant_mgr::get_tenant(tenant_id, true).map_err(ApiError::NotFound)?;
     let new_timeline_info = async {
-        match tenant.create_timeline(
+        let (status, timeline) = match tenant.try_create_timeline(
             request_data.new_timeline_id.map(TimelineId::from),
             request_data.ancestor_timeline_id.map(TimelineId::from),
-            request_data.ancestor_start_lsn,
-            request_data.pg_version.unwrap_or(crate::DEFAULT_PG_VERSION)
+            request_data.ancestor_start_lsn.map(AncestorStart::Lsn),
+            request_data.pg_version.unwrap_or(crate::DEFAULT_PG_VERSION),
+            initdb_params,
+            None,
+            request_data.created_by.clone(),
         ).await {
-            Ok(Some(new_timeline)) => {
-                // Created. Construct a TimelineInfo for it.
-                let timeline_info = build_timeline_info(state, &new_timeline, false, false)
-                    .await
-                    .map_err(ApiError::InternalServerError)?;
-                Ok(Some(timeline_info))
-            }
-            Ok(None) => Ok(None), // timeline already exists
-            Err(err) => Err(ApiError::InternalServerError(err)),
-        }
+            Ok(TimelineCreateResult::Created(new_timeline)) => (StatusCode::CREATED, new_timeline),
+            Ok(TimelineCreateResult::AlreadyExists(existing_timeline)) => (StatusCode::OK, existing_timeline),
+            // Another request is creating the same timeline right now
+            Err(TenantError::AlreadyExists(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let timeline_info = build_timeline_info(state, &timeline, false, false)
+            .await
+            .map_err(ApiError::InternalServerError)?;
+        Ok(Some((status, timeline_info)))
     }
     .instrument(info_span!("timeline_create", tenant = %tenant_id, new_timeline = ?request_data.new_timeline_id, lsn=?request_data.ancestor_start_lsn, pg_version=?request_data.pg_version))
         .await?;
 
     Ok(match new_timeline_info {
-        Some(info) => json_response(StatusCode::CREATED, info)?,
+        Some((status, info)) => json_response(status, info)?,
         None => json_response(StatusCode::CONFLICT, ())?,
     })
 }
@@ -296,12 +350,15 @@ async fn timeline_detail_handler(request: Request<Body>) -> Result<Response<Body
 
     let timeline_info = async {
         let timeline = tokio::task::spawn_blocking(move || {
-            tenant_mgr::get_tenant(tenant_id, true)?.get_timeline(timeline_id, false)
+            tenant_mgr::get_tenant(tenant_id, true)
+                .map_err(ApiError::NotFound)?
+                .get_timeline(timeline_id, false)
+                .map_err(ApiError::from)
         })
         .await
         .map_err(|e: JoinError| ApiError::InternalServerError(e.into()))?;
 
-        let timeline = timeline.map_err(ApiError::NotFound)?;
+        let timeline = timeline?;
 
         let timeline_info = build_timeline_info(
             state,
@@ -333,8 +390,8 @@ async fn get_lsn_by_timestamp_handler(request: Request<Body>) -> Result<Response
     let timestamp_pg = postgres_ffi::to_pg_timestamp(timestamp);
 
     let timeline = tenant_mgr::get_tenant(tenant_id, true)
-        .and_then(|tenant| tenant.get_timeline(timeline_id, true))
-        .map_err(ApiError::NotFound)?;
+        .map_err(ApiError::NotFound)?
+        .get_timeline(timeline_id, true)?;
     let result = match timeline
         .find_lsn_for_timestamp(timestamp_pg)
         .map_err(ApiError::InternalServerError)?
@@ -465,13 +522,24 @@ async fn timeline_delete_handler(request: Request<Body>) -> Result<Response<Body
     check_permission(&request, Some(tenant_id))?;
 
     let state = get_state(&request);
-    tenant_mgr::delete_timeline(tenant_id, timeline_id)
+    let deletion_result = tenant_mgr::delete_timeline(tenant_id, timeline_id)
         .instrument(info_span!("timeline_delete", tenant = %tenant_id, timeline = %timeline_id))
-        .await
-        // FIXME: Errors from `delete_timeline` can occur for a number of reasons, incuding both
-        // user and internal errors. Replace this with better handling once the error type permits
-        // it.
-        .map_err(ApiError::InternalServerError)?;
+        .await;
+    if let Err(e) = deletion_result {
+        return match e.downcast::<TenantError>() {
+            // Unlike the other conflicts, retrying won't help until the children are deleted,
+            // so report them in a separate status with a body that lists them
+            Ok(TenantError::HasChildren(child_timeline_ids)) => json_response(
+                StatusCode::PRECONDITION_FAILED,
+                TimelineHasChildrenError {
+                    msg: TenantError::HasChildren(child_timeline_ids.clone()).to_string(),
+                    child_timeline_ids,
+                },
+            ),
+            Ok(e) => Err(ApiError::from(e)),
+            Err(e) => Err(ApiError::InternalServerError(e)),
+        };
+    }
 
     let mut remote_index = state.remote_index.write().await;
     remote_index.remove_timeline_entry(TenantTimelineId {
@@ -605,6 +673,10 @@ async fn tenant_create_handler(mut request: Request<Body>) -> Result<Response<Bo
                 .map_err(ApiError::BadRequest)?,
         );
     }
+    if let Some(gc_cutoff_policy) = request_data.gc_cutoff_policy {
+        tenant_conf.gc_cutoff_policy =
+            Some(gc_cutoff_policy.parse().map_err(ApiError::BadRequest)?);
+    }
 
     if let Some(walreceiver_connect_timeout) = request_data.walreceiver_connect_timeout {
         tenant_conf.walreceiver_connect_timeout = Some(
@@ -637,6 +709,7 @@ async fn tenant_create_handler(mut request: Request<Body>) -> Result<Response<Bo
     }
 
     tenant_conf.compaction_target_size = request_data.compaction_target_size;
+    tenant_conf.compaction_max_size = request_data.compaction_max_size;
     tenant_conf.compaction_threshold = request_data.compaction_threshold;
 
     if let Some(compaction_period) = request_data.compaction_period {
@@ -646,6 +719,13 @@ async fn tenant_create_handler(mut request: Request<Body>) -> Result<Response<Bo
                 .map_err(ApiError::BadRequest)?,
         );
     }
+    tenant_conf.compaction_concurrency = request_data.compaction_concurrency;
+    tenant_conf.max_ancestor_depth = request_data.max_ancestor_depth;
+    tenant_conf.compaction_batch_size = request_data.compaction_batch_size;
+    tenant_conf.max_layers_per_get = request_data.max_layers_per_get;
+    tenant_conf.max_upload_bytes_per_sec = request_data.max_upload_bytes_per_sec;
+    tenant_conf.gc_respect_remote_branchpoints = request_data.gc_respect_remote_branchpoints;
+    tenant_conf.checkpoint_max_inmem_bytes = request_data.checkpoint_max_inmem_bytes;
 
     let target_tenant_id = request_data
         .new_tenant_id
@@ -693,6 +773,10 @@ async fn tenant_config_handler(mut request: Request<Body>) -> Result<Response<Bo
                 .map_err(ApiError::BadRequest)?,
         );
     }
+    if let Some(gc_cutoff_policy) = request_data.gc_cutoff_policy {
+        tenant_conf.gc_cutoff_policy =
+            Some(gc_cutoff_policy.parse().map_err(ApiError::BadRequest)?);
+    }
     if let Some(walreceiver_connect_timeout) = request_data.walreceiver_connect_timeout {
         tenant_conf.walreceiver_connect_timeout = Some(
             humantime::parse_duration(&walreceiver_connect_timeout)
@@ -723,6 +807,7 @@ async fn tenant_config_handler(mut request: Request<Body>) -> Result<Response<Bo
         );
     }
     tenant_conf.compaction_target_size = request_data.compaction_target_size;
+    tenant_conf.compaction_max_size = request_data.compaction_max_size;
     tenant_conf.compaction_threshold = request_data.compaction_threshold;
 
     if let Some(compaction_period) = request_data.compaction_period {
@@ -732,6 +817,13 @@ async fn tenant_config_handler(mut request: Request<Body>) -> Result<Response<Bo
                 .map_err(ApiError::BadRequest)?,
         );
     }
+    tenant_conf.compaction_concurrency = request_data.compaction_concurrency;
+    tenant_conf.max_ancestor_depth = request_data.max_ancestor_depth;
+    tenant_conf.compaction_batch_size = request_data.compaction_batch_size;
+    tenant_conf.max_layers_per_get = request_data.max_layers_per_get;
+    tenant_conf.max_upload_bytes_per_sec = request_data.max_upload_bytes_per_sec;
+    tenant_conf.gc_respect_remote_branchpoints = request_data.gc_respect_remote_branchpoints;
+    tenant_conf.checkpoint_max_inmem_bytes = request_data.checkpoint_max_inmem_bytes;
 
     tokio::task::spawn_blocking(move || {
         let _enter = info_span!("tenant_config", tenant = ?tenant_id).entered();
@@ -791,15 +883,26 @@ async fn timeline_gc_handler(mut request: Request<Body>) -> Result<Response<Body
     // FIXME: currently this will return a 500 error on bad tenant id; it should be 4XX
     let tenant = tenant_mgr::get_tenant(tenant_id, false).map_err(ApiError::NotFound)?;
     let gc_req: TimelineGcRequest = json_request(&mut request).await?;
+    let remote_branchpoints = tenant.remote_branchpoints().await;
 
     let _span_guard =
         info_span!("manual_gc", tenant = %tenant_id, timeline = %timeline_id).entered();
-    let gc_horizon = gc_req.gc_horizon.unwrap_or_else(|| tenant.get_gc_horizon());
+    // One-shot overrides of the tenant's GC settings
+    let mut gc_request = GcRequest::from_tenant_conf(&tenant, Some(timeline_id));
+    gc_request.remote_branchpoints = remote_branchpoints;
+    if let Some(gc_horizon) = gc_req.gc_horizon {
+        gc_request.horizon = gc_horizon;
+    }
+    if let Some(pitr_interval) = gc_req.pitr_interval {
+        gc_request.pitr = humantime::parse_duration(&pitr_interval)
+            .with_context(bad_duration("pitr_interval", &pitr_interval))
+            .map_err(ApiError::BadRequest)?;
+    }
+    gc_request.force_checkpoint = true;
+    gc_request.validate().map_err(ApiError::BadRequest)?;
 
-    // Use tenant's pitr setting
-    let pitr = tenant.get_pitr_interval();
     let result = tenant
-        .gc_iteration(Some(timeline_id), gc_horizon, pitr, true)
+        .gc_iteration(&gc_request, &CancellationToken::new())
         // FIXME: `gc_iteration` can return an error for multiple reasons; we should handle it
         // better once the types support it.
         .map_err(ApiError::InternalServerError)?;
@@ -814,9 +917,7 @@ async fn timeline_compact_handler(request: Request<Body>) -> Result<Response<Bod
     check_permission(&request, Some(tenant_id))?;
 
     let tenant = tenant_mgr::get_tenant(tenant_id, true).map_err(ApiError::NotFound)?;
-    let timeline = tenant
-        .get_timeline(timeline_id, true)
-        .map_err(ApiError::NotFound)?;
+    let timeline = tenant.get_timeline(timeline_id, true)?;
     timeline.compact().map_err(ApiError::InternalServerError)?;
 
     json_response(StatusCode::OK, ())
@@ -830,9 +931,7 @@ async fn timeline_checkpoint_handler(request: Request<Body>) -> Result<Response<
     check_permission(&request, Some(tenant_id))?;
 
     let tenant = tenant_mgr::get_tenant(tenant_id, true).map_err(ApiError::NotFound)?;
-    let timeline = tenant
-        .get_timeline(timeline_id, true)
-        .map_err(ApiError::NotFound)?;
+    let timeline = tenant.get_timeline(timeline_id, true)?;
     timeline
         .checkpoint(CheckpointConfig::Forced)
         .map_err(ApiError::InternalServerError)?;
@@ -8,6 +8,7 @@ use std::path::{Path, PathBuf};
 
 use anyhow::{bail, ensure, Context, Result};
 use bytes::Bytes;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 use walkdir::WalkDir;
 
@@ -307,6 +308,7 @@ pub fn import_basebackup_from_tar<Reader: Read>(
     tline: &Timeline,
     reader: Reader,
     base_lsn: Lsn,
+    cancel: &CancellationToken,
 ) -> Result<()> {
     info!("importing base at {base_lsn}");
     let mut modification = tline.begin_modification(base_lsn);
@@ -316,6 +318,9 @@ pub fn import_basebackup_from_tar<Reader: Read>(
 
     // Import base
     for base_tar_entry in tar::Archive::new(reader).entries()? {
+        if cancel.is_cancelled() {
+            bail!("basebackup import at {base_lsn} cancelled");
+        }
         let entry = base_tar_entry?;
         let header = entry.header();
         let len = header.entry_size()? as usize;
@@ -552,8 +557,11 @@ fn import_file<Reader: Read>(
         // zenith.signal is not necessarily the last file, that we handle
         // but it is ok to call `finish_write()`, because final `modification.commit()`
         // will update lsn once more to the final one.
+        // If the timeline already starts after the previous LSN, there is nothing to update.
         let writer = modification.tline.writer();
-        writer.finish_write(prev_lsn);
+        if prev_lsn > modification.tline.get_last_record_lsn() {
+            writer.finish_write(prev_lsn)?;
+        }
 
         debug!("imported zenith signal {}", prev_lsn);
     } else if file_path.starts_with("pg_tblspc") {
@@ -518,13 +518,10 @@ impl PageServerHandler {
         pgb.write_message(&BeMessage::CopyInResponse)?;
         pgb.flush().await?;
 
-        // import_basebackup_from_tar() is not async, mainly because the Tar crate
-        // it uses is not async. So we need to jump through some hoops:
-        // - convert the input from client connection to a synchronous Read
-        // - use block_in_place()
         let mut copyin_stream = Box::pin(copyin_stream(pgb));
-        let reader = SyncIoBridge::new(StreamReader::new(&mut copyin_stream));
-        tokio::task::block_in_place(|| timeline.import_basebackup_from_tar(reader, base_lsn))?;
+        timeline
+            .import_basebackup_from_tar_async(StreamReader::new(&mut copyin_stream), base_lsn)
+            .await?;
         timeline.initialize()?;
 
         // Drain the rest of the Copy data
@@ -713,9 +710,11 @@ impl PageServerHandler {
     #[instrument(skip(self, timeline, req), fields(rel = %req.rel, blkno = %req.blkno, req_lsn = %req.lsn))]
     async fn handle_get_page_at_lsn_request(
         &self,
-        timeline: &Timeline,
+        timeline: &Arc<Timeline>,
         req: &PagestreamGetPageRequest,
     ) -> Result<PagestreamBeMessage> {
+        // Fail right away while the WAL redo is down, instead of waiting for it to fail the read
+        timeline.check_walredo_available()?;
         let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
         let lsn = Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn)
             .await?;
@@ -1060,8 +1059,7 @@ impl postgres_backend_async::Handler for PageServerHandler {
 }
 
 fn get_local_timeline(tenant_id: TenantId, timeline_id: TimelineId) -> Result<Arc<Timeline>> {
-    tenant_mgr::get_tenant(tenant_id, true)
-        .and_then(|tenant| tenant.get_timeline(timeline_id, true))
+    Ok(tenant_mgr::get_tenant(tenant_id, true)?.get_timeline(timeline_id, true)?)
 }
 
 ///
@@ -471,6 +471,37 @@ impl Timeline {
         Ok(result.to_keyspace())
     }
 
+    ///
+    /// Iterate through all the keys in use at the given LSN, in key order, together
+    /// with their values at that LSN.
+    ///
+    /// The keys come from [`Timeline::collect_keyspace`], which holds one range per
+    /// relation or SLRU segment, so the memory use does not depend on the number of keys.
+    /// Each value is only looked up, and reconstructed with WAL redo if needed, when the
+    /// iterator gets to its key. The values of the keys inherited from the ancestors are
+    /// read from the ancestor timelines, as with [`Timeline::get`].
+    ///
+    /// Fails right away if the LSN is behind the GC cutoff.
+    pub fn iter_keys(&self, lsn: Lsn) -> impl Iterator<Item = Result<(Key, Bytes)>> + '_ {
+        let keyspace = self
+            .check_lsn_is_in_scope(lsn, &self.get_latest_gc_cutoff_lsn())
+            .and_then(|()| self.collect_keyspace(lsn));
+        let (ranges, error) = match keyspace {
+            Ok(keyspace) => (keyspace.ranges, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+
+        error.into_iter().map(Err).chain(
+            ranges
+                .into_iter()
+                .flat_map(|range| {
+                    std::iter::successors(Some(range.start), |key| Some(key.next()))
+                        .take_while(move |key| *key < range.end)
+                })
+                .map(move |key| Ok((key, self.get(key, lsn)?))),
+        )
+    }
+
     /// Get cached size of relation if it not updated after specified LSN
     pub fn get_cached_rel_size(&self, tag: &RelTag, lsn: Lsn) -> Option<BlockNumber> {
         let rel_size_cache = self.rel_size_cache.read().unwrap();
@@ -973,7 +1004,7 @@ impl<'a> DatadirModification<'a> {
             writer.delete(key_range, lsn)?;
         }
 
-        writer.finish_write(lsn);
+        writer.finish_write(lsn)?;
 
         if pending_nblocks != 0 {
             writer.update_current_logical_size(pending_nblocks * i64::from(BLCKSZ));
@@ -1179,7 +1210,7 @@ fn rel_dir_to_key(spcnode: Oid, dbnode: Oid) -> Key {
     }
 }
 
-fn rel_block_to_key(rel: RelTag, blknum: BlockNumber) -> Key {
+pub(crate) fn rel_block_to_key(rel: RelTag, blknum: BlockNumber) -> Key {
     Key {
         field1: 0x00,
         field2: rel.spcnode,
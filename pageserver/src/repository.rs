@@ -3,9 +3,12 @@ use anyhow::{bail, Result};
 use byteorder::{ByteOrder, BE};
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use std::collections::HashMap;
 use std::fmt;
 use std::ops::{AddAssign, Range};
 use std::time::Duration;
+use utils::id::TimelineId;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
 /// Key used in the Repository kv-store.
@@ -189,7 +192,8 @@ impl Value {
 ///
 /// Result of performing GC
 ///
-#[derive(Default, Serialize)]
+#[serde_as]
+#[derive(Default, Clone, Serialize)]
 pub struct GcResult {
     pub layers_total: u64,
     pub layers_needed_by_cutoff: u64,
@@ -197,9 +201,20 @@ pub struct GcResult {
     pub layers_needed_by_branches: u64,
     pub layers_not_updated: u64,
     pub layers_removed: u64, // # of layer files removed because they have been made obsolete by newer ondisk files.
+    pub bytes_removed: u64,
 
     #[serde(serialize_with = "serialize_duration_as_millis")]
     pub elapsed: Duration,
+
+    // Results of the individual timelines processed by the GC iteration.
+    #[serde_as(as = "HashMap<DisplayFromStr, _>")]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub per_timeline: HashMap<TimelineId, GcResult>,
+
+    // Timelines skipped because another GC iteration was collecting them at the same time.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub timelines_skipped: Vec<TimelineId>,
 }
 
 // helper function for `GcResult`, serializing a `Duration` as an integer number of milliseconds
@@ -218,7 +233,42 @@ impl AddAssign for GcResult {
         self.layers_needed_by_branches += other.layers_needed_by_branches;
         self.layers_not_updated += other.layers_not_updated;
         self.layers_removed += other.layers_removed;
+        self.bytes_removed += other.bytes_removed;
 
         self.elapsed += other.elapsed;
+        self.per_timeline.extend(other.per_timeline);
+        self.timelines_skipped.extend(other.timelines_skipped);
+    }
+}
+
+///
+/// Result of performing compaction
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CompactionResult {
+    pub image_layers_created: u64,
+    pub delta_layers_created: u64,
+    pub layers_removed: u64, // # of level 0 delta layers replaced by the newly created delta layers.
+    pub bytes_written: u64,
+}
+
+impl AddAssign for CompactionResult {
+    fn add_assign(&mut self, other: Self) {
+        self.image_layers_created += other.image_layers_created;
+        self.delta_layers_created += other.delta_layers_created;
+        self.layers_removed += other.layers_removed;
+        self.bytes_written += other.bytes_written;
     }
 }
+
+///
+/// Result of a timeline deletion that also cleans up the remote storage
+///
+#[derive(Default, Serialize)]
+pub struct TimelineDeletionResult {
+    pub local_files_removed: bool,
+    pub remote_layers_removed: u64,
+
+    #[serde(serialize_with = "serialize_duration_as_millis")]
+    pub elapsed: Duration,
+}
@@ -148,7 +148,7 @@ use std::{
     fmt::Debug,
     num::{NonZeroU32, NonZeroUsize},
     ops::ControlFlow,
-    path::{Path, PathBuf},
+    path::PathBuf,
     sync::{Condvar, Mutex},
 };
 
@@ -156,10 +156,7 @@ use anyhow::{anyhow, bail, Context};
 use futures::stream::{FuturesUnordered, StreamExt};
 use once_cell::sync::OnceCell;
 use remote_storage::GenericRemoteStorage;
-use tokio::{
-    fs,
-    time::{Duration, Instant},
-};
+use tokio::time::{Duration, Instant};
 use tracing::*;
 
 use self::{
@@ -175,7 +172,7 @@ use crate::{
     task_mgr,
     task_mgr::TaskKind,
     task_mgr::BACKGROUND_RUNTIME,
-    tenant::metadata::TimelineMetadata,
+    tenant::metadata::{self, TimelineMetadata},
     tenant_mgr::{attach_local_tenants, TenantAttachData},
 };
 use crate::{
@@ -188,6 +185,7 @@ use utils::id::{TenantId, TenantTimelineId, TimelineId};
 
 use self::download::download_index_parts;
 pub use self::download::gather_tenant_timelines_index_parts;
+pub use self::upload::set_upload_rate_limit;
 
 static SYNC_QUEUE: OnceCell<SyncQueue> = OnceCell::new();
 
@@ -1068,21 +1066,24 @@ async fn update_local_metadata(
     };
     let remote_lsn = remote_metadata.disk_consistent_lsn();
 
-    let local_metadata_path = conf.metadata_path(sync_id.timeline_id, sync_id.tenant_id);
-    let local_lsn = if local_metadata_path.exists() {
-        let local_metadata = read_metadata_file(&local_metadata_path)
-            .await
-            .with_context(|| {
-                format!(
-                    "Failed to load local metadata from path '{}'",
-                    local_metadata_path.display()
-                )
-            })?;
-
-        Some(local_metadata.disk_consistent_lsn())
-    } else {
-        None
-    };
+    // The newest valid generation of the local metadata, as the timeline would load it
+    let timeline_path = conf.timeline_path(&sync_id.timeline_id, &sync_id.tenant_id);
+    let local_metadata = tokio::task::spawn_blocking({
+        let timeline_path = timeline_path.clone();
+        move || match metadata::latest_metadata_path(&timeline_path)? {
+            Some(_) => metadata::load_metadata_from_dir(&timeline_path).map(Some),
+            None => Ok(None),
+        }
+    })
+    .await
+    .context("Failed to join the local metadata load task")?
+    .with_context(|| {
+        format!(
+            "Failed to load local metadata from timeline directory '{}'",
+            timeline_path.display()
+        )
+    })?;
+    let local_lsn = local_metadata.map(|metadata| metadata.disk_consistent_lsn());
 
     if local_lsn < Some(remote_lsn) {
         info!("Updating local timeline metadata from remote timeline: local disk_consistent_lsn={local_lsn:?}, remote disk_consistent_lsn={remote_lsn}");
@@ -1093,23 +1094,29 @@ async fn update_local_metadata(
             timeline_id,
         } = sync_id;
         tokio::task::spawn_blocking(move || {
-            crate::tenant::save_metadata(conf, timeline_id, tenant_id, &cloned_metadata, true)
+            crate::tenant::save_metadata(
+                conf,
+                timeline_id,
+                tenant_id,
+                &cloned_metadata,
+                local_lsn.is_none(),
+            )
         })
         .await
         .with_context(|| {
             format!(
                 "failed to join save_metadata task for {}",
-                local_metadata_path.display()
+                timeline_path.display()
             )
         })?
         .with_context(|| {
             format!(
-                "Failed to write remote metadata bytes locally to path '{}'",
-                local_metadata_path.display()
+                "Failed to write remote metadata bytes locally to timeline directory '{}'",
+                timeline_path.display()
             )
         })?;
     } else {
-        info!("Local metadata at path '{}' has later disk consistent Lsn ({local_lsn:?}) than the remote one ({remote_lsn}), skipping the update", local_metadata_path.display());
+        info!("Local metadata in '{}' has later disk consistent Lsn ({local_lsn:?}) than the remote one ({remote_lsn}), skipping the update", timeline_path.display());
     }
 
     Ok(())
@@ -1149,15 +1156,6 @@ async fn delete_timeline_data(
     register_sync_status(sync_id, sync_start, TASK_NAME, Some(sync_status));
 }
 
-async fn read_metadata_file(metadata_path: &Path) -> anyhow::Result<TimelineMetadata> {
-    TimelineMetadata::from_bytes(
-        &fs::read(metadata_path)
-            .await
-            .context("Failed to read local metadata bytes from fs")?,
-    )
-    .context("Failed to parse metadata bytes")
-}
-
 async fn upload_timeline_data(
     conf: &'static PageServerConf,
     (storage, index, sync_queue): (&GenericRemoteStorage, &RemoteIndex, &SyncQueue),
@@ -1442,7 +1440,20 @@ fn compare_local_and_remote_timeline(
         })
         .collect::<HashSet<_>>();
 
-    let (initial_timeline_status, awaits_download) = if !needed_to_download_files.is_empty() {
+    // The local metadata might be an older generation, if the newest one got torn,
+    // then the remote one is downloaded instead, along with the layers written after it
+    let remote_metadata_is_newer =
+        remote_entry.metadata.disk_consistent_lsn() > local_metadata.disk_consistent_lsn();
+    if remote_metadata_is_newer {
+        info!(
+            "Remote metadata has later disk consistent Lsn ({}) than the local one ({})",
+            remote_entry.metadata.disk_consistent_lsn(),
+            local_metadata.disk_consistent_lsn()
+        );
+    }
+
+    let needs_sync = !needed_to_download_files.is_empty() || remote_metadata_is_newer;
+    let (initial_timeline_status, awaits_download) = if needs_sync {
         new_sync_tasks.push_back((
             sync_id,
             SyncTask::download(LayersDownload::from_skipped_layers(
@@ -1522,6 +1533,7 @@ fn register_sync_status(
 
 #[cfg(test)]
 mod test_utils {
+    use tokio::fs;
     use utils::lsn::Lsn;
 
     use crate::tenant::harness::TenantHarness;
@@ -1903,5 +1915,41 @@ mod tests {
                 )]
             );
         }
+
+        #[test]
+        fn needs_download_of_newer_remote_metadata() {
+            // the local metadata fell back to an older generation, while all the layers are present
+            let mut new_sync_tasks = VecDeque::default();
+            let sync_id = TenantTimelineId::generate();
+            let local_metadata = dummy_metadata(0x02.into());
+            let local_files =
+                HashMap::from([(PathBuf::from("first_file"), LayerFileMetadata::new(123))]);
+            let mut remote_entry = RemoteTimeline::new(dummy_metadata(0x03.into()));
+            remote_entry
+                .add_timeline_layers([(PathBuf::from("first_file"), LayerFileMetadata::new(123))]);
+
+            let (status, sync_needed) = compare_local_and_remote_timeline(
+                &mut new_sync_tasks,
+                sync_id,
+                local_metadata,
+                local_files.clone(),
+                &remote_entry,
+            );
+
+            assert_eq!(status, LocalTimelineInitStatus::NeedsSync);
+            assert!(sync_needed);
+
+            let new_sync_tasks = new_sync_tasks.into_iter().collect::<Vec<_>>();
+
+            assert_eq!(
+                &new_sync_tasks,
+                &[(
+                    sync_id,
+                    SyncTask::download(LayersDownload::from_skipped_layers(
+                        local_files.keys().cloned().collect()
+                    ))
+                )]
+            );
+        }
     }
 }
@@ -1,14 +1,29 @@
 //! Timeline synchronization logic to compress and upload to the remote storage all new timeline files from the checkpoints.
 
-use std::{fmt::Debug, path::PathBuf};
+use std::{
+    collections::{hash_map, HashMap},
+    fmt::Debug,
+    future::Future,
+    io,
+    num::NonZeroU64,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{self, Poll},
+};
 
 use anyhow::Context;
 use futures::stream::{FuturesUnordered, StreamExt};
+use once_cell::sync::Lazy;
 use remote_storage::GenericRemoteStorage;
-use tokio::fs;
+use tokio::{
+    fs,
+    io::{AsyncRead, ReadBuf},
+    time::{sleep, Duration, Instant, Sleep},
+};
 use tracing::{debug, error, info, warn};
 
-use utils::id::TenantTimelineId;
+use utils::id::{TenantId, TenantTimelineId};
 
 use super::{
     index::{IndexPart, RemoteTimeline},
@@ -107,52 +122,66 @@ pub(super) async fn upload_timeline_layers<'a>(
         layers_to_upload.len(),
     );
 
+    let rate_limiter = UPLOAD_RATE_LIMITERS
+        .lock()
+        .unwrap()
+        .get(&sync_id.tenant_id)
+        .cloned();
     let mut upload_tasks = layers_to_upload
         .into_iter()
-        .map(|(source_path, known_metadata)| async move {
-            let source_file = match fs::File::open(&source_path).await.with_context(|| {
-                format!(
-                    "Failed to upen a source file for layer '{}'",
-                    source_path.display()
-                )
-            }) {
-                Ok(file) => file,
-                Err(e) => return Err(UploadError::MissingLocalFile(source_path, e)),
-            };
-
-            let fs_size = source_file
-                .metadata()
-                .await
-                .with_context(|| {
+        .map(|(source_path, known_metadata)| {
+            let rate_limiter = rate_limiter.clone();
+            async move {
+                let source_file = match fs::File::open(&source_path).await.with_context(|| {
                     format!(
-                        "Failed to get the source file metadata for layer '{}'",
+                        "Failed to upen a source file for layer '{}'",
                         source_path.display()
                     )
-                })
-                .map_err(UploadError::Other)?
-                .len();
-
-            // FIXME: this looks bad
-            if let Some(metadata_size) = known_metadata.file_size() {
-                if metadata_size != fs_size {
-                    return Err(UploadError::Other(anyhow::anyhow!(
-                        "File {source_path:?} has its current FS size {fs_size} diferent from initially determined {metadata_size}"
-                    )));
+                }) {
+                    Ok(file) => file,
+                    Err(e) => return Err(UploadError::MissingLocalFile(source_path, e)),
+                };
+
+                let fs_size = source_file
+                    .metadata()
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to get the source file metadata for layer '{}'",
+                            source_path.display()
+                        )
+                    })
+                    .map_err(UploadError::Other)?
+                    .len();
+
+                // FIXME: this looks bad
+                if let Some(metadata_size) = known_metadata.file_size() {
+                    if metadata_size != fs_size {
+                        return Err(UploadError::Other(anyhow::anyhow!(
+                            "File {source_path:?} has its current FS size {fs_size} diferent from initially determined {metadata_size}"
+                        )));
+                    }
+                } else {
+                    // this is a silly state we would like to avoid
                 }
-            } else {
-                // this is a silly state we would like to avoid
-            }
 
-            let fs_size = usize::try_from(fs_size).with_context(|| format!("File {source_path:?} size {fs_size} could not be converted to usize"))
-                .map_err(UploadError::Other)?;
+                let fs_size = usize::try_from(fs_size).with_context(|| format!("File {source_path:?} size {fs_size} could not be converted to usize"))
+                    .map_err(UploadError::Other)?;
 
-            match storage
-                .upload_storage_object(Box::new(source_file), fs_size, &source_path)
-                .await
-                .with_context(|| format!("Failed to upload layer file for {sync_id}"))
-            {
-                Ok(()) => Ok(source_path),
-                Err(e) => Err(UploadError::MissingLocalFile(source_path, e)),
+                let source: Box<dyn AsyncRead + Unpin + Send + Sync> = match rate_limiter {
+                    Some(rate_limiter) => {
+                        Box::new(ThrottledReader::new(source_file, rate_limiter))
+                    }
+                    None => Box::new(source_file),
+                };
+                match storage
+                    .upload_storage_object(source, fs_size, &source_path)
+                    .await
+                    .with_context(|| format!("Failed to upload layer file for {sync_id}"))
+                {
+                    Ok(()) => Ok(source_path),
+                    Err(e) => Err(UploadError::MissingLocalFile(source_path, e)),
+                }
             }
         })
         .collect::<FuturesUnordered<_>>();
@@ -217,6 +246,152 @@ enum UploadError {
     Other(anyhow::Error),
 }
 
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Upload rate limiters of the tenants that have one, see [`set_upload_rate_limit`].
+static UPLOAD_RATE_LIMITERS: Lazy<Mutex<HashMap<TenantId, Arc<UploadRateLimiter>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Limits the rate of the layer uploads of the tenant, shared by all its timelines,
+/// to `max_bytes_per_sec`. 0 removes the limit.
+pub fn set_upload_rate_limit(tenant_id: TenantId, max_bytes_per_sec: u64) {
+    let mut rate_limiters = UPLOAD_RATE_LIMITERS.lock().unwrap();
+    match NonZeroU64::new(max_bytes_per_sec) {
+        Some(bytes_per_sec) => match rate_limiters.entry(tenant_id) {
+            hash_map::Entry::Occupied(o) => o.get().set_rate(bytes_per_sec),
+            hash_map::Entry::Vacant(v) => {
+                v.insert(Arc::new(UploadRateLimiter::new(bytes_per_sec)));
+            }
+        },
+        None => {
+            rate_limiters.remove(&tenant_id);
+        }
+    }
+}
+
+/// A token bucket of the bytes allowed to upload, refilled at the limit rate
+/// and holding up to a second worth of uploads.
+struct UploadRateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    bytes_per_sec: NonZeroU64,
+    available: u64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, now: Instant) {
+        let rate = u128::from(self.bytes_per_sec.get());
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_nanos();
+        let refill = elapsed * rate / NANOS_PER_SEC;
+        if u128::from(self.available) + refill >= rate {
+            self.available = self.bytes_per_sec.get();
+            self.refilled_at = now;
+        } else if refill > 0 {
+            self.available += refill as u64;
+            // Account only the time it took to refill the whole bytes, keeping the fraction of the next byte
+            let refill_nanos = (refill * NANOS_PER_SEC + rate - 1) / rate;
+            self.refilled_at += Duration::from_nanos(refill_nanos as u64);
+        }
+    }
+}
+
+impl UploadRateLimiter {
+    fn new(bytes_per_sec: NonZeroU64) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket {
+                bytes_per_sec,
+                available: bytes_per_sec.get(),
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn set_rate(&self, bytes_per_sec: NonZeroU64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill(Instant::now());
+        bucket.bytes_per_sec = bytes_per_sec;
+        bucket.available = bucket.available.min(bytes_per_sec.get());
+    }
+
+    /// Takes up to `wanted` bytes from the bucket. If there are not enough bytes for the whole
+    /// request, or for a full bucket in case of larger requests, returns the time to wait for them.
+    fn try_acquire(&self, wanted: usize) -> Result<usize, Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill(Instant::now());
+        let rate = bucket.bytes_per_sec.get();
+        let needed = (wanted as u64).min(rate);
+        if bucket.available >= needed {
+            let acquired = (wanted as u64).min(bucket.available);
+            bucket.available -= acquired;
+            Ok(acquired as usize)
+        } else {
+            let missing = u128::from(needed - bucket.available);
+            let rate = u128::from(rate);
+            let wait_nanos = (missing * NANOS_PER_SEC + rate - 1) / rate;
+            Err(Duration::from_nanos(wait_nanos as u64))
+        }
+    }
+
+    /// Returns the acquired bytes that were not uploaded to the bucket.
+    fn release(&self, unused: usize) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.available = (bucket.available + unused as u64).min(bucket.bytes_per_sec.get());
+    }
+}
+
+/// Reads the layer file no faster than the upload rate limiter of its tenant allows.
+struct ThrottledReader<R> {
+    inner: R,
+    rate_limiter: Arc<UploadRateLimiter>,
+    delay: Option<Pin<Box<Sleep>>>,
+    read_buf: Vec<u8>,
+}
+
+impl<R> ThrottledReader<R> {
+    fn new(inner: R, rate_limiter: Arc<UploadRateLimiter>) -> Self {
+        Self {
+            inner,
+            rate_limiter,
+            delay: None,
+            read_buf: Vec::new(),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ThrottledReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                futures::ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+            match this.rate_limiter.try_acquire(buf.remaining()) {
+                Ok(acquired) => {
+                    this.read_buf.resize(acquired, 0);
+                    let mut limited_buf = ReadBuf::new(&mut this.read_buf);
+                    let poll = Pin::new(&mut this.inner).poll_read(cx, &mut limited_buf);
+                    let read = limited_buf.filled().len();
+                    buf.put_slice(limited_buf.filled());
+                    this.rate_limiter.release(acquired - read);
+                    return poll;
+                }
+                Err(wait) => this.delay = Some(Box::pin(sleep(wait))),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -230,7 +405,7 @@ mod tests {
 
     use crate::{
         storage_sync::{
-            index::RelativePath,
+            index::{LayerFileMetadata, RelativePath},
             test_utils::{create_local_timeline, dummy_metadata},
         },
         tenant::harness::{TenantHarness, TIMELINE_ID},
@@ -421,6 +596,60 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn rate_limited_layer_upload() -> anyhow::Result<()> {
+        const RATE: u64 = 64 * 1024;
+        let harness = TenantHarness::create("rate_limited_layer_upload")?;
+        let sync_queue = SyncQueue::new(NonZeroUsize::new(100).unwrap());
+        let sync_id = TenantTimelineId::new(harness.tenant_id, TIMELINE_ID);
+        let storage = GenericRemoteStorage::new(LocalFs::new(
+            tempdir()?.path().to_owned(),
+            harness.conf.workdir.clone(),
+        )?);
+
+        let local_timeline_path = harness.timeline_path(&TIMELINE_ID);
+        fs::create_dir_all(&local_timeline_path).await?;
+        let mut layers_to_upload = HashMap::new();
+        for layer_file in ["a", "b", "c"] {
+            let layer_path = local_timeline_path.join(layer_file);
+            fs::write(&layer_path, vec![0u8; RATE as usize / 2]).await?;
+            layers_to_upload.insert(layer_path, LayerFileMetadata::new(RATE / 2));
+        }
+        let timeline_upload = LayersUpload {
+            layers_to_upload,
+            uploaded_layers: HashMap::new(),
+            metadata: None,
+        };
+
+        let started = Instant::now();
+        set_upload_rate_limit(harness.tenant_id, RATE);
+        let upload_result = upload_timeline_layers(
+            &storage,
+            &sync_queue,
+            None,
+            sync_id,
+            SyncData::new(0, timeline_upload),
+        )
+        .await;
+        let elapsed = started.elapsed();
+        set_upload_rate_limit(harness.tenant_id, 0);
+
+        match upload_result {
+            UploadedTimeline::Successful(upload_data) => {
+                assert_eq!(upload_data.data.uploaded_layers.len(), 3)
+            }
+            wrong_result => panic!("Expected a successful upload, but got: {wrong_result:?}"),
+        }
+        // A second worth of the layers is uploaded right away, the remaining half a second
+        // worth of them is uploaded at the limited rate
+        assert!(
+            elapsed >= Duration::from_millis(500),
+            "Upload took {elapsed:?}, faster than the rate limit allows"
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_upload_index_part() -> anyhow::Result<()> {
         let harness = TenantHarness::create("test_upload_index_part")?;
@@ -46,6 +46,7 @@ use tokio::runtime::Runtime;
 use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tokio::task_local;
+use tokio_util::sync::CancellationToken;
 
 use tracing::{debug, error, info, warn};
 
@@ -205,6 +206,10 @@ pub enum TaskKind {
 
     // task that handles attaching a tenant
     Attach,
+
+    // Retries the read that found the WAL redo of a timeline unavailable,
+    // until the WAL redo works again and the page reads are accepted.
+    WalRedoProbe,
 }
 
 #[derive(Default)]
@@ -450,6 +455,33 @@ pub async fn shutdown_watcher() {
     }
 }
 
+/// Returns a [`CancellationToken`] that gets cancelled once the current task is requested to
+/// shut down, so that the request can be observed by the blocking code the task calls into.
+///
+/// The token stops tracking the shutdown requests after it gets cancelled.
+pub fn shutdown_token() -> CancellationToken {
+    let mut shutdown_rx = SHUTDOWN_RX
+        .try_with(|rx| rx.clone())
+        .expect("shutdown_token() called in an unexpected task or thread");
+
+    let token = CancellationToken::new();
+    let task_token = token.clone();
+    tokio::spawn(async move {
+        while !*shutdown_rx.borrow() {
+            tokio::select! {
+                _ = task_token.cancelled() => return,
+                changed = shutdown_rx.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        task_token.cancel();
+    });
+    token
+}
+
 /// Has the current task been requested to shut down?
 pub fn is_shutdown_requested() -> bool {
     if let Ok(shutdown_rx) = SHUTDOWN_RX.try_with(|rx| rx.clone()) {
@@ -13,37 +13,45 @@
 
 use anyhow::{bail, Context};
 use pageserver_api::models::TimelineState;
-use tokio::sync::watch;
+use tokio::io::AsyncRead;
+use tokio::sync::{broadcast, watch};
+use tokio_util::io::SyncIoBridge;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 use utils::crashsafe::path_with_suffix_extension;
 
-use std::cmp::min;
-use std::collections::hash_map::Entry;
+use std::collections::hash_map::{DefaultHasher, Entry};
 use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::Write;
 use std::ops::Bound::Included;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
-use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::MutexGuard;
 use std::sync::{Mutex, RwLock};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use self::metadata::TimelineMetadata;
-use crate::config::PageServerConf;
+use self::timeline_settings::{
+    load_timeline_settings, save_timeline_settings, TimelineSettings, TIMELINE_CREATOR_MAX_LEN,
+    TIMELINE_LABEL_MAX_LEN,
+};
+use crate::config::{PageServerConf, TIMELINE_UNINIT_MARK_SUFFIX};
 use crate::import_datadir;
-use crate::metrics::{remove_tenant_metrics, STORAGE_TIME};
-use crate::repository::GcResult;
-use crate::storage_sync::index::RemoteIndex;
-use crate::task_mgr;
-use crate::tenant_config::TenantConfOpt;
+use crate::metrics::{remove_tenant_metrics, STORAGE_TIME, TENANT_LOCAL_SIZE};
+use crate::repository::{CompactionResult, GcResult, Key, TimelineDeletionResult};
+use crate::storage_sync::{self, index::RemoteIndex};
+use crate::task_mgr::{self, TaskKind};
+use crate::tenant_config::{GcCutoffPolicy, TenantConfOpt};
 use crate::virtual_file::VirtualFile;
 use crate::walredo::WalRedoManager;
 use crate::{CheckpointConfig, TEMP_FILE_SUFFIX};
@@ -52,7 +60,7 @@ pub use pageserver_api::models::TenantState;
 use toml_edit;
 use utils::{
     crashsafe,
-    id::{TenantId, TimelineId},
+    id::{TenantId, TenantTimelineId, TimelineId},
     lsn::{Lsn, RecordLsn},
 };
 
@@ -71,10 +79,11 @@ mod par_fsync;
 pub mod storage_layer;
 
 mod timeline;
+pub mod timeline_settings;
 
 use storage_layer::Layer;
 
-pub use timeline::Timeline;
+pub use timeline::{KeyVersion, ReadTooExpensive, Timeline, ValueKind, WalRedoUnavailable};
 
 // re-export this function so that page_cache.rs can use it.
 pub use crate::tenant::ephemeral_file::writeback as writeback_ephemeral_file;
@@ -88,6 +97,288 @@ pub use crate::tenant::timeline::WalReceiverInfo;
 /// Parts of the `.neon/tenants/<tenant_id>/timelines/<timeline_id>` directory prefix.
 pub const TIMELINES_SEGMENT_NAME: &str = "timelines";
 
+/// How long [`Tenant::delete_timeline_remote`] waits for the remote layers to get unregistered.
+const REMOTE_DELETION_TIMEOUT: Duration = Duration::from_secs(60);
+const REMOTE_DELETION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often [`Tenant::shutdown`] checks whether the in-flight compaction and GC have finished.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Name prefix of the initdb cache files, followed by the postgres version and the cache key.
+const INITDB_CACHE_FILE_PREFIX: &str = "initdb-v";
+
+/// Written at the top of a newly created tenant config file.
+const TENANT_CONFIG_HEADER: &str = r#"# This file contains a specific per-tenant's config.
+#  It is read in case of pageserver restart.
+
+[tenant_config]
+"#;
+
+/// How many state transitions a subscriber may fall behind before it starts missing them.
+const STATE_TRANSITIONS_CAPACITY: usize = 64;
+
+/// A single state change of a tenant or a timeline.
+///
+/// Unlike the state updates watch channel, every transition is delivered to the
+/// subscribers, including the ones quickly overridden by the next transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateTransition<S> {
+    pub from: S,
+    pub to: S,
+    pub at: Instant,
+}
+
+/// Error of the [`Tenant`] operations on its timelines, telling apart the cases callers
+/// (e.g. the HTTP API) handle differently from the internal failures.
+#[derive(Debug, thiserror::Error)]
+pub enum TenantError {
+    #[error("{0} was not found")]
+    NotFound(String),
+    #[error("{0} is not active")]
+    NotActive(String),
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+    /// The timeline can't be deleted before its child timelines, listed in the error.
+    #[error("Cannot delete timeline which has child timelines: {0:?}")]
+    HasChildren(Vec<TimelineId>),
+    /// Conflicts with a concurrent operation, e.g. compaction or GC, and can be retried.
+    #[error("{0} is busy, try again later")]
+    Busy(String),
+    /// No `initdb` is configured for the requested postgres version, see
+    /// [`PageServerConf::available_pg_versions`].
+    #[error("unsupported pg_version {pg_version}, available: {available:?}")]
+    UnsupportedPgVersion {
+        pg_version: u32,
+        available: Vec<u32>,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// A branch to create with [`Tenant::create_timelines_from_manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchSpec {
+    pub id: TimelineId,
+    /// An existing timeline of the tenant, or another branch of the manifest.
+    pub ancestor: TimelineId,
+    /// Branch at the last record LSN of the ancestor if not set.
+    pub start_lsn: Option<Lsn>,
+}
+
+/// [`Tenant::create_timelines_from_manifest`] failed to create a branch of the manifest.
+/// The branches created before it are kept.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to create timeline {failed} from the manifest, created {created:?}, not created {remaining:?}: {error:#}")]
+pub struct ManifestBranchError {
+    pub failed: TimelineId,
+    /// Branches created before the failure, in the creation order.
+    pub created: Vec<TimelineId>,
+    /// Branches not created, the failed one first, in the creation order.
+    pub remaining: Vec<TimelineId>,
+    pub error: TenantError,
+}
+
+/// Snapshot of a timeline's state, see [`Tenant::timeline_infos`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelineInfo {
+    pub timeline_id: TimelineId,
+    pub ancestor_timeline_id: Option<TimelineId>,
+    pub ancestor_lsn: Lsn,
+    pub last_record_lsn: Lsn,
+    pub disk_consistent_lsn: Lsn,
+    pub state: TimelineState,
+    /// Total size of the timeline's layer files, in bytes.
+    pub physical_size: u64,
+}
+
+/// Server encodings supported by Postgres, normalized the same way Postgres does it when
+/// parsing the encoding name: lowercase, with all non-alphanumeric characters removed.
+const INITDB_ENCODINGS: &[&str] = &[
+    "sqlascii",
+    "utf8",
+    "eucjp",
+    "euccn",
+    "euckr",
+    "euctw",
+    "eucjis2004",
+    "muleinternal",
+    "latin1",
+    "latin2",
+    "latin3",
+    "latin4",
+    "latin5",
+    "latin6",
+    "latin7",
+    "latin8",
+    "latin9",
+    "latin10",
+    "iso88595",
+    "iso88596",
+    "iso88597",
+    "iso88598",
+    "win1250",
+    "win1251",
+    "win1252",
+    "win1253",
+    "win1254",
+    "win1255",
+    "win1256",
+    "win1257",
+    "win1258",
+    "win866",
+    "win874",
+    "koi8r",
+    "koi8u",
+];
+
+/// Parameters of the `initdb` run that bootstraps a new timeline.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InitdbParams {
+    /// Database encoding, `utf8` if not set.
+    pub encoding: Option<String>,
+    /// Database locale, initdb defaults to `C` if not set.
+    pub locale: Option<String>,
+    /// Superuser name, [`PageServerConf::superuser`] if not set.
+    pub superuser_override: Option<String>,
+}
+
+impl InitdbParams {
+    /// Checks the parameters before they are passed to initdb.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(encoding) = &self.encoding {
+            let normalized = encoding
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect::<String>()
+                .to_ascii_lowercase();
+            anyhow::ensure!(
+                INITDB_ENCODINGS.contains(&normalized.as_str()),
+                "unsupported encoding {encoding:?}"
+            );
+        }
+        if let Some(locale) = &self.locale {
+            anyhow::ensure!(
+                !locale.is_empty()
+                    && !locale.starts_with('-')
+                    && locale
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "_.@-".contains(c)),
+                "invalid locale {locale:?}"
+            );
+        }
+        if let Some(superuser) = &self.superuser_override {
+            anyhow::ensure!(
+                !superuser.is_empty() && !superuser.starts_with('-'),
+                "invalid superuser name {superuser:?}"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Failed `initdb` run, with everything it printed, to help triaging bootstrap failures.
+#[derive(Debug, thiserror::Error)]
+#[error("initdb failed with exit code {exit_code:?}, stderr: '{stderr}', stdout: '{stdout}'")]
+pub struct InitdbError {
+    /// `None` if initdb was terminated by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Custom initialization of a bootstrapped timeline, e.g. installing extensions or seeding roles,
+/// see [`Tenant::create_timeline`].
+pub type TimelineInitHook = Box<dyn FnOnce(&Timeline) -> anyhow::Result<()> + Send>;
+
+/// Outcome of [`Tenant::try_create_timeline`].
+pub enum TimelineCreateResult {
+    /// The timeline did not exist and got created.
+    Created(Arc<Timeline>),
+    /// A timeline with the requested id already exists, nothing was created.
+    AlreadyExists(Arc<Timeline>),
+}
+
+/// Where to start a new timeline on its ancestor, see [`Tenant::create_timeline`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AncestorStart {
+    /// Branch at the given LSN.
+    Lsn(Lsn),
+    /// Branch at the LSN of the ancestor's savepoint with this name, see [`Timeline::create_savepoint`].
+    Savepoint(String),
+}
+
+/// Progress of the timelines initialization during tenant attach or load, see [`Tenant::attach_progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AttachProgress {
+    /// Number of timelines to initialize.
+    pub total: usize,
+    /// Timelines initialized successfully so far.
+    pub completed: usize,
+    /// Timelines that failed to initialize and were marked as broken.
+    pub failed: usize,
+    /// Set once the attach is over, successfully or not.
+    pub done: bool,
+}
+
+/// Leftovers of interrupted timeline creations removed by [`Tenant::reconcile_uninit_marks`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UninitMarkReconciliation {
+    /// Partially created timeline directories removed together with their uninit marks.
+    pub removed_timelines: usize,
+    /// Uninit marks removed after their timeline directory was already gone.
+    pub removed_marks: usize,
+}
+
+/// Parameters of a single GC iteration, see [`Tenant::gc_iteration`].
+///
+/// Periodic GC takes the horizon and PITR interval from the tenant config,
+/// but a manual GC can override them for one iteration without changing the config.
+#[derive(Debug, Clone, Default)]
+pub struct GcRequest {
+    /// The timeline to GC, or `None` for all timelines of the tenant.
+    pub target_timeline_id: Option<TimelineId>,
+    /// Delta from the last record LSN to preserve all object versions.
+    pub horizon: u64,
+    /// Time interval to preserve all object versions for point-in-time recovery.
+    pub pitr: Duration,
+    /// How the cutoffs derived from `horizon` and `pitr` combine into the GC cutoff.
+    pub cutoff_policy: GcCutoffPolicy,
+    /// Forces a checkpoint of the timelines before GC, so that in-memory layers
+    /// get garbage collected too. Used in tests for deterministic results.
+    pub force_checkpoint: bool,
+    /// Branchpoints of the tenant's timelines known to the remote storage, as
+    /// `(ancestor timeline, ancestor LSN)` pairs, see [`Tenant::remote_branchpoints`].
+    pub remote_branchpoints: Vec<(TimelineId, Lsn)>,
+}
+
+impl GcRequest {
+    /// GC request for the periodic GC, with the tenant's configured horizon and PITR interval.
+    pub fn from_tenant_conf(tenant: &Tenant, target_timeline_id: Option<TimelineId>) -> Self {
+        Self {
+            target_timeline_id,
+            horizon: tenant.get_gc_horizon(),
+            pitr: tenant.get_pitr_interval(),
+            cutoff_policy: tenant.get_gc_cutoff_policy(),
+            force_checkpoint: false,
+            remote_branchpoints: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // `horizon` is unsigned, and a horizon beyond the last record LSN of a timeline
+        // only means that there is nothing to collect on it.
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context("System time is before the Unix epoch")?;
+        anyhow::ensure!(
+            self.pitr <= since_epoch,
+            "pitr interval {:?} reaches before the Unix epoch",
+            self.pitr
+        );
+        Ok(())
+    }
+}
+
 ///
 /// Tenant consists of multiple timelines. Keep them in a hash table.
 ///
@@ -96,6 +387,10 @@ pub struct Tenant {
     pub conf: &'static PageServerConf,
 
     state: watch::Sender<TenantState>,
+    state_transitions: broadcast::Sender<StateTransition<TenantState>>,
+    // Set when the tenant got activated without GC and compaction, because it had no timelines
+    // to run them for. The first timeline created afterwards enables them.
+    background_jobs_deferred: AtomicBool,
 
     // Overridden tenant-specific config parameters.
     // We keep TenantConfOpt sturct here to preserve the information
@@ -112,6 +407,12 @@ pub struct Tenant {
     // with timelines, which in turn may cause dropping replication connection, expiration of wait_for_lsn
     // timeout...
     gc_cs: Mutex<()>,
+    // The last timeline compacted by the previous compaction iteration, if it was limited
+    // by `compaction_batch_size`. The next iteration continues from the timeline after it.
+    last_compacted_timeline: Mutex<Option<TimelineId>>,
+    // Updated by `init_attach_timelines` after every timeline it initializes, so the
+    // progress can be observed while the `timelines` mutex is held by the attach.
+    attach_progress: Arc<Mutex<AttachProgress>>,
     walredo_mgr: Arc<dyn WalRedoManager + Send + Sync>,
 
     // provides access to timeline data sitting in the remote storage
@@ -153,6 +454,8 @@ impl UninitializedTimeline<'_> {
         self.initialize_with_lock(&mut timelines, true)
     }
 
+    /// The single place that inserts initialized timelines into the tenant map.
+    /// Fails without touching the map if the timeline is there already.
     fn initialize_with_lock(
         mut self,
         timelines: &mut HashMap<TimelineId, Arc<Timeline>>,
@@ -191,6 +494,7 @@ impl UninitializedTimeline<'_> {
                     )
                 })?;
                 new_timeline.set_state(TimelineState::Active);
+                // Inserted only once nothing can fail, the map never has a half-initialized timeline
                 v.insert(Arc::clone(&new_timeline));
                 new_timeline.launch_wal_receiver();
             }
@@ -204,16 +508,27 @@ impl UninitializedTimeline<'_> {
         &self,
         reader: impl std::io::Read,
         base_lsn: Lsn,
+    ) -> anyhow::Result<()> {
+        self.import_basebackup_from_tar_cancellable(reader, base_lsn, CancellationToken::new())
+    }
+
+    /// Same as [`UninitializedTimeline::import_basebackup_from_tar`], but stops importing and
+    /// fails once `cancel` is cancelled, which is checked before every archive entry.
+    /// As with any other import failure, dropping the timeline removes the imported data.
+    pub fn import_basebackup_from_tar_cancellable(
+        &self,
+        reader: impl std::io::Read,
+        base_lsn: Lsn,
+        cancel: CancellationToken,
     ) -> anyhow::Result<()> {
         let raw_timeline = self.raw_timeline()?;
-        import_datadir::import_basebackup_from_tar(raw_timeline, reader, base_lsn).with_context(
-            || {
+        import_datadir::import_basebackup_from_tar(raw_timeline, reader, base_lsn, &cancel)
+            .with_context(|| {
                 format!(
                     "Failed to import basebackup for timeline {}/{}",
                     self.owning_tenant.tenant_id, self.timeline_id
                 )
-            },
-        )?;
+            })?;
 
         fail::fail_point!("before-checkpoint-new-timeline", |_| {
             bail!("failpoint before-checkpoint-new-timeline");
@@ -230,6 +545,20 @@ impl UninitializedTimeline<'_> {
         Ok(())
     }
 
+    /// Same as [`UninitializedTimeline::import_basebackup_from_tar`], but streams the archive
+    /// from an async reader, e.g. a network connection, instead of buffering it.
+    ///
+    /// The import itself is synchronous (the `tar` crate is not async), so it runs in
+    /// [`tokio::task::block_in_place`], which requires the multi-threaded runtime.
+    pub async fn import_basebackup_from_tar_async(
+        &self,
+        reader: impl AsyncRead + Unpin,
+        base_lsn: Lsn,
+    ) -> anyhow::Result<()> {
+        let reader = SyncIoBridge::new(reader);
+        tokio::task::block_in_place(|| self.import_basebackup_from_tar(reader, base_lsn))
+    }
+
     fn raw_timeline(&self) -> anyhow::Result<&Timeline> {
         Ok(&self
             .raw_timeline
@@ -344,19 +673,19 @@ impl Tenant {
         &self,
         timeline_id: TimelineId,
         active_only: bool,
-    ) -> anyhow::Result<Arc<Timeline>> {
+    ) -> Result<Arc<Timeline>, TenantError> {
         let timelines_accessor = self.timelines.lock().unwrap();
-        let timeline = timelines_accessor.get(&timeline_id).with_context(|| {
-            format!("Timeline {}/{} was not found", self.tenant_id, timeline_id)
+        let timeline = timelines_accessor.get(&timeline_id).ok_or_else(|| {
+            TenantError::NotFound(format!("Timeline {}/{}", self.tenant_id, timeline_id))
         })?;
 
         if active_only && !timeline.is_active() {
-            anyhow::bail!(
-                "Timeline {}/{} is not active, state: {:?}",
+            Err(TenantError::NotActive(format!(
+                "Timeline {}/{} in state {:?}",
                 self.tenant_id,
                 timeline_id,
                 timeline.current_state()
-            )
+            )))
         } else {
             Ok(Arc::clone(timeline))
         }
@@ -365,11 +694,104 @@ impl Tenant {
     /// Lists timelines the tenant contains.
     /// Up to tenant's implementation to omit certain timelines that ar not considered ready for use.
     pub fn list_timelines(&self) -> Vec<Arc<Timeline>> {
-        self.timelines
+        self.list_timelines_filtered(&[
+            TimelineState::Active,
+            TimelineState::Suspended,
+            TimelineState::Paused,
+            TimelineState::Broken,
+        ])
+    }
+
+    /// Lists timelines whose current state is one of `states`, sorted by timeline id.
+    pub fn list_timelines_filtered(&self, states: &[TimelineState]) -> Vec<Arc<Timeline>> {
+        let mut timelines = self
+            .timelines
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|timeline| states.contains(&timeline.current_state()))
+            .map(Arc::clone)
+            .collect::<Vec<_>>();
+        timelines.sort_by_key(|timeline| timeline.timeline_id);
+        timelines
+    }
+
+    /// Collects the info of all timelines at once, under the `timelines` lock, sorted by timeline id.
+    pub fn timeline_infos(&self) -> Vec<TimelineInfo> {
+        let timelines = self.timelines.lock().unwrap();
+        let mut infos = timelines
+            .values()
+            .map(|timeline| TimelineInfo {
+                timeline_id: timeline.timeline_id,
+                ancestor_timeline_id: timeline.get_ancestor_timeline_id(),
+                ancestor_lsn: timeline.get_ancestor_lsn(),
+                last_record_lsn: timeline.get_last_record_lsn(),
+                disk_consistent_lsn: timeline.get_disk_consistent_lsn(),
+                state: timeline.current_state(),
+                physical_size: timeline.get_physical_size(),
+            })
+            .collect::<Vec<_>>();
+        infos.sort_by_key(|info| info.timeline_id);
+        infos
+    }
+
+    /// Finds the stored versions of the key in all the tenant's timelines, sorted by timeline id
+    /// and LSN. A timeline also gets the versions it inherits from its ancestors, up to the
+    /// branch point, see [`Timeline::key_history`].
+    ///
+    /// Meant for debugging, it reads all the layers containing the key in every timeline.
+    pub fn locate_key(&self, key: Key) -> anyhow::Result<Vec<(TimelineId, Lsn)>> {
+        let timelines = self
+            .timelines
             .lock()
             .unwrap()
             .values()
             .map(Arc::clone)
+            .collect::<Vec<_>>();
+
+        let mut locations = BTreeSet::new();
+        for timeline in timelines {
+            let history = timeline.key_history(key).with_context(|| {
+                format!(
+                    "Failed to look up key {key} in timeline {}",
+                    timeline.timeline_id
+                )
+            })?;
+            locations.extend(
+                history
+                    .into_iter()
+                    .map(|version| (timeline.timeline_id, version.lsn)),
+            );
+        }
+        Ok(locations.into_iter().collect())
+    }
+
+    /// Finds the timeline whose `[ancestor_lsn, last_record_lsn]` range contains the given LSN.
+    /// If there are several, the deepest branch is returned, see [`Tenant::find_timeline_for_lsn_all`].
+    pub fn find_timeline_for_lsn(&self, lsn: Lsn) -> Option<(TimelineId, Arc<Timeline>)> {
+        self.find_timeline_for_lsn_all(lsn).into_iter().next()
+    }
+
+    /// Finds all timelines whose `[ancestor_lsn, last_record_lsn]` range contains the given LSN.
+    /// The deepest branches come first, timelines of the same depth are sorted by id.
+    pub fn find_timeline_for_lsn_all(&self, lsn: Lsn) -> Vec<(TimelineId, Arc<Timeline>)> {
+        let timelines = self.list_timelines();
+        let mut found = Vec::new();
+        for timeline in timelines {
+            if timeline.get_ancestor_lsn() <= lsn && lsn <= timeline.get_last_record_lsn() {
+                // The ancestor might be missing from the tenant, consider such timeline a root one.
+                let depth = self.ancestor_chain_length(&timeline).unwrap_or(0);
+                found.push((depth, timeline));
+            }
+        }
+        found.sort_by(|(depth_a, timeline_a), (depth_b, timeline_b)| {
+            depth_b
+                .cmp(depth_a)
+                .then(timeline_a.timeline_id.cmp(&timeline_b.timeline_id))
+        });
+        found
+            .into_iter()
+            .map(|(_, timeline)| (timeline.timeline_id, timeline))
             .collect()
     }
 
@@ -403,6 +825,7 @@ impl Tenant {
         self.prepare_timeline(
             new_timeline_id,
             new_metadata,
+            TimelineSettings::default(),
             timeline_uninit_mark,
             true,
             None,
@@ -414,33 +837,217 @@ impl Tenant {
     /// Returns the new timeline ID and reference to its Timeline object.
     ///
     /// If the caller specified the timeline ID to use (`new_timeline_id`), and timeline with
-    /// the same timeline ID already exists, returns None. If `new_timeline_id` is not given,
-    /// a new unique ID is generated.
+    /// the same timeline ID already exists, returns [`TenantError::AlreadyExists`].
+    /// If `new_timeline_id` is not given, a new unique ID is generated.
+    /// Without `ancestor_start`, a new branch starts at the last record LSN of its ancestor.
+    ///
+    /// `initdb_params` and `init_hook` are only used to bootstrap a timeline without an ancestor.
+    /// The hook runs after the datadir import, before the `before-checkpoint-new-timeline`
+    /// failpoint and the checkpoint, so whatever it writes is flushed together with the imported data.
+    /// If the hook fails, the timeline is not created and its files are removed.
+    ///
+    /// `created_by` is a free-form description of the creator, stored in the timeline settings.
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_timeline(
-        &self,
+        self: &Arc<Self>,
         new_timeline_id: Option<TimelineId>,
         ancestor_timeline_id: Option<TimelineId>,
-        mut ancestor_start_lsn: Option<Lsn>,
+        ancestor_start: Option<AncestorStart>,
         pg_version: u32,
-    ) -> anyhow::Result<Option<Arc<Timeline>>> {
-        anyhow::ensure!(
-            self.is_active(),
-            "Cannot create timelines on inactive tenant"
-        );
+        initdb_params: InitdbParams,
+        init_hook: Option<TimelineInitHook>,
+        created_by: Option<String>,
+    ) -> Result<Arc<Timeline>, TenantError> {
+        match self
+            .try_create_timeline(
+                new_timeline_id,
+                ancestor_timeline_id,
+                ancestor_start,
+                pg_version,
+                initdb_params,
+                init_hook,
+                created_by,
+            )
+            .await?
+        {
+            TimelineCreateResult::Created(timeline) => Ok(timeline),
+            TimelineCreateResult::AlreadyExists(timeline) => Err(TenantError::AlreadyExists(
+                format!("Timeline {}/{}", self.tenant_id, timeline.timeline_id),
+            )),
+        }
+    }
+
+    /// Creates the branches of the manifest, e.g. to restore a tree of timelines.
+    ///
+    /// The branches are created after their ancestors, the independent ones in the manifest order.
+    /// The whole manifest is validated before creating anything: the new timelines must not exist,
+    /// and the ancestors must either exist or be in the manifest, without cycles.
+    ///
+    /// Stops at the first branch that fails to be created, with [`ManifestBranchError`] naming it
+    /// and the branches that remain. The branches created before the failure are kept.
+    pub async fn create_timelines_from_manifest(
+        self: &Arc<Self>,
+        manifest: Vec<BranchSpec>,
+    ) -> anyhow::Result<Vec<Arc<Timeline>>> {
+        anyhow::ensure!(self.is_active(), "Tenant {} is not active", self.tenant_id);
+        let specs = {
+            let timelines = self.timelines.lock().unwrap();
+            let mut spec_ids = HashSet::with_capacity(manifest.len());
+            for spec in &manifest {
+                anyhow::ensure!(
+                    spec_ids.insert(spec.id),
+                    "Timeline {} is listed in the manifest more than once",
+                    spec.id
+                );
+                anyhow::ensure!(
+                    !timelines.contains_key(&spec.id),
+                    "Timeline {} already exists",
+                    spec.id
+                );
+            }
+            for spec in &manifest {
+                anyhow::ensure!(
+                    spec_ids.contains(&spec.ancestor) || timelines.contains_key(&spec.ancestor),
+                    "Ancestor timeline {} of timeline {} was not found",
+                    spec.ancestor,
+                    spec.id
+                );
+            }
+            tree_sort_branch_specs(manifest)?
+        };
+
+        let mut created = Vec::with_capacity(specs.len());
+        for (i, spec) in specs.iter().enumerate() {
+            let result = self
+                .create_timeline(
+                    Some(spec.id),
+                    Some(spec.ancestor),
+                    spec.start_lsn.map(AncestorStart::Lsn),
+                    // Branches inherit the postgres version of their ancestors
+                    crate::DEFAULT_PG_VERSION,
+                    InitdbParams::default(),
+                    None,
+                    None,
+                )
+                .await;
+            match result {
+                Ok(timeline) => created.push(timeline),
+                Err(error) => {
+                    return Err(ManifestBranchError {
+                        failed: spec.id,
+                        created: created
+                            .iter()
+                            .map(|timeline| timeline.timeline_id)
+                            .collect(),
+                        remaining: specs[i..].iter().map(|spec| spec.id).collect(),
+                        error,
+                    }
+                    .into())
+                }
+            }
+        }
+        info!("created {} timelines from the manifest", created.len());
+        Ok(created)
+    }
+
+    /// Same as [`Tenant::create_timeline`], but tells whether the timeline got created
+    /// or existed already, returning the existing timeline in the latter case.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn try_create_timeline(
+        self: &Arc<Self>,
+        new_timeline_id: Option<TimelineId>,
+        ancestor_timeline_id: Option<TimelineId>,
+        ancestor_start: Option<AncestorStart>,
+        pg_version: u32,
+        initdb_params: InitdbParams,
+        init_hook: Option<TimelineInitHook>,
+        created_by: Option<String>,
+    ) -> Result<TimelineCreateResult, TenantError> {
+        if !self.is_active() {
+            return Err(TenantError::NotActive(format!("Tenant {}", self.tenant_id)));
+        }
+        if let Some(created_by) = &created_by {
+            if created_by.len() > TIMELINE_CREATOR_MAX_LEN {
+                return Err(TenantError::Other(anyhow::anyhow!(
+                    "Timeline creator is {} bytes long, the limit is {} bytes",
+                    created_by.len(),
+                    TIMELINE_CREATOR_MAX_LEN
+                )));
+            }
+        }
 
         let new_timeline_id = new_timeline_id.unwrap_or_else(TimelineId::generate);
 
-        if self.get_timeline(new_timeline_id, false).is_ok() {
-            debug!("timeline {new_timeline_id} already exists");
-            return Ok(None);
+        // Checking for the existing timeline and placing the uninit mark under the same lock
+        // lets only one of the concurrent creations of the same timeline proceed.
+        let timeline_uninit_mark = {
+            let timelines = self.timelines.lock().unwrap();
+            if let Some(existing_timeline) = timelines.get(&new_timeline_id) {
+                debug!("timeline {new_timeline_id} already exists");
+                return Ok(TimelineCreateResult::AlreadyExists(Arc::clone(
+                    existing_timeline,
+                )));
+            }
+            // Branches inherit the version of their ancestor, only the new timelines need initdb
+            if ancestor_timeline_id.is_none() {
+                let available = self.conf.available_pg_versions();
+                if !available.contains(&pg_version) {
+                    return Err(TenantError::UnsupportedPgVersion {
+                        pg_version,
+                        available,
+                    });
+                }
+            }
+            self.create_timeline_uninit_mark(new_timeline_id, &timelines)?
+        };
+
+        let loaded_timeline = self
+            .create_new_timeline(
+                new_timeline_id,
+                ancestor_timeline_id,
+                ancestor_start,
+                pg_version,
+                initdb_params,
+                init_hook,
+                timeline_uninit_mark,
+                created_by,
+            )
+            .await?;
+
+        // Have added new timeline into the tenant, now its background tasks are needed.
+        // If they were enabled already, keep GC and compaction as they are: either might be paused.
+        if self.background_jobs_deferred.load(Ordering::Relaxed) {
+            self.activate(true);
         }
 
+        Ok(TimelineCreateResult::Created(loaded_timeline))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn create_new_timeline(
+        self: &Arc<Self>,
+        new_timeline_id: TimelineId,
+        ancestor_timeline_id: Option<TimelineId>,
+        ancestor_start: Option<AncestorStart>,
+        pg_version: u32,
+        initdb_params: InitdbParams,
+        init_hook: Option<TimelineInitHook>,
+        timeline_uninit_mark: TimelineUninitMark,
+        created_by: Option<String>,
+    ) -> anyhow::Result<Arc<Timeline>> {
         let loaded_timeline = match ancestor_timeline_id {
             Some(ancestor_timeline_id) => {
                 let ancestor_timeline = self
                     .get_timeline(ancestor_timeline_id, false)
                     .context("Cannot branch off the timeline that's not present in pageserver")?;
 
+                let mut ancestor_start_lsn = match ancestor_start {
+                    Some(AncestorStart::Lsn(lsn)) => Some(lsn),
+                    Some(AncestorStart::Savepoint(name)) => {
+                        Some(ancestor_timeline.get_savepoint_lsn(&name)?)
+                    }
+                    None => None,
+                };
                 if let Some(lsn) = ancestor_start_lsn.as_mut() {
                     // Wait for the WAL to arrive and be processed on the parent branch up
                     // to the requested branch point. The repository code itself doesn't
@@ -463,54 +1070,86 @@ impl Tenant {
                     }
                 }
 
-                self.branch_timeline(ancestor_timeline_id, new_timeline_id, ancestor_start_lsn)?
+                // Branching creates the timeline files and waits for the GC lock,
+                // run it on a blocking thread to not stall the other tasks of the runtime.
+                let tenant = Arc::clone(self);
+                let span = tracing::Span::current();
+                tokio::task::spawn_blocking(move || {
+                    let _enter = span.enter();
+                    tenant.branch_timeline_with_uninit_mark(
+                        ancestor_timeline_id,
+                        new_timeline_id,
+                        ancestor_start_lsn,
+                        timeline_uninit_mark,
+                        created_by,
+                    )
+                })
+                .await
+                .context("Failed to join the branch timeline task")??
+            }
+            None => {
+                self.bootstrap_timeline(
+                    new_timeline_id,
+                    pg_version,
+                    &initdb_params,
+                    init_hook,
+                    timeline_uninit_mark,
+                    created_by,
+                )
+                .await?
             }
-            None => self.bootstrap_timeline(new_timeline_id, pg_version)?,
         };
-
-        // Have added new timeline into the tenant, now its background tasks are needed.
-        self.activate(true);
-
-        Ok(Some(loaded_timeline))
+        Ok(loaded_timeline)
     }
 
     /// perform one garbage collection iteration, removing old data files from disk.
     /// this function is periodically called by gc task.
     /// also it can be explicitly requested through page server api 'do_gc' command.
     ///
-    /// 'target_timeline_id' specifies the timeline to GC, or None for all.
-    /// `horizon` specifies delta from last lsn to preserve all object versions (pitr interval).
-    /// `checkpoint_before_gc` parameter is used to force compaction of storage before GC
-    /// to make tests more deterministic.
-    /// TODO Do we still need it or we can call checkpoint explicitly in tests where needed?
+    /// See [`GcRequest`] for the parameters of the iteration.
+    /// TODO Do we still need `force_checkpoint` or we can call checkpoint explicitly in tests where needed?
     pub fn gc_iteration(
         &self,
-        target_timeline_id: Option<TimelineId>,
-        horizon: u64,
-        pitr: Duration,
-        checkpoint_before_gc: bool,
+        request: &GcRequest,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<GcResult> {
         anyhow::ensure!(
             self.is_active(),
             "Cannot run GC iteration on inactive tenant"
         );
+        request.validate().context("Invalid GC request")?;
 
-        let timeline_str = target_timeline_id
+        let timeline_str = request
+            .target_timeline_id
             .map(|x| x.to_string())
             .unwrap_or_else(|| "-".to_string());
 
         STORAGE_TIME
             .with_label_values(&["gc", &self.tenant_id.to_string(), &timeline_str])
-            .observe_closure_duration(|| {
-                self.gc_iteration_internal(target_timeline_id, horizon, pitr, checkpoint_before_gc)
-            })
+            .observe_closure_duration(|| self.gc_iteration_internal(request, false, cancel))
+    }
+
+    /// Estimates the outcome of a [`Tenant::gc_iteration`] with the same request.
+    ///
+    /// All timelines get scanned the same way as for the real GC, but no layers are removed
+    /// and the timelines' GC info and cutoffs are left untouched. `force_checkpoint` is ignored.
+    pub fn gc_iteration_dry_run(&self, request: &GcRequest) -> anyhow::Result<GcResult> {
+        anyhow::ensure!(
+            self.is_active(),
+            "Cannot run GC iteration on inactive tenant"
+        );
+        request.validate().context("Invalid GC request")?;
+
+        self.gc_iteration_internal(request, true, &CancellationToken::new())
     }
 
     /// Perform one compaction iteration.
     /// This function is periodically called by compactor task.
     /// Also it can be explicitly requested per timeline through page server
     /// api's 'compact' command.
-    pub fn compaction_iteration(&self) -> anyhow::Result<()> {
+    ///
+    /// Stops early if the tenant stops being active, returns the number of timelines compacted.
+    pub fn compaction_iteration(&self) -> anyhow::Result<usize> {
         anyhow::ensure!(
             self.is_active(),
             "Cannot run compaction iteration on inactive tenant"
@@ -521,26 +1160,164 @@ impl Tenant {
         // compactions.  We don't want to block everything else while the
         // compaction runs.
         let timelines = self.timelines.lock().unwrap();
-        let timelines_to_compact = timelines
+        let active_timelines = timelines
             .iter()
             .filter(|(_, timeline)| timeline.is_active())
             .map(|(timeline_id, timeline)| (*timeline_id, timeline.clone()))
             .collect::<Vec<_>>();
         drop(timelines);
+        let timelines_to_compact = self.next_compaction_batch(active_timelines);
+
+        // Independent timelines are compacted in parallel, at most `compaction_concurrency` at a time.
+        // A failure on one timeline does not stop the compaction of the others.
+        let num_threads = self
+            .get_compaction_concurrency()
+            .max(1)
+            .min(timelines_to_compact.len());
+        let next_timeline_idx = AtomicUsize::new(0);
+        let compacted_timelines = AtomicUsize::new(0);
+        let totals = Mutex::new(CompactionResult::default());
+        let compaction_errors = Mutex::new(Vec::new());
+        let parent_span = Span::current();
+
+        let compaction_worker = || {
+            while let Some((timeline_id, timeline)) =
+                timelines_to_compact.get(next_timeline_idx.fetch_add(1, Ordering::Relaxed))
+            {
+                // Pausing the tenant halts the compaction promptly,
+                // the remaining timelines are left for the later iterations.
+                if !self.is_active() {
+                    info!(
+                        "Tenant became inactive, stopping compaction before timeline {timeline_id}"
+                    );
+                    break;
+                }
+                let _entered =
+                    info_span!(parent: &parent_span, "compact_timeline", timeline = %timeline_id)
+                        .entered();
+                match timeline.compact() {
+                    Ok(result) => {
+                        compacted_timelines.fetch_add(1, Ordering::Relaxed);
+                        *totals.lock().unwrap() += result;
+                    }
+                    Err(e) => {
+                        error!("Compaction failed: {e:?}");
+                        compaction_errors.lock().unwrap().push((*timeline_id, e));
+                    }
+                }
+                fail::fail_point!("compaction-iteration-after-timeline");
+            }
+        };
+
+        crossbeam_utils::thread::scope(|s| {
+            // Spawn `num_threads - 1`, as the current thread is also a worker.
+            let handles = (1..num_threads)
+                .map(|_| s.spawn(|_| compaction_worker()))
+                .collect::<Vec<_>>();
+
+            compaction_worker();
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+        .unwrap();
+
+        let totals = totals.into_inner().unwrap();
+        info!(
+            "Compacted {} timelines: {} image and {} delta layers created, {} layers removed, {} bytes written",
+            compacted_timelines.load(Ordering::Relaxed),
+            totals.image_layers_created,
+            totals.delta_layers_created,
+            totals.layers_removed,
+            totals.bytes_written
+        );
+
+        let compaction_errors = compaction_errors.into_inner().unwrap();
+        if !compaction_errors.is_empty() {
+            bail!(
+                "Compaction failed for {} of {} timelines: {}",
+                compaction_errors.len(),
+                timelines_to_compact.len(),
+                compaction_errors
+                    .iter()
+                    .map(|(timeline_id, e)| format!("{timeline_id}: {e:#}"))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+        }
+
+        Ok(compacted_timelines.into_inner())
+    }
+
+    /// Total size of the layer files of all tenant's timelines, including the broken ones.
+    /// The result is also exported as a per-tenant gauge.
+    ///
+    /// Uses the physical sizes the timelines track along with their layer maps, so no files are
+    /// accessed and temporary or uninitialized files are not counted.
+    pub fn total_local_size(&self) -> anyhow::Result<u64> {
+        let total_size = self
+            .timelines
+            .lock()
+            .unwrap()
+            .values()
+            .map(|timeline| timeline.get_physical_size())
+            .sum();
+
+        TENANT_LOCAL_SIZE
+            .with_label_values(&[&self.tenant_id.to_string()])
+            .set(total_size);
+        Ok(total_size)
+    }
+
+    /// Perform one compaction iteration on a single timeline.
+    /// Unlike [`Tenant::compaction_iteration`], this is only requested explicitly,
+    /// to rebuild the layers of one timeline without compacting the whole tenant.
+    pub fn compact_timeline(&self, timeline_id: TimelineId) -> anyhow::Result<CompactionResult> {
+        anyhow::ensure!(
+            self.is_active(),
+            "Cannot run compaction iteration on inactive tenant"
+        );
 
-        for (timeline_id, timeline) in &timelines_to_compact {
-            let _entered = info_span!("compact_timeline", timeline = %timeline_id).entered();
-            timeline.compact()?;
+        let timeline = self.get_timeline(timeline_id, true)?;
+
+        let _entered = info_span!("compact_timeline", timeline = %timeline_id).entered();
+        timeline.compact()
+    }
+
+    /// Picks the timelines for the next compaction iteration: all of them, or at most
+    /// `compaction_batch_size`, continuing after the timeline compacted last time.
+    fn next_compaction_batch(
+        &self,
+        mut timelines: Vec<(TimelineId, Arc<Timeline>)>,
+    ) -> Vec<(TimelineId, Arc<Timeline>)> {
+        let batch_size = self.get_compaction_batch_size();
+        let mut last_compacted_timeline = self.last_compacted_timeline.lock().unwrap();
+        if batch_size == 0 || batch_size >= timelines.len() {
+            *last_compacted_timeline = None;
+            return timelines;
         }
 
-        Ok(())
+        timelines.sort_by_key(|(timeline_id, _)| *timeline_id);
+        let start = match *last_compacted_timeline {
+            Some(last_id) => timelines
+                .iter()
+                .position(|(timeline_id, _)| *timeline_id > last_id)
+                .unwrap_or(0),
+            None => 0,
+        };
+        timelines.rotate_left(start);
+        timelines.truncate(batch_size);
+        *last_compacted_timeline = timelines.last().map(|(timeline_id, _)| *timeline_id);
+        timelines
     }
 
     /// Flush all in-memory data to disk.
     ///
-    /// Used at graceful shutdown.
+    /// Used at graceful shutdown. `progress` is called with the timeline id and
+    /// the number of bytes flushed after each timeline is checkpointed.
     ///
-    pub fn checkpoint(&self) -> anyhow::Result<()> {
+    pub fn checkpoint(&self, mut progress: impl FnMut(TimelineId, u64)) -> anyhow::Result<()> {
         // Scan through the hashmap and collect a list of all the timelines,
         // while holding the lock. Then drop the lock and actually perform the
         // checkpoints. We don't want to block everything else while the
@@ -556,38 +1333,95 @@ impl Tenant {
             let _entered =
                 info_span!("checkpoint", timeline = %timeline_id, tenant = %self.tenant_id)
                     .entered();
-            timeline.checkpoint(CheckpointConfig::Flush)?;
+            let flushed_bytes = timeline.checkpoint(CheckpointConfig::Flush)?;
+            progress(*timeline_id, flushed_bytes);
         }
 
         Ok(())
     }
 
+    /// Drains the tenant: pauses it, stops its background loops and waits for the in-flight
+    /// compaction and GC iterations to finish, then flushes all in-memory data to disk.
+    ///
+    /// Fails without checkpointing if some timelines are still busy after `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) -> anyhow::Result<()> {
+        let tenant_id = self.tenant_id;
+        let deadline = Instant::now() + timeout;
+
+        // Forbids new GC and compaction iterations from starting
+        self.set_state(TenantState::Paused);
+        let stop_background_loops = async {
+            task_mgr::shutdown_tasks(Some(TaskKind::Compaction), Some(tenant_id), None).await;
+            task_mgr::shutdown_tasks(Some(TaskKind::GarbageCollector), Some(tenant_id), None).await;
+        };
+        if tokio::time::timeout(timeout, stop_background_loops)
+            .await
+            .is_err()
+        {
+            warn!("Background loops of tenant {tenant_id} did not stop in {timeout:?}");
+        }
+
+        // Compaction and GC hold the layer removal lock of the timeline they work on,
+        // including the iterations started outside of the background loops.
+        loop {
+            let busy_timelines = self
+                .list_timelines()
+                .into_iter()
+                .filter(|timeline| timeline.layer_removal_guard().is_err())
+                .map(|timeline| timeline.timeline_id)
+                .collect::<Vec<_>>();
+            if busy_timelines.is_empty() {
+                break;
+            }
+            anyhow::ensure!(
+                Instant::now() < deadline,
+                "Tenant {tenant_id} did not shut down in {timeout:?}, busy timelines: {busy_timelines:?}"
+            );
+            tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+        }
+
+        self.checkpoint(|timeline_id, flushed_bytes| {
+            info!("checkpointed timeline {tenant_id}/{timeline_id}, flushed {flushed_bytes} bytes")
+        })
+    }
+
     /// Removes timeline-related in-memory data
-    pub fn delete_timeline(&self, timeline_id: TimelineId) -> anyhow::Result<()> {
+    pub fn delete_timeline(&self, timeline_id: TimelineId) -> Result<(), TenantError> {
         // in order to be retriable detach needs to be idempotent
         // (or at least to a point that each time the detach is called it can make progress)
         let mut timelines = self.timelines.lock().unwrap();
 
         // Ensure that there are no child timelines **attached to that pageserver**,
         // because detach removes files, which will break child branches
-        let children_exist = timelines
-            .iter()
-            .any(|(_, entry)| entry.get_ancestor_timeline_id() == Some(timeline_id));
-
-        anyhow::ensure!(
-            !children_exist,
-            "Cannot delete timeline which has child timelines"
-        );
+        let children = child_timeline_ids(&timelines, timeline_id);
+        if !children.is_empty() {
+            return Err(TenantError::HasChildren(children));
+        }
         let timeline_entry = match timelines.entry(timeline_id) {
             Entry::Occupied(e) => e,
-            Entry::Vacant(_) => bail!("timeline not found"),
+            Entry::Vacant(_) => {
+                return Err(TenantError::NotFound(format!(
+                    "Timeline {}/{}",
+                    self.tenant_id, timeline_id
+                )))
+            }
         };
 
         let timeline = timeline_entry.get();
+        // Pause the timeline only once nothing removes its layers, to not leave it paused if
+        // compaction or GC is running
+        let layer_removal_guard = match timeline.try_layer_removal_guard()? {
+            Some(guard) => guard,
+            None => {
+                info!("cannot delete timeline {timeline_id}: compaction or GC is running");
+                return Err(TenantError::Busy(format!(
+                    "Timeline {}/{}",
+                    self.tenant_id, timeline_id
+                )));
+            }
+        };
         timeline.set_state(TimelineState::Paused);
 
-        let layer_removal_guard = timeline.layer_removal_guard()?;
-
         let local_timeline_directory = self.conf.timeline_path(&timeline_id, &self.tenant_id);
         std::fs::remove_dir_all(&local_timeline_directory).with_context(|| {
             format!(
@@ -603,24 +1437,222 @@ impl Tenant {
         Ok(())
     }
 
-    /// Allows to retrieve remote timeline index from the tenant. Used in walreceiver to grab remote consistent lsn.
-    pub fn get_remote_index(&self) -> &RemoteIndex {
-        &self.remote_index
-    }
+    /// Removes the timeline locally, the same way [`Tenant::delete_timeline`] does, and then
+    /// schedules the deletion of all its layers in the remote storage, waiting until `storage_sync`
+    /// unregisters them from the remote index.
+    ///
+    /// The operation is idempotent: if a previous attempt got interrupted after the local files
+    /// were removed, the retry skips the local part and continues with the remote one.
+    pub async fn delete_timeline_remote(
+        &self,
+        timeline_id: TimelineId,
+    ) -> anyhow::Result<TimelineDeletionResult> {
+        let now = Instant::now();
+        let mut result = TimelineDeletionResult::default();
+
+        let timeline_is_local = {
+            let timelines = self.timelines.lock().unwrap();
+            // Check children here too: the local timeline may already be gone after an interrupted deletion
+            let children = child_timeline_ids(&timelines, timeline_id);
+            if !children.is_empty() {
+                return Err(TenantError::HasChildren(children).into());
+            }
+            timelines.contains_key(&timeline_id)
+        };
 
-    pub fn current_state(&self) -> TenantState {
-        *self.state.borrow()
-    }
+        if timeline_is_local {
+            self.delete_timeline(timeline_id)?;
+            result.local_files_removed = true;
+        } else {
+            anyhow::ensure!(self.upload_layers, "timeline not found");
+        }
 
-    pub fn is_active(&self) -> bool {
-        matches!(self.current_state(), TenantState::Active { .. })
+        if !self.upload_layers {
+            debug!("Remote storage is not configured, skipping remote deletion");
+            result.elapsed = now.elapsed();
+            return Ok(result);
+        }
+
+        let sync_id = TenantTimelineId {
+            tenant_id: self.tenant_id,
+            timeline_id,
+        };
+        let layers_to_delete = match self.remote_index.read().await.timeline_entry(&sync_id) {
+            Some(remote_timeline) => remote_timeline
+                .stored_files()
+                .keys()
+                .cloned()
+                .collect::<HashSet<_>>(),
+            None => {
+                anyhow::ensure!(
+                    timeline_is_local,
+                    "timeline not found either locally or in the remote index"
+                );
+                info!("No remote index entry for the timeline, nothing to delete remotely");
+                result.elapsed = now.elapsed();
+                return Ok(result);
+            }
+        };
+
+        if !layers_to_delete.is_empty() {
+            storage_sync::schedule_layer_delete(
+                self.tenant_id,
+                timeline_id,
+                layers_to_delete.clone(),
+            );
+
+            loop {
+                let layers_pending = match self.remote_index.read().await.timeline_entry(&sync_id) {
+                    Some(remote_timeline) => layers_to_delete
+                        .iter()
+                        .filter(|path| remote_timeline.stored_files().contains_key(*path))
+                        .count(),
+                    None => 0,
+                };
+                if layers_pending == 0 {
+                    break;
+                }
+                anyhow::ensure!(
+                    now.elapsed() < REMOTE_DELETION_TIMEOUT,
+                    "Timed out waiting for {layers_pending} remote layers of timeline {timeline_id} to be deleted, retry the deletion to continue"
+                );
+                tokio::time::sleep(REMOTE_DELETION_POLL_INTERVAL).await;
+            }
+        }
+
+        self.remote_index
+            .write()
+            .await
+            .remove_timeline_entry(sync_id);
+        info!(
+            "Removed {} remote layers of the timeline",
+            layers_to_delete.len()
+        );
+
+        result.remote_layers_removed = layers_to_delete.len() as u64;
+        result.elapsed = now.elapsed();
+        Ok(result)
+    }
+
+    /// Attaches a human-readable label to the timeline, or removes it if `None` is given.
+    /// The label is persisted in the timeline settings.
+    pub fn set_timeline_label(
+        &self,
+        timeline_id: TimelineId,
+        label: Option<String>,
+    ) -> anyhow::Result<()> {
+        if let Some(label) = &label {
+            anyhow::ensure!(
+                label.len() <= TIMELINE_LABEL_MAX_LEN,
+                "Timeline label is {} bytes long, the limit is {} bytes",
+                label.len(),
+                TIMELINE_LABEL_MAX_LEN
+            );
+        }
+
+        let timeline = self.get_timeline(timeline_id, false)?;
+        timeline
+            .set_label(label)
+            .with_context(|| format!("Failed to update label of timeline {timeline_id}"))
+    }
+
+    /// Makes the timeline read-only, or writable again. Writes to a read-only timeline fail,
+    /// but new timelines can still be branched from it.
+    /// The flag is persisted in the timeline settings.
+    pub fn set_timeline_read_only(
+        &self,
+        timeline_id: TimelineId,
+        read_only: bool,
+    ) -> anyhow::Result<()> {
+        let timeline = self.get_timeline(timeline_id, false)?;
+        timeline
+            .set_read_only(read_only)
+            .with_context(|| format!("Failed to update read-only flag of timeline {timeline_id}"))
+    }
+
+    /// Overrides the tenant's compaction settings for the given timeline, persisting them
+    /// in the timeline metadata. `None` falls back to the tenant config.
+    pub fn set_timeline_compaction_overrides(
+        &self,
+        timeline_id: TimelineId,
+        image_creation_threshold: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let timeline = self.get_timeline(timeline_id, false)?;
+        timeline
+            .set_compaction_overrides(image_creation_threshold)
+            .with_context(|| {
+                format!("Failed to update compaction overrides of timeline {timeline_id}")
+            })
+    }
+
+    /// Allows to retrieve remote timeline index from the tenant.
+    pub fn get_remote_index(&self) -> &RemoteIndex {
+        &self.remote_index
+    }
+
+    /// Branchpoints of the tenant's timelines in the remote index, for [`GcRequest::remote_branchpoints`].
+    /// Empty if GC should not respect the remote branchpoints, according to the tenant config.
+    pub async fn remote_branchpoints(&self) -> Vec<(TimelineId, Lsn)> {
+        if !self.get_gc_respect_remote_branchpoints() {
+            return Vec::new();
+        }
+        let remote_index = self.remote_index.read().await;
+        remote_index
+            .tenant_entry(&self.tenant_id)
+            .into_iter()
+            .flat_map(|tenant_entry| tenant_entry.iter())
+            .filter_map(|(_, remote_timeline)| {
+                let metadata = &remote_timeline.metadata;
+                let ancestor_timeline_id = metadata.ancestor_timeline()?;
+                Some((ancestor_timeline_id, metadata.ancestor_lsn()))
+            })
+            .collect()
+    }
+
+    /// The disk consistent LSN of the timeline in the remote storage, i.e. the LSN up to which
+    /// the timeline data survives losing the local files. Used in walreceiver to report it to safekeepers.
+    ///
+    /// Returns `None` if the timeline has not been uploaded yet.
+    pub async fn remote_consistent_lsn(&self, timeline_id: TimelineId) -> Option<Lsn> {
+        self.remote_index
+            .read()
+            .await
+            .timeline_entry(&TenantTimelineId {
+                tenant_id: self.tenant_id,
+                timeline_id,
+            })
+            .map(|remote_timeline| remote_timeline.metadata.disk_consistent_lsn())
+    }
+
+    /// Returns a snapshot of the timelines attach progress.
+    pub fn attach_progress(&self) -> AttachProgress {
+        *self.attach_progress.lock().unwrap()
+    }
+
+    pub fn current_state(&self) -> TenantState {
+        *self.state.borrow()
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.current_state(), TenantState::Active { .. })
+    }
+
+    pub fn should_run_gc(&self) -> bool {
+        matches!(
+            self.current_state(),
+            TenantState::Active {
+                gc_enabled: true,
+                ..
+            }
+        )
     }
 
-    pub fn should_run_tasks(&self) -> bool {
+    pub fn should_run_compaction(&self) -> bool {
         matches!(
             self.current_state(),
             TenantState::Active {
-                background_jobs_running: true
+                compaction_enabled: true,
+                ..
             }
         )
     }
@@ -628,21 +1660,155 @@ impl Tenant {
     /// Changes tenant status to active, if it was not broken before.
     /// Otherwise, ignores the state change, logging an error.
     pub fn activate(&self, enable_background_jobs: bool) {
+        self.background_jobs_deferred
+            .store(!enable_background_jobs, Ordering::Relaxed);
         self.set_state(TenantState::Active {
-            background_jobs_running: enable_background_jobs,
+            gc_enabled: enable_background_jobs,
+            compaction_enabled: enable_background_jobs,
         });
     }
 
+    /// Enables or disables GC of an active tenant, leaving compaction as it is.
+    pub fn set_gc_enabled(&self, enabled: bool) -> Result<(), TenantError> {
+        match self.current_state() {
+            TenantState::Active {
+                compaction_enabled, ..
+            } => {
+                // An explicit choice, the next created timeline should not change it
+                self.background_jobs_deferred
+                    .store(false, Ordering::Relaxed);
+                self.set_state(TenantState::Active {
+                    gc_enabled: enabled,
+                    compaction_enabled,
+                });
+                Ok(())
+            }
+            _ => Err(TenantError::NotActive(format!("Tenant {}", self.tenant_id))),
+        }
+    }
+
+    /// Enables or disables compaction of an active tenant, leaving GC as it is.
+    pub fn set_compaction_enabled(&self, enabled: bool) -> Result<(), TenantError> {
+        match self.current_state() {
+            TenantState::Active { gc_enabled, .. } => {
+                self.background_jobs_deferred
+                    .store(false, Ordering::Relaxed);
+                self.set_state(TenantState::Active {
+                    gc_enabled,
+                    compaction_enabled: enabled,
+                });
+                Ok(())
+            }
+            _ => Err(TenantError::NotActive(format!("Tenant {}", self.tenant_id))),
+        }
+    }
+
+    /// Attempts to bring a broken tenant back to operation: reloads its config and re-attaches
+    /// the timelines that are broken or were not loaded, reading their metadata from disk again.
+    /// The tenant gets activated only if all of its timelines load successfully.
+    pub fn try_recover(&self) -> anyhow::Result<()> {
+        let tenant_id = self.tenant_id;
+        anyhow::ensure!(
+            self.current_state() == TenantState::Broken,
+            "Tenant {tenant_id} is not broken, current state: {:?}",
+            self.current_state()
+        );
+        info!("attempting to recover broken tenant {tenant_id}");
+
+        let tenant_conf = Self::load_tenant_config(self.conf, tenant_id, false)?;
+
+        let timelines_path = self.conf.timelines_path(&tenant_id);
+        let mut timelines_to_attach = HashMap::new();
+        for timeline_dir_entry in fs::read_dir(&timelines_path)
+            .with_context(|| format!("Failed to list timelines dir {}", timelines_path.display()))?
+        {
+            let timeline_dir = timeline_dir_entry?.path();
+            // Temporary files and uninit marks have suffixes and don't parse as timeline ids
+            let timeline_id = match timeline_dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<TimelineId>().ok())
+            {
+                Some(timeline_id) if timeline_dir.is_dir() => timeline_id,
+                _ => continue,
+            };
+            if self
+                .conf
+                .timeline_uninit_mark_file_path(tenant_id, timeline_id)
+                .exists()
+            {
+                warn!("Skipping uninitialized timeline {tenant_id}/{timeline_id}");
+                continue;
+            }
+            let loaded = self
+                .timelines
+                .lock()
+                .unwrap()
+                .get(&timeline_id)
+                .map(|timeline| timeline.current_state() != TimelineState::Broken)
+                .unwrap_or(false);
+            if !loaded {
+                let metadata = load_metadata(self.conf, timeline_id, tenant_id)?;
+                timelines_to_attach.insert(timeline_id, metadata);
+            }
+        }
+
+        // Loaded timelines are needed as ancestors, the broken ones get replaced.
+        self.timelines
+            .lock()
+            .unwrap()
+            .retain(|_, timeline| timeline.current_state() != TimelineState::Broken);
+        self.init_attach_timelines(timelines_to_attach)?;
+
+        let timelines = self.timelines.lock().unwrap();
+        let broken_timelines = timelines
+            .iter()
+            .filter(|(_, timeline)| timeline.current_state() == TimelineState::Broken)
+            .map(|(timeline_id, _)| *timeline_id)
+            .collect::<Vec<_>>();
+        anyhow::ensure!(
+            broken_timelines.is_empty(),
+            "Timelines {broken_timelines:?} of tenant {tenant_id} failed to load"
+        );
+        let has_timelines = !timelines.is_empty();
+        drop(timelines);
+
+        self.apply_loaded_tenant_config(tenant_conf);
+        self.background_jobs_deferred
+            .store(!has_timelines, Ordering::Relaxed);
+        self.set_state_impl(
+            TenantState::Active {
+                gc_enabled: has_timelines,
+                compaction_enabled: has_timelines,
+            },
+            true,
+        );
+        info!("recovered broken tenant {tenant_id}");
+        Ok(())
+    }
+
     pub fn set_state(&self, new_state: TenantState) {
+        self.set_state_impl(new_state, false)
+    }
+
+    /// Transitions out of [`TenantState::Broken`] are ignored, unless `leave_broken` is set.
+    /// That's only done by [`Tenant::try_recover`].
+    fn set_state_impl(&self, new_state: TenantState, leave_broken: bool) {
         match (self.current_state(), new_state) {
             (equal_state_1, equal_state_2) if equal_state_1 == equal_state_2 => {
                 debug!("Ignoring new state, equal to the existing one: {equal_state_2:?}");
             }
-            (TenantState::Broken, _) => {
+            (TenantState::Broken, _) if !leave_broken => {
                 error!("Ignoring state update {new_state:?} for broken tenant");
             }
             (_, new_state) => {
-                self.state.send_replace(new_state);
+                let old_state = self.state.send_replace(new_state);
+                // Fails only if there are no subscribers at the moment
+                let _ = self.state_transitions.send(StateTransition {
+                    from: old_state,
+                    to: new_state,
+                    at: Instant::now(),
+                });
 
                 let timelines_accessor = self.timelines.lock().unwrap();
                 let not_broken_timelines = timelines_accessor
@@ -650,12 +1816,23 @@ impl Tenant {
                     .filter(|timeline| timeline.current_state() != TimelineState::Broken);
                 match new_state {
                     TenantState::Active {
-                        background_jobs_running,
+                        gc_enabled,
+                        compaction_enabled,
                     } => {
-                        if background_jobs_running {
-                            // Spawn gc and compaction loops. The loops will shut themselves
-                            // down when they notice that the tenant is inactive.
-                            crate::tenant_tasks::start_background_loops(self.tenant_id);
+                        // Spawn the loops that got enabled by this transition. The loops will
+                        // shut themselves down when they notice that the tenant is inactive.
+                        let (was_gc_enabled, was_compaction_enabled) = match old_state {
+                            TenantState::Active {
+                                gc_enabled,
+                                compaction_enabled,
+                            } => (gc_enabled, compaction_enabled),
+                            TenantState::Paused | TenantState::Broken => (false, false),
+                        };
+                        if gc_enabled && !was_gc_enabled {
+                            crate::tenant_tasks::start_gc_loop(self.tenant_id);
+                        }
+                        if compaction_enabled && !was_compaction_enabled {
+                            crate::tenant_tasks::start_compaction_loop(self.tenant_id);
                         }
 
                         for timeline in not_broken_timelines {
@@ -675,6 +1852,13 @@ impl Tenant {
     pub fn subscribe_for_state_updates(&self) -> watch::Receiver<TenantState> {
         self.state.subscribe()
     }
+
+    /// Subscribes for every state transition of the tenant, happening after the call.
+    pub fn subscribe_for_state_transitions(
+        &self,
+    ) -> broadcast::Receiver<StateTransition<TenantState>> {
+        self.state_transitions.subscribe()
+    }
 }
 
 /// Given a Vec of timelines and their ancestors (timeline_id, ancestor_id),
@@ -707,19 +1891,94 @@ fn tree_sort_timelines(
         }
     }
 
-    // All timelines should be visited now. Unless there were timelines with missing ancestors.
+    // All timelines should be visited now. Unless there were timelines with missing ancestors,
+    // or the ancestor relationships form a cycle (e.g. due to corrupted metadata).
     if !later.is_empty() {
+        // (timeline, ancestor) for every timeline that was not visited
+        let unvisited_ancestors = later
+            .iter()
+            .flat_map(|(ancestor_id, children)| {
+                children
+                    .iter()
+                    .map(move |(child_id, _)| (*child_id, *ancestor_id))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut cycle_ids = BTreeSet::new();
+        for &start_id in unvisited_ancestors.keys() {
+            let mut current_id = start_id;
+            // A cycle cannot be longer than the number of unvisited timelines
+            for _ in 0..unvisited_ancestors.len() {
+                match unvisited_ancestors.get(&current_id) {
+                    Some(&ancestor_id) if ancestor_id == start_id => {
+                        cycle_ids.insert(start_id);
+                        break;
+                    }
+                    Some(&ancestor_id) => current_id = ancestor_id,
+                    None => break,
+                }
+            }
+        }
+
         for (missing_id, orphan_ids) in later {
+            if unvisited_ancestors.contains_key(&missing_id) {
+                // Ancestor exists, but is a part of a cycle or a descendant of it
+                continue;
+            }
             for (orphan_id, _) in orphan_ids {
                 error!("could not load timeline {orphan_id} because its ancestor timeline {missing_id} could not be loaded");
             }
         }
+        if !cycle_ids.is_empty() {
+            bail!("could not load tenant because timelines {cycle_ids:?} form an ancestor cycle");
+        }
         bail!("could not load tenant because some timelines are missing ancestors");
     }
 
     Ok(result)
 }
 
+/// Same as [`tree_sort_timelines`], for the branches of a manifest:
+/// the branches of the manifest come after their ancestors from the manifest.
+fn tree_sort_branch_specs(specs: Vec<BranchSpec>) -> anyhow::Result<Vec<BranchSpec>> {
+    let spec_ids = specs.iter().map(|spec| spec.id).collect::<HashSet<_>>();
+    let mut result = Vec::with_capacity(specs.len());
+
+    let mut now = Vec::with_capacity(specs.len());
+    // (ancestor, children)
+    let mut later: HashMap<TimelineId, Vec<BranchSpec>> = HashMap::with_capacity(specs.len());
+
+    // Reversed, so that the independent branches are created in the manifest order
+    for spec in specs.into_iter().rev() {
+        if spec_ids.contains(&spec.ancestor) {
+            later.entry(spec.ancestor).or_default().push(spec);
+        } else {
+            now.push(spec);
+        }
+    }
+
+    while let Some(spec) = now.pop() {
+        // All children of this can be created now
+        if let Some(mut children) = later.remove(&spec.id) {
+            now.append(&mut children);
+        }
+        result.push(spec);
+    }
+
+    // Every branch whose ancestor is in the manifest is reachable from the other branches,
+    // unless the ancestor relationships form a cycle.
+    if !later.is_empty() {
+        let cycle_ids = later
+            .values()
+            .flatten()
+            .map(|spec| spec.id)
+            .collect::<BTreeSet<_>>();
+        bail!("timelines {cycle_ids:?} of the manifest form an ancestor cycle");
+    }
+
+    Ok(result)
+}
+
 /// Private functions
 impl Tenant {
     pub fn get_checkpoint_distance(&self) -> u64 {
@@ -736,6 +1995,13 @@ impl Tenant {
             .unwrap_or(self.conf.default_tenant_conf.checkpoint_timeout)
     }
 
+    pub fn get_checkpoint_max_inmem_bytes(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .checkpoint_max_inmem_bytes
+            .unwrap_or(self.conf.default_tenant_conf.checkpoint_max_inmem_bytes)
+    }
+
     pub fn get_compaction_target_size(&self) -> u64 {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -743,6 +2009,13 @@ impl Tenant {
             .unwrap_or(self.conf.default_tenant_conf.compaction_target_size)
     }
 
+    pub fn get_compaction_max_size(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .merge(self.conf.default_tenant_conf)
+            .effective_compaction_max_size()
+    }
+
     pub fn get_compaction_period(&self) -> Duration {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -785,14 +2058,84 @@ impl Tenant {
             .unwrap_or(self.conf.default_tenant_conf.pitr_interval)
     }
 
-    pub fn update_tenant_config(&self, new_tenant_conf: TenantConfOpt) {
-        self.tenant_conf.write().unwrap().update(&new_tenant_conf);
+    pub fn get_compaction_concurrency(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compaction_concurrency
+            .unwrap_or(self.conf.default_tenant_conf.compaction_concurrency)
+    }
+
+    pub fn get_max_ancestor_depth(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .max_ancestor_depth
+            .unwrap_or(self.conf.default_tenant_conf.max_ancestor_depth)
+    }
+
+    pub fn get_compaction_batch_size(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compaction_batch_size
+            .unwrap_or(self.conf.default_tenant_conf.compaction_batch_size)
+    }
+
+    pub fn get_max_upload_bytes_per_sec(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .max_upload_bytes_per_sec
+            .unwrap_or(self.conf.default_tenant_conf.max_upload_bytes_per_sec)
+    }
+
+    pub fn get_gc_cutoff_policy(&self) -> GcCutoffPolicy {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .gc_cutoff_policy
+            .unwrap_or(self.conf.default_tenant_conf.gc_cutoff_policy)
+    }
+
+    pub fn get_gc_respect_remote_branchpoints(&self) -> bool {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .gc_respect_remote_branchpoints
+            .unwrap_or(self.conf.default_tenant_conf.gc_respect_remote_branchpoints)
+    }
+
+    /// Applies the new settings on top of the current ones.
+    /// Rejects the update if the resulting config is invalid, see [`TenantConfOpt::validate`].
+    pub fn update_tenant_config(&self, new_tenant_conf: TenantConfOpt) -> anyhow::Result<()> {
+        {
+            let mut tenant_conf = self.tenant_conf.write().unwrap();
+            tenant_conf
+                .validate_update(&new_tenant_conf, self.conf.default_tenant_conf)
+                .context("Invalid tenant config")?;
+            tenant_conf.update(&new_tenant_conf);
+        }
+        storage_sync::set_upload_rate_limit(self.tenant_id, self.get_max_upload_bytes_per_sec());
+        Ok(())
+    }
+
+    /// Applies the settings read from the tenant's config file on attach.
+    /// Unlike [`Tenant::update_tenant_config`], an invalid config is applied with a warning:
+    /// the configs saved before the validation was introduced must not make the tenant unavailable.
+    pub fn apply_loaded_tenant_config(&self, loaded_tenant_conf: TenantConfOpt) {
+        {
+            let mut tenant_conf = self.tenant_conf.write().unwrap();
+            tenant_conf.update(&loaded_tenant_conf);
+            if let Err(e) = tenant_conf.validate(self.conf.default_tenant_conf) {
+                warn!(
+                    "Tenant {} has an invalid config, fix it with a config update: {e:#}",
+                    self.tenant_id
+                );
+            }
+        }
+        storage_sync::set_upload_rate_limit(self.tenant_id, self.get_max_upload_bytes_per_sec());
     }
 
     fn create_timeline_data(
         &self,
         new_timeline_id: TimelineId,
         new_metadata: TimelineMetadata,
+        new_settings: TimelineSettings,
         ancestor: Option<Arc<Timeline>>,
     ) -> anyhow::Result<Timeline> {
         if let Some(ancestor_timeline_id) = new_metadata.ancestor_timeline() {
@@ -807,6 +2150,7 @@ impl Tenant {
             self.conf,
             Arc::clone(&self.tenant_conf),
             new_metadata,
+            new_settings,
             ancestor,
             new_timeline_id,
             self.tenant_id,
@@ -825,23 +2169,34 @@ impl Tenant {
         upload_layers: bool,
     ) -> Tenant {
         let (state, _) = watch::channel(TenantState::Paused);
-        Tenant {
+        let (state_transitions, _) = broadcast::channel(STATE_TRANSITIONS_CAPACITY);
+        let tenant = Tenant {
             tenant_id,
             conf,
             tenant_conf: Arc::new(RwLock::new(tenant_conf)),
             timelines: Mutex::new(HashMap::new()),
             gc_cs: Mutex::new(()),
+            last_compacted_timeline: Mutex::new(None),
+            attach_progress: Arc::new(Mutex::new(AttachProgress::default())),
             walredo_mgr,
             remote_index,
             upload_layers,
             state,
-        }
+            state_transitions,
+            background_jobs_deferred: AtomicBool::new(false),
+        };
+        storage_sync::set_upload_rate_limit(tenant_id, tenant.get_max_upload_bytes_per_sec());
+        tenant
     }
 
     /// Locate and load config
+    ///
+    /// Unknown top-level keys, e.g. left by a different pageserver version, are skipped with a warning,
+    /// unless `strict` is set.
     pub(super) fn load_tenant_config(
         conf: &'static PageServerConf,
         tenant_id: TenantId,
+        strict: bool,
     ) -> anyhow::Result<TenantConfOpt> {
         let target_config_path = conf.tenant_config_path(tenant_id);
         let target_config_display = target_config_path.display();
@@ -873,8 +2228,8 @@ impl Tenant {
                         format!("Failed to parse config from file '{target_config_display}' as pageserver config")
                     })?;
                 }
-                _ => bail!("config file {target_config_display} has unrecognized pageserver option '{key}'"),
-
+                _ if strict => bail!("config file {target_config_display} has unrecognized pageserver option '{key}'"),
+                _ => warn!("config file {target_config_display} has unrecognized pageserver option '{key}', ignoring it"),
             }
         }
 
@@ -889,20 +2244,57 @@ impl Tenant {
         let _enter = info_span!("saving tenantconf").entered();
         info!("persisting tenantconf to {}", target_config_path.display());
 
-        // TODO this will prepend comments endlessly
-        let mut conf_content = r#"# This file contains a specific per-tenant's config.
-#  It is read in case of pageserver restart.
-
-[tenant_config]
-"#
-        .to_string();
+        // Start from the existing file to preserve its comments and other contents,
+        // only the values of the tenant config table are replaced.
+        let mut conf_doc = if !first_save && target_config_path.exists() {
+            fs::read_to_string(target_config_path)
+                .with_context(|| {
+                    format!(
+                        "Failed to read config file '{}'",
+                        target_config_path.display()
+                    )
+                })?
+                .parse::<toml_edit::Document>()
+                .with_context(|| {
+                    format!(
+                        "Failed to parse config file '{}' as toml",
+                        target_config_path.display()
+                    )
+                })?
+        } else {
+            TENANT_CONFIG_HEADER
+                .parse::<toml_edit::Document>()
+                .expect("tenant config header is valid toml")
+        };
 
-        // Convert the config to a toml file.
-        conf_content += &toml_edit::easy::to_string(&tenant_conf)?;
+        // Convert the config to toml.
+        let new_values = toml_edit::easy::to_string(&tenant_conf)?
+            .parse::<toml_edit::Document>()
+            .context("Failed to parse serialized tenant config")?;
+
+        let tenant_config_table = conf_doc
+            .entry("tenant_config")
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .context("'tenant_config' in the config file is not a table")?;
+        let old_keys = tenant_config_table
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .collect::<Vec<_>>();
+        for key in old_keys {
+            tenant_config_table.remove(&key);
+        }
+        for (key, item) in new_values.iter() {
+            tenant_config_table.insert(key, item.clone());
+        }
+        let conf_content = conf_doc.to_string();
 
         let mut target_config_file = VirtualFile::open_with_options(
             target_config_path,
-            OpenOptions::new().write(true).create_new(first_save),
+            OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .create_new(first_save),
         )?;
 
         target_config_file
@@ -970,11 +2362,18 @@ impl Tenant {
     //   we do.
     fn gc_iteration_internal(
         &self,
-        target_timeline_id: Option<TimelineId>,
-        horizon: u64,
-        pitr: Duration,
-        checkpoint_before_gc: bool,
+        request: &GcRequest,
+        dry_run: bool,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<GcResult> {
+        let GcRequest {
+            target_timeline_id,
+            horizon,
+            pitr,
+            cutoff_policy,
+            force_checkpoint,
+            ref remote_branchpoints,
+        } = *request;
         let mut totals: GcResult = Default::default();
         let now = Instant::now();
 
@@ -997,9 +2396,6 @@ impl Tenant {
                 .iter()
                 .filter(|(_, timeline)| timeline.is_active())
                 .map(|(timeline_id, timeline_entry)| {
-                    // This is unresolved question for now, how to do gc in presence of remote timelines
-                    // especially when this is combined with branching.
-                    // Somewhat related: https://github.com/neondatabase/neon/issues/999
                     if let Some(ancestor_timeline_id) = &timeline_entry.get_ancestor_timeline_id() {
                         // If target_timeline is specified, we only need to know branchpoints of its children
                         if let Some(timeline_id) = target_timeline_id {
@@ -1023,6 +2419,16 @@ impl Tenant {
         };
         drop(timelines);
 
+        // Timelines that are present only in the remote storage are not loaded, but their
+        // branchpoints need the ancestor's data all the same, until they get downloaded.
+        // Those of the loaded timelines are already in the set.
+        // Somewhat related: https://github.com/neondatabase/neon/issues/999
+        for &(ancestor_timeline_id, ancestor_lsn) in remote_branchpoints {
+            if target_timeline_id.map_or(true, |id| id == ancestor_timeline_id) {
+                all_branchpoints.insert((ancestor_timeline_id, ancestor_lsn));
+            }
+        }
+
         // Ok, we now know all the branch points.
         // Update the GC information for each timeline.
         let mut gc_timelines = Vec::with_capacity(timeline_ids.len());
@@ -1047,9 +2453,15 @@ impl Tenant {
                     ))
                     .map(|&x| x.1)
                     .collect();
-                timeline.update_gc_info(branchpoints, cutoff, pitr)?;
+                // Dry run must not affect the cutoffs of the next real GC iteration
+                let dry_run_gc_info = if dry_run {
+                    Some(timeline.calculate_gc_info(branchpoints, cutoff, pitr, cutoff_policy)?)
+                } else {
+                    timeline.update_gc_info(branchpoints, cutoff, pitr, cutoff_policy)?;
+                    None
+                };
 
-                gc_timelines.push(timeline);
+                gc_timelines.push((timeline, dry_run_gc_info));
             }
         }
         drop(gc_cs);
@@ -1064,26 +2476,48 @@ impl Tenant {
         //
         // See comments in [`Tenant::branch_timeline`] for more information
         // about why branch creation task can run concurrently with timeline's GC iteration.
-        for timeline in gc_timelines {
-            if task_mgr::is_shutdown_requested() {
+        for (timeline, dry_run_gc_info) in gc_timelines {
+            if task_mgr::is_shutdown_requested() || cancel.is_cancelled() {
                 // We were requested to shut down. Stop and return with the progress we
                 // made.
                 break;
             }
 
+            if let Some(gc_info) = dry_run_gc_info {
+                let result = timeline.gc_dry_run(&gc_info)?;
+                totals += result.clone();
+                totals.per_timeline.insert(timeline.timeline_id, result);
+                continue;
+            }
+
+            // Don't wait for `layer_removal_cs` behind another GC iteration that is
+            // collecting the same timeline, it will do the job.
+            let _gc_guard = match timeline.try_start_gc() {
+                Some(guard) => guard,
+                None => {
+                    info!(
+                        "timeline {} is being garbage collected by another GC iteration, skipping",
+                        timeline.timeline_id
+                    );
+                    totals.timelines_skipped.push(timeline.timeline_id);
+                    continue;
+                }
+            };
+
             // If requested, force flush all in-memory layers to disk first,
             // so that they too can be garbage collected. That's
             // used in tests, so we want as deterministic results as possible.
-            if checkpoint_before_gc {
+            if force_checkpoint {
                 timeline.checkpoint(CheckpointConfig::Forced)?;
                 info!(
-                    "timeline {} checkpoint_before_gc done",
+                    "timeline {} checkpoint before GC done",
                     timeline.timeline_id
                 );
             }
 
-            let result = timeline.gc()?;
-            totals += result;
+            let result = timeline.gc(cancel)?;
+            totals += result.clone();
+            totals.per_timeline.insert(timeline.timeline_id, result);
         }
 
         totals.elapsed = now.elapsed();
@@ -1091,19 +2525,35 @@ impl Tenant {
     }
 
     /// Branch an existing timeline
+    ///
+    /// Does blocking filesystem operations, async callers should run it via `spawn_blocking`.
     fn branch_timeline(
         &self,
         src: TimelineId,
         dst: TimelineId,
         start_lsn: Option<Lsn>,
+    ) -> anyhow::Result<Arc<Timeline>> {
+        let timeline_uninit_mark = {
+            let timelines = self.timelines.lock().unwrap();
+            self.create_timeline_uninit_mark(dst, &timelines)?
+        };
+        self.branch_timeline_with_uninit_mark(src, dst, start_lsn, timeline_uninit_mark, None)
+    }
+
+    /// Same as [`Tenant::branch_timeline`], for the callers that have placed the uninit mark
+    /// of the new timeline already.
+    fn branch_timeline_with_uninit_mark(
+        &self,
+        src: TimelineId,
+        dst: TimelineId,
+        start_lsn: Option<Lsn>,
+        timeline_uninit_mark: TimelineUninitMark,
+        created_by: Option<String>,
     ) -> anyhow::Result<Arc<Timeline>> {
         // We need to hold this lock to prevent GC from starting at the same time. GC scans the directory to learn
         // about timelines, so otherwise a race condition is possible, where we create new timeline and GC
         // concurrently removes data that is needed by the new timeline.
-        let _gc_cs = self.gc_cs.lock().unwrap();
-        let timelines = self.timelines.lock().unwrap();
-        let timeline_uninit_mark = self.create_timeline_uninit_mark(dst, &timelines)?;
-        drop(timelines);
+        let gc_cs = self.gc_cs.lock().unwrap();
 
         // In order for the branch creation task to not wait for GC/compaction,
         // we need to make sure that the starting LSN of the child branch is not out of scope midway by
@@ -1121,7 +2571,14 @@ impl Tenant {
             )
         })?;
 
-        let latest_gc_cutoff_lsn = src_timeline.get_latest_gc_cutoff_lsn();
+        let max_ancestor_depth = self.get_max_ancestor_depth();
+        if max_ancestor_depth > 0 {
+            let new_depth = self.ancestor_chain_length(&src_timeline)? + 1;
+            anyhow::ensure!(
+                new_depth <= max_ancestor_depth,
+                "Cannot branch off timeline {src}: the new timeline would have an ancestor chain of length {new_depth}, exceeding max_ancestor_depth {max_ancestor_depth}"
+            );
+        }
 
         // If no start LSN is specified, we branch the new timeline from the source timeline's last record LSN
         let start_lsn = start_lsn.unwrap_or_else(|| {
@@ -1130,24 +2587,7 @@ impl Tenant {
             lsn
         });
 
-        // Check if the starting LSN is out of scope because it is less than
-        // 1. the latest GC cutoff LSN or
-        // 2. the planned GC cutoff LSN, which is from an in-queue GC iteration.
-        src_timeline
-            .check_lsn_is_in_scope(start_lsn, &latest_gc_cutoff_lsn)
-            .context(format!(
-                "invalid branch start lsn: less than latest GC cutoff {}",
-                *latest_gc_cutoff_lsn,
-            ))?;
-        {
-            let gc_info = src_timeline.gc_info.read().unwrap();
-            let cutoff = min(gc_info.pitr_cutoff, gc_info.horizon_cutoff);
-            if start_lsn < cutoff {
-                bail!(format!(
-                    "invalid branch start lsn: less than planned GC cutoff {cutoff}"
-                ));
-            }
-        }
+        Self::check_branch_start_lsn(&src_timeline, start_lsn)?;
 
         // Determine prev-LSN for the new timeline. We can only determine it if
         // the timeline was branched at the current end of the source timeline.
@@ -1169,36 +2609,99 @@ impl Tenant {
             dst_prev,
             Some(src),
             start_lsn,
-            *src_timeline.latest_gc_cutoff_lsn.read(), // FIXME: should we hold onto this guard longer?
+            *src_timeline.get_latest_gc_cutoff_lsn(),
             src_timeline.initdb_lsn,
             src_timeline.pg_version,
         );
-        let mut timelines = self.timelines.lock().unwrap();
-        let new_timeline = self
-            .prepare_timeline(
-                dst,
-                metadata,
-                timeline_uninit_mark,
-                false,
-                Some(src_timeline),
-            )?
-            .initialize_with_lock(&mut timelines, true)?;
-        drop(timelines);
-        info!("branched timeline {dst} from {src} at {start_lsn}");
+        drop(gc_cs);
+        let settings = TimelineSettings {
+            created_at: SystemTime::now(),
+            created_by,
+            ..TimelineSettings::default()
+        };
 
-        Ok(new_timeline)
+        // Create the timeline files without holding the locks, GC does not know about
+        // the new timeline until it gets into the timelines map.
+        let uninit_timeline = self.prepare_timeline(
+            dst,
+            metadata,
+            settings,
+            timeline_uninit_mark,
+            false,
+            Some(Arc::clone(&src_timeline)),
+        )?;
+
+        // GC might have run meanwhile, so check the start LSN again, under the same lock
+        // that keeps GC out until the new timeline is visible to it.
+        let _gc_cs = self.gc_cs.lock().unwrap();
+        Self::check_branch_start_lsn(&src_timeline, start_lsn)?;
+        let new_timeline = {
+            let mut timelines = self.timelines.lock().unwrap();
+            uninit_timeline.initialize_with_lock(&mut timelines, true)?
+        };
+        info!("branched timeline {dst} from {src} at {start_lsn}");
+
+        Ok(new_timeline)
+    }
+
+    /// Check if the starting LSN of a branch is out of scope because it is less than
+    /// 1. the latest GC cutoff LSN or
+    /// 2. the planned GC cutoff LSN, which is from an in-queue GC iteration.
+    fn check_branch_start_lsn(src_timeline: &Timeline, start_lsn: Lsn) -> anyhow::Result<()> {
+        let latest_gc_cutoff_lsn = src_timeline.get_latest_gc_cutoff_lsn();
+        src_timeline
+            .check_lsn_is_in_scope(start_lsn, &latest_gc_cutoff_lsn)
+            .context(format!(
+                "invalid branch start lsn: less than latest GC cutoff {}",
+                *latest_gc_cutoff_lsn,
+            ))?;
+
+        let gc_info = src_timeline.gc_info.read().unwrap();
+        let cutoff = gc_info
+            .cutoff_policy
+            .combine(gc_info.horizon_cutoff, gc_info.pitr_cutoff);
+        if start_lsn < cutoff {
+            bail!(format!(
+                "invalid branch start lsn: less than planned GC cutoff {cutoff}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Number of ancestors of the given timeline, up to the root timeline.
+    fn ancestor_chain_length(&self, timeline: &Timeline) -> anyhow::Result<usize> {
+        let timelines = self.timelines.lock().unwrap();
+        let mut length = 0;
+        let mut ancestor_id = timeline.get_ancestor_timeline_id();
+        while let Some(id) = ancestor_id {
+            length += 1;
+            anyhow::ensure!(
+                length <= timelines.len(),
+                "Cycle in the ancestor chain of timeline {}",
+                timeline.timeline_id
+            );
+            ancestor_id = timelines
+                .get(&id)
+                .with_context(|| format!("Ancestor timeline {id} is not loaded"))?
+                .get_ancestor_timeline_id();
+        }
+        Ok(length)
     }
 
     /// - run initdb to init temporary instance and get bootstrap data
     /// - after initialization complete, remove the temp dir.
-    fn bootstrap_timeline(
-        &self,
+    ///
+    /// The caller places the uninit mark of the new timeline.
+    async fn bootstrap_timeline(
+        self: &Arc<Self>,
         timeline_id: TimelineId,
         pg_version: u32,
+        initdb_params: &InitdbParams,
+        init_hook: Option<TimelineInitHook>,
+        timeline_uninit_mark: TimelineUninitMark,
+        created_by: Option<String>,
     ) -> anyhow::Result<Arc<Timeline>> {
-        let timelines = self.timelines.lock().unwrap();
-        let timeline_uninit_mark = self.create_timeline_uninit_mark(timeline_id, &timelines)?;
-        drop(timelines);
+        initdb_params.validate()?;
         // create a `tenant/{tenant_id}/timelines/basebackup-{timeline_id}.{TEMP_FILE_SUFFIX}/`
         // temporary directory for basebackup files for the given timeline.
         let initdb_path = path_with_suffix_extension(
@@ -1219,7 +2722,14 @@ impl Tenant {
             })?;
         }
         // Init temporarily repo to get bootstrap data, this creates a directory in the `initdb_path` path
-        run_initdb(self.conf, &initdb_path, pg_version)?;
+        run_initdb_cached(
+            self.conf,
+            &initdb_path,
+            pg_version,
+            initdb_params,
+            timeline_id,
+        )
+        .await?;
         // this new directory is very temporary, set to remove it immediately after bootstrap, we don't need it
         scopeguard::defer! {
             if let Err(e) = fs::remove_dir_all(&initdb_path) {
@@ -1227,7 +2737,39 @@ impl Tenant {
                 error!("Failed to remove temporary initdb directory '{}': {}", initdb_path.display(), e);
             }
         }
-        let pgdata_path = &initdb_path;
+
+        // Importing creates the timeline files and writes its layers,
+        // run it on a blocking thread to not stall the other tasks of the runtime.
+        let tenant = Arc::clone(self);
+        let pgdata_path = initdb_path.clone();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _enter = span.enter();
+            tenant.import_bootstrapped_timeline(
+                timeline_id,
+                &pgdata_path,
+                pg_version,
+                init_hook,
+                timeline_uninit_mark,
+                created_by,
+            )
+        })
+        .await
+        .context("Failed to join the bootstrap timeline task")?
+    }
+
+    /// Creates the root timeline from the data directory produced by initdb, see [`Tenant::bootstrap_timeline`].
+    ///
+    /// Does blocking filesystem operations, async callers should run it via `spawn_blocking`.
+    fn import_bootstrapped_timeline(
+        &self,
+        timeline_id: TimelineId,
+        pgdata_path: &Path,
+        pg_version: u32,
+        init_hook: Option<TimelineInitHook>,
+        timeline_uninit_mark: TimelineUninitMark,
+        created_by: Option<String>,
+    ) -> anyhow::Result<Arc<Timeline>> {
         let pgdata_lsn = import_datadir::get_lsn_from_controlfile(pgdata_path)?.align();
 
         // Import the contents of the data directory at the initial checkpoint
@@ -1243,8 +2785,19 @@ impl Tenant {
             pgdata_lsn,
             pg_version,
         );
-        let raw_timeline =
-            self.prepare_timeline(timeline_id, new_metadata, timeline_uninit_mark, true, None)?;
+        let new_settings = TimelineSettings {
+            created_at: SystemTime::now(),
+            created_by,
+            ..TimelineSettings::default()
+        };
+        let raw_timeline = self.prepare_timeline(
+            timeline_id,
+            new_metadata,
+            new_settings,
+            timeline_uninit_mark,
+            true,
+            None,
+        )?;
 
         let tenant_id = raw_timeline.owning_tenant.tenant_id;
         let unfinished_timeline = raw_timeline.raw_timeline()?;
@@ -1257,6 +2810,13 @@ impl Tenant {
             format!("Failed to import pgdatadir for timeline {tenant_id}/{timeline_id}")
         })?;
 
+        // On failure, the uninitialized timeline is dropped, removing its files
+        if let Some(init_hook) = init_hook {
+            init_hook(unfinished_timeline).with_context(|| {
+                format!("Failed to run init hook for timeline {tenant_id}/{timeline_id}")
+            })?;
+        }
+
         fail::fail_point!("before-checkpoint-new-timeline", |_| {
             anyhow::bail!("failpoint before-checkpoint-new-timeline");
         });
@@ -1283,6 +2843,7 @@ impl Tenant {
         &self,
         new_timeline_id: TimelineId,
         new_metadata: TimelineMetadata,
+        new_settings: TimelineSettings,
         uninit_mark: TimelineUninitMark,
         init_layers: bool,
         ancestor: Option<Arc<Timeline>>,
@@ -1293,6 +2854,7 @@ impl Tenant {
             &uninit_mark.timeline_path,
             new_timeline_id,
             new_metadata,
+            new_settings,
             ancestor,
         ) {
             Ok(new_timeline) => {
@@ -1322,60 +2884,102 @@ impl Tenant {
         timeline_path: &Path,
         new_timeline_id: TimelineId,
         new_metadata: TimelineMetadata,
+        new_settings: TimelineSettings,
         ancestor: Option<Arc<Timeline>>,
     ) -> anyhow::Result<Timeline> {
         let timeline_data = self
-            .create_timeline_data(new_timeline_id, new_metadata.clone(), ancestor)
+            .create_timeline_data(
+                new_timeline_id,
+                new_metadata.clone(),
+                new_settings.clone(),
+                ancestor,
+            )
             .context("Failed to create timeline data structure")?;
-        crashsafe::create_dir_all(timeline_path).context("Failed to create timeline directory")?;
+        retry_on_transient_io_errors("timeline directory creation", |attempt| {
+            crashsafe::create_dir_all(timeline_path)?;
+            if attempt > 1 {
+                // The directory might have been created by the failed attempt, without the fsyncs
+                crashsafe::fsync_file_and_parent(timeline_path)?;
+            }
+            Ok(())
+        })
+        .context("Failed to create timeline directory")?;
 
         fail::fail_point!("after-timeline-uninit-mark-creation", |_| {
             anyhow::bail!("failpoint after-timeline-uninit-mark-creation");
         });
 
-        save_metadata(
-            self.conf,
-            new_timeline_id,
-            self.tenant_id,
-            &new_metadata,
-            true,
-        )
+        retry_on_transient_io_errors("timeline metadata creation", |attempt| {
+            if attempt > 1 {
+                // The file is created exclusively, remove whatever the failed attempt left
+                metadata::remove_metadata(timeline_path)?;
+            }
+            save_metadata(
+                self.conf,
+                new_timeline_id,
+                self.tenant_id,
+                &new_metadata,
+                true,
+            )
+        })
         .context("Failed to create timeline metadata")?;
+        save_timeline_settings(self.conf, new_timeline_id, self.tenant_id, &new_settings)
+            .context("Failed to create timeline settings")?;
 
         Ok(timeline_data)
     }
 
     /// Attempts to create an uninit mark file for the timeline initialization.
-    /// Bails, if the timeline is already loaded into the memory (i.e. initialized before), or the uninit mark file already exists.
+    /// Fails with [`TenantError::AlreadyExists`], if the timeline is already loaded into the memory
+    /// (i.e. initialized before), its directory or its uninit mark file already exists.
     ///
     /// This way, we need to hold the timelines lock only for small amount of time during the mark check/creation per timeline init.
     fn create_timeline_uninit_mark(
         &self,
         timeline_id: TimelineId,
         timelines: &MutexGuard<HashMap<TimelineId, Arc<Timeline>>>,
-    ) -> anyhow::Result<TimelineUninitMark> {
+    ) -> Result<TimelineUninitMark, TenantError> {
         let tenant_id = self.tenant_id;
 
-        anyhow::ensure!(
-            timelines.get(&timeline_id).is_none(),
-            "Timeline {tenant_id}/{timeline_id} already exists in pageserver's memory"
-        );
+        if timelines.get(&timeline_id).is_some() {
+            return Err(TenantError::AlreadyExists(format!(
+                "Timeline {tenant_id}/{timeline_id}"
+            )));
+        }
         let timeline_path = self.conf.timeline_path(&timeline_id, &tenant_id);
-        anyhow::ensure!(
-            !timeline_path.exists(),
-            "Timeline {} already exists, cannot create its uninit mark file",
-            timeline_path.display()
-        );
+        if timeline_path.exists() {
+            return Err(TenantError::AlreadyExists(format!(
+                "Directory {} of timeline {tenant_id}/{timeline_id}",
+                timeline_path.display()
+            )));
+        }
 
         let uninit_mark_path = self
             .conf
             .timeline_uninit_mark_file_path(tenant_id, timeline_id);
-        fs::File::create(&uninit_mark_path)
-            .context("Failed to create uninit mark file")
-            .and_then(|_| {
-                crashsafe::fsync_file_and_parent(&uninit_mark_path)
-                    .context("Failed to fsync uninit mark file")
-            })
+        // The mark is created exclusively, so that of the concurrent creations, only one proceeds
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&uninit_mark_path)
+        {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(TenantError::AlreadyExists(format!(
+                    "Uninit mark of timeline {tenant_id}/{timeline_id}"
+                )));
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context("Failed to create uninit mark file")
+                    .context(format!(
+                        "Failed to crate uninit mark for timeline {tenant_id}/{timeline_id}"
+                    ))
+                    .into())
+            }
+        }
+        crashsafe::fsync_file_and_parent(&uninit_mark_path)
+            .context("Failed to fsync uninit mark file")
             .with_context(|| {
                 format!("Failed to crate uninit mark for timeline {tenant_id}/{timeline_id}")
             })?;
@@ -1385,10 +2989,121 @@ impl Tenant {
         Ok(uninit_mark)
     }
 
+    /// Removes the leftovers of operations interrupted by a crash or restart: the entries ending
+    /// with [`TEMP_FILE_SUFFIX`] in the tenant, timelines and timeline directories.
+    /// Returns the number of removed entries.
+    ///
+    /// Only safe to call when loading the tenant, before anything creates temporary files in it.
+    pub fn cleanup_stale_temp_files(&self) -> anyhow::Result<usize> {
+        let tenant_path = self.conf.tenant_path(&self.tenant_id);
+        let timelines_path = self.conf.timelines_path(&self.tenant_id);
+        let mut removed = remove_temp_entries(&tenant_path)?;
+        if !timelines_path.is_dir() {
+            return Ok(removed);
+        }
+        removed += remove_temp_entries(&timelines_path)?;
+
+        for entry in fs::read_dir(&timelines_path).with_context(|| {
+            format!(
+                "Failed to list timelines directory {}",
+                timelines_path.display()
+            )
+        })? {
+            let path = entry
+                .context("Failed to list timelines directory entry")?
+                .path();
+            if path.is_dir() {
+                removed += remove_temp_entries(&path)?;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Finishes the cleanup of timeline creations interrupted by a crash or restart.
+    /// A timeline directory with an uninit mark next to it was never fully created,
+    /// so both are removed; a mark whose directory is already gone is removed alone.
+    ///
+    /// Only safe to call when loading the tenant, before its timelines are attached:
+    /// a running tenant has uninit marks for the timelines it is creating.
+    pub fn reconcile_uninit_marks(&self) -> anyhow::Result<UninitMarkReconciliation> {
+        let mut reconciliation = UninitMarkReconciliation::default();
+        let timelines_path = self.conf.timelines_path(&self.tenant_id);
+        if !timelines_path.is_dir() {
+            return Ok(reconciliation);
+        }
+
+        for entry in fs::read_dir(&timelines_path).with_context(|| {
+            format!(
+                "Failed to list timelines directory {}",
+                timelines_path.display()
+            )
+        })? {
+            let uninit_mark = entry
+                .context("Failed to list timelines directory entry")?
+                .path();
+            let timeline_dir_name = match uninit_mark
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(TIMELINE_UNINIT_MARK_SUFFIX))
+                .and_then(|name| name.strip_suffix('.'))
+            {
+                Some(timeline_dir_name) if uninit_mark.is_file() => timeline_dir_name,
+                _ => continue,
+            };
+            if let Ok(timeline_id) = timeline_dir_name.parse::<TimelineId>() {
+                anyhow::ensure!(
+                    !self.timelines.lock().unwrap().contains_key(&timeline_id),
+                    "Timeline {}/{timeline_id} is loaded, cannot reconcile its uninit mark",
+                    self.tenant_id
+                );
+            }
+
+            let timeline_dir = timelines_path.join(timeline_dir_name);
+            if timeline_dir.exists() {
+                info!(
+                    "Removing partially created timeline {} and its uninit mark",
+                    timeline_dir.display()
+                );
+                fs::remove_dir_all(&timeline_dir).with_context(|| {
+                    format!(
+                        "Failed to remove uninit marked timeline directory {}",
+                        timeline_dir.display()
+                    )
+                })?;
+                reconciliation.removed_timelines += 1;
+            } else {
+                info!("Removing stale uninit mark {}", uninit_mark.display());
+                reconciliation.removed_marks += 1;
+            }
+            fs::remove_file(&uninit_mark).with_context(|| {
+                format!("Failed to remove uninit mark {}", uninit_mark.display())
+            })?;
+        }
+
+        if reconciliation != UninitMarkReconciliation::default() {
+            crashsafe::fsync(&timelines_path).with_context(|| {
+                format!(
+                    "Failed to fsync timelines directory {}",
+                    timelines_path.display()
+                )
+            })?;
+        }
+        Ok(reconciliation)
+    }
+
     pub(super) fn init_attach_timelines(
         &self,
         timelines: HashMap<TimelineId, TimelineMetadata>,
     ) -> anyhow::Result<()> {
+        *self.attach_progress.lock().unwrap() = AttachProgress {
+            total: timelines.len(),
+            ..AttachProgress::default()
+        };
+        scopeguard::defer! {
+            self.attach_progress.lock().unwrap().done = true;
+        }
+
         let sorted_timelines = if timelines.len() == 1 {
             timelines.into_iter().collect()
         } else if !timelines.is_empty() {
@@ -1410,6 +3125,7 @@ impl Tenant {
 
             if timelines_accessor.contains_key(&timeline_id) {
                 warn!("Timeline {tenant_id}/{timeline_id} already exists in the tenant map, skipping its initialization");
+                self.attach_progress.lock().unwrap().completed += 1;
                 continue;
             }
 
@@ -1417,8 +3133,14 @@ impl Tenant {
                 .ancestor_timeline()
                 .and_then(|ancestor_timeline_id| timelines_accessor.get(&ancestor_timeline_id))
                 .cloned();
+            let settings = load_timeline_settings(self.conf, timeline_id, tenant_id)?;
             let dummy_timeline = self
-                .create_timeline_data(timeline_id, metadata.clone(), ancestor.clone())
+                .create_timeline_data(
+                    timeline_id,
+                    metadata.clone(),
+                    settings.clone(),
+                    ancestor.clone(),
+                )
                 .with_context(|| {
                     format!("Failed to crate dummy timeline data for {tenant_id}/{timeline_id}")
                 })?;
@@ -1427,35 +3149,101 @@ impl Tenant {
                 timeline_id,
                 raw_timeline: Some((dummy_timeline, TimelineUninitMark::dummy())),
             };
+            // The map gets either the fully initialized timeline or a broken one, and nothing
+            // in between: `initialize_with_lock` inserts the timeline only after all of its
+            // fallible steps succeeded, leaving the map untouched on failure.
             match timeline.initialize_with_lock(&mut timelines_accessor, true) {
-                Ok(initialized_timeline) => {
-                    timelines_accessor.insert(timeline_id, initialized_timeline);
+                Ok(_) => {
+                    self.attach_progress.lock().unwrap().completed += 1;
                 }
                 Err(e) => {
                     error!("Failed to initialize timeline {tenant_id}/{timeline_id}: {e:?}");
                     let broken_timeline = self
-                        .create_timeline_data(timeline_id, metadata, ancestor)
+                        .create_timeline_data(timeline_id, metadata, settings, ancestor)
                         .with_context(|| {
                             format!("Failed to crate broken timeline data for {tenant_id}/{timeline_id}")
                         })?;
                     broken_timeline.set_state(TimelineState::Broken);
-                    timelines_accessor.insert(timeline_id, Arc::new(broken_timeline));
+                    let replaced =
+                        timelines_accessor.insert(timeline_id, Arc::new(broken_timeline));
+                    debug_assert!(
+                        replaced.is_none(),
+                        "Failed initialization left timeline {tenant_id}/{timeline_id} in the tenant map"
+                    );
+                    self.attach_progress.lock().unwrap().failed += 1;
                 }
             }
         }
 
         Ok(())
     }
+
+    /// Same as [`Self::init_attach_timelines`], but initializes only the timelines from `only`,
+    /// together with all their ancestors, even if those are not in `only`: a branch cannot be
+    /// initialized without its ancestor. The rest of `timelines` is left for a later attach.
+    pub fn init_attach_timelines_selective(
+        &self,
+        timelines: HashMap<TimelineId, TimelineMetadata>,
+        only: &HashSet<TimelineId>,
+    ) -> anyhow::Result<()> {
+        let mut required = HashSet::with_capacity(only.len());
+        for &requested_id in only {
+            let mut current_id = Some(requested_id);
+            // Stop at the timelines already visited, this also guards against ancestor cycles
+            while let Some(timeline_id) = current_id.filter(|id| !required.contains(id)) {
+                let metadata = timelines.get(&timeline_id).with_context(|| {
+                    if timeline_id == requested_id {
+                        format!("Timeline {timeline_id} is not among the timelines to attach")
+                    } else {
+                        format!("Ancestor timeline {timeline_id} of the requested timeline {requested_id} is not among the timelines to attach")
+                    }
+                })?;
+                required.insert(timeline_id);
+                current_id = metadata.ancestor_timeline();
+            }
+        }
+
+        let skipped = timelines.len() - required.len();
+        if skipped > 0 {
+            info!(
+                "Attaching {} timelines of tenant {}, skipping {skipped} others",
+                required.len(),
+                self.tenant_id
+            );
+        }
+        self.init_attach_timelines(
+            timelines
+                .into_iter()
+                .filter(|(timeline_id, _)| required.contains(timeline_id))
+                .collect(),
+        )
+    }
+}
+
+fn load_metadata(
+    conf: &'static PageServerConf,
+    timeline_id: TimelineId,
+    tenant_id: TenantId,
+) -> anyhow::Result<TimelineMetadata> {
+    metadata::load_metadata_from_dir(&conf.timeline_path(&timeline_id, &tenant_id))
 }
 
 /// Create the cluster temporarily in 'initdbpath' directory inside the repository
 /// to get bootstrap data for timeline initialization.
-fn run_initdb(
+async fn run_initdb(
     conf: &'static PageServerConf,
     initdb_target_dir: &Path,
     pg_version: u32,
+    initdb_params: &InitdbParams,
 ) -> anyhow::Result<()> {
-    let initdb_bin_path = conf.pg_bin_dir(pg_version)?.join("initdb");
+    initdb_params.validate()?;
+    let superuser = initdb_params
+        .superuser_override
+        .as_deref()
+        .unwrap_or(&conf.superuser);
+    let encoding = initdb_params.encoding.as_deref().unwrap_or("utf8");
+
+    let initdb_bin_path = conf.initdb_bin_path(pg_version)?;
     let initdb_lib_dir = conf.pg_lib_dir(pg_version)?;
     info!(
         "running {} in {}, libdir: {}",
@@ -1464,10 +3252,15 @@ fn run_initdb(
         initdb_lib_dir.display(),
     );
 
-    let initdb_output = Command::new(&initdb_bin_path)
+    let mut initdb_command = tokio::process::Command::new(&initdb_bin_path);
+    initdb_command
         .args(&["-D", &initdb_target_dir.to_string_lossy()])
-        .args(&["-U", &conf.superuser])
-        .args(&["-E", "utf8"])
+        .args(&["-U", superuser])
+        .args(&["-E", encoding]);
+    if let Some(locale) = &initdb_params.locale {
+        initdb_command.arg(format!("--locale={locale}"));
+    }
+    let initdb_output = initdb_command
         .arg("--no-instructions")
         // This is only used for a temporary installation that is deleted shortly after,
         // so no need to fsync it
@@ -1475,8 +3268,8 @@ fn run_initdb(
         .env_clear()
         .env("LD_LIBRARY_PATH", &initdb_lib_dir)
         .env("DYLD_LIBRARY_PATH", &initdb_lib_dir)
-        .stdout(Stdio::null())
         .output()
+        .await
         .with_context(|| {
             format!(
                 "failed to execute {} at target dir {}",
@@ -1485,11 +3278,184 @@ fn run_initdb(
             )
         })?;
     if !initdb_output.status.success() {
-        bail!(
-            "initdb failed: '{}'",
-            String::from_utf8_lossy(&initdb_output.stderr)
+        return Err(InitdbError {
+            exit_code: initdb_output.status.code(),
+            stdout: String::from_utf8_lossy(&initdb_output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&initdb_output.stderr).into_owned(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Same as [`run_initdb`], but reuses the output of a previous initdb run with the same
+/// parameters, if it's present in the cache, and saves the output into the cache otherwise.
+///
+/// Cache failures are not fatal: initdb is run as usual if the cache cannot be used.
+/// Only the runs with the default [`InitdbParams`] are cached.
+async fn run_initdb_cached(
+    conf: &'static PageServerConf,
+    initdb_target_dir: &Path,
+    pg_version: u32,
+    initdb_params: &InitdbParams,
+    timeline_id: TimelineId,
+) -> anyhow::Result<()> {
+    if *initdb_params != InitdbParams::default() {
+        return run_initdb(conf, initdb_target_dir, pg_version, initdb_params).await;
+    }
+    let cache_file = match initdb_cache_file_path(conf, pg_version) {
+        Ok(cache_file) => cache_file,
+        Err(e) => {
+            warn!("Cannot use initdb cache: {e:#}");
+            return run_initdb(conf, initdb_target_dir, pg_version, initdb_params).await;
+        }
+    };
+
+    if cache_file.exists() {
+        let (unpacked_cache_file, target_dir) = (cache_file.clone(), initdb_target_dir.to_owned());
+        let unpack_result =
+            run_initdb_cache_task(move || unpack_initdb_cache(&unpacked_cache_file, &target_dir))
+                .await;
+        match unpack_result {
+            Ok(()) => {
+                info!("restored initdb output from {}", cache_file.display());
+                return Ok(());
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to restore initdb output from {}, running initdb: {e:#}",
+                    cache_file.display()
+                );
+                let target_dir = initdb_target_dir.to_owned();
+                run_initdb_cache_task(move || {
+                    ignore_absent_files(|| fs::remove_dir_all(&target_dir)).with_context(|| {
+                        format!(
+                            "Failed to remove partially restored initdb directory {}",
+                            target_dir.display()
+                        )
+                    })
+                })
+                .await?;
+            }
+        }
+    }
+
+    run_initdb(conf, initdb_target_dir, pg_version, initdb_params).await?;
+
+    let (saved_cache_file, target_dir) = (cache_file.clone(), initdb_target_dir.to_owned());
+    if let Err(e) = run_initdb_cache_task(move || {
+        save_initdb_cache(&saved_cache_file, &target_dir, timeline_id)
+    })
+    .await
+    {
+        warn!(
+            "Failed to save initdb output into {}: {e:#}",
+            cache_file.display()
         );
     }
+    Ok(())
+}
+
+/// Unpacking and saving the initdb cache read and write the whole initdb output,
+/// so they run on a blocking thread, to not stall the other tasks of the runtime.
+async fn run_initdb_cache_task<F>(f: F) -> anyhow::Result<()>
+where
+    F: FnOnce() -> anyhow::Result<()> + Send + 'static,
+{
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _enter = span.enter();
+        f()
+    })
+    .await
+    .context("Failed to join the initdb cache task")?
+}
+
+/// initdb output is deterministic for the given postgres version and superuser name,
+/// so they, together with the initdb binary path and modification time, make up the cache key.
+fn initdb_cache_file_path(conf: &PageServerConf, pg_version: u32) -> anyhow::Result<PathBuf> {
+    let initdb_bin_path = conf.initdb_bin_path(pg_version)?;
+    let initdb_mtime = initdb_bin_path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| {
+            format!(
+                "Failed to get modification time of {}",
+                initdb_bin_path.display()
+            )
+        })?;
+
+    let mut hasher = DefaultHasher::new();
+    conf.superuser.hash(&mut hasher);
+    initdb_bin_path.hash(&mut hasher);
+    initdb_mtime.hash(&mut hasher);
+
+    Ok(conf.initdb_cache_path().join(format!(
+        "{}{pg_version}-{:016x}.tar",
+        INITDB_CACHE_FILE_PREFIX,
+        hasher.finish()
+    )))
+}
+
+fn unpack_initdb_cache(cache_file: &Path, initdb_target_dir: &Path) -> anyhow::Result<()> {
+    let cache = File::open(cache_file).context("Failed to open initdb cache file")?;
+    fs::create_dir_all(initdb_target_dir).context("Failed to create initdb directory")?;
+    tar::Archive::new(cache)
+        .unpack(initdb_target_dir)
+        .context("Failed to unpack initdb cache file")
+}
+
+fn save_initdb_cache(
+    cache_file: &Path,
+    initdb_dir: &Path,
+    timeline_id: TimelineId,
+) -> anyhow::Result<()> {
+    let cache_dir = cache_file
+        .parent()
+        .context("initdb cache file has no parent directory")?;
+    fs::create_dir_all(cache_dir).context("Failed to create initdb cache directory")?;
+
+    // Several timelines may be bootstrapped concurrently, write into a file unique for this one
+    // and atomically rename it into place.
+    let temp_file =
+        path_with_suffix_extension(cache_file, &format!("{timeline_id}.{TEMP_FILE_SUFFIX}"));
+    let mut builder = tar::Builder::new(
+        File::create(&temp_file).context("Failed to create temporary initdb cache file")?,
+    );
+    let write_result = builder
+        .append_dir_all(".", initdb_dir)
+        .and_then(|()| builder.into_inner())
+        .and_then(|file| file.sync_all())
+        .context("Failed to write initdb cache file")
+        .and_then(|()| {
+            fs::rename(&temp_file, cache_file).context("Failed to rename initdb cache file")
+        });
+    if write_result.is_err() {
+        let _ = fs::remove_file(&temp_file);
+    }
+    write_result?;
+
+    // Remove the outdated caches of the same postgres version, built by a different initdb binary.
+    let cache_file_name = cache_file.file_name().and_then(|name| name.to_str());
+    let version_prefix = cache_file_name
+        .and_then(|name| name.rsplit_once('-'))
+        .map(|(prefix, _)| prefix);
+    if let Some(version_prefix) = version_prefix {
+        for entry in fs::read_dir(cache_dir)?.flatten() {
+            let entry_name = entry.file_name();
+            let entry_name = entry_name.to_string_lossy();
+            if Some(entry_name.as_ref()) != cache_file_name
+                && entry_name.starts_with(&format!("{version_prefix}-"))
+                && !entry_name.ends_with(TEMP_FILE_SUFFIX)
+            {
+                info!("removing outdated initdb cache file {entry_name}");
+                if let Err(e) = fs::remove_file(entry.path()) {
+                    warn!("Failed to remove outdated initdb cache file {entry_name}: {e}");
+                }
+            }
+        }
+    }
 
     Ok(())
 }
@@ -1499,29 +3465,108 @@ impl Drop for Tenant {
         remove_tenant_metrics(&self.tenant_id);
     }
 }
-/// Dump contents of a layer file to stdout.
-pub fn dump_layerfile_from_path(path: &Path, verbose: bool) -> anyhow::Result<()> {
+/// Error of dumping a layer file.
+#[derive(Debug, thiserror::Error)]
+pub enum DumpLayerError {
+    #[error("unrecognized magic identifier: {0:?}")]
+    UnrecognizedMagic(u16),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Opens the layer file and reads its two-byte "magic" value, that all layer files start with,
+/// to identify the kind of file.
+fn open_layerfile(path: &Path) -> anyhow::Result<(File, u16)> {
     use std::os::unix::fs::FileExt;
 
-    // All layer files start with a two-byte "magic" value, to identify the kind of
-    // file.
     let file = File::open(path)?;
     let mut header_buf = [0u8; 2];
     file.read_exact_at(&mut header_buf, 0)?;
+    Ok((file, u16::from_be_bytes(header_buf)))
+}
 
-    match u16::from_be_bytes(header_buf) {
+/// Dump contents of a layer file to stdout.
+/// If `key_range` is given, only the entries with keys in that range are printed.
+pub fn dump_layerfile_from_path(
+    path: &Path,
+    verbose: bool,
+    key_range: Option<&Range<Key>>,
+) -> anyhow::Result<()> {
+    let (file, magic) = open_layerfile(path)?;
+    match magic {
         crate::IMAGE_FILE_MAGIC => {
-            image_layer::ImageLayer::new_for_path(path, file)?.dump(verbose)?
+            image_layer::ImageLayer::new_for_path(path, file)?.dump_key_range(verbose, key_range)?
         }
         crate::DELTA_FILE_MAGIC => {
-            delta_layer::DeltaLayer::new_for_path(path, file)?.dump(verbose)?
+            delta_layer::DeltaLayer::new_for_path(path, file)?.dump_key_range(verbose, key_range)?
         }
-        magic => bail!("unrecognized magic identifier: {:?}", magic),
+        magic => return Err(DumpLayerError::UnrecognizedMagic(magic).into()),
     }
 
     Ok(())
 }
 
+/// Same as [`dump_layerfile_from_path`], but returns the contents as JSON.
+pub fn dump_layerfile_as_json(
+    path: &Path,
+    verbose: bool,
+    key_range: Option<&Range<Key>>,
+) -> Result<serde_json::Value, DumpLayerError> {
+    let (file, magic) = open_layerfile(path)?;
+    let dump = match magic {
+        crate::IMAGE_FILE_MAGIC => {
+            image_layer::ImageLayer::new_for_path(path, file)?.dump_json(verbose, key_range)?
+        }
+        crate::DELTA_FILE_MAGIC => {
+            delta_layer::DeltaLayer::new_for_path(path, file)?.dump_json(verbose, key_range)?
+        }
+        magic => return Err(DumpLayerError::UnrecognizedMagic(magic)),
+    };
+    Ok(dump)
+}
+
+/// Ids of the timelines branched off the given one, sorted.
+fn child_timeline_ids(
+    timelines: &HashMap<TimelineId, Arc<Timeline>>,
+    timeline_id: TimelineId,
+) -> Vec<TimelineId> {
+    let mut children = timelines
+        .values()
+        .filter(|timeline| timeline.get_ancestor_timeline_id() == Some(timeline_id))
+        .map(|timeline| timeline.timeline_id)
+        .collect::<Vec<_>>();
+    children.sort();
+    children
+}
+
+/// Removes the files and directories in `dir` ending with [`TEMP_FILE_SUFFIX`], returning their count.
+fn remove_temp_entries(dir: &Path) -> anyhow::Result<usize> {
+    let mut removed = 0;
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to list directory {}", dir.display()))?
+    {
+        let path = entry
+            .with_context(|| format!("Failed to list directory {} entry", dir.display()))?
+            .path();
+        let is_temporary = path.file_name().map_or(false, |name| {
+            name.to_string_lossy().ends_with(TEMP_FILE_SUFFIX)
+        });
+        if !is_temporary {
+            continue;
+        }
+
+        info!("Removing stale temporary {}", path.display());
+        if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
 fn ignore_absent_files<F>(fs_operation: F) -> io::Result<()>
 where
     F: Fn() -> io::Result<()>,
@@ -1535,6 +3580,48 @@ where
     })
 }
 
+const TRANSIENT_IO_ERROR_MAX_ATTEMPTS: u32 = 4;
+const TRANSIENT_IO_ERROR_BASE_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Errors that might go away on their own, unlike e.g. `AlreadyExists` or `PermissionDenied`.
+fn is_transient_io_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Runs the filesystem operation, retrying it with an exponential backoff while it fails with
+/// transient IO errors, up to [`TRANSIENT_IO_ERROR_MAX_ATTEMPTS`] times.
+/// The operation gets the number of the current attempt, starting from 1, to clean up after
+/// the failed attempts if needed.
+fn retry_on_transient_io_errors<T, F>(operation_name: &str, mut operation: F) -> anyhow::Result<T>
+where
+    F: FnMut(u32) -> anyhow::Result<T>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation(attempt) {
+            Ok(result) => return Ok(result),
+            Err(e) => {
+                let is_transient = e
+                    .chain()
+                    .filter_map(|cause| cause.downcast_ref::<io::Error>())
+                    .any(is_transient_io_error);
+                if !is_transient || attempt >= TRANSIENT_IO_ERROR_MAX_ATTEMPTS {
+                    return Err(e);
+                }
+                let backoff = TRANSIENT_IO_ERROR_BASE_BACKOFF * 2u32.pow(attempt - 1);
+                warn!(
+                    "{operation_name} failed on attempt {attempt}, retrying in {backoff:?}: {e:#}"
+                );
+                std::thread::sleep(backoff);
+                attempt += 1;
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod harness {
     use bytes::{Bytes, BytesMut};
@@ -1580,15 +3667,24 @@ pub mod harness {
                 checkpoint_distance: Some(tenant_conf.checkpoint_distance),
                 checkpoint_timeout: Some(tenant_conf.checkpoint_timeout),
                 compaction_target_size: Some(tenant_conf.compaction_target_size),
+                compaction_max_size: Some(tenant_conf.compaction_max_size),
                 compaction_period: Some(tenant_conf.compaction_period),
                 compaction_threshold: Some(tenant_conf.compaction_threshold),
                 gc_horizon: Some(tenant_conf.gc_horizon),
                 gc_period: Some(tenant_conf.gc_period),
                 image_creation_threshold: Some(tenant_conf.image_creation_threshold),
                 pitr_interval: Some(tenant_conf.pitr_interval),
+                gc_cutoff_policy: Some(tenant_conf.gc_cutoff_policy),
                 walreceiver_connect_timeout: Some(tenant_conf.walreceiver_connect_timeout),
                 lagging_wal_timeout: Some(tenant_conf.lagging_wal_timeout),
                 max_lsn_wal_lag: Some(tenant_conf.max_lsn_wal_lag),
+                compaction_concurrency: Some(tenant_conf.compaction_concurrency),
+                max_ancestor_depth: Some(tenant_conf.max_ancestor_depth),
+                compaction_batch_size: Some(tenant_conf.compaction_batch_size),
+                max_layers_per_get: Some(tenant_conf.max_layers_per_get),
+                max_upload_bytes_per_sec: Some(tenant_conf.max_upload_bytes_per_sec),
+                gc_respect_remote_branchpoints: Some(tenant_conf.gc_respect_remote_branchpoints),
+                checkpoint_max_inmem_bytes: Some(tenant_conf.checkpoint_max_inmem_bytes),
             }
         }
     }
@@ -1646,8 +3742,13 @@ pub mod harness {
         }
 
         pub fn try_load(&self) -> anyhow::Result<Tenant> {
-            let walredo_mgr = Arc::new(TestRedoManager);
+            self.try_load_with_redo_manager(Arc::new(TestRedoManager))
+        }
 
+        pub fn try_load_with_redo_manager(
+            &self,
+            walredo_mgr: Arc<dyn WalRedoManager + Send + Sync>,
+        ) -> anyhow::Result<Tenant> {
             let tenant = Tenant::new(
                 self.conf,
                 TenantConfOpt::from(self.tenant_conf),
@@ -1656,6 +3757,8 @@ pub mod harness {
                 RemoteIndex::default(),
                 false,
             );
+            tenant.reconcile_uninit_marks()?;
+            tenant.cleanup_stale_temp_files()?;
             // populate tenant with locally available timelines
             let mut timelines_to_load = HashMap::new();
             for timeline_dir_entry in fs::read_dir(self.conf.timelines_path(&self.tenant_id))
@@ -1674,7 +3777,8 @@ pub mod harness {
             }
             tenant.init_attach_timelines(timelines_to_load)?;
             tenant.set_state(TenantState::Active {
-                background_jobs_running: false,
+                gc_enabled: false,
+                compaction_enabled: false,
             });
 
             Ok(tenant)
@@ -1685,26 +3789,6 @@ pub mod harness {
         }
     }
 
-    fn load_metadata(
-        conf: &'static PageServerConf,
-        timeline_id: TimelineId,
-        tenant_id: TenantId,
-    ) -> anyhow::Result<TimelineMetadata> {
-        let metadata_path = conf.metadata_path(timeline_id, tenant_id);
-        let metadata_bytes = std::fs::read(&metadata_path).with_context(|| {
-            format!(
-                "Failed to read metadata bytes from path {}",
-                metadata_path.display()
-            )
-        })?;
-        TimelineMetadata::from_bytes(&metadata_bytes).with_context(|| {
-            format!(
-                "Failed to parse metadata bytes from path {}",
-                metadata_path.display()
-            )
-        })
-    }
-
     // Mock WAL redo manager that doesn't do much
     pub struct TestRedoManager;
 
@@ -1738,12 +3822,13 @@ pub mod harness {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::METADATA_FILE_NAME;
     use crate::keyspace::KeySpaceAccum;
     use crate::repository::{Key, Value};
     use crate::tenant::harness::*;
+    use crate::walrecord::NeonWalRecord;
+    use crate::walredo::WalRedoError;
     use crate::DEFAULT_PG_VERSION;
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
     use hex_literal::hex;
     use once_cell::sync::Lazy;
     use rand::{thread_rng, Rng};
@@ -1751,8 +3836,31 @@ mod tests {
     static TEST_KEY: Lazy<Key> =
         Lazy::new(|| Key::from_slice(&hex!("112222222233333333444444445500000001")));
 
-    #[test]
-    fn test_basic() -> anyhow::Result<()> {
+    /// Configures a callback for the failpoint that only fires on the calling thread, so that
+    /// the tests running in parallel and passing the same failpoint are not affected.
+    /// Failpoints are global for the process: the returned scenario serializes the tests
+    /// configuring them, and removes the failpoint when dropped.
+    #[cfg(feature = "testing")]
+    fn cfg_failpoint_callback<F>(
+        name: &str,
+        callback: F,
+    ) -> anyhow::Result<fail::FailScenario<'static>>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let scenario = fail::FailScenario::setup();
+        let test_thread = std::thread::current().id();
+        fail::cfg_callback(name, move || {
+            if std::thread::current().id() == test_thread {
+                callback()
+            }
+        })
+        .map_err(anyhow::Error::msg)?;
+        Ok(scenario)
+    }
+
+    #[test]
+    fn test_basic() -> anyhow::Result<()> {
         let tenant = TenantHarness::create("test_basic")?.load();
         let tline = tenant
             .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
@@ -1760,12 +3868,12 @@ mod tests {
 
         let writer = tline.writer();
         writer.put(*TEST_KEY, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
-        writer.finish_write(Lsn(0x10));
+        writer.finish_write(Lsn(0x10))?;
         drop(writer);
 
         let writer = tline.writer();
         writer.put(*TEST_KEY, Lsn(0x20), &Value::Image(TEST_IMG("foo at 0x20")))?;
-        writer.finish_write(Lsn(0x20));
+        writer.finish_write(Lsn(0x20))?;
         drop(writer);
 
         assert_eq!(tline.get(*TEST_KEY, Lsn(0x10))?, TEST_IMG("foo at 0x10"));
@@ -1775,6 +3883,52 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_write_lsn_does_not_go_backwards() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_write_lsn_does_not_go_backwards")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION)?
+            .initialize()?;
+
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x20), &Value::Image(TEST_IMG("foo at 0x20")))?;
+        writer.finish_write(Lsn(0x20))?;
+        // Finishing the same LSN again is fine
+        writer.finish_write(Lsn(0x20))?;
+
+        let err = writer
+            .finish_write(Lsn(0x18))
+            .expect_err("finishing a write behind the last record LSN should fail");
+        assert!(
+            err.to_string().contains("behind the last record LSN"),
+            "{err}"
+        );
+        assert_eq!(tline.get_last_record_lsn(), Lsn(0x20));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_lag() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_flush_lag")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        assert_eq!(tline.flush_lag(), 0);
+
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x20), &Value::Image(TEST_IMG("foo at 0x20")))?;
+        writer.finish_write(Lsn(0x20))?;
+        drop(writer);
+        assert_eq!(tline.flush_lag(), 0x10);
+
+        tline.checkpoint(CheckpointConfig::Forced)?;
+        assert_eq!(tline.get_disk_consistent_lsn(), Lsn(0x20));
+        assert_eq!(tline.flush_lag(), 0);
+
+        Ok(())
+    }
+
     #[test]
     fn no_duplicate_timelines() -> anyhow::Result<()> {
         let tenant = TenantHarness::create("no_duplicate_timelines")?.load();
@@ -1787,7 +3941,7 @@ mod tests {
             Err(e) => assert_eq!(
                 e.to_string(),
                 format!(
-                    "Timeline {}/{} already exists in pageserver's memory",
+                    "Timeline {}/{} already exists",
                     tenant.tenant_id, TIMELINE_ID
                 )
             ),
@@ -1796,126 +3950,2761 @@ mod tests {
         Ok(())
     }
 
-    /// Convenience function to create a page image with given string as the only content
-    pub fn test_value(s: &str) -> Value {
-        let mut buf = BytesMut::new();
-        buf.extend_from_slice(s.as_bytes());
-        Value::Image(buf.freeze())
-    }
-
-    ///
-    /// Test branch creation
-    ///
+    #[tokio::test]
+    async fn delete_timeline_remote_is_idempotent() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("delete_timeline_remote_is_idempotent")?;
+        let tenant = harness.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        make_some_layers(&tline, Lsn(0x20))?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x20)))?;
+        drop(tline);
+
+        match tenant.delete_timeline_remote(TIMELINE_ID).await {
+            Ok(_) => panic!("deletion of a timeline with children should fail"),
+            Err(e) => match e.downcast::<TenantError>() {
+                Ok(TenantError::HasChildren(children)) => {
+                    assert_eq!(children, vec![NEW_TIMELINE_ID])
+                }
+                other => panic!("unexpected error: {other:?}"),
+            },
+        }
+
+        let result = tenant.delete_timeline_remote(NEW_TIMELINE_ID).await?;
+        assert!(result.local_files_removed);
+        assert_eq!(result.remote_layers_removed, 0);
+        assert!(!harness.timeline_path(&NEW_TIMELINE_ID).exists());
+
+        // Nothing is left neither locally nor remotely
+        assert!(tenant
+            .delete_timeline_remote(NEW_TIMELINE_ID)
+            .await
+            .is_err());
+
+        let result = tenant.delete_timeline_remote(TIMELINE_ID).await?;
+        assert!(result.local_files_removed);
+        assert!(tenant.list_timelines().is_empty());
+
+        Ok(())
+    }
+
+    /// Convenience function to create a page image with given string as the only content
+    pub fn test_value(s: &str) -> Value {
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(s.as_bytes());
+        Value::Image(buf.freeze())
+    }
+
+    ///
+    /// Test branch creation
+    ///
+    #[test]
+    fn test_branch() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_branch")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let writer = tline.writer();
+        use std::str::from_utf8;
+
+        #[allow(non_snake_case)]
+        let TEST_KEY_A: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
+        #[allow(non_snake_case)]
+        let TEST_KEY_B: Key = Key::from_hex("112222222233333333444444445500000002").unwrap();
+
+        // Insert a value on the timeline
+        writer.put(TEST_KEY_A, Lsn(0x20), &test_value("foo at 0x20"))?;
+        writer.put(TEST_KEY_B, Lsn(0x20), &test_value("foobar at 0x20"))?;
+        writer.finish_write(Lsn(0x20))?;
+
+        writer.put(TEST_KEY_A, Lsn(0x30), &test_value("foo at 0x30"))?;
+        writer.finish_write(Lsn(0x30))?;
+        writer.put(TEST_KEY_A, Lsn(0x40), &test_value("foo at 0x40"))?;
+        writer.finish_write(Lsn(0x40))?;
+
+        //assert_current_logical_size(&tline, Lsn(0x40));
+
+        // Branch the history, modify relation differently on the new timeline
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x30)))?;
+        let newtline = tenant
+            .get_timeline(NEW_TIMELINE_ID, true)
+            .expect("Should have a local timeline");
+        let new_writer = newtline.writer();
+        new_writer.put(TEST_KEY_A, Lsn(0x40), &test_value("bar at 0x40"))?;
+        new_writer.finish_write(Lsn(0x40))?;
+
+        // Check page contents on both branches
+        assert_eq!(
+            from_utf8(&tline.get(TEST_KEY_A, Lsn(0x40))?)?,
+            "foo at 0x40"
+        );
+        assert_eq!(
+            from_utf8(&newtline.get(TEST_KEY_A, Lsn(0x40))?)?,
+            "bar at 0x40"
+        );
+        assert_eq!(
+            from_utf8(&newtline.get(TEST_KEY_B, Lsn(0x40))?)?,
+            "foobar at 0x20"
+        );
+
+        //assert_current_logical_size(&tline, Lsn(0x40));
+
+        Ok(())
+    }
+
+    /// Creates the [`TIMELINE_ID`] timeline in the tenant, with the layers of [`make_some_layers`]
+    /// starting at `Lsn(0x20)`.
+    fn create_timeline_with_layers(tenant: &Tenant) -> anyhow::Result<Arc<Timeline>> {
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        make_some_layers(tline.as_ref(), Lsn(0x20))?;
+        Ok(tline)
+    }
+
+    /// Loads a new tenant for the test, with a timeline created by [`create_timeline_with_layers`].
+    fn tenant_with_layers(test_name: &'static str) -> anyhow::Result<(Tenant, Arc<Timeline>)> {
+        let tenant = TenantHarness::create(test_name)?.load();
+        let tline = create_timeline_with_layers(&tenant)?;
+        Ok((tenant, tline))
+    }
+
+    fn make_some_layers(tline: &Timeline, start_lsn: Lsn) -> anyhow::Result<()> {
+        let mut lsn = start_lsn;
+        #[allow(non_snake_case)]
+        {
+            let writer = tline.writer();
+            // Create a relation on the timeline
+            writer.put(
+                *TEST_KEY,
+                lsn,
+                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
+            )?;
+            writer.finish_write(lsn)?;
+            lsn += 0x10;
+            writer.put(
+                *TEST_KEY,
+                lsn,
+                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
+            )?;
+            writer.finish_write(lsn)?;
+            lsn += 0x10;
+        }
+        tline.checkpoint(CheckpointConfig::Forced)?;
+        {
+            let writer = tline.writer();
+            writer.put(
+                *TEST_KEY,
+                lsn,
+                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
+            )?;
+            writer.finish_write(lsn)?;
+            lsn += 0x10;
+            writer.put(
+                *TEST_KEY,
+                lsn,
+                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
+            )?;
+            writer.finish_write(lsn)?;
+        }
+        tline.checkpoint(CheckpointConfig::Forced)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeline_label() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_timeline_label")?;
+        {
+            let tenant = harness.load();
+            let tline = create_timeline_with_layers(&tenant)?;
+            assert_eq!(tline.get_label(), None);
+
+            tenant.set_timeline_label(TIMELINE_ID, Some("main branch".to_string()))?;
+            assert!(tenant
+                .set_timeline_label(TIMELINE_ID, Some("x".repeat(TIMELINE_LABEL_MAX_LEN + 1)))
+                .is_err());
+            // Metadata updates on checkpoint should keep the label
+            make_some_layers(tline.as_ref(), Lsn(0x60))?;
+        }
+
+        let tenant = harness.load();
+        let tline = tenant.get_timeline(TIMELINE_ID, true)?;
+        assert_eq!(tline.get_label().as_deref(), Some("main branch"));
+
+        tenant.set_timeline_label(TIMELINE_ID, None)?;
+        let settings = load_timeline_settings(harness.conf, TIMELINE_ID, harness.tenant_id)?;
+        assert_eq!(settings.label, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeline_creation_info() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_timeline_creation_info")?;
+        let created_at = {
+            let tenant = Arc::new(harness.load());
+            let tline = create_timeline_with_layers(&tenant)?;
+            // Not created through the API, so the creation is not recorded
+            assert_eq!(tline.get_created_at(), SystemTime::UNIX_EPOCH);
+            assert_eq!(tline.get_created_by(), None);
+
+            let err = tenant
+                .create_timeline(
+                    Some(NEW_TIMELINE_ID),
+                    Some(TIMELINE_ID),
+                    Some(AncestorStart::Lsn(Lsn(0x40))),
+                    DEFAULT_PG_VERSION,
+                    InitdbParams::default(),
+                    None,
+                    Some("x".repeat(TIMELINE_CREATOR_MAX_LEN + 1)),
+                )
+                .await
+                .expect_err("Too long creator should fail the timeline creation");
+            assert!(
+                format!("{err:#}").contains("Timeline creator is"),
+                "{err:#}"
+            );
+
+            let before_branch = SystemTime::now();
+            let new_tline = tenant
+                .create_timeline(
+                    Some(NEW_TIMELINE_ID),
+                    Some(TIMELINE_ID),
+                    Some(AncestorStart::Lsn(Lsn(0x40))),
+                    DEFAULT_PG_VERSION,
+                    InitdbParams::default(),
+                    None,
+                    Some("console".to_string()),
+                )
+                .await?;
+            assert!(new_tline.get_created_at() >= before_branch);
+            assert!(new_tline.get_created_at() <= SystemTime::now());
+            assert_eq!(new_tline.get_created_by().as_deref(), Some("console"));
+            // Metadata updates on checkpoint should keep the creation info
+            make_some_layers(new_tline.as_ref(), Lsn(0x60))?;
+            new_tline.get_created_at()
+        };
+
+        let tenant = harness.load();
+        let new_tline = tenant.get_timeline(NEW_TIMELINE_ID, true)?;
+        assert_eq!(new_tline.get_created_at(), created_at);
+        assert_eq!(new_tline.get_created_by().as_deref(), Some("console"));
+        let settings = load_timeline_settings(harness.conf, NEW_TIMELINE_ID, harness.tenant_id)?;
+        assert_eq!(settings.created_at, created_at);
+        assert_eq!(settings.created_by.as_deref(), Some("console"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_timeline_rejects_writes() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_read_only_timeline_rejects_writes")?;
+        {
+            let tenant = harness.load();
+            let tline = create_timeline_with_layers(&tenant)?;
+            assert!(!tline.is_read_only());
+
+            tenant.set_timeline_read_only(TIMELINE_ID, true)?;
+            assert!(tline.is_read_only());
+            let writer = tline.writer();
+            let err = writer
+                .put(*TEST_KEY, Lsn(0x60), &Value::Image(TEST_IMG("foo at 0x60")))
+                .expect_err("Writes to a read-only timeline should fail");
+            assert!(err.to_string().contains("is read-only"), "{err}");
+            assert!(writer.finish_write(Lsn(0x60)).is_err());
+            drop(writer);
+            assert_eq!(tline.get_last_record_lsn(), Lsn(0x50));
+        }
+
+        // The flag survives the restart
+        let tenant = harness.load();
+        let tline = tenant.get_timeline(TIMELINE_ID, true)?;
+        assert!(tline.is_read_only());
+        assert!(load_timeline_settings(harness.conf, TIMELINE_ID, harness.tenant_id)?.read_only);
+
+        tenant.set_timeline_read_only(TIMELINE_ID, false)?;
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x60), &Value::Image(TEST_IMG("foo at 0x60")))?;
+        writer.finish_write(Lsn(0x60))?;
+        drop(writer);
+        assert_eq!(tline.get(*TEST_KEY, Lsn(0x60))?, TEST_IMG("foo at 0x60"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeline_compaction_overrides() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_timeline_compaction_overrides")?;
+        let tenant_threshold;
+        {
+            let tenant = harness.load();
+            tenant_threshold = tenant.get_image_creation_threshold();
+            let tline = create_timeline_with_layers(&tenant)?;
+            tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+            let new_tline = tenant.get_timeline(NEW_TIMELINE_ID, true)?;
+
+            let override_threshold = tenant_threshold + 5;
+            tenant.set_timeline_compaction_overrides(TIMELINE_ID, Some(override_threshold))?;
+            assert_eq!(tline.get_image_creation_threshold(), override_threshold);
+            // Other timelines keep using the tenant config
+            assert_eq!(new_tline.get_image_creation_threshold(), tenant_threshold);
+
+            assert!(tenant
+                .set_timeline_compaction_overrides(TIMELINE_ID, Some(0))
+                .is_err());
+            assert_eq!(tline.get_image_creation_threshold(), override_threshold);
+        }
+
+        // The override survives the restart
+        let tenant = harness.load();
+        let tline = tenant.get_timeline(TIMELINE_ID, true)?;
+        assert_eq!(
+            tline.get_image_creation_threshold_override(),
+            Some(tenant_threshold + 5)
+        );
+        assert_eq!(tline.get_image_creation_threshold(), tenant_threshold + 5);
+
+        tenant.set_timeline_compaction_overrides(TIMELINE_ID, None)?;
+        assert_eq!(tline.get_image_creation_threshold(), tenant_threshold);
+        let settings = load_timeline_settings(harness.conf, TIMELINE_ID, harness.tenant_id)?;
+        assert_eq!(settings.image_creation_threshold, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeline_image_creation_threshold_override() -> anyhow::Result<()> {
+        let mut harness = TenantHarness::create("test_timeline_image_creation_threshold_override")?;
+        // Compact every flushed layer into L1 deltas, image creation only counts those,
+        // and repartition on every compaction, so that the new deltas are counted
+        harness.tenant_conf.compaction_threshold = 1;
+        harness.tenant_conf.checkpoint_distance = 1024;
+        let tenant = harness.load();
+        let tline = crate::pgdatadir_mapping::create_test_timeline(
+            &tenant,
+            TIMELINE_ID,
+            DEFAULT_PG_VERSION,
+        )?;
+        let tenant_threshold = tenant.get_image_creation_threshold();
+        tenant.set_timeline_compaction_overrides(TIMELINE_ID, Some(tenant_threshold + 1))?;
+
+        // Just enough deltas to create the image layer with the tenant's threshold
+        for i in 1..=tenant_threshold as u64 {
+            let lsn = Lsn(i * 0x100);
+            let mut m = tline.begin_modification(lsn);
+            m.put_control_file(Bytes::from(format!("control file at {lsn}")))?;
+            m.put_checkpoint(Bytes::from(format!("checkpoint at {lsn}")))?;
+            m.commit()?;
+            tline.checkpoint(CheckpointConfig::Flush)?;
+            assert_eq!(tline.compact()?.image_layers_created, 0);
+        }
+        assert_eq!(tline.compact()?.image_layers_created, 0);
+
+        tenant.set_timeline_compaction_overrides(TIMELINE_ID, None)?;
+        assert_eq!(tline.compact()?.image_layers_created, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch_from_read_only_timeline() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_branch_from_read_only_timeline")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
+        writer.finish_write(Lsn(0x10))?;
+        drop(writer);
+
+        tenant.set_timeline_read_only(TIMELINE_ID, true)?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x10)))?;
+        let new_tline = tenant.get_timeline(NEW_TIMELINE_ID, true)?;
+
+        // The branch is writable and sees the data of its read-only ancestor
+        assert!(!new_tline.is_read_only());
+        assert_eq!(
+            new_tline.get(*TEST_KEY, Lsn(0x10))?,
+            TEST_IMG("foo at 0x10")
+        );
+        let writer = new_tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x20), &Value::Image(TEST_IMG("foo at 0x20")))?;
+        writer.finish_write(Lsn(0x20))?;
+        drop(writer);
+        assert_eq!(
+            new_tline.get(*TEST_KEY, Lsn(0x20))?,
+            TEST_IMG("foo at 0x20")
+        );
+        assert!(tline.is_read_only());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_persist_tenant_config_keeps_single_header() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_persist_tenant_config_keeps_single_header")?;
+        let config_path = harness.conf.tenant_config_path(harness.tenant_id);
+
+        Tenant::persist_tenant_config(
+            &config_path,
+            TenantConfOpt {
+                gc_horizon: Some(1024),
+                compaction_threshold: Some(5),
+                ..TenantConfOpt::default()
+            },
+            true,
+        )?;
+        // User comments should survive the rewrites
+        let mut content = fs::read_to_string(&config_path)?;
+        content += "# user comment\n";
+        fs::write(&config_path, content)?;
+
+        // Settings written by an older save are replaced on load
+        Tenant::persist_tenant_config(
+            &config_path,
+            TenantConfOpt {
+                compaction_batch_size: Some(3),
+                ..TenantConfOpt::default()
+            },
+            false,
+        )?;
+        assert_eq!(
+            Tenant::load_tenant_config(harness.conf, harness.tenant_id, true)?
+                .compaction_batch_size,
+            Some(3)
+        );
+
+        for gc_horizon in [2048, 4096] {
+            Tenant::persist_tenant_config(
+                &config_path,
+                TenantConfOpt {
+                    gc_horizon: Some(gc_horizon),
+                    ..TenantConfOpt::default()
+                },
+                false,
+            )?;
+        }
+
+        let content = fs::read_to_string(&config_path)?;
+        assert_eq!(
+            content
+                .matches("# This file contains a specific per-tenant's config.")
+                .count(),
+            1,
+            "unexpected config file content: {content}"
+        );
+        assert_eq!(content.matches("[tenant_config]").count(), 1);
+        assert_eq!(content.matches("# user comment").count(), 1);
+
+        let tenant_conf = Tenant::load_tenant_config(harness.conf, harness.tenant_id, true)?;
+        assert_eq!(
+            tenant_conf,
+            TenantConfOpt {
+                gc_horizon: Some(4096),
+                ..TenantConfOpt::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_tenant_config_unknown_keys() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_load_tenant_config_unknown_keys")?;
+        fs::write(
+            harness.conf.tenant_config_path(harness.tenant_id),
+            "unknown_option = 42\n\n[tenant_config]\ngc_horizon = 1024\n",
+        )?;
+
+        let tenant_conf = Tenant::load_tenant_config(harness.conf, harness.tenant_id, false)?;
+        assert_eq!(tenant_conf.gc_horizon, Some(1024));
+
+        let err = Tenant::load_tenant_config(harness.conf, harness.tenant_id, true)
+            .expect_err("strict mode should reject unknown keys");
+        assert!(err.to_string().contains("unknown_option"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_loaded_invalid_tenant_config() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_apply_loaded_invalid_tenant_config")?;
+        fs::write(
+            harness.conf.tenant_config_path(harness.tenant_id),
+            "[tenant_config]\ncompaction_threshold = 0\n",
+        )?;
+        let tenant = harness.load();
+
+        // A zero threshold is rejected by the updates,
+        // but the configs saved before the validation still get applied on attach
+        let tenant_conf = Tenant::load_tenant_config(harness.conf, harness.tenant_id, false)?;
+        assert!(tenant.update_tenant_config(tenant_conf).is_err());
+        assert_ne!(tenant.get_compaction_threshold(), 0);
+        tenant.apply_loaded_tenant_config(tenant_conf);
+        assert_eq!(tenant.get_compaction_threshold(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_initdb_cache_roundtrip() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_initdb_cache_roundtrip")?;
+        let cache_dir = harness.conf.initdb_cache_path();
+
+        let initdb_dir = harness.conf.workdir.join("initdb");
+        fs::create_dir_all(initdb_dir.join("global"))?;
+        fs::write(initdb_dir.join("PG_VERSION"), "14\n")?;
+        fs::write(initdb_dir.join("global").join("pg_control"), [1u8, 2, 3])?;
+
+        let outdated_cache_file = cache_dir.join("initdb-v14-0000000000000000.tar");
+        fs::create_dir_all(&cache_dir)?;
+        fs::write(&outdated_cache_file, "outdated")?;
+
+        let cache_file = cache_dir.join("initdb-v14-0123456789abcdef.tar");
+        save_initdb_cache(&cache_file, &initdb_dir, TIMELINE_ID)?;
+        assert!(cache_file.exists());
+        assert!(!outdated_cache_file.exists());
+
+        let restored_dir = harness.conf.workdir.join("restored");
+        unpack_initdb_cache(&cache_file, &restored_dir)?;
+        assert_eq!(fs::read_to_string(restored_dir.join("PG_VERSION"))?, "14\n");
+        assert_eq!(
+            fs::read(restored_dir.join("global").join("pg_control"))?,
+            vec![1u8, 2, 3]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_layerfile_as_json() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_dump_layerfile_as_json")?;
+        let tenant = harness.load();
+        let tline = create_timeline_with_layers(&tenant)?;
+
+        let layer_paths = tline
+            .layers
+            .read()
+            .unwrap()
+            .iter_historic_layers()
+            .filter_map(|layer| layer.local_path())
+            .collect::<Vec<_>>();
+        assert!(!layer_paths.is_empty());
+        for layer_path in layer_paths {
+            let dump = dump_layerfile_as_json(&layer_path, true, None)?;
+            assert!(
+                matches!(dump["kind"].as_str(), Some("image" | "delta")),
+                "unexpected dump: {dump}"
+            );
+            assert_eq!(dump["timeline_id"], TIMELINE_ID.to_string());
+            assert!(!dump["entries"].as_array().unwrap().is_empty());
+        }
+
+        let not_a_layer = harness.conf.workdir.join("not_a_layer");
+        fs::write(&not_a_layer, [0xde, 0xad, 0xbe, 0xef])?;
+        match dump_layerfile_as_json(&not_a_layer, false, None) {
+            Err(DumpLayerError::UnrecognizedMagic(magic)) => assert_eq!(magic, 0xdead),
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_layerfile_key_range() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_dump_layerfile_key_range")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let other_key = TEST_KEY.next();
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
+        writer.put(other_key, Lsn(0x10), &Value::Image(TEST_IMG("bar at 0x10")))?;
+        writer.finish_write(Lsn(0x10))?;
+        drop(writer);
+        tline.checkpoint(CheckpointConfig::Flush)?;
+
+        let layer_paths = tline
+            .layers
+            .read()
+            .unwrap()
+            .iter_historic_layers()
+            .filter_map(|layer| layer.local_path())
+            .collect::<Vec<_>>();
+        let dumped_keys = |key_range: Option<&Range<Key>>| -> anyhow::Result<Vec<String>> {
+            let mut keys = Vec::new();
+            for layer_path in &layer_paths {
+                let dump = dump_layerfile_as_json(layer_path, true, key_range)?;
+                for entry in dump["entries"].as_array().unwrap() {
+                    keys.push(entry["key"].as_str().unwrap().to_owned());
+                }
+            }
+            Ok(keys)
+        };
+
+        assert_eq!(
+            dumped_keys(None)?,
+            vec![TEST_KEY.to_string(), other_key.to_string()]
+        );
+        assert_eq!(
+            dumped_keys(Some(&(other_key..Key::MAX)))?,
+            vec![other_key.to_string()]
+        );
+        assert_eq!(
+            dumped_keys(Some(&(*TEST_KEY..other_key)))?,
+            vec![TEST_KEY.to_string()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_recover() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_try_recover")?;
+        {
+            let tenant = harness.load();
+            create_timeline_with_layers(&tenant)?;
+        }
+
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let metadata_path =
+            metadata::latest_metadata_path(&timeline_path)?.expect("timeline should have metadata");
+        let metadata_bytes = std::fs::read(&metadata_path)?;
+        // Leave no older generations to fall back to
+        metadata::remove_metadata(&timeline_path)?;
+        let mut corrupted_bytes = metadata_bytes.clone();
+        corrupted_bytes[8] ^= 0xff;
+        std::fs::write(&metadata_path, &corrupted_bytes)?;
+
+        // Emulate a tenant that failed to load on startup
+        let tenant = Tenant::new(
+            harness.conf,
+            TenantConfOpt::from(harness.tenant_conf),
+            Arc::new(TestRedoManager),
+            harness.tenant_id,
+            RemoteIndex::default(),
+            false,
+        );
+        tenant.set_state(TenantState::Broken);
+        tenant.activate(false);
+        assert_eq!(tenant.current_state(), TenantState::Broken);
+
+        assert!(tenant.try_recover().is_err());
+        assert_eq!(tenant.current_state(), TenantState::Broken);
+        assert!(tenant.get_timeline(TIMELINE_ID, false).is_err());
+
+        std::fs::write(&metadata_path, &metadata_bytes)?;
+        tenant.try_recover()?;
+        assert!(tenant.is_active());
+        let tline = tenant.get_timeline(TIMELINE_ID, true)?;
+        assert_eq!(
+            tline.get(*TEST_KEY, Lsn(0x50))?,
+            TEST_IMG(&format!("foo at {}", Lsn(0x50)))
+        );
+
+        assert!(
+            tenant.try_recover().is_err(),
+            "active tenant cannot be recovered"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cleanup_stale_temp_files() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_cleanup_stale_temp_files")?;
+        let tenant = harness.load();
+        tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        assert_eq!(tenant.cleanup_stale_temp_files()?, 0);
+
+        let tenant_path = harness.conf.tenant_path(&harness.tenant_id);
+        let timelines_path = harness.conf.timelines_path(&harness.tenant_id);
+        let timeline_path = harness.conf.timeline_path(&TIMELINE_ID, &harness.tenant_id);
+        let place_stale_artifacts = || -> anyhow::Result<()> {
+            fs::write(tenant_path.join(format!("config.{TEMP_FILE_SUFFIX}")), b"")?;
+            let basebackup_dir =
+                timelines_path.join(format!("basebackup-{NEW_TIMELINE_ID}.{TEMP_FILE_SUFFIX}"));
+            fs::create_dir(&basebackup_dir)?;
+            fs::write(basebackup_dir.join("PG_VERSION"), b"14")?;
+            fs::write(timeline_path.join(format!("layer.{TEMP_FILE_SUFFIX}")), b"")?;
+            Ok(())
+        };
+
+        place_stale_artifacts()?;
+        let uninit_mark_with_timeline = harness
+            .conf
+            .timeline_uninit_mark_file_path(harness.tenant_id, TIMELINE_ID);
+        fs::write(&uninit_mark_with_timeline, b"")?;
+        assert_eq!(tenant.cleanup_stale_temp_files()?, 3);
+        assert_eq!(tenant.cleanup_stale_temp_files()?, 0);
+        // Only the leftovers are removed
+        assert!(metadata::latest_metadata_path(&timeline_path)?.is_some());
+        assert!(uninit_mark_with_timeline.exists());
+        fs::remove_file(&uninit_mark_with_timeline)?;
+
+        // The leftovers don't prevent the tenant from loading
+        place_stale_artifacts()?;
+        drop(tenant);
+        let tenant = harness.try_load()?;
+        tenant.get_timeline(TIMELINE_ID, false)?;
+        assert_eq!(tenant.cleanup_stale_temp_files()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile_uninit_marks() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_reconcile_uninit_marks")?;
+        let tenant = harness.load();
+        tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        // Loaded timelines are never reconciled
+        let loaded_timeline_mark = harness
+            .conf
+            .timeline_uninit_mark_file_path(harness.tenant_id, TIMELINE_ID);
+        fs::write(&loaded_timeline_mark, b"")?;
+        assert!(tenant.reconcile_uninit_marks().is_err());
+        fs::remove_file(&loaded_timeline_mark)?;
+        assert_eq!(
+            tenant.reconcile_uninit_marks()?,
+            UninitMarkReconciliation::default()
+        );
+        drop(tenant);
+
+        // A crash in the middle of the timeline creation
+        let uninit_timeline_id = TimelineId::generate();
+        let uninit_timeline_dir = harness
+            .conf
+            .timeline_path(&uninit_timeline_id, &harness.tenant_id);
+        let uninit_timeline_mark = harness
+            .conf
+            .timeline_uninit_mark_file_path(harness.tenant_id, uninit_timeline_id);
+        fs::write(&uninit_timeline_mark, b"")?;
+        fs::create_dir(&uninit_timeline_dir)?;
+        fs::write(uninit_timeline_dir.join("PG_VERSION"), b"14")?;
+        // A crash after the timeline directory removal, before the mark one
+        let stale_mark = harness
+            .conf
+            .timeline_uninit_mark_file_path(harness.tenant_id, NEW_TIMELINE_ID);
+        fs::write(&stale_mark, b"")?;
+
+        let tenant = Tenant::new(
+            harness.conf,
+            TenantConfOpt::from(harness.tenant_conf),
+            Arc::new(TestRedoManager),
+            harness.tenant_id,
+            RemoteIndex::default(),
+            false,
+        );
+        assert_eq!(
+            tenant.reconcile_uninit_marks()?,
+            UninitMarkReconciliation {
+                removed_timelines: 1,
+                removed_marks: 1,
+            }
+        );
+        assert!(!uninit_timeline_dir.exists());
+        assert!(!uninit_timeline_mark.exists());
+        assert!(!stale_mark.exists());
+        // The complete timeline is left intact
+        assert!(metadata::latest_metadata_path(&harness.timeline_path(&TIMELINE_ID))?.is_some());
+        assert_eq!(
+            tenant.reconcile_uninit_marks()?,
+            UninitMarkReconciliation::default()
+        );
+        drop(tenant);
+
+        let tenant = harness.try_load()?;
+        tenant.get_timeline(TIMELINE_ID, false)?;
+        assert!(tenant.get_timeline(uninit_timeline_id, false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_info_snapshot() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_gc_info_snapshot")?;
+
+        let snapshot = tline.gc_info_snapshot();
+        assert_eq!(snapshot.horizon_cutoff, Lsn(0));
+        assert!(snapshot.retain_lsns.is_empty());
+
+        tline.update_gc_info(
+            vec![Lsn(0x30)],
+            Lsn(0x40),
+            Duration::ZERO,
+            GcCutoffPolicy::Min,
+        )?;
+        let snapshot = tline.gc_info_snapshot();
+        assert_eq!(snapshot, *tline.gc_info.read().unwrap());
+        assert_eq!(snapshot.horizon_cutoff, Lsn(0x40));
+        assert_eq!(snapshot.retain_lsns, vec![Lsn(0x30)]);
+
+        Ok(())
+    }
+
+    fn set_divergent_gc_cutoffs(tline: &Timeline, cutoff_policy: GcCutoffPolicy) {
+        *tline.gc_info.write().unwrap() = timeline::GcInfo {
+            retain_lsns: Vec::new(),
+            horizon_cutoff: Lsn(0x30),
+            pitr_cutoff: Lsn(0x48),
+            cutoff_policy,
+        };
+    }
+
+    #[test]
+    fn test_gc_cutoff_policy_branch_scope() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_gc_cutoff_policy_branch_scope")?;
+
+        // Between the horizon and PITR cutoffs: only needed by the horizon
+        set_divergent_gc_cutoffs(&tline, GcCutoffPolicy::Min);
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x38)))?;
+
+        set_divergent_gc_cutoffs(&tline, GcCutoffPolicy::Max);
+        let err = tenant
+            .branch_timeline(TIMELINE_ID, TimelineId::generate(), Some(Lsn(0x38)))
+            .expect_err("branching below the later cutoff should fail with the max policy");
+        assert!(
+            err.to_string()
+                .contains(&format!("less than planned GC cutoff {}", Lsn(0x48))),
+            "{err}"
+        );
+        tenant.branch_timeline(TIMELINE_ID, TimelineId::generate(), Some(Lsn(0x48)))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_cutoff_policy_layer_removal() -> anyhow::Result<()> {
+        let gc_with_policy = |cutoff_policy: GcCutoffPolicy| -> anyhow::Result<(Lsn, GcResult)> {
+            let test_name = match cutoff_policy {
+                GcCutoffPolicy::Min => "test_gc_cutoff_policy_layer_removal_min",
+                GcCutoffPolicy::Max => "test_gc_cutoff_policy_layer_removal_max",
+            };
+            let tenant = TenantHarness::create(test_name)?.load();
+            let tline = crate::pgdatadir_mapping::create_test_timeline(
+                &tenant,
+                TIMELINE_ID,
+                DEFAULT_PG_VERSION,
+            )?;
+            // Every image layer makes the layers before it obsolete
+            for lsn in [Lsn(0x20), Lsn(0x30), Lsn(0x40), Lsn(0x50)] {
+                let mut m = tline.begin_modification(lsn);
+                m.put_control_file(Bytes::from(format!("control file at {lsn}")))?;
+                m.put_checkpoint(Bytes::from(format!("checkpoint at {lsn}")))?;
+                m.commit()?;
+                tline.checkpoint(CheckpointConfig::Flush)?;
+                tline.force_create_image_layers(Key::MIN..Key::MAX, lsn)?;
+            }
+
+            set_divergent_gc_cutoffs(&tline, cutoff_policy);
+            let result = tline.gc(&CancellationToken::new())?;
+            let latest_gc_cutoff = *tline.get_latest_gc_cutoff_lsn();
+            Ok((latest_gc_cutoff, result))
+        };
+
+        // No image layer is between the layers ending before the horizon cutoff
+        // and the cutoff itself, so the min policy keeps them all
+        let (min_cutoff, min_result) = gc_with_policy(GcCutoffPolicy::Min)?;
+        assert_eq!(min_cutoff, Lsn(0x30));
+        assert_eq!(min_result.layers_removed, 0);
+
+        // The max policy uses the later PITR cutoff, so the layers covered by
+        // the images at 0x30 and 0x40 are removed
+        let (max_cutoff, max_result) = gc_with_policy(GcCutoffPolicy::Max)?;
+        assert_eq!(max_cutoff, Lsn(0x48));
+        assert!(max_result.layers_needed_by_cutoff < max_result.layers_total);
+        assert!(max_result.layers_removed > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_basebackup() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_export_basebackup")?.load();
+        let tline = crate::pgdatadir_mapping::create_test_timeline(
+            &tenant,
+            TIMELINE_ID,
+            DEFAULT_PG_VERSION,
+        )?;
+        let mut m = tline.begin_modification(Lsn(0x20));
+        m.put_control_file(postgres_ffi::v14::ControlFileData::default().encode())?;
+        m.put_checkpoint(postgres_ffi::v14::CheckPoint::default().encode()?)?;
+        m.commit()?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x20)))?;
+        let newtline = tenant.get_timeline(NEW_TIMELINE_ID, false)?;
+
+        let err = newtline
+            .export_basebackup_to_tar(Vec::new(), Lsn(0x10))
+            .expect_err("export below the ancestor LSN should fail");
+        assert!(err.to_string().contains("ancestor LSN"), "{err}");
+
+        let beyond_end = tline.get_last_record_lsn() + 0x10;
+        let err = tline
+            .export_basebackup_to_tar(Vec::new(), beyond_end)
+            .expect_err("export past the last record LSN should fail");
+        assert!(err.to_string().contains("last record LSN"), "{err}");
+
+        let mut tarball = Vec::new();
+        tline.export_basebackup_to_tar(&mut tarball, Lsn(0x20))?;
+        let mut archive = tar::Archive::new(tarball.as_slice());
+        let paths = archive
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().into_owned()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for expected in ["global/pg_control", "zenith.signal", "pg_hba.conf"] {
+            assert!(
+                paths.iter().any(|path| path == expected),
+                "{expected} is missing in {paths:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_on_transient_io_errors() -> anyhow::Result<()> {
+        // Fails with the given errors first, then succeeds
+        fn faulty_operation(
+            errors: Vec<io::ErrorKind>,
+        ) -> (Arc<AtomicUsize>, impl FnMut(u32) -> anyhow::Result<u32>) {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let calls_clone = Arc::clone(&calls);
+            let operation = move |attempt| {
+                let call = calls_clone.fetch_add(1, Ordering::Relaxed);
+                assert_eq!(attempt as usize, call + 1);
+                match errors.get(call) {
+                    Some(kind) => {
+                        Err(io::Error::new(*kind, "injected fault")).context("faulty operation")
+                    }
+                    None => Ok(attempt),
+                }
+            };
+            (calls, operation)
+        }
+
+        let (calls, operation) =
+            faulty_operation(vec![io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock]);
+        assert_eq!(retry_on_transient_io_errors("test", operation)?, 3);
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+        for kind in [
+            io::ErrorKind::AlreadyExists,
+            io::ErrorKind::PermissionDenied,
+        ] {
+            let (calls, operation) = faulty_operation(vec![kind]);
+            let err = retry_on_transient_io_errors("test", operation).unwrap_err();
+            assert_eq!(err.root_cause().to_string(), "injected fault");
+            assert_eq!(
+                calls.load(Ordering::Relaxed),
+                1,
+                "{kind:?} should fail fast"
+            );
+        }
+
+        let (calls, operation) = faulty_operation(vec![
+            io::ErrorKind::Interrupted;
+            TRANSIENT_IO_ERROR_MAX_ATTEMPTS as usize + 1
+        ]);
+        assert!(retry_on_transient_io_errors("test", operation).is_err());
+        assert_eq!(
+            calls.load(Ordering::Relaxed),
+            TRANSIENT_IO_ERROR_MAX_ATTEMPTS as usize
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_layer_map_stats() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_layer_map_stats")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let stats = tline.layer_map_stats();
+        assert_eq!(stats.image_layers + stats.delta_layers, 0);
+        assert_eq!(stats.covered_keys, 0);
+        assert_eq!(stats.lsn_range, None);
+        assert_eq!(stats.max_delta_depth, 0);
+
+        // Each checkpoint creates an L0 delta layer, covering the whole keyspace
+        make_some_layers(tline.as_ref(), Lsn(0x20))?;
+        let stats = tline.layer_map_stats();
+        assert_eq!(stats.image_layers, 0);
+        assert_eq!(stats.delta_layers, 2);
+        assert_eq!(stats.max_delta_depth, 2);
+        assert_eq!(
+            stats.covered_keys,
+            (Key::MAX.to_i128() - Key::MIN.to_i128()) as u128
+        );
+        let lsn_range = stats.lsn_range.expect("timeline should have layers");
+        assert!(lsn_range.start <= Lsn(0x20), "{lsn_range:?}");
+        assert!(lsn_range.end > Lsn(0x50), "{lsn_range:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeline_infos() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_timeline_infos")?.load();
+        assert!(tenant.timeline_infos().is_empty());
+
+        let tline = create_timeline_with_layers(&tenant)?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+        let newtline = tenant.get_timeline(NEW_TIMELINE_ID, false)?;
+
+        let infos = tenant.timeline_infos();
+        let expected = [&tline, &newtline]
+            .into_iter()
+            .map(|timeline| TimelineInfo {
+                timeline_id: timeline.timeline_id,
+                ancestor_timeline_id: timeline.get_ancestor_timeline_id(),
+                ancestor_lsn: timeline.get_ancestor_lsn(),
+                last_record_lsn: timeline.get_last_record_lsn(),
+                disk_consistent_lsn: timeline.get_disk_consistent_lsn(),
+                state: timeline.current_state(),
+                physical_size: timeline.get_physical_size(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(infos, expected);
+
+        assert_eq!(infos[0].ancestor_timeline_id, None);
+        assert!(infos[0].physical_size > 0);
+        assert_eq!(infos[1].ancestor_timeline_id, Some(TIMELINE_ID));
+        assert_eq!(infos[1].ancestor_lsn, Lsn(0x40));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_timeline_for_lsn() -> anyhow::Result<()> {
+        let (tenant, _) = tenant_with_layers("test_find_timeline_for_lsn")?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+        let newtline = tenant.get_timeline(NEW_TIMELINE_ID, true)?;
+        make_some_layers(newtline.as_ref(), Lsn(0x60))?;
+
+        let found_ids = |lsn| {
+            tenant
+                .find_timeline_for_lsn_all(lsn)
+                .into_iter()
+                .map(|(timeline_id, _)| timeline_id)
+                .collect::<Vec<_>>()
+        };
+
+        // Only the parent covers the LSNs before the branch point
+        assert_eq!(found_ids(Lsn(0x30)), vec![TIMELINE_ID]);
+        // Both cover the branch point, the child is deeper
+        assert_eq!(found_ids(Lsn(0x40)), vec![NEW_TIMELINE_ID, TIMELINE_ID]);
+        assert_eq!(
+            tenant
+                .find_timeline_for_lsn(Lsn(0x50))
+                .map(|(timeline_id, _)| timeline_id),
+            Some(NEW_TIMELINE_ID)
+        );
+        // Only the child has WAL that far
+        assert_eq!(found_ids(Lsn(0x90)), vec![NEW_TIMELINE_ID]);
+        assert!(tenant.find_timeline_for_lsn(Lsn(0x1000)).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_progress() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_checkpoint_progress")?;
+
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x60), &Value::Image(TEST_IMG("foo at 0x60")))?;
+        writer.finish_write(Lsn(0x60))?;
+        drop(writer);
+
+        let mut progress = Vec::new();
+        tenant
+            .checkpoint(|timeline_id, flushed_bytes| progress.push((timeline_id, flushed_bytes)))?;
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].0, TIMELINE_ID);
+        assert!(progress[0].1 > 0);
+
+        // Nothing left in memory, nothing to flush
+        progress.clear();
+        tenant
+            .checkpoint(|timeline_id, flushed_bytes| progress.push((timeline_id, flushed_bytes)))?;
+        assert_eq!(progress, vec![(TIMELINE_ID, 0)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_sort_timelines_cycle() -> anyhow::Result<()> {
+        let metadata_with_ancestor = |ancestor_id| {
+            TimelineMetadata::new(
+                Lsn(0x20),
+                None,
+                Some(ancestor_id),
+                Lsn(0x10),
+                Lsn(0),
+                Lsn(0),
+                DEFAULT_PG_VERSION,
+            )
+        };
+        let root_metadata = TimelineMetadata::new(
+            Lsn(0x20),
+            None,
+            None,
+            Lsn(0),
+            Lsn(0),
+            Lsn(0),
+            DEFAULT_PG_VERSION,
+        );
+        let root_id = TimelineId::generate();
+        let orphan_id = TimelineId::generate();
+
+        let mut timelines = HashMap::new();
+        timelines.insert(root_id, root_metadata.clone());
+        timelines.insert(orphan_id, metadata_with_ancestor(TimelineId::generate()));
+        let err = tree_sort_timelines(timelines).expect_err("missing ancestor should fail");
+        assert!(err.to_string().contains("missing ancestors"), "{err}");
+
+        let mut timelines = HashMap::new();
+        timelines.insert(root_id, root_metadata);
+        timelines.insert(TIMELINE_ID, metadata_with_ancestor(NEW_TIMELINE_ID));
+        timelines.insert(NEW_TIMELINE_ID, metadata_with_ancestor(TIMELINE_ID));
+        let err = tree_sort_timelines(timelines).expect_err("cycle should fail");
+        let err = err.to_string();
+        assert!(err.contains("cycle"), "{err}");
+        assert!(!err.contains("missing ancestors"), "{err}");
+        assert!(err.contains(&TIMELINE_ID.to_string()), "{err}");
+        assert!(err.contains(&NEW_TIMELINE_ID.to_string()), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_initdb_params_validation() {
+        InitdbParams::default().validate().unwrap();
+        for encoding in [
+            "utf8",
+            "UTF-8",
+            "SQL_ASCII",
+            "latin1",
+            "ISO_8859_5",
+            "WIN1251",
+        ] {
+            InitdbParams {
+                encoding: Some(encoding.to_string()),
+                locale: Some("en_US.UTF-8".to_string()),
+                superuser_override: Some("admin".to_string()),
+            }
+            .validate()
+            .unwrap();
+        }
+
+        for encoding in ["", "utf16", "SJIS", "-Dutf8"] {
+            let params = InitdbParams {
+                encoding: Some(encoding.to_string()),
+                ..InitdbParams::default()
+            };
+            let err = params.validate().unwrap_err();
+            assert!(err.to_string().contains("unsupported encoding"), "{err}");
+        }
+        for locale in ["", "--no-sync", "en_US UTF-8"] {
+            let params = InitdbParams {
+                locale: Some(locale.to_string()),
+                ..InitdbParams::default()
+            };
+            params.validate().unwrap_err();
+        }
+        InitdbParams {
+            superuser_override: Some("-D".to_string()),
+            ..InitdbParams::default()
+        }
+        .validate()
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_initdb_failure_output() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let harness = TenantHarness::create("test_initdb_failure_output")?;
+        let pg_distrib_dir = tempfile::tempdir()?;
+        let mut conf = harness.conf.clone();
+        conf.pg_distrib_dir = pg_distrib_dir.path().to_path_buf();
+        let conf: &'static PageServerConf = Box::leak(Box::new(conf));
+
+        let initdb_bin_dir = conf.pg_bin_dir(DEFAULT_PG_VERSION)?;
+        fs::create_dir_all(&initdb_bin_dir)?;
+        let initdb_path = initdb_bin_dir.join("initdb");
+        fs::write(
+            &initdb_path,
+            "#!/bin/sh\necho 'initdb stdout'\necho 'initdb stderr' >&2\nexit 3\n",
+        )?;
+        fs::set_permissions(&initdb_path, fs::Permissions::from_mode(0o755))?;
+
+        let err = run_initdb(
+            conf,
+            &harness.conf.workdir.join("initdb_target"),
+            DEFAULT_PG_VERSION,
+            &InitdbParams::default(),
+        )
+        .await
+        .expect_err("failing initdb should fail the run");
+        let initdb_error = err
+            .downcast_ref::<InitdbError>()
+            .expect("initdb failure should be reported as InitdbError");
+        assert_eq!(initdb_error.exit_code, Some(3));
+        assert_eq!(initdb_error.stdout.trim(), "initdb stdout");
+        assert_eq!(initdb_error.stderr.trim(), "initdb stderr");
+
+        Ok(())
+    }
+
+    /// Returns a config whose `initdb` copies a minimal shut down cluster: a control file
+    /// with its checkpoint right after the first WAL page header, followed by a single record.
+    fn fake_initdb_conf(harness: &TenantHarness) -> anyhow::Result<&'static PageServerConf> {
+        use postgres_ffi::v14::xlog_utils::{generate_wal_segment, XLOG_SIZE_OF_XLOG_LONG_PHD};
+        use postgres_ffi::{encode_logical_message, XLogFileName, PG_TLI, WAL_SEGMENT_SIZE};
+        use std::os::unix::fs::PermissionsExt;
+
+        const SYSTEM_ID: u64 = 42;
+        let cluster_dir = harness.conf.workdir.join("initdb_cluster");
+        fs::create_dir_all(cluster_dir.join("global"))?;
+        fs::create_dir_all(cluster_dir.join("pg_wal"))?;
+
+        let checkpoint_lsn = WAL_SEGMENT_SIZE as u64 + XLOG_SIZE_OF_XLOG_LONG_PHD as u64;
+        let mut control_file = postgres_ffi::v14::ControlFileData {
+            system_identifier: SYSTEM_ID,
+            state: postgres_ffi::v14::DBState_DB_SHUTDOWNED,
+            checkPoint: checkpoint_lsn,
+            ..Default::default()
+        };
+        control_file.checkPointCopy.redo = checkpoint_lsn;
+        fs::write(cluster_dir.join("global/pg_control"), control_file.encode())?;
+
+        let mut wal_segment = generate_wal_segment(1, SYSTEM_ID)?.to_vec();
+        let record = encode_logical_message("test", "init hook");
+        wal_segment[XLOG_SIZE_OF_XLOG_LONG_PHD..XLOG_SIZE_OF_XLOG_LONG_PHD + record.len()]
+            .copy_from_slice(&record);
+        fs::write(
+            cluster_dir
+                .join("pg_wal")
+                .join(XLogFileName(PG_TLI, 1, WAL_SEGMENT_SIZE)),
+            wal_segment,
+        )?;
+
+        let mut conf = harness.conf.clone();
+        conf.pg_distrib_dir = harness.conf.workdir.join("pg_distrib");
+        let conf: &'static PageServerConf = Box::leak(Box::new(conf));
+        let initdb_bin_dir = conf.pg_bin_dir(DEFAULT_PG_VERSION)?;
+        fs::create_dir_all(&initdb_bin_dir)?;
+        let initdb_path = initdb_bin_dir.join("initdb");
+        // Called as `initdb -D <target dir> ...`
+        fs::write(
+            &initdb_path,
+            format!("#!/bin/sh\ncp -R '{}' \"$2\"\n", cluster_dir.display()),
+        )?;
+        fs::set_permissions(&initdb_path, fs::Permissions::from_mode(0o755))?;
+        Ok(conf)
+    }
+
+    #[tokio::test]
+    async fn test_timeline_init_hook() -> anyhow::Result<()> {
+        let mut harness = TenantHarness::create("test_timeline_init_hook")?;
+        harness.conf = fake_initdb_conf(&harness)?;
+        let tenant = Arc::new(harness.load());
+
+        let hook_lsn = Arc::new(Mutex::new(None));
+        let init_hook: TimelineInitHook = Box::new({
+            let hook_lsn = Arc::clone(&hook_lsn);
+            move |timeline: &Timeline| {
+                // The hook sees the imported data
+                timeline.get_control_file(timeline.get_last_record_lsn())?;
+                *hook_lsn.lock().unwrap() = Some(timeline.get_last_record_lsn());
+                Ok(())
+            }
+        });
+        let tline = tenant
+            .create_timeline(
+                Some(TIMELINE_ID),
+                None,
+                None,
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                Some(init_hook),
+                None,
+            )
+            .await?;
+        assert_eq!(*hook_lsn.lock().unwrap(), Some(tline.get_last_record_lsn()));
+        assert!(tenant.get_timeline(TIMELINE_ID, false).is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failing_timeline_init_hook() -> anyhow::Result<()> {
+        let mut harness = TenantHarness::create("test_failing_timeline_init_hook")?;
+        harness.conf = fake_initdb_conf(&harness)?;
+        let tenant = Arc::new(harness.load());
+
+        let err = tenant
+            .create_timeline(
+                Some(TIMELINE_ID),
+                None,
+                None,
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                Some(Box::new(|_: &Timeline| -> anyhow::Result<()> {
+                    anyhow::bail!("init hook failure")
+                })),
+                None,
+            )
+            .await
+            .expect_err("failing init hook should fail the timeline creation");
+        let err = format!("{err:#}");
+        assert!(err.contains("Failed to run init hook"), "{err}");
+        assert!(err.contains("init hook failure"), "{err}");
+
+        assert!(matches!(
+            tenant.get_timeline(TIMELINE_ID, false),
+            Err(TenantError::NotFound(_))
+        ));
+        assert!(!harness
+            .conf
+            .timeline_path(&TIMELINE_ID, &tenant.tenant_id)
+            .exists());
+        assert!(!harness
+            .conf
+            .timeline_uninit_mark_file_path(tenant.tenant_id, TIMELINE_ID)
+            .exists());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_import_basebackup_from_async_reader() -> anyhow::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        // Streams the archive in small chunks, as it would come from the network
+        fn stream_archive(
+            archive: Vec<u8>,
+        ) -> (
+            tokio::io::DuplexStream,
+            tokio::task::JoinHandle<std::io::Result<()>>,
+        ) {
+            let (mut writer, reader) = tokio::io::duplex(64);
+            let write_task = tokio::spawn(async move {
+                for chunk in archive.chunks(100) {
+                    writer.write_all(chunk).await?;
+                }
+                writer.shutdown().await
+            });
+            (reader, write_task)
+        }
+
+        let tenant = TenantHarness::create("test_import_basebackup_from_async_reader")?.load();
+
+        let build_archive = |pg_control: Option<&[u8]>| -> anyhow::Result<Vec<u8>> {
+            let mut archive = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            archive.append_data(&mut header, "global", std::io::empty())?;
+            if let Some(pg_control) = pg_control {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(pg_control.len() as u64);
+                archive.append_data(&mut header, "global/pg_control", pg_control)?;
+            }
+            Ok(archive.into_inner()?)
+        };
+        let pg_control = postgres_ffi::v14::ControlFileData::default().encode();
+
+        // The whole archive is read, but it has no pg_control
+        let timeline = tenant.create_empty_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION)?;
+        let (reader, write_task) = stream_archive(build_archive(None)?);
+        let err = timeline
+            .import_basebackup_from_tar_async(reader, Lsn(0x10))
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("pg_control file not found"),
+            "{err:#}"
+        );
+        write_task.await??;
+        drop(timeline);
+
+        let timeline = tenant.create_empty_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION)?;
+        let (reader, write_task) = stream_archive(build_archive(Some(&pg_control))?);
+        timeline
+            .import_basebackup_from_tar_async(reader, Lsn(0x10))
+            .await?;
+        write_task.await??;
+        let tline = timeline.initialize()?;
+        assert_eq!(tline.get_control_file(Lsn(0x10))?, pg_control);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_basebackup_import() -> anyhow::Result<()> {
+        // Cancels the import once the archive is read past `cancel_at`
+        struct CancellingReader {
+            inner: std::io::Cursor<Vec<u8>>,
+            cancel_at: u64,
+            cancel: CancellationToken,
+        }
+
+        impl std::io::Read for CancellingReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.inner.position() >= self.cancel_at {
+                    self.cancel.cancel();
+                }
+                self.inner.read(buf)
+            }
+        }
+
+        let tenant = TenantHarness::create("test_cancel_basebackup_import")?.load();
+        let timeline_path = tenant.conf.timeline_path(&TIMELINE_ID, &tenant.tenant_id);
+        let timeline = tenant.create_empty_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION)?;
+
+        let mut archive = tar::Builder::new(Vec::new());
+        for dir in ["global", "base", "pg_xact", "pg_multixact", "pg_twophase"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            archive.append_data(&mut header, dir, std::io::empty())?;
+        }
+        let archive = archive.into_inner()?;
+
+        let cancel = CancellationToken::new();
+        let reader = CancellingReader {
+            inner: std::io::Cursor::new(archive),
+            cancel_at: 1024,
+            cancel: cancel.clone(),
+        };
+        let err = timeline
+            .import_basebackup_from_tar_cancellable(reader, Lsn(0x10), cancel)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("cancelled"), "{err:#}");
+
+        // Dropping the timeline cleans up the partial import
+        assert!(timeline_path.exists());
+        drop(timeline);
+        assert!(!timeline_path.exists());
+        assert!(!tenant
+            .conf
+            .timeline_uninit_mark_file_path(tenant.tenant_id, TIMELINE_ID)
+            .exists());
+        assert!(tenant.get_timeline(TIMELINE_ID, false).is_err());
+        assert!(tenant.list_timelines().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_create_same_timeline() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_concurrent_create_same_timeline")?;
+        let tenant = Arc::new(harness.load());
+        create_timeline_with_layers(&tenant)?;
+
+        let creations = (0..16)
+            .map(|_| {
+                let tenant = Arc::clone(&tenant);
+                tokio::spawn(async move {
+                    tenant
+                        .try_create_timeline(
+                            Some(NEW_TIMELINE_ID),
+                            Some(TIMELINE_ID),
+                            None,
+                            DEFAULT_PG_VERSION,
+                            InitdbParams::default(),
+                            None,
+                            None,
+                        )
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut created = Vec::new();
+        for creation in creations {
+            match creation.await? {
+                Ok(TimelineCreateResult::Created(timeline)) => created.push(timeline),
+                Ok(TimelineCreateResult::AlreadyExists(_)) | Err(TenantError::AlreadyExists(_)) => {
+                }
+                Err(e) => panic!("unexpected timeline creation error: {e:?}"),
+            }
+        }
+        assert_eq!(created.len(), 1, "exactly one creation should succeed");
+
+        let new_tline = tenant.get_timeline(NEW_TIMELINE_ID, false)?;
+        assert!(Arc::ptr_eq(&new_tline, &created[0]));
+        assert!(!harness
+            .conf
+            .timeline_uninit_mark_file_path(tenant.tenant_id, NEW_TIMELINE_ID)
+            .exists());
+        assert!(metadata::latest_metadata_path(
+            &harness
+                .conf
+                .timeline_path(&NEW_TIMELINE_ID, &tenant.tenant_id)
+        )?
+        .is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_existing_timeline() -> anyhow::Result<()> {
+        let tenant = Arc::new(TenantHarness::create("test_create_existing_timeline")?.load());
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+
+        match tenant
+            .try_create_timeline(
+                Some(TIMELINE_ID),
+                None,
+                None,
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+            .await?
+        {
+            TimelineCreateResult::AlreadyExists(existing) => {
+                assert!(Arc::ptr_eq(&existing, &tline))
+            }
+            TimelineCreateResult::Created(_) => panic!("existing timeline should not be created"),
+        }
+        assert!(matches!(
+            tenant
+                .create_timeline(
+                    Some(TIMELINE_ID),
+                    None,
+                    None,
+                    DEFAULT_PG_VERSION,
+                    InitdbParams::default(),
+                    None,
+                    None,
+                )
+                .await,
+            Err(TenantError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            tenant.get_timeline(NEW_TIMELINE_ID, false),
+            Err(TenantError::NotFound(_))
+        ));
+        assert!(matches!(
+            tenant.delete_timeline(NEW_TIMELINE_ID),
+            Err(TenantError::NotFound(_))
+        ));
+
+        tline.set_state(TimelineState::Paused);
+        assert!(matches!(
+            tenant.get_timeline(TIMELINE_ID, true),
+            Err(TenantError::NotActive(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_timeline_unsupported_pg_version() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_create_timeline_unsupported_pg_version")?;
+        let tenant = Arc::new(harness.load());
+        create_timeline_with_layers(&tenant)?;
+
+        let err = tenant
+            .create_timeline(
+                Some(NEW_TIMELINE_ID),
+                None,
+                None,
+                99,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+            .await
+            .expect_err("Should not bootstrap a timeline of an unconfigured version");
+        // The test config has no postgres installed
+        assert!(
+            matches!(
+                &err,
+                TenantError::UnsupportedPgVersion {
+                    pg_version: 99,
+                    available,
+                } if available.is_empty()
+            ),
+            "{err:?}"
+        );
+        assert_eq!(err.to_string(), "unsupported pg_version 99, available: []");
+        assert!(!harness
+            .conf
+            .timeline_uninit_mark_file_path(tenant.tenant_id, NEW_TIMELINE_ID)
+            .exists());
+        assert!(!harness.timeline_path(&NEW_TIMELINE_ID).exists());
+
+        // Branches don't run initdb, the version is not checked for them
+        let new_tline = tenant
+            .create_timeline(
+                Some(NEW_TIMELINE_ID),
+                Some(TIMELINE_ID),
+                None,
+                99,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+            .await?;
+        assert_eq!(new_tline.pg_version, DEFAULT_PG_VERSION);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_timelines_from_manifest() -> anyhow::Result<()> {
+        let (tenant, _) = tenant_with_layers("test_create_timelines_from_manifest")?;
+        let tenant = Arc::new(tenant);
+        let ids = (0..6).map(|_| TimelineId::generate()).collect::<Vec<_>>();
+        let spec = |id: usize, ancestor: TimelineId, start_lsn: Option<Lsn>| BranchSpec {
+            id: ids[id],
+            ancestor,
+            start_lsn,
+        };
+
+        // Invalid manifests create nothing
+        for (invalid_manifest, expected_err) in [
+            (
+                vec![spec(0, TIMELINE_ID, None), spec(0, TIMELINE_ID, None)],
+                "more than once",
+            ),
+            (
+                vec![spec(0, TIMELINE_ID, None), spec(1, ids[5], None)],
+                "was not found",
+            ),
+            (
+                vec![
+                    spec(0, TIMELINE_ID, None),
+                    spec(1, ids[2], None),
+                    spec(2, ids[1], None),
+                ],
+                "ancestor cycle",
+            ),
+        ] {
+            let err = tenant
+                .create_timelines_from_manifest(invalid_manifest)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains(expected_err), "{err}");
+            assert!(tenant.get_timeline(ids[0], false).is_err());
+        }
+        let err = tenant
+            .create_timelines_from_manifest(vec![BranchSpec {
+                id: TIMELINE_ID,
+                ancestor: TIMELINE_ID,
+                start_lsn: None,
+            }])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err}");
+
+        // Children listed before their ancestors are created after them
+        let created = tenant
+            .create_timelines_from_manifest(vec![
+                spec(1, ids[0], None),
+                spec(0, TIMELINE_ID, Some(Lsn(0x40))),
+                spec(2, TIMELINE_ID, None),
+            ])
+            .await?;
+        let created_ids = created
+            .iter()
+            .map(|timeline| timeline.timeline_id)
+            .collect::<Vec<_>>();
+        assert_eq!(created_ids, vec![ids[0], ids[1], ids[2]]);
+        assert_eq!(created[0].get_ancestor_lsn(), Lsn(0x40));
+        assert_eq!(created[1].get_ancestor_timeline_id(), Some(ids[0]));
+        assert_eq!(created[1].get_ancestor_lsn(), Lsn(0x40));
+        assert_eq!(created[2].get_ancestor_lsn(), Lsn(0x50));
+
+        // The branches created before the failure are kept
+        let err = tenant
+            .create_timelines_from_manifest(vec![
+                spec(3, ids[0], None),
+                // Before the ancestor LSN of its ancestor
+                spec(4, ids[3], Some(Lsn(0x30))),
+                spec(5, ids[4], None),
+            ])
+            .await
+            .unwrap_err();
+        let manifest_err = err
+            .downcast_ref::<ManifestBranchError>()
+            .unwrap_or_else(|| panic!("unexpected error: {err:?}"));
+        assert_eq!(manifest_err.failed, ids[4]);
+        assert_eq!(manifest_err.created, vec![ids[3]]);
+        assert_eq!(manifest_err.remaining, vec![ids[4], ids[5]]);
+        tenant.get_timeline(ids[3], true)?;
+        assert!(tenant.get_timeline(ids[4], false).is_err());
+        assert!(tenant.get_timeline(ids[5], false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_batch_rotation() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_compaction_batch_rotation")?.load();
+        let mut timeline_ids = Vec::new();
+        for _ in 0..5 {
+            let timeline_id = TimelineId::generate();
+            tenant
+                .create_empty_timeline(timeline_id, Lsn(0), DEFAULT_PG_VERSION)?
+                .initialize()?;
+            timeline_ids.push(timeline_id);
+        }
+        timeline_ids.sort();
+
+        let next_batch = || {
+            tenant
+                .next_compaction_batch(
+                    tenant
+                        .list_timelines()
+                        .into_iter()
+                        .map(|timeline| (timeline.timeline_id, timeline))
+                        .collect(),
+                )
+                .into_iter()
+                .map(|(timeline_id, _)| timeline_id)
+                .collect::<Vec<_>>()
+        };
+
+        // All timelines by default
+        assert_eq!(next_batch().len(), 5);
+
+        tenant.update_tenant_config(TenantConfOpt {
+            compaction_batch_size: Some(2),
+            ..TenantConfOpt::default()
+        })?;
+        assert_eq!(next_batch(), timeline_ids[0..2]);
+        assert_eq!(next_batch(), timeline_ids[2..4]);
+        assert_eq!(next_batch(), vec![timeline_ids[4], timeline_ids[0]]);
+        assert_eq!(next_batch(), timeline_ids[1..3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_background_task_flags() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_background_task_flags")?.load();
+        assert!(!tenant.should_run_gc());
+        assert!(!tenant.should_run_compaction());
+
+        tenant.set_state(TenantState::Paused);
+        assert!(matches!(
+            tenant.set_gc_enabled(true),
+            Err(TenantError::NotActive(_))
+        ));
+        assert!(matches!(
+            tenant.set_compaction_enabled(true),
+            Err(TenantError::NotActive(_))
+        ));
+        assert_eq!(tenant.current_state(), TenantState::Paused);
+
+        tenant.activate(false);
+        tenant.set_compaction_enabled(false)?;
+        tenant.set_gc_enabled(false)?;
+        assert_eq!(
+            tenant.current_state(),
+            TenantState::Active {
+                gc_enabled: false,
+                compaction_enabled: false,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_timeline_keeps_paused_gc() -> anyhow::Result<()> {
+        let (tenant, _) = tenant_with_layers("test_create_timeline_keeps_paused_gc")?;
+        let tenant = Arc::new(tenant);
+        let create_branch = |timeline_id| {
+            tenant.create_timeline(
+                Some(timeline_id),
+                Some(TIMELINE_ID),
+                None,
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+        };
+
+        // Activated without the background jobs, the created timeline enables them
+        tenant.activate(false);
+        create_branch(NEW_TIMELINE_ID).await?;
+        assert!(tenant.should_run_gc());
+        assert!(tenant.should_run_compaction());
+
+        tenant.set_gc_enabled(false)?;
+        create_branch(TimelineId::generate()).await?;
+        assert!(!tenant.should_run_gc());
+        assert!(tenant.should_run_compaction());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_timeline_state() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_wait_for_timeline_state")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        assert_eq!(tline.current_state(), TimelineState::Suspended);
+
+        tline
+            .wait_for_state(TimelineState::Suspended, Duration::ZERO)
+            .await?;
+        assert!(tline
+            .wait_for_state(TimelineState::Active, Duration::from_millis(10))
+            .await
+            .is_err());
+
+        let activate = tokio::spawn({
+            let tline = Arc::clone(&tline);
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                tline.set_state(TimelineState::Active);
+            }
+        });
+        tline
+            .wait_for_state(TimelineState::Active, Duration::from_secs(10))
+            .await?;
+        activate.await?;
+
+        let break_timeline = tokio::spawn({
+            let tline = Arc::clone(&tline);
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                tline.set_state(TimelineState::Broken);
+            }
+        });
+        let err = tline
+            .wait_for_state(TimelineState::Suspended, Duration::from_secs(10))
+            .await
+            .expect_err("waiting should stop once the timeline is broken");
+        assert!(err.to_string().contains("became broken"), "{err}");
+        break_timeline.await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_attach_progress() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_attach_progress")?;
+        let tenant = harness.load();
+        assert_eq!(
+            tenant.attach_progress(),
+            AttachProgress {
+                done: true,
+                ..AttachProgress::default()
+            }
+        );
+        tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        drop(tenant);
+
+        let tenant = harness.load();
+        assert_eq!(
+            tenant.attach_progress(),
+            AttachProgress {
+                total: 1,
+                completed: 1,
+                failed: 0,
+                done: true,
+            }
+        );
+        drop(tenant);
+
+        // A timeline without local files fails to initialize, but does not stop the attach
+        let metadata = load_metadata(harness.conf, TIMELINE_ID, harness.tenant_id)?;
+        let tenant = Tenant::new(
+            harness.conf,
+            TenantConfOpt::from(harness.tenant_conf),
+            Arc::new(TestRedoManager),
+            harness.tenant_id,
+            RemoteIndex::default(),
+            false,
+        );
+        tenant.init_attach_timelines(HashMap::from([
+            (TIMELINE_ID, metadata.clone()),
+            (NEW_TIMELINE_ID, metadata),
+        ]))?;
+        assert_eq!(
+            tenant.attach_progress(),
+            AttachProgress {
+                total: 2,
+                completed: 1,
+                failed: 1,
+                done: true,
+            }
+        );
+        assert_eq!(
+            tenant.get_timeline(NEW_TIMELINE_ID, false)?.current_state(),
+            TimelineState::Broken
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_timelines_during_attach() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_list_timelines_during_attach")?;
+        let mut timeline_ids = vec![TIMELINE_ID];
+        {
+            let tenant = harness.load();
+            let tline = create_timeline_with_layers(&tenant)?;
+            for _ in 0..8 {
+                let branch_id = TimelineId::generate();
+                tenant.branch_timeline(TIMELINE_ID, branch_id, Some(Lsn(0x40)))?;
+                timeline_ids.push(branch_id);
+            }
+        }
+        let mut timelines = timeline_ids
+            .iter()
+            .map(|&timeline_id| {
+                load_metadata(harness.conf, timeline_id, harness.tenant_id)
+                    .map(|metadata| (timeline_id, metadata))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+        // No local files, fails to initialize and gets inserted as broken
+        let broken_id = TimelineId::generate();
+        let broken_metadata = timelines[&TIMELINE_ID].clone();
+        timelines.insert(broken_id, broken_metadata);
+        timeline_ids.push(broken_id);
+        timeline_ids.sort();
+
+        let tenant = Arc::new(Tenant::new(
+            harness.conf,
+            TenantConfOpt::from(harness.tenant_conf),
+            Arc::new(TestRedoManager),
+            harness.tenant_id,
+            RemoteIndex::default(),
+            false,
+        ));
+        let lister = {
+            let tenant = Arc::clone(&tenant);
+            std::thread::spawn(move || {
+                let mut snapshots = Vec::new();
+                loop {
+                    let attach_done = tenant.attach_progress().done;
+                    snapshots.push(
+                        tenant
+                            .list_timelines()
+                            .iter()
+                            .map(|tline| (tline.timeline_id, tline.current_state()))
+                            .collect::<Vec<_>>(),
+                    );
+                    if attach_done {
+                        break snapshots;
+                    }
+                }
+            })
+        };
+        tenant.init_attach_timelines(timelines)?;
+        let snapshots = lister.join().unwrap();
+
+        // Listing sees either none or all of the timelines, each one either active or broken
+        for snapshot in &snapshots {
+            if snapshot.is_empty() {
+                continue;
+            }
+            let listed_ids = snapshot.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+            assert_eq!(listed_ids, timeline_ids);
+            for (timeline_id, state) in snapshot {
+                let expected_state = if *timeline_id == broken_id {
+                    TimelineState::Broken
+                } else {
+                    TimelineState::Active
+                };
+                assert_eq!(*state, expected_state, "timeline {timeline_id}");
+            }
+        }
+        assert_eq!(
+            snapshots.last().map(Vec::len),
+            Some(timeline_ids.len()),
+            "The last listing happens after the attach"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_attached_timeline_inserted_once() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_attached_timeline_inserted_once")?;
+        {
+            let tenant = harness.load();
+            create_timeline_with_layers(&tenant)?;
+        }
+
+        let tenant = harness.load();
+        let attached = tenant.get_timeline(TIMELINE_ID, false)?;
+        // Held by the tenant map and by us only
+        assert_eq!(Arc::strong_count(&attached), 2);
+
+        // Initializing the same timeline again is refused, the attached one stays in the map
+        let metadata = load_metadata(harness.conf, TIMELINE_ID, harness.tenant_id)?;
+        let duplicate = UninitializedTimeline {
+            owning_tenant: &tenant,
+            timeline_id: TIMELINE_ID,
+            raw_timeline: Some((
+                tenant.create_timeline_data(
+                    TIMELINE_ID,
+                    metadata.clone(),
+                    TimelineSettings::default(),
+                    None,
+                )?,
+                TimelineUninitMark::dummy(),
+            )),
+        };
+        let err = duplicate
+            .initialize()
+            .expect_err("Should not initialize a timeline that is in the map already");
+        assert!(
+            err.to_string()
+                .contains("Found freshly initialized timeline"),
+            "{err}"
+        );
+        assert!(Arc::ptr_eq(
+            &tenant.get_timeline(TIMELINE_ID, false)?,
+            &attached
+        ));
+
+        // Attaching it again skips it, without replacing it or marking it as failed
+        tenant.init_attach_timelines(HashMap::from([(TIMELINE_ID, metadata)]))?;
+        assert!(Arc::ptr_eq(
+            &tenant.get_timeline(TIMELINE_ID, false)?,
+            &attached
+        ));
+        assert_eq!(Arc::strong_count(&attached), 2);
+        assert_eq!(tenant.list_timelines().len(), 1);
+        assert_eq!(
+            tenant.attach_progress(),
+            AttachProgress {
+                total: 1,
+                completed: 1,
+                failed: 0,
+                done: true,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_transitions() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_state_transitions")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        tline.set_state(TimelineState::Active);
+
+        let mut tenant_transitions = tenant.subscribe_for_state_transitions();
+        let mut timeline_transitions = tline.subscribe_for_state_transitions();
+        let active = TenantState::Active {
+            gc_enabled: false,
+            compaction_enabled: false,
+        };
+        tenant.set_state(TenantState::Paused);
+        tenant.set_state(active);
+        tenant.set_state(TenantState::Paused);
+        // Equal state, not a transition
+        tenant.set_state(TenantState::Paused);
+
+        let mut received = Vec::new();
+        while let Ok(transition) = tenant_transitions.try_recv() {
+            received.push((transition.from, transition.to));
+        }
+        assert_eq!(
+            received,
+            vec![
+                (active, TenantState::Paused),
+                (TenantState::Paused, active),
+                (active, TenantState::Paused),
+            ]
+        );
+
+        let mut received = Vec::new();
+        while let Ok(transition) = timeline_transitions.try_recv() {
+            received.push((transition.from, transition.to));
+        }
+        assert_eq!(
+            received,
+            vec![
+                (TimelineState::Active, TimelineState::Suspended),
+                (TimelineState::Suspended, TimelineState::Active),
+                (TimelineState::Active, TimelineState::Suspended),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_shutdown")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
+        writer.finish_write(Lsn(0x10))?;
+        drop(writer);
+
+        // Pretend that compaction is running on the timeline
+        let layer_removal_guard = tline.layer_removal_guard()?;
+        let err = tenant
+            .shutdown(Duration::from_millis(300))
+            .await
+            .expect_err("shutdown should time out on a busy timeline");
+        assert!(err.to_string().contains(&TIMELINE_ID.to_string()));
+        assert_eq!(tenant.current_state(), TenantState::Paused);
+        drop(layer_removal_guard);
+
+        tenant.shutdown(Duration::from_secs(10)).await?;
+        assert_eq!(tline.get_disk_consistent_lsn(), Lsn(0x10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_timeline_with_children() -> anyhow::Result<()> {
+        let (tenant, _) = tenant_with_layers("test_delete_timeline_with_children")?;
+        let mut children = Vec::new();
+        for _ in 0..3 {
+            let child_id = TimelineId::generate();
+            tenant.branch_timeline(TIMELINE_ID, child_id, Some(Lsn(0x30)))?;
+            children.push(child_id);
+        }
+        // A grandchild doesn't block the deletion directly
+        tenant.branch_timeline(children[0], NEW_TIMELINE_ID, Some(Lsn(0x30)))?;
+        children.sort();
+
+        match tenant.delete_timeline(TIMELINE_ID) {
+            Err(TenantError::HasChildren(blocking)) => assert_eq!(blocking, children),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        match tenant.delete_timeline(children[0]) {
+            Err(TenantError::HasChildren(blocking)) => {
+                assert_eq!(blocking, vec![NEW_TIMELINE_ID])
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_busy_timeline() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_delete_busy_timeline")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        tline.set_state(TimelineState::Active);
+
+        // Pretend that GC is running on the timeline
+        let layer_removal_guard = tline.layer_removal_guard()?;
+        assert!(matches!(
+            tenant.delete_timeline(TIMELINE_ID),
+            Err(TenantError::Busy(_))
+        ));
+        assert_eq!(tline.current_state(), TimelineState::Active);
+        assert!(tenant.get_timeline(TIMELINE_ID, true).is_ok());
+        drop(layer_removal_guard);
+
+        tenant.delete_timeline(TIMELINE_ID)?;
+        assert!(matches!(
+            tenant.get_timeline(TIMELINE_ID, false),
+            Err(TenantError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_ancestor_depth() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_max_ancestor_depth")?.load();
+        tenant.update_tenant_config(TenantConfOpt {
+            max_ancestor_depth: Some(2),
+            ..TenantConfOpt::default()
+        })?;
+
+        create_timeline_with_layers(&tenant)?;
+
+        let first_branch = TimelineId::generate();
+        tenant.branch_timeline(TIMELINE_ID, first_branch, Some(Lsn(0x40)))?;
+        let second_branch = TimelineId::generate();
+        tenant.branch_timeline(first_branch, second_branch, Some(Lsn(0x40)))?;
+
+        match tenant.branch_timeline(second_branch, TimelineId::generate(), Some(Lsn(0x40))) {
+            Ok(_) => panic!("branching beyond max_ancestor_depth should fail"),
+            Err(err) => {
+                assert!(err.to_string().contains("ancestor chain of length 3"));
+            }
+        }
+
+        // Branching off a shallower timeline is still possible
+        tenant.branch_timeline(first_branch, TimelineId::generate(), Some(Lsn(0x40)))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_timelines_filtered() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_list_timelines_filtered")?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+        let newtline = tenant.get_timeline(NEW_TIMELINE_ID, false)?;
+
+        tline.set_state(TimelineState::Active);
+        newtline.set_state(TimelineState::Broken);
+
+        let ids = |timelines: Vec<Arc<Timeline>>| {
+            timelines
+                .iter()
+                .map(|timeline| timeline.timeline_id)
+                .collect::<Vec<_>>()
+        };
+        let mut all_ids = vec![TIMELINE_ID, NEW_TIMELINE_ID];
+        all_ids.sort();
+
+        assert_eq!(ids(tenant.list_timelines()), all_ids);
+        assert_eq!(
+            ids(tenant.list_timelines_filtered(&[TimelineState::Active])),
+            vec![TIMELINE_ID]
+        );
+        assert_eq!(
+            ids(tenant.list_timelines_filtered(&[TimelineState::Broken])),
+            vec![NEW_TIMELINE_ID]
+        );
+        assert!(tenant
+            .list_timelines_filtered(&[TimelineState::Paused])
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_local_size() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_total_local_size")?.load();
+        assert_eq!(tenant.total_local_size()?, 0);
+
+        let tline = create_timeline_with_layers(&tenant)?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+        let newtline = tenant.get_timeline(NEW_TIMELINE_ID, true)?;
+        make_some_layers(newtline.as_ref(), Lsn(0x60))?;
+
+        let expected_size = tline.get_physical_size_non_incremental()?
+            + newtline.get_physical_size_non_incremental()?;
+        assert!(expected_size > 0);
+        // Broken timelines are still counted
+        newtline.set_state(TimelineState::Broken);
+        assert_eq!(tenant.total_local_size()?, expected_size);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_timeline() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_compact_timeline")?;
+
+        tenant.compact_timeline(TIMELINE_ID)?;
+
+        match tenant.compact_timeline(NEW_TIMELINE_ID) {
+            Ok(_) => panic!("compaction of a missing timeline should fail"),
+            Err(e) => assert_eq!(
+                e.to_string(),
+                format!(
+                    "Timeline {}/{} was not found",
+                    tenant.tenant_id, NEW_TIMELINE_ID
+                )
+            ),
+        }
+
+        tline.set_state(TimelineState::Paused);
+        assert!(tenant.compact_timeline(TIMELINE_ID).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_compaction_stops_on_inactive_tenant() -> anyhow::Result<()> {
+        let mut harness = TenantHarness::create("test_compaction_stops_on_inactive_tenant")?;
+        harness.tenant_conf.compaction_concurrency = 1;
+        let tenant = Arc::new(harness.load());
+        for _ in 0..3 {
+            let tline = tenant
+                .create_empty_timeline(TimelineId::generate(), Lsn(0), DEFAULT_PG_VERSION)?
+                .initialize()?;
+            make_some_layers(tline.as_ref(), Lsn(0x20))?;
+            tline.set_state(TimelineState::Active);
+        }
+        assert_eq!(tenant.compaction_iteration()?, 3);
+
+        // Pause the tenant right after the first timeline gets compacted,
+        // with a single worker the compaction runs on the test thread
+        let paused_tenant = Arc::clone(&tenant);
+        let scenario = cfg_failpoint_callback("compaction-iteration-after-timeline", move || {
+            paused_tenant.set_state(TenantState::Paused)
+        })?;
+        let compacted = tenant.compaction_iteration();
+        drop(scenario);
+
+        assert_eq!(compacted?, 1);
+        assert_eq!(tenant.current_state(), TenantState::Paused);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_concurrent_gc_iterations() -> anyhow::Result<()> {
+        let (tenant, _) = tenant_with_layers("test_concurrent_gc_iterations")?;
+        let tenant = Arc::new(tenant);
+
+        let gc_request = GcRequest {
+            horizon: 0,
+            ..GcRequest::default()
+        };
+
+        // Start the second GC iteration while the first one is collecting the timeline
+        let overlapping_gc = Arc::new(Mutex::new(None));
+        let (callback_tenant, callback_gc, callback_request) = (
+            Arc::clone(&tenant),
+            Arc::clone(&overlapping_gc),
+            gc_request.clone(),
+        );
+        // The overlapping iteration runs on another thread, so the failpoint does not fire for it
+        let scenario = cfg_failpoint_callback("before-timeline-gc", move || {
+            let tenant = Arc::clone(&callback_tenant);
+            let request = callback_request.clone();
+            let result = std::thread::spawn(move || {
+                tenant.gc_iteration(&request, &CancellationToken::new())
+            })
+            .join()
+            .expect("overlapping GC iteration should not panic");
+            *callback_gc.lock().unwrap() = Some(result);
+        })?;
+        let first_gc = tenant.gc_iteration(&gc_request, &CancellationToken::new());
+        drop(scenario);
+
+        let first_gc = first_gc?;
+        assert!(first_gc.per_timeline.contains_key(&TIMELINE_ID));
+        assert!(first_gc.timelines_skipped.is_empty());
+
+        let overlapping_gc = overlapping_gc
+            .lock()
+            .unwrap()
+            .take()
+            .expect("overlapping GC iteration should have run")?;
+        assert!(overlapping_gc.per_timeline.is_empty());
+        assert_eq!(overlapping_gc.timelines_skipped, vec![TIMELINE_ID]);
+
+        // Once the first iteration is done, the timeline is collected again
+        let gc = tenant.gc_iteration(&gc_request, &CancellationToken::new())?;
+        assert!(gc.per_timeline.contains_key(&TIMELINE_ID));
+        assert!(gc.timelines_skipped.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_compaction() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_parallel_compaction")?.load();
+        tenant.update_tenant_config(TenantConfOpt {
+            compaction_concurrency: Some(2),
+            ..TenantConfOpt::default()
+        })?;
+
+        let tline = create_timeline_with_layers(&tenant)?;
+        let new_tline = tenant
+            .create_empty_timeline(NEW_TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        make_some_layers(new_tline.as_ref(), Lsn(0x20))?;
+
+        tenant.compaction_iteration()?;
+
+        assert_eq!(
+            tline.get(*TEST_KEY, Lsn(0x50))?,
+            TEST_IMG(&format!("foo at {}", Lsn(0x50)))
+        );
+        assert_eq!(
+            new_tline.get(*TEST_KEY, Lsn(0x50))?,
+            TEST_IMG(&format!("foo at {}", Lsn(0x50)))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_dry_run() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_gc_dry_run")?;
+
+        let estimate = tenant.gc_iteration_dry_run(&GcRequest {
+            target_timeline_id: Some(TIMELINE_ID),
+            horizon: 0x10,
+            ..GcRequest::default()
+        })?;
+        assert!(estimate.layers_total > 0);
+        // Nothing should be changed by the dry run
+        assert_eq!(*tline.get_latest_gc_cutoff_lsn(), Lsn(0));
+        assert_eq!(tline.gc_info.read().unwrap().horizon_cutoff, Lsn(0));
+
+        let result = tenant.gc_iteration(
+            &GcRequest {
+                target_timeline_id: Some(TIMELINE_ID),
+                horizon: 0x10,
+                ..GcRequest::default()
+            },
+            &CancellationToken::new(),
+        )?;
+        assert_eq!(estimate.layers_total, result.layers_total);
+        assert_eq!(estimate.layers_removed, result.layers_removed);
+        assert_eq!(estimate.bytes_removed, result.bytes_removed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_per_timeline_results() -> anyhow::Result<()> {
+        let (tenant, _) = tenant_with_layers("test_gc_per_timeline_results")?;
+        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+        let newtline = tenant.get_timeline(NEW_TIMELINE_ID, true)?;
+        make_some_layers(newtline.as_ref(), Lsn(0x60))?;
+
+        let result = tenant.gc_iteration(
+            &GcRequest {
+                horizon: 0x10,
+                ..GcRequest::default()
+            },
+            &CancellationToken::new(),
+        )?;
+        assert_eq!(result.per_timeline.len(), 2);
+        assert_eq!(
+            result
+                .per_timeline
+                .values()
+                .map(|r| r.layers_total)
+                .sum::<u64>(),
+            result.layers_total
+        );
+        assert_eq!(
+            result
+                .per_timeline
+                .values()
+                .map(|r| r.layers_removed)
+                .sum::<u64>(),
+            result.layers_removed
+        );
+        assert!(result
+            .per_timeline
+            .values()
+            .all(|r| r.per_timeline.is_empty()));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_gc_cancellation() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_gc_cancellation")?.load();
+        let tline = crate::pgdatadir_mapping::create_test_timeline(
+            &tenant,
+            TIMELINE_ID,
+            DEFAULT_PG_VERSION,
+        )?;
+        // Every image layer makes the layers before it obsolete
+        for lsn in [Lsn(0x20), Lsn(0x30), Lsn(0x40), Lsn(0x50)] {
+            let mut m = tline.begin_modification(lsn);
+            m.put_control_file(Bytes::from(format!("control file at {lsn}")))?;
+            m.put_checkpoint(Bytes::from(format!("checkpoint at {lsn}")))?;
+            m.commit()?;
+            tline.checkpoint(CheckpointConfig::Flush)?;
+            tline.force_create_image_layers(Key::MIN..Key::MAX, lsn)?;
+        }
+        let layers_before = tline.layers.read().unwrap().iter_historic_layers().count();
+
+        let gc_request = GcRequest {
+            target_timeline_id: Some(TIMELINE_ID),
+            horizon: 0,
+            ..GcRequest::default()
+        };
+        let estimate = tenant.gc_iteration_dry_run(&gc_request)?;
+        assert!(estimate.layers_removed > 1);
+
+        // Cancel the iteration right after it removes the first layer
+        let cancel = CancellationToken::new();
+        let callback_cancel = cancel.clone();
+        let scenario = cfg_failpoint_callback("timeline-gc-after-layer-removal", move || {
+            callback_cancel.cancel()
+        })?;
+        let result = tenant.gc_iteration(&gc_request, &cancel);
+        drop(scenario);
+
+        assert_eq!(result?.layers_removed, 1);
+        assert_eq!(
+            tline.layers.read().unwrap().iter_historic_layers().count(),
+            layers_before - 1
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_request_overrides() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_gc_request_overrides")?;
+
+        let conf_request = GcRequest::from_tenant_conf(&tenant, Some(TIMELINE_ID));
+        assert_eq!(conf_request.horizon, tenant.get_gc_horizon());
+        assert_eq!(conf_request.pitr, tenant.get_pitr_interval());
+
+        let invalid_request = GcRequest {
+            pitr: Duration::MAX,
+            ..conf_request.clone()
+        };
+        assert!(invalid_request.validate().is_err());
+        assert!(tenant
+            .gc_iteration(&invalid_request, &CancellationToken::new())
+            .is_err());
+        assert!(tenant.gc_iteration_dry_run(&invalid_request).is_err());
+
+        // A one-shot aggressive GC does not change the tenant config
+        tenant.gc_iteration(
+            &GcRequest {
+                horizon: 0x10,
+                force_checkpoint: true,
+                ..conf_request.clone()
+            },
+            &CancellationToken::new(),
+        )?;
+        assert_eq!(tline.gc_info.read().unwrap().horizon_cutoff, Lsn(0x40));
+        assert_eq!(
+            GcRequest::from_tenant_conf(&tenant, None).horizon,
+            conf_request.horizon
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remote_consistent_lsn() -> anyhow::Result<()> {
+        let (tenant, _) = tenant_with_layers("test_remote_consistent_lsn")?;
+        assert_eq!(tenant.remote_consistent_lsn(TIMELINE_ID).await, None);
+
+        let uploaded_metadata = TimelineMetadata::new(
+            Lsn(0x30),
+            None,
+            None,
+            Lsn(0),
+            Lsn(0),
+            Lsn(0),
+            DEFAULT_PG_VERSION,
+        );
+        tenant.get_remote_index().write().await.add_timeline_entry(
+            TenantTimelineId {
+                tenant_id: tenant.tenant_id,
+                timeline_id: TIMELINE_ID,
+            },
+            storage_sync::index::RemoteTimeline::new(uploaded_metadata),
+        );
+        assert_eq!(
+            tenant.remote_consistent_lsn(TIMELINE_ID).await,
+            Some(Lsn(0x30))
+        );
+        assert_eq!(tenant.remote_consistent_lsn(NEW_TIMELINE_ID).await, None);
+
+        Ok(())
+    }
+
     #[test]
-    fn test_branch() -> anyhow::Result<()> {
-        let tenant = TenantHarness::create("test_branch")?.load();
+    fn test_checkpoint_max_inmem_bytes() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_checkpoint_max_inmem_bytes")?.load();
         let tline = tenant
             .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
             .initialize()?;
-        let writer = tline.writer();
-        use std::str::from_utf8;
 
-        #[allow(non_snake_case)]
-        let TEST_KEY_A: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
-        #[allow(non_snake_case)]
-        let TEST_KEY_B: Key = Key::from_hex("112222222233333333444444445500000002").unwrap();
+        // A few huge records take a lot of space in a tiny LSN range
+        let huge_img = Bytes::from(vec![0u8; 64 * 1024]);
+        let writer = tline.writer();
+        for lsn in [Lsn(0x10), Lsn(0x20)] {
+            writer.put(*TEST_KEY, lsn, &Value::Image(huge_img.clone()))?;
+            writer.finish_write(lsn)?;
+        }
+        drop(writer);
 
-        // Insert a value on the timeline
-        writer.put(TEST_KEY_A, Lsn(0x20), &test_value("foo at 0x20"))?;
-        writer.put(TEST_KEY_B, Lsn(0x20), &test_value("foobar at 0x20"))?;
-        writer.finish_write(Lsn(0x20));
+        tline.check_checkpoint_distance()?;
+        assert!(tline.layers.read().unwrap().open_layer.is_some());
 
-        writer.put(TEST_KEY_A, Lsn(0x30), &test_value("foo at 0x30"))?;
-        writer.finish_write(Lsn(0x30));
-        writer.put(TEST_KEY_A, Lsn(0x40), &test_value("foo at 0x40"))?;
-        writer.finish_write(Lsn(0x40));
+        tenant.update_tenant_config(TenantConfOpt {
+            checkpoint_max_inmem_bytes: Some(64 * 1024),
+            ..TenantConfOpt::default()
+        })?;
+        tline.check_checkpoint_distance()?;
+        assert!(tline.layers.read().unwrap().open_layer.is_none());
 
-        //assert_current_logical_size(&tline, Lsn(0x40));
+        Ok(())
+    }
 
-        // Branch the history, modify relation differently on the new timeline
-        tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x30)))?;
-        let newtline = tenant
-            .get_timeline(NEW_TIMELINE_ID, true)
-            .expect("Should have a local timeline");
-        let new_writer = newtline.writer();
-        new_writer.put(TEST_KEY_A, Lsn(0x40), &test_value("bar at 0x40"))?;
-        new_writer.finish_write(Lsn(0x40));
+    #[tokio::test]
+    async fn test_gc_respects_remote_branchpoints() -> anyhow::Result<()> {
+        let (tenant, tline) = tenant_with_layers("test_gc_respects_remote_branchpoints")?;
 
-        // Check page contents on both branches
-        assert_eq!(
-            from_utf8(&tline.get(TEST_KEY_A, Lsn(0x40))?)?,
-            "foo at 0x40"
+        // A child branch that exists only in the remote storage
+        let child_metadata = TimelineMetadata::new(
+            Lsn(0x30),
+            None,
+            Some(TIMELINE_ID),
+            Lsn(0x30),
+            Lsn(0),
+            Lsn(0),
+            DEFAULT_PG_VERSION,
         );
-        assert_eq!(
-            from_utf8(&newtline.get(TEST_KEY_A, Lsn(0x40))?)?,
-            "bar at 0x40"
+        tenant.get_remote_index().write().await.add_timeline_entry(
+            TenantTimelineId {
+                tenant_id: tenant.tenant_id,
+                timeline_id: NEW_TIMELINE_ID,
+            },
+            storage_sync::index::RemoteTimeline::new(child_metadata),
         );
+        assert!(tenant.get_timeline(NEW_TIMELINE_ID, false).is_err());
+        assert!(tenant.get_gc_respect_remote_branchpoints());
+
+        let mut gc_request = GcRequest {
+            target_timeline_id: Some(TIMELINE_ID),
+            horizon: 0,
+            force_checkpoint: true,
+            remote_branchpoints: tenant.remote_branchpoints().await,
+            ..GcRequest::default()
+        };
         assert_eq!(
-            from_utf8(&newtline.get(TEST_KEY_B, Lsn(0x40))?)?,
-            "foobar at 0x20"
+            gc_request.remote_branchpoints,
+            vec![(TIMELINE_ID, Lsn(0x30))]
         );
+        tenant.gc_iteration(&gc_request, &CancellationToken::new())?;
+        assert_eq!(tline.gc_info.read().unwrap().retain_lsns, vec![Lsn(0x30)]);
 
-        //assert_current_logical_size(&tline, Lsn(0x40));
+        tenant.update_tenant_config(TenantConfOpt {
+            gc_respect_remote_branchpoints: Some(false),
+            ..TenantConfOpt::default()
+        })?;
+        gc_request.remote_branchpoints = tenant.remote_branchpoints().await;
+        assert!(gc_request.remote_branchpoints.is_empty());
+        tenant.gc_iteration(&gc_request, &CancellationToken::new())?;
+        assert!(tline.gc_info.read().unwrap().retain_lsns.is_empty());
 
         Ok(())
     }
 
-    fn make_some_layers(tline: &Timeline, start_lsn: Lsn) -> anyhow::Result<()> {
-        let mut lsn = start_lsn;
-        #[allow(non_snake_case)]
-        {
-            let writer = tline.writer();
-            // Create a relation on the timeline
-            writer.put(
-                *TEST_KEY,
-                lsn,
-                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
-            )?;
-            writer.finish_write(lsn);
-            lsn += 0x10;
-            writer.put(
-                *TEST_KEY,
-                lsn,
-                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
-            )?;
-            writer.finish_write(lsn);
-            lsn += 0x10;
-        }
-        tline.checkpoint(CheckpointConfig::Forced)?;
-        {
-            let writer = tline.writer();
-            writer.put(
-                *TEST_KEY,
-                lsn,
-                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
-            )?;
-            writer.finish_write(lsn);
-            lsn += 0x10;
-            writer.put(
-                *TEST_KEY,
-                lsn,
-                &Value::Image(TEST_IMG(&format!("foo at {}", lsn))),
-            )?;
-            writer.finish_write(lsn);
-        }
-        tline.checkpoint(CheckpointConfig::Forced)
+    #[tokio::test]
+    async fn test_branch_at_savepoint() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_branch_at_savepoint")?;
+        let tenant = Arc::new(harness.load());
+        let tline = create_timeline_with_layers(&tenant)?;
+
+        assert_eq!(tline.create_savepoint("v1".to_string())?, Lsn(0x50));
+        assert!(tline.create_savepoint("v1".to_string()).is_err());
+        assert!(tline.create_savepoint(String::new()).is_err());
+        assert!(tline
+            .create_savepoint("x".repeat(timeline_settings::TIMELINE_SAVEPOINT_NAME_MAX_LEN + 1))
+            .is_err());
+        make_some_layers(tline.as_ref(), Lsn(0x60))?;
+        assert_eq!(
+            load_timeline_settings(harness.conf, TIMELINE_ID, harness.tenant_id)?.savepoints,
+            tline.get_savepoints()
+        );
+
+        let new_tline = tenant
+            .create_timeline(
+                Some(NEW_TIMELINE_ID),
+                Some(TIMELINE_ID),
+                Some(AncestorStart::Savepoint("v1".to_string())),
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+            .await?;
+        assert_eq!(new_tline.get_ancestor_lsn(), Lsn(0x50));
+        assert_eq!(
+            new_tline.get(*TEST_KEY, Lsn(0x50))?,
+            TEST_IMG(&format!("foo at {}", Lsn(0x50)))
+        );
+
+        let err = tenant
+            .create_timeline(
+                None,
+                Some(TIMELINE_ID),
+                Some(AncestorStart::Savepoint("v2".to_string())),
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("has no savepoint 'v2'"),
+            "{err:#}"
+        );
+
+        // The savepoint data is garbage collected, the savepoint cannot be branched at anymore
+        tenant.gc_iteration(
+            &GcRequest {
+                target_timeline_id: Some(TIMELINE_ID),
+                horizon: 0x10,
+                ..GcRequest::default()
+            },
+            &CancellationToken::new(),
+        )?;
+        let err = tenant
+            .create_timeline(
+                None,
+                Some(TIMELINE_ID),
+                Some(AncestorStart::Savepoint("v1".to_string())),
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Savepoint 'v1' at 0/50 was removed by GC"),
+            "{err:#}"
+        );
+
+        Ok(())
     }
 
     #[test]
     fn test_prohibit_branch_creation_on_garbage_collected_data() -> anyhow::Result<()> {
-        let tenant =
-            TenantHarness::create("test_prohibit_branch_creation_on_garbage_collected_data")?
-                .load();
-        let tline = tenant
-            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
-            .initialize()?;
-        make_some_layers(tline.as_ref(), Lsn(0x20))?;
+        let (tenant, _) =
+            tenant_with_layers("test_prohibit_branch_creation_on_garbage_collected_data")?;
 
         // this removes layers before lsn 40 (50 minus 10), so there are two remaining layers, image and delta for 31-50
         // FIXME: this doesn't actually remove any layer currently, given how the checkpointing
         // and compaction works. But it does set the 'cutoff' point so that the cross check
         // below should fail.
-        tenant.gc_iteration(Some(TIMELINE_ID), 0x10, Duration::ZERO, false)?;
+        tenant.gc_iteration(
+            &GcRequest {
+                target_timeline_id: Some(TIMELINE_ID),
+                horizon: 0x10,
+                ..GcRequest::default()
+            },
+            &CancellationToken::new(),
+        )?;
 
         // try to branch at lsn 25, should fail because we already garbage collected the data
         match tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x25))) {
@@ -1982,31 +6771,29 @@ mod tests {
 
     #[test]
     fn test_retain_data_in_parent_which_is_needed_for_child() -> anyhow::Result<()> {
-        let tenant =
-            TenantHarness::create("test_retain_data_in_parent_which_is_needed_for_child")?.load();
-        let tline = tenant
-            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
-            .initialize()?;
-        make_some_layers(tline.as_ref(), Lsn(0x20))?;
+        let (tenant, _) =
+            tenant_with_layers("test_retain_data_in_parent_which_is_needed_for_child")?;
 
         tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
         let newtline = tenant
             .get_timeline(NEW_TIMELINE_ID, true)
             .expect("Should have a local timeline");
         // this removes layers before lsn 40 (50 minus 10), so there are two remaining layers, image and delta for 31-50
-        tenant.gc_iteration(Some(TIMELINE_ID), 0x10, Duration::ZERO, false)?;
+        tenant.gc_iteration(
+            &GcRequest {
+                target_timeline_id: Some(TIMELINE_ID),
+                horizon: 0x10,
+                ..GcRequest::default()
+            },
+            &CancellationToken::new(),
+        )?;
         assert!(newtline.get(*TEST_KEY, Lsn(0x25)).is_ok());
 
         Ok(())
     }
     #[test]
     fn test_parent_keeps_data_forever_after_branching() -> anyhow::Result<()> {
-        let tenant =
-            TenantHarness::create("test_parent_keeps_data_forever_after_branching")?.load();
-        let tline = tenant
-            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
-            .initialize()?;
-        make_some_layers(tline.as_ref(), Lsn(0x20))?;
+        let (tenant, _) = tenant_with_layers("test_parent_keeps_data_forever_after_branching")?;
 
         tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
         let newtline = tenant
@@ -2016,7 +6803,14 @@ mod tests {
         make_some_layers(newtline.as_ref(), Lsn(0x60))?;
 
         // run gc on parent
-        tenant.gc_iteration(Some(TIMELINE_ID), 0x10, Duration::ZERO, false)?;
+        tenant.gc_iteration(
+            &GcRequest {
+                target_timeline_id: Some(TIMELINE_ID),
+                horizon: 0x10,
+                ..GcRequest::default()
+            },
+            &CancellationToken::new(),
+        )?;
 
         // Check that the data is still accessible on the branch.
         assert_eq!(
@@ -2055,11 +6849,7 @@ mod tests {
         // create two timelines
         {
             let tenant = harness.load();
-            let tline = tenant
-                .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
-                .initialize()?;
-
-            make_some_layers(tline.as_ref(), Lsn(0x20))?;
+            let tline = create_timeline_with_layers(&tenant)?;
             tline.checkpoint(CheckpointConfig::Forced)?;
 
             tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
@@ -2087,6 +6877,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn timeline_load_selective() -> anyhow::Result<()> {
+        const TEST_NAME: &str = "timeline_load_selective";
+        let harness = TenantHarness::create(TEST_NAME)?;
+        let first_branch = TimelineId::generate();
+        let second_branch = TimelineId::generate();
+        {
+            let tenant = harness.load();
+            let tline = create_timeline_with_layers(&tenant)?;
+            tline.checkpoint(CheckpointConfig::Forced)?;
+
+            tenant.branch_timeline(TIMELINE_ID, first_branch, Some(Lsn(0x40)))?;
+            tenant.branch_timeline(TIMELINE_ID, second_branch, Some(Lsn(0x40)))?;
+            tenant.branch_timeline(first_branch, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+        }
+
+        let timelines = [TIMELINE_ID, first_branch, second_branch, NEW_TIMELINE_ID]
+            .into_iter()
+            .map(|timeline_id| {
+                load_metadata(harness.conf, timeline_id, harness.tenant_id)
+                    .map(|metadata| (timeline_id, metadata))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+        let tenant = Tenant::new(
+            harness.conf,
+            TenantConfOpt::from(harness.tenant_conf),
+            Arc::new(TestRedoManager),
+            harness.tenant_id,
+            RemoteIndex::default(),
+            false,
+        );
+
+        let missing = TimelineId::generate();
+        assert!(tenant
+            .init_attach_timelines_selective(timelines.clone(), &HashSet::from([missing]))
+            .is_err());
+
+        // Ancestors of the requested timeline are loaded too, unrelated branches are not
+        tenant.init_attach_timelines_selective(timelines, &HashSet::from([NEW_TIMELINE_ID]))?;
+        let mut loaded = tenant
+            .list_timelines()
+            .iter()
+            .map(|tline| tline.timeline_id)
+            .collect::<Vec<_>>();
+        loaded.sort();
+        let mut expected = vec![TIMELINE_ID, first_branch, NEW_TIMELINE_ID];
+        expected.sort();
+        assert_eq!(loaded, expected);
+        assert_eq!(tenant.attach_progress().completed, 3);
+
+        Ok(())
+    }
+
     #[test]
     fn corrupt_metadata() -> anyhow::Result<()> {
         const TEST_NAME: &str = "corrupt_metadata";
@@ -2098,11 +6941,15 @@ mod tests {
             .initialize()?;
         drop(tenant);
 
-        let metadata_path = harness.timeline_path(&TIMELINE_ID).join(METADATA_FILE_NAME);
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let metadata_path =
+            metadata::latest_metadata_path(&timeline_path)?.expect("timeline should have metadata");
 
         assert!(metadata_path.is_file());
 
         let mut metadata_bytes = std::fs::read(&metadata_path)?;
+        // Leave no older generations to fall back to
+        metadata::remove_metadata(&timeline_path)?;
         assert_eq!(metadata_bytes.len(), 512);
         metadata_bytes[8] ^= 1;
         std::fs::write(metadata_path, metadata_bytes)?;
@@ -2138,7 +6985,7 @@ mod tests {
 
         let writer = tline.writer();
         writer.put(*TEST_KEY, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
-        writer.finish_write(Lsn(0x10));
+        writer.finish_write(Lsn(0x10))?;
         drop(writer);
 
         tline.checkpoint(CheckpointConfig::Forced)?;
@@ -2146,7 +6993,7 @@ mod tests {
 
         let writer = tline.writer();
         writer.put(*TEST_KEY, Lsn(0x20), &Value::Image(TEST_IMG("foo at 0x20")))?;
-        writer.finish_write(Lsn(0x20));
+        writer.finish_write(Lsn(0x20))?;
         drop(writer);
 
         tline.checkpoint(CheckpointConfig::Forced)?;
@@ -2154,7 +7001,7 @@ mod tests {
 
         let writer = tline.writer();
         writer.put(*TEST_KEY, Lsn(0x30), &Value::Image(TEST_IMG("foo at 0x30")))?;
-        writer.finish_write(Lsn(0x30));
+        writer.finish_write(Lsn(0x30))?;
         drop(writer);
 
         tline.checkpoint(CheckpointConfig::Forced)?;
@@ -2162,11 +7009,12 @@ mod tests {
 
         let writer = tline.writer();
         writer.put(*TEST_KEY, Lsn(0x40), &Value::Image(TEST_IMG("foo at 0x40")))?;
-        writer.finish_write(Lsn(0x40));
+        writer.finish_write(Lsn(0x40))?;
         drop(writer);
 
         tline.checkpoint(CheckpointConfig::Forced)?;
-        tline.compact()?;
+        // Without the datadir layout there's no keyspace to partition, nothing gets compacted
+        assert_eq!(tline.compact()?, CompactionResult::default());
 
         assert_eq!(tline.get(*TEST_KEY, Lsn(0x10))?, TEST_IMG("foo at 0x10"));
         assert_eq!(tline.get(*TEST_KEY, Lsn(0x1f))?, TEST_IMG("foo at 0x10"));
@@ -2177,6 +7025,272 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_max_layers_per_get() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_max_layers_per_get")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+
+        // An image followed by a stack of WAL records, each in its own delta layer
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
+        writer.finish_write(Lsn(0x10))?;
+        drop(writer);
+        tline.checkpoint(CheckpointConfig::Forced)?;
+        for lsn in [0x20, 0x30, 0x40, 0x50] {
+            let writer = tline.writer();
+            writer.put(
+                *TEST_KEY,
+                Lsn(lsn),
+                &Value::WalRecord(NeonWalRecord::Postgres {
+                    will_init: false,
+                    rec: Bytes::from_static(b"record"),
+                }),
+            )?;
+            writer.finish_write(Lsn(lsn))?;
+            drop(writer);
+            tline.checkpoint(CheckpointConfig::Forced)?;
+        }
+
+        tenant.update_tenant_config(TenantConfOpt {
+            max_layers_per_get: Some(3),
+            ..TenantConfOpt::default()
+        })?;
+        let err = tline.get(*TEST_KEY, Lsn(0x50)).unwrap_err();
+        let too_expensive = err
+            .downcast_ref::<ReadTooExpensive>()
+            .unwrap_or_else(|| panic!("unexpected error: {err:?}"));
+        assert_eq!(too_expensive.max_layers, 3);
+        // Image layer, 0x20 and 0x30 deltas
+        tline.get(*TEST_KEY, Lsn(0x30))?;
+
+        tenant.update_tenant_config(TenantConfOpt {
+            max_layers_per_get: Some(0),
+            ..TenantConfOpt::default()
+        })?;
+        tline.get(*TEST_KEY, Lsn(0x50))?;
+
+        Ok(())
+    }
+
+    // Mock WAL redo manager that fails with the given error once, and works otherwise
+    #[derive(Default)]
+    struct FlakyRedoManager {
+        next_error: Mutex<Option<WalRedoError>>,
+        calls: AtomicUsize,
+    }
+
+    impl FlakyRedoManager {
+        fn fail_next(&self, error: WalRedoError) {
+            *self.next_error.lock().unwrap() = Some(error);
+        }
+    }
+
+    impl WalRedoManager for FlakyRedoManager {
+        fn request_redo(
+            &self,
+            key: Key,
+            lsn: Lsn,
+            base_img: Option<Bytes>,
+            records: Vec<(Lsn, NeonWalRecord)>,
+            pg_version: u32,
+        ) -> Result<Bytes, WalRedoError> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            match self.next_error.lock().unwrap().take() {
+                Some(error) => Err(error),
+                None => TestRedoManager.request_redo(key, lsn, base_img, records, pg_version),
+            }
+        }
+    }
+
+    #[test]
+    fn test_walredo_unavailable_rejects_page_reads() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_walredo_unavailable_rejects_page_reads")?;
+        let walredo_mgr = Arc::new(FlakyRedoManager::default());
+        let tenant = harness.try_load_with_redo_manager(Arc::clone(&walredo_mgr) as _)?;
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let writer = tline.writer();
+        writer.put(*TEST_KEY, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
+        writer.finish_write(Lsn(0x10))?;
+        for lsn in [Lsn(0x20), Lsn(0x30), Lsn(0x40)] {
+            writer.put(
+                *TEST_KEY,
+                lsn,
+                &Value::WalRecord(NeonWalRecord::Postgres {
+                    will_init: false,
+                    rec: Bytes::from_static(b"record"),
+                }),
+            )?;
+            writer.finish_write(lsn)?;
+        }
+        drop(writer);
+        assert!(tline.is_active());
+
+        walredo_mgr.fail_next(WalRedoError::Timeout(Duration::from_secs(1)));
+        let err = tline.get(*TEST_KEY, Lsn(0x20)).unwrap_err();
+        let unavailable = err
+            .downcast_ref::<WalRedoUnavailable>()
+            .unwrap_or_else(|| panic!("unexpected error: {err:?}"));
+        assert_eq!(unavailable.lsn, Lsn(0x20));
+        // The timeline state is not changed by the outage
+        assert!(tline.is_active());
+        assert!(tenant.get_timeline(TIMELINE_ID, true).is_ok());
+        // Reads without WAL redo keep working
+        assert_eq!(tline.get(*TEST_KEY, Lsn(0x10))?, TEST_IMG("foo at 0x10"));
+
+        walredo_mgr.fail_next(WalRedoError::InvalidState);
+        assert!(!tline.probe_walredo());
+        // The page service rejects the reads until the WAL redo is back
+        let unavailable = tline
+            .check_walredo_available()
+            .expect_err("page reads should be rejected during the WAL redo outage");
+        assert_eq!(unavailable.lsn, Lsn(0x20));
+
+        // The WAL redo is back, the reads resume
+        assert!(tline.probe_walredo());
+        tline.check_walredo_available()?;
+        tline.get(*TEST_KEY, Lsn(0x30))?;
+        assert_eq!(walredo_mgr.calls.load(Ordering::Relaxed), 4);
+
+        // Invalid records are fatal for the read, but don't suspend the timeline
+        walredo_mgr.fail_next(WalRedoError::InvalidRecord);
+        let err = tline.get(*TEST_KEY, Lsn(0x40)).unwrap_err();
+        assert!(
+            err.downcast_ref::<WalRedoUnavailable>().is_none(),
+            "{err:?}"
+        );
+        assert!(matches!(
+            err.downcast_ref::<WalRedoError>(),
+            Some(WalRedoError::InvalidRecord)
+        ));
+        tline.check_walredo_available()?;
+        tline.get(*TEST_KEY, Lsn(0x40))?;
+
+        // A probe failing for other reasons ends the outage too, to not reject the reads forever
+        walredo_mgr.fail_next(WalRedoError::InvalidState);
+        assert!(tline.get(*TEST_KEY, Lsn(0x20)).is_err());
+        assert!(tline.check_walredo_available().is_err());
+        walredo_mgr.fail_next(WalRedoError::InvalidRecord);
+        assert!(tline.probe_walredo());
+        tline.check_walredo_available()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_history() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_key_history")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let record = || {
+            Value::WalRecord(NeonWalRecord::Postgres {
+                will_init: false,
+                rec: Bytes::from_static(b"record"),
+            })
+        };
+        let put = |tline: &Timeline, lsn: Lsn, value: &Value| -> anyhow::Result<()> {
+            let writer = tline.writer();
+            writer.put(*TEST_KEY, lsn, value)?;
+            writer.finish_write(lsn)?;
+            Ok(())
+        };
+
+        // Older versions in the same layer as an image are reported too
+        put(&tline, Lsn(0x10), &Value::Image(TEST_IMG("foo at 0x10")))?;
+        put(&tline, Lsn(0x20), &record())?;
+        put(&tline, Lsn(0x30), &Value::Image(TEST_IMG("foo at 0x30")))?;
+        tline.checkpoint(CheckpointConfig::Forced)?;
+        put(&tline, Lsn(0x40), &record())?;
+        tline.checkpoint(CheckpointConfig::Forced)?;
+        // In the open layer, after the branch point
+        put(&tline, Lsn(0x50), &record())?;
+
+        let branch = tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x40)))?;
+        put(&branch, Lsn(0x60), &record())?;
+
+        let history = branch.key_history(*TEST_KEY)?;
+        let versions = history
+            .iter()
+            .map(|version| (version.lsn, version.kind, version.timeline_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            versions,
+            vec![
+                (Lsn(0x10), ValueKind::Image, TIMELINE_ID),
+                (Lsn(0x20), ValueKind::WalRecord, TIMELINE_ID),
+                (Lsn(0x30), ValueKind::Image, TIMELINE_ID),
+                (Lsn(0x40), ValueKind::WalRecord, TIMELINE_ID),
+                (Lsn(0x60), ValueKind::WalRecord, NEW_TIMELINE_ID),
+            ]
+        );
+        // The first three versions were flushed into the same layer
+        assert_eq!(history[0].layer, history[2].layer);
+        assert_ne!(history[2].layer, history[3].layer);
+
+        assert_eq!(tline.key_history(*TEST_KEY)?.len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locate_key() -> anyhow::Result<()> {
+        let tenant = TenantHarness::create("test_locate_key")?.load();
+        let tline = tenant
+            .create_empty_timeline(TIMELINE_ID, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let put = |tline: &Timeline, lsn: Lsn| -> anyhow::Result<()> {
+            let writer = tline.writer();
+            writer.put(
+                *TEST_KEY,
+                lsn,
+                &Value::Image(TEST_IMG(&format!("foo at {lsn}"))),
+            )?;
+            writer.finish_write(lsn)?;
+            Ok(())
+        };
+
+        put(&tline, Lsn(0x10))?;
+        tline.checkpoint(CheckpointConfig::Forced)?;
+        put(&tline, Lsn(0x20))?;
+        // Not visible from the branch
+        put(&tline, Lsn(0x30))?;
+
+        let branch = tenant.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Some(Lsn(0x20)))?;
+        put(&branch, Lsn(0x40))?;
+
+        // A timeline without the key
+        let other_timeline_id = TimelineId::generate();
+        let other_tline = tenant
+            .create_empty_timeline(other_timeline_id, Lsn(0), DEFAULT_PG_VERSION)?
+            .initialize()?;
+        let writer = other_tline.writer();
+        writer.put(TEST_KEY.next(), Lsn(0x10), &Value::Image(TEST_IMG("bar")))?;
+        writer.finish_write(Lsn(0x10))?;
+        drop(writer);
+
+        let mut expected = vec![
+            (TIMELINE_ID, Lsn(0x10)),
+            (TIMELINE_ID, Lsn(0x20)),
+            (TIMELINE_ID, Lsn(0x30)),
+            // The versions inherited from the parent count for the branch too
+            (NEW_TIMELINE_ID, Lsn(0x10)),
+            (NEW_TIMELINE_ID, Lsn(0x20)),
+            (NEW_TIMELINE_ID, Lsn(0x40)),
+        ];
+        expected.sort();
+        assert_eq!(tenant.locate_key(*TEST_KEY)?, expected);
+        assert_eq!(
+            tenant.locate_key(TEST_KEY.next())?,
+            vec![(other_timeline_id, Lsn(0x10))]
+        );
+
+        Ok(())
+    }
+
     //
     // Insert 1000 key-value pairs with increasing keys, checkpoint,
     // repeat 50 times.
@@ -2203,7 +7317,7 @@ mod tests {
                     lsn,
                     &Value::Image(TEST_IMG(&format!("{} at {}", blknum, lsn))),
                 )?;
-                writer.finish_write(lsn);
+                writer.finish_write(lsn)?;
                 drop(writer);
 
                 keyspace.add_key(test_key);
@@ -2214,7 +7328,7 @@ mod tests {
 
             let cutoff = tline.get_last_record_lsn();
 
-            tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO)?;
+            tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO, GcCutoffPolicy::Min)?;
             tline.checkpoint(CheckpointConfig::Forced)?;
             tline.compact()?;
             tline.gc()?;
@@ -2251,7 +7365,7 @@ mod tests {
                 lsn,
                 &Value::Image(TEST_IMG(&format!("{} at {}", blknum, lsn))),
             )?;
-            writer.finish_write(lsn);
+            writer.finish_write(lsn)?;
             updated[blknum] = lsn;
             drop(writer);
 
@@ -2269,7 +7383,7 @@ mod tests {
                     lsn,
                     &Value::Image(TEST_IMG(&format!("{} at {}", blknum, lsn))),
                 )?;
-                writer.finish_write(lsn);
+                writer.finish_write(lsn)?;
                 drop(writer);
                 updated[blknum] = lsn;
             }
@@ -2286,7 +7400,7 @@ mod tests {
             // Perform a cycle of checkpoint, compaction, and GC
             println!("checkpointing {}", lsn);
             let cutoff = tline.get_last_record_lsn();
-            tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO)?;
+            tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO, GcCutoffPolicy::Min)?;
             tline.checkpoint(CheckpointConfig::Forced)?;
             tline.compact()?;
             tline.gc()?;
@@ -2323,7 +7437,7 @@ mod tests {
                 lsn,
                 &Value::Image(TEST_IMG(&format!("{} at {}", blknum, lsn))),
             )?;
-            writer.finish_write(lsn);
+            writer.finish_write(lsn)?;
             updated[blknum] = lsn;
             drop(writer);
 
@@ -2350,7 +7464,7 @@ mod tests {
                     &Value::Image(TEST_IMG(&format!("{} at {}", blknum, lsn))),
                 )?;
                 println!("updating {} at {}", blknum, lsn);
-                writer.finish_write(lsn);
+                writer.finish_write(lsn)?;
                 drop(writer);
                 updated[blknum] = lsn;
             }
@@ -2367,7 +7481,7 @@ mod tests {
             // Perform a cycle of checkpoint, compaction, and GC
             println!("checkpointing {}", lsn);
             let cutoff = tline.get_last_record_lsn();
-            tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO)?;
+            tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO, GcCutoffPolicy::Min)?;
             tline.checkpoint(CheckpointConfig::Forced)?;
             tline.compact()?;
             tline.gc()?;
@@ -2413,7 +7527,7 @@ mod tests {
                     &Value::Image(TEST_IMG(&format!("{} {} at {}", idx, blknum, lsn))),
                 )?;
                 println!("updating [{}][{}] at {}", idx, blknum, lsn);
-                writer.finish_write(lsn);
+                writer.finish_write(lsn)?;
                 drop(writer);
                 updated[idx][blknum] = lsn;
             }
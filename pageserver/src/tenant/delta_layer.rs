@@ -342,6 +342,14 @@ impl Layer for DeltaLayer {
 
     /// debugging function to print out the contents of the layer
     fn dump(&self, verbose: bool) -> Result<()> {
+        self.dump_key_range(verbose, None)
+    }
+}
+
+impl DeltaLayer {
+    /// Same as [`Layer::dump`], but with `key_range` given, only the entries with keys
+    /// in that range are printed.
+    pub fn dump_key_range(&self, verbose: bool, key_range: Option<&Range<Key>>) -> Result<()> {
         println!(
             "----- delta layer for ten {} tli {} keys {}-{} lsn {}-{} ----",
             self.tenant_id,
@@ -395,28 +403,109 @@ impl Layer for DeltaLayer {
             Ok(desc)
         };
 
-        tree_reader.visit(
-            &[0u8; DELTA_KEY_SIZE],
-            VisitDirection::Forwards,
-            |delta_key, val| {
-                let blob_ref = BlobRef(val);
-                let key = DeltaKey::extract_key_from_buf(delta_key);
-                let lsn = DeltaKey::extract_lsn_from_buf(delta_key);
-
-                let desc = match dump_blob(blob_ref) {
-                    Ok(desc) => desc,
-                    Err(err) => format!("ERROR: {}", err),
-                };
-                println!("  key {} at {}: {}", key, lsn, desc);
-                true
-            },
-        )?;
+        let search_key = match key_range {
+            Some(key_range) => DeltaKey::from_key_lsn(&key_range.start, Lsn(0)).0,
+            None => [0u8; DELTA_KEY_SIZE],
+        };
+        tree_reader.visit(&search_key, VisitDirection::Forwards, |delta_key, val| {
+            let blob_ref = BlobRef(val);
+            let key = DeltaKey::extract_key_from_buf(delta_key);
+            let lsn = DeltaKey::extract_lsn_from_buf(delta_key);
+            if let Some(key_range) = key_range {
+                if key >= key_range.end {
+                    return false;
+                }
+            }
+
+            let desc = match dump_blob(blob_ref) {
+                Ok(desc) => desc,
+                Err(err) => format!("ERROR: {}", err),
+            };
+            println!("  key {} at {}: {}", key, lsn, desc);
+            true
+        })?;
 
         Ok(())
     }
-}
 
-impl DeltaLayer {
+    /// Machine-readable counterpart of [`DeltaLayer::dump_key_range`].
+    pub fn dump_json(
+        &self,
+        verbose: bool,
+        key_range: Option<&Range<Key>>,
+    ) -> Result<serde_json::Value> {
+        let mut dump = serde_json::json!({
+            "kind": "delta",
+            "tenant_id": self.tenant_id.to_string(),
+            "timeline_id": self.timeline_id.to_string(),
+            "key_range": {
+                "start": self.key_range.start.to_string(),
+                "end": self.key_range.end.to_string(),
+            },
+            "lsn_range": {
+                "start": self.lsn_range.start.to_string(),
+                "end": self.lsn_range.end.to_string(),
+            },
+        });
+
+        if !verbose {
+            return Ok(dump);
+        }
+
+        let inner = self.load()?;
+        let file = inner.file.as_ref().unwrap();
+        let tree_reader = DiskBtreeReader::<_, DELTA_KEY_SIZE>::new(
+            inner.index_start_blk,
+            inner.index_root_blk,
+            file,
+        );
+
+        let mut cursor = file.block_cursor();
+        let mut describe_blob = |blob_ref: BlobRef| -> anyhow::Result<serde_json::Value> {
+            let buf = cursor.read_blob(blob_ref.pos())?;
+            let desc = match Value::des(&buf)? {
+                Value::Image(img) => serde_json::json!({
+                    "type": "image",
+                    "size": img.len(),
+                }),
+                Value::WalRecord(rec) => serde_json::json!({
+                    "type": "wal_record",
+                    "size": buf.len(),
+                    "will_init": rec.will_init(),
+                }),
+            };
+            Ok(desc)
+        };
+
+        let search_key = match key_range {
+            Some(key_range) => DeltaKey::from_key_lsn(&key_range.start, Lsn(0)).0,
+            None => [0u8; DELTA_KEY_SIZE],
+        };
+        let mut entries = Vec::new();
+        tree_reader.visit(&search_key, VisitDirection::Forwards, |delta_key, val| {
+            let key = DeltaKey::extract_key_from_buf(delta_key);
+            if let Some(key_range) = key_range {
+                if key >= key_range.end {
+                    return false;
+                }
+            }
+
+            let mut entry = match describe_blob(BlobRef(val)) {
+                Ok(desc) => desc,
+                Err(err) => serde_json::json!({ "error": err.to_string() }),
+            };
+            entry["key"] = key.to_string().into();
+            entry["lsn"] = DeltaKey::extract_lsn_from_buf(delta_key).to_string().into();
+            entries.push(entry);
+            true
+        })?;
+        dump["index_start_blk"] = inner.index_start_blk.into();
+        dump["index_root_blk"] = inner.index_root_blk.into();
+        dump["entries"] = entries.into();
+
+        Ok(dump)
+    }
+
     fn path_for(
         path_or_conf: &PathOrConf,
         timeline_id: TimelineId,
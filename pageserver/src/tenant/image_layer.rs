@@ -200,6 +200,14 @@ impl Layer for ImageLayer {
 
     /// debugging function to print out the contents of the layer
     fn dump(&self, verbose: bool) -> Result<()> {
+        self.dump_key_range(verbose, None)
+    }
+}
+
+impl ImageLayer {
+    /// Same as [`Layer::dump`], but with `key_range` given, only the entries with keys
+    /// in that range are printed.
+    pub fn dump_key_range(&self, verbose: bool, key_range: Option<&Range<Key>>) -> Result<()> {
         println!(
             "----- image layer for ten {} tli {} key {}-{} at {} ----",
             self.tenant_id, self.timeline_id, self.key_range.start, self.key_range.end, self.lsn
@@ -216,16 +224,73 @@ impl Layer for ImageLayer {
 
         tree_reader.dump()?;
 
-        tree_reader.visit(&[0u8; KEY_SIZE], VisitDirection::Forwards, |key, value| {
+        let mut search_key = [0u8; KEY_SIZE];
+        if let Some(key_range) = key_range {
+            key_range.start.write_to_byte_slice(&mut search_key);
+        }
+        tree_reader.visit(&search_key, VisitDirection::Forwards, |key, value| {
+            if let Some(key_range) = key_range {
+                if Key::from_slice(key) >= key_range.end {
+                    return false;
+                }
+            }
             println!("key: {} offset {}", hex::encode(key), value);
             true
         })?;
 
         Ok(())
     }
-}
 
-impl ImageLayer {
+    /// Machine-readable counterpart of [`ImageLayer::dump_key_range`].
+    pub fn dump_json(
+        &self,
+        verbose: bool,
+        key_range: Option<&Range<Key>>,
+    ) -> Result<serde_json::Value> {
+        let mut dump = serde_json::json!({
+            "kind": "image",
+            "tenant_id": self.tenant_id.to_string(),
+            "timeline_id": self.timeline_id.to_string(),
+            "key_range": {
+                "start": self.key_range.start.to_string(),
+                "end": self.key_range.end.to_string(),
+            },
+            "lsn": self.lsn.to_string(),
+        });
+
+        if !verbose {
+            return Ok(dump);
+        }
+
+        let inner = self.load()?;
+        let file = inner.file.as_ref().unwrap();
+        let tree_reader =
+            DiskBtreeReader::<_, KEY_SIZE>::new(inner.index_start_blk, inner.index_root_blk, file);
+
+        let mut search_key = [0u8; KEY_SIZE];
+        if let Some(key_range) = key_range {
+            key_range.start.write_to_byte_slice(&mut search_key);
+        }
+        let mut entries = Vec::new();
+        tree_reader.visit(&search_key, VisitDirection::Forwards, |key, value| {
+            if let Some(key_range) = key_range {
+                if Key::from_slice(key) >= key_range.end {
+                    return false;
+                }
+            }
+            entries.push(serde_json::json!({
+                "key": Key::from_slice(key).to_string(),
+                "offset": value,
+            }));
+            true
+        })?;
+        dump["index_start_blk"] = inner.index_start_blk.into();
+        dump["index_root_blk"] = inner.index_root_blk.into();
+        dump["entries"] = entries.into();
+
+        Ok(dump)
+    }
+
     fn path_for(
         path_or_conf: &PathOrConf,
         timeline_id: TimelineId,
@@ -222,6 +222,20 @@ impl LayerRTreeObject {
     }
 }
 
+/// Summary of the historic layers in a [`LayerMap`], see [`LayerMap::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerMapStats {
+    pub image_layers: usize,
+    pub delta_layers: usize,
+    /// Number of keys covered by at least one layer.
+    pub covered_keys: u128,
+    /// From the lowest start to the highest end LSN of all layers, `None` if there are no layers.
+    pub lsn_range: Option<Range<Lsn>>,
+    /// The largest number of delta layers overlapping at a single key, i.e. how many layers
+    /// a `get` of that key might need to visit, if there's no image layer below.
+    pub max_delta_depth: usize,
+}
+
 /// Return value of LayerMap::search
 pub struct SearchResult {
     pub layer: Arc<dyn Layer>,
@@ -517,6 +531,65 @@ impl LayerMap {
         Ok(ranges)
     }
 
+    /// Collect the statistics of the historic layers, to help explain the read amplification.
+    pub fn stats(&self) -> LayerMapStats {
+        let mut image_layers = 0;
+        let mut delta_layers = 0;
+        let mut key_ranges = Vec::new();
+        // (key, +1 for the start of a delta layer's key range, -1 for its end)
+        let mut delta_bounds = Vec::new();
+        let mut lsn_range: Option<Range<Lsn>> = None;
+        for e in self.historic_layers.iter() {
+            let l = &e.layer;
+            let key_range = l.get_key_range();
+            let key_range = key_range.start.to_i128()..key_range.end.to_i128();
+            if l.is_incremental() {
+                delta_layers += 1;
+                delta_bounds.push((key_range.start, 1));
+                delta_bounds.push((key_range.end, -1));
+            } else {
+                image_layers += 1;
+            }
+            key_ranges.push(key_range);
+
+            let layer_lsn_range = l.get_lsn_range();
+            lsn_range = Some(match lsn_range {
+                Some(range) => {
+                    range.start.min(layer_lsn_range.start)..range.end.max(layer_lsn_range.end)
+                }
+                None => layer_lsn_range,
+            });
+        }
+
+        key_ranges.sort_by_key(|range| range.start);
+        let mut covered_keys = 0u128;
+        let mut covered_until = i128::MIN;
+        for range in key_ranges {
+            let start = range.start.max(covered_until);
+            if range.end > start {
+                covered_keys += (range.end - start) as u128;
+                covered_until = range.end;
+            }
+        }
+
+        // Key ranges are exclusive at the end, so ends go before the starts at the same key
+        delta_bounds.sort();
+        let mut depth = 0i64;
+        let mut max_delta_depth = 0;
+        for (_, change) in delta_bounds {
+            depth += change;
+            max_delta_depth = max_delta_depth.max(depth as usize);
+        }
+
+        LayerMapStats {
+            image_layers,
+            delta_layers,
+            covered_keys,
+            lsn_range,
+            max_delta_depth,
+        }
+    }
+
     /// Count how many L1 delta layers there are that overlap with the
     /// given key and LSN range.
     pub fn count_deltas(&self, key_range: &Range<Key>, lsn_range: &Range<Lsn>) -> Result<usize> {
@@ -6,20 +6,23 @@
 //!
 //! The module contains all structs and related helper methods related to timeline metadata.
 
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, ensure, Context};
+use anyhow::{anyhow, bail, ensure, Context};
 use serde::{Deserialize, Serialize};
-use tracing::info_span;
+use tracing::{info_span, warn};
 use utils::{
     bin_ser::BeSer,
+    crashsafe::path_with_suffix_extension,
     id::{TenantId, TimelineId},
     lsn::Lsn,
 };
 
-use crate::config::PageServerConf;
+use crate::config::{PageServerConf, METADATA_FILE_NAME};
 use crate::virtual_file::VirtualFile;
+use crate::TEMP_FILE_SUFFIX;
 
 /// Use special format number to enable backward compatibility.
 const METADATA_FORMAT_VERSION: u16 = 4;
@@ -156,6 +159,17 @@ impl TimelineMetadata {
         );
         let hdr = TimelineMetadataHeader::des(&metadata_bytes[0..METADATA_HDR_SIZE])?;
 
+        // Check the version first: metadata written by a newer pageserver may use a different
+        // layout, so its checksum cannot be verified here.
+        ensure!(
+            matches!(
+                hdr.format_version,
+                METADATA_FORMAT_VERSION | METADATA_OLD_FORMAT_VERSION
+            ),
+            "unsupported metadata format version {}, latest supported version is {METADATA_FORMAT_VERSION}",
+            hdr.format_version
+        );
+
         let metadata_size = hdr.size as usize;
         ensure!(
             metadata_size <= METADATA_MAX_SIZE,
@@ -229,6 +243,11 @@ impl TimelineMetadata {
 }
 
 /// Save timeline metadata to file
+///
+/// Every save writes a new generation of the metadata, `metadata.<generation>`, into a
+/// temporary file first, and then atomically renames it, so a crash never leaves a torn
+/// newest generation behind. The newest valid one of the older generations is kept as
+/// a fallback, the rest are removed, see [`load_metadata_from_dir`].
 pub fn save_metadata(
     conf: &'static PageServerConf,
     timeline_id: TimelineId,
@@ -237,11 +256,22 @@ pub fn save_metadata(
     first_save: bool,
 ) -> anyhow::Result<()> {
     let _enter = info_span!("saving metadata").entered();
-    let path = conf.metadata_path(timeline_id, tenant_id);
-    // use OpenOptions to ensure file presence is consistent with first_save
+    let timeline_dir = conf.timeline_path(&timeline_id, &tenant_id);
+    let generations = list_metadata_generations(&timeline_dir)?;
+    if first_save {
+        if let Some((_, path)) = generations.first() {
+            bail!("Metadata file {} already exists", path.display());
+        }
+    }
+    let generation = generations
+        .first()
+        .map_or(1, |(newest_generation, _)| newest_generation + 1);
+    let path = metadata_generation_path(&timeline_dir, generation);
+
+    let temp_path = path_with_suffix_extension(&path, TEMP_FILE_SUFFIX);
     let mut file = VirtualFile::open_with_options(
-        &path,
-        OpenOptions::new().write(true).create_new(first_save),
+        &temp_path,
+        OpenOptions::new().write(true).create(true).truncate(true),
     )?;
 
     let metadata_bytes = data.to_bytes().context("Failed to get metadata bytes")?;
@@ -250,24 +280,146 @@ pub fn save_metadata(
         bail!("Could not write all the metadata bytes in a single call");
     }
     file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, &path)
+        .with_context(|| format!("Failed to move new metadata file to {}", path.display()))?;
+
+    // fsync the parent directory to ensure the directory entries are durable
+    let timeline_dir_file = File::open(&timeline_dir)?;
+    timeline_dir_file.sync_all()?;
+
+    let fallback_path = generations
+        .iter()
+        .map(|(_, path)| path)
+        .find(|path| read_metadata(path).is_ok());
+    for (_, old_path) in &generations {
+        if Some(old_path) != fallback_path {
+            if let Err(e) = fs::remove_file(old_path) {
+                warn!(
+                    "Failed to remove old metadata generation {}: {e}",
+                    old_path.display()
+                );
+            }
+        }
+    }
 
-    // fsync the parent directory to ensure the directory entry is durable
-    if first_save {
-        let timeline_dir = File::open(
-            &path
-                .parent()
-                .expect("Metadata should always have a parent dir"),
-        )?;
-        timeline_dir.sync_all()?;
+    Ok(())
+}
+
+/// Loads the newest valid timeline metadata generation from the timeline directory.
+///
+/// If the newest generation fails to load, e.g. because of a checksum mismatch, falls back
+/// to the older ones kept by [`save_metadata`]. Layers written after the loaded generation
+/// are then ignored as future layers, unless the remote index has a newer metadata:
+/// storage sync compares the loaded metadata with the remote one and downloads the newer.
+pub fn load_metadata_from_dir(timeline_dir: &Path) -> anyhow::Result<TimelineMetadata> {
+    let mut newest_err = None;
+    for (generation, path) in list_metadata_generations(timeline_dir)? {
+        match read_metadata(&path) {
+            Ok(metadata) => {
+                if let Some(e) = newest_err {
+                    warn!("Falling back to the metadata generation {generation}: {e:#}");
+                }
+                return Ok(metadata);
+            }
+            Err(e) => match newest_err {
+                Some(_) => warn!("Failed to load the metadata generation {generation}: {e:#}"),
+                None => newest_err = Some(e),
+            },
+        }
     }
+    Err(newest_err.unwrap_or_else(|| {
+        anyhow!(
+            "No metadata file found in the timeline directory {}",
+            timeline_dir.display()
+        )
+    }))
+}
 
+/// Path to the newest metadata generation in the timeline directory, if there is any.
+pub fn latest_metadata_path(timeline_dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    if !timeline_dir.exists() {
+        return Ok(None);
+    }
+    Ok(list_metadata_generations(timeline_dir)?
+        .into_iter()
+        .next()
+        .map(|(_, path)| path))
+}
+
+/// Removes all metadata generations from the timeline directory, e.g. to retry the first save.
+pub fn remove_metadata(timeline_dir: &Path) -> anyhow::Result<()> {
+    for (_, path) in list_metadata_generations(timeline_dir)? {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove metadata file {}", path.display()))?;
+    }
     Ok(())
 }
 
+/// Checks if the timeline directory entry is one of the metadata generations.
+pub fn is_metadata_file(file_name: &str) -> bool {
+    parse_metadata_generation(file_name).is_some()
+}
+
+/// The metadata file written before the generations were introduced is the generation 0.
+fn parse_metadata_generation(file_name: &str) -> Option<u64> {
+    if file_name == METADATA_FILE_NAME {
+        return Some(0);
+    }
+    file_name
+        .strip_prefix(METADATA_FILE_NAME)?
+        .strip_prefix('.')?
+        .parse()
+        .ok()
+}
+
+fn metadata_generation_path(timeline_dir: &Path, generation: u64) -> PathBuf {
+    timeline_dir.join(format!("{METADATA_FILE_NAME}.{generation}"))
+}
+
+/// Lists the metadata generations in the timeline directory, the newest first.
+fn list_metadata_generations(timeline_dir: &Path) -> anyhow::Result<Vec<(u64, PathBuf)>> {
+    let mut generations = Vec::new();
+    let entries = fs::read_dir(timeline_dir).with_context(|| {
+        format!(
+            "Failed to list timeline directory {}",
+            timeline_dir.display()
+        )
+    })?;
+    for entry in entries {
+        let entry = entry.context("Failed to list timeline directory entry")?;
+        if let Some(generation) = entry
+            .file_name()
+            .to_str()
+            .and_then(parse_metadata_generation)
+        {
+            generations.push((generation, entry.path()));
+        }
+    }
+    generations.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+    Ok(generations)
+}
+
+fn read_metadata(metadata_path: &Path) -> anyhow::Result<TimelineMetadata> {
+    let metadata_bytes = fs::read(metadata_path).with_context(|| {
+        format!(
+            "Failed to read metadata bytes from path {}",
+            metadata_path.display()
+        )
+    })?;
+    TimelineMetadata::from_bytes(&metadata_bytes).with_context(|| {
+        format!(
+            "Failed to parse metadata bytes from path {}",
+            metadata_path.display()
+        )
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tenant::harness::TIMELINE_ID;
+    use crate::tenant::harness::{TenantHarness, TIMELINE_ID};
 
     #[test]
     fn metadata_serializes_correctly() {
@@ -295,6 +447,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn metadata_format_version_round_trip() {
+        let original_metadata = TimelineMetadata::new(
+            Lsn(0x200),
+            None,
+            None,
+            Lsn(0),
+            Lsn(0x100),
+            Lsn(0x100),
+            crate::DEFAULT_PG_VERSION,
+        );
+
+        let metadata_bytes = original_metadata.to_bytes().unwrap();
+        let hdr = TimelineMetadataHeader::des(&metadata_bytes[0..METADATA_HDR_SIZE]).unwrap();
+        assert_eq!(hdr.format_version, METADATA_FORMAT_VERSION);
+
+        let deserialized_metadata = TimelineMetadata::from_bytes(&metadata_bytes).unwrap();
+        assert_eq!(deserialized_metadata.body, original_metadata.body);
+        assert_eq!(
+            deserialized_metadata.to_bytes().unwrap(),
+            metadata_bytes,
+            "Metadata should be written back in the same format"
+        );
+    }
+
+    #[test]
+    fn metadata_unknown_format_version() {
+        // A newer pageserver may change both the body layout and the checksum,
+        // make sure the version is reported instead of a checksum mismatch.
+        let future_version = METADATA_FORMAT_VERSION + 1;
+        let hdr = TimelineMetadataHeader {
+            checksum: 0xdeadbeef,
+            size: METADATA_MAX_SIZE as u16,
+            format_version: future_version,
+        };
+        let mut metadata_bytes = vec![0xffu8; METADATA_MAX_SIZE];
+        metadata_bytes[0..METADATA_HDR_SIZE].copy_from_slice(&hdr.ser().unwrap());
+
+        let err = TimelineMetadata::from_bytes(&metadata_bytes)
+            .expect_err("Should not deserialize metadata of an unknown version");
+        let err_msg = err.to_string();
+        assert!(
+            err_msg.contains(&format!(
+                "unsupported metadata format version {future_version}"
+            )),
+            "Unexpected error: {err_msg}"
+        );
+        assert!(!err_msg.contains("checksum"), "Unexpected error: {err_msg}");
+    }
+
     // Generate old version metadata and read it with current code.
     // Ensure that it is upgraded correctly
     #[test]
@@ -362,4 +564,74 @@ mod tests {
             METADATA_OLD_FORMAT_VERSION, METADATA_FORMAT_VERSION
         );
     }
+
+    #[test]
+    fn torn_metadata_falls_back_to_previous_generation() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("torn_metadata_falls_back_to_previous_generation")?;
+        let (conf, tenant_id) = (harness.conf, harness.tenant_id);
+        let timeline_dir = harness.timeline_path(&TIMELINE_ID);
+        fs::create_dir_all(&timeline_dir)?;
+
+        let first = TimelineMetadata::new(
+            Lsn(0x10),
+            None,
+            None,
+            Lsn(0),
+            Lsn(0x10),
+            Lsn(0x10),
+            crate::DEFAULT_PG_VERSION,
+        );
+        let second = TimelineMetadata::new(
+            Lsn(0x20),
+            Some(Lsn(0x18)),
+            None,
+            Lsn(0),
+            Lsn(0x10),
+            Lsn(0x10),
+            crate::DEFAULT_PG_VERSION,
+        );
+        let generation_path = |generation| metadata_generation_path(&timeline_dir, generation);
+
+        save_metadata(conf, TIMELINE_ID, tenant_id, &first, true)?;
+        assert!(save_metadata(conf, TIMELINE_ID, tenant_id, &second, true).is_err());
+        save_metadata(conf, TIMELINE_ID, tenant_id, &second, false)?;
+        assert_eq!(load_metadata_from_dir(&timeline_dir)?, second);
+        assert_eq!(
+            latest_metadata_path(&timeline_dir)?,
+            Some(generation_path(2))
+        );
+        assert!(!path_with_suffix_extension(generation_path(2), TEMP_FILE_SUFFIX).exists());
+
+        // Torn write of the newest generation
+        let metadata_bytes = fs::read(generation_path(2))?;
+        fs::write(generation_path(2), &metadata_bytes[..100])?;
+        assert_eq!(load_metadata_from_dir(&timeline_dir)?, first);
+
+        // The next save keeps the valid generation as the fallback, not the torn one
+        save_metadata(conf, TIMELINE_ID, tenant_id, &second, false)?;
+        assert_eq!(load_metadata_from_dir(&timeline_dir)?, second);
+        assert!(generation_path(1).exists());
+        assert!(!generation_path(2).exists());
+        save_metadata(conf, TIMELINE_ID, tenant_id, &first, false)?;
+        assert_eq!(load_metadata_from_dir(&timeline_dir)?, first);
+        assert!(!generation_path(1).exists());
+        assert!(generation_path(3).exists());
+
+        // The metadata file written before the generations is the oldest one
+        fs::write(timeline_dir.join(METADATA_FILE_NAME), &metadata_bytes)?;
+        assert_eq!(load_metadata_from_dir(&timeline_dir)?, first);
+
+        // Without a valid older generation, the newest generation error is returned
+        fs::write(generation_path(4), &metadata_bytes[..100])?;
+        fs::remove_file(timeline_dir.join(METADATA_FILE_NAME))?;
+        fs::remove_file(generation_path(3))?;
+        let err = load_metadata_from_dir(&timeline_dir).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Failed to parse metadata bytes from path"),
+            "{err}"
+        );
+
+        Ok(())
+    }
 }
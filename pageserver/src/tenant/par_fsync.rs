@@ -2,6 +2,7 @@ use std::{
     io,
     path::{Path, PathBuf},
     sync::atomic::{AtomicUsize, Ordering},
+    sync::Mutex,
 };
 
 use crate::virtual_file::VirtualFile;
@@ -11,45 +12,123 @@ fn fsync_path(path: &Path) -> io::Result<()> {
     file.sync_all()
 }
 
-fn parallel_worker(paths: &[PathBuf], next_path_idx: &AtomicUsize) -> io::Result<()> {
+fn parallel_worker(
+    paths: &[PathBuf],
+    next_path_idx: &AtomicUsize,
+    failures: &Mutex<Vec<(PathBuf, io::Error)>>,
+) {
     while let Some(path) = paths.get(next_path_idx.fetch_add(1, Ordering::Relaxed)) {
-        fsync_path(path)?;
+        if let Err(e) = fsync_path(path) {
+            failures.lock().unwrap().push((path.clone(), e));
+        }
     }
-
-    Ok(())
 }
 
-pub fn par_fsync(paths: &[PathBuf]) -> io::Result<()> {
+/// Fsyncs all the given files and directories, in parallel if there are several of them.
+///
+/// A failure doesn't stop the other paths from being synced. All the paths that failed
+/// are returned with their errors, in the order of `paths`.
+pub fn par_fsync(paths: &[PathBuf]) -> Result<(), Vec<(PathBuf, io::Error)>> {
     const PARALLEL_PATH_THRESHOLD: usize = 1;
+    let failures = Mutex::new(Vec::new());
+    let next_path_idx = AtomicUsize::new(0);
+
     if paths.len() <= PARALLEL_PATH_THRESHOLD {
-        for path in paths {
-            fsync_path(path)?;
-        }
-        return Ok(());
+        parallel_worker(paths, &next_path_idx, &failures);
+    } else {
+        /// Use at most this number of threads.
+        /// Increasing this limit will
+        /// - use more memory
+        /// - increase the cost of spawn/join latency
+        const MAX_NUM_THREADS: usize = 64;
+        let num_threads = paths.len().min(MAX_NUM_THREADS);
+
+        crossbeam_utils::thread::scope(|s| {
+            let mut handles = vec![];
+            // Spawn `num_threads - 1`, as the current thread is also a worker.
+            for _ in 1..num_threads {
+                handles.push(s.spawn(|_| parallel_worker(paths, &next_path_idx, &failures)));
+            }
+
+            parallel_worker(paths, &next_path_idx, &failures);
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+        .unwrap();
     }
 
-    /// Use at most this number of threads.
-    /// Increasing this limit will
-    /// - use more memory
-    /// - increase the cost of spawn/join latency
-    const MAX_NUM_THREADS: usize = 64;
-    let num_threads = paths.len().min(MAX_NUM_THREADS);
-    let next_path_idx = AtomicUsize::new(0);
+    let mut failures = failures.into_inner().unwrap();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        failures.sort_by_key(|(path, _)| paths.iter().position(|p| p == path));
+        Err(failures)
+    }
+}
 
-    crossbeam_utils::thread::scope(|s| -> io::Result<()> {
-        let mut handles = vec![];
-        // Spawn `num_threads - 1`, as the current thread is also a worker.
-        for _ in 1..num_threads {
-            handles.push(s.spawn(|_| parallel_worker(paths, &next_path_idx)));
-        }
+/// Combines the failures of [`par_fsync`] into one error, for the callers that give up on any of them.
+pub fn fsync_failures_error(failures: Vec<(PathBuf, io::Error)>) -> anyhow::Error {
+    let failures = failures
+        .iter()
+        .map(|(path, e)| format!("{}: {e}", path.display()))
+        .collect::<Vec<_>>();
+    anyhow::anyhow!(
+        "failed to fsync {} path(s): {}",
+        failures.len(),
+        failures.join(", ")
+    )
+}
 
-        parallel_worker(paths, &next_path_idx)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
 
-        for handle in handles {
-            handle.join().unwrap()?;
-        }
+    #[test]
+    fn par_fsync_reports_every_failure() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let existing = (0..4)
+            .map(|i| {
+                let path = tempdir.path().join(format!("layer_{i}"));
+                fs::write(&path, b"layer")?;
+                Ok(path)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let missing = vec![
+            tempdir.path().join("missing_1"),
+            tempdir.path().join("missing_2"),
+        ];
+
+        let mut paths = existing.clone();
+        paths.insert(1, missing[0].clone());
+        paths.push(missing[1].clone());
+        paths.push(tempdir.path().to_owned());
+        assert!(par_fsync(&existing).is_ok());
+
+        let failures = par_fsync(&paths).expect_err("Missing files should fail to fsync");
+        let failed_paths = failures
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(failed_paths, missing);
+        assert!(failures
+            .iter()
+            .all(|(_, e)| e.kind() == io::ErrorKind::NotFound));
+
+        // A single path is synced without spawning threads, the failure is reported the same way
+        let failures = par_fsync(&missing[..1]).expect_err("Missing file should fail to fsync");
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, missing[0]);
+
+        let err = fsync_failures_error(par_fsync(&paths).unwrap_err()).to_string();
+        assert!(err.contains("failed to fsync 2 path(s)"), "{err}");
+        assert!(
+            err.contains("missing_1") && err.contains("missing_2"),
+            "{err}"
+        );
 
         Ok(())
-    })
-    .unwrap()
+    }
 }
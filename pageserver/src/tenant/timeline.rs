@@ -6,12 +6,13 @@ use fail::fail_point;
 use itertools::Itertools;
 use once_cell::sync::OnceCell;
 use pageserver_api::models::TimelineState;
-use tokio::sync::watch;
+use tokio::sync::{broadcast, watch};
 use tokio::task::spawn_blocking;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 
 use std::cmp::{max, min, Ordering};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::ops::{Deref, Range};
 use std::path::PathBuf;
@@ -25,20 +26,26 @@ use crate::tenant::{
     filename::{DeltaFileName, ImageFileName},
     image_layer::{ImageLayer, ImageLayerWriter},
     inmemory_layer::InMemoryLayer,
-    layer_map::{LayerMap, SearchResult},
-    metadata::{save_metadata, TimelineMetadata},
+    layer_map::{LayerMap, LayerMapStats, SearchResult},
+    metadata::{is_metadata_file, save_metadata, TimelineMetadata},
     par_fsync,
     storage_layer::{Layer, ValueReconstructResult, ValueReconstructState},
+    timeline_settings::{
+        save_timeline_settings, TimelineSettings, TIMELINE_SAVEPOINTS_MAX,
+        TIMELINE_SAVEPOINT_NAME_MAX_LEN,
+    },
+    StateTransition, STATE_TRANSITIONS_CAPACITY,
 };
 
-use crate::config::{PageServerConf, METADATA_FILE_NAME};
+use crate::basebackup::Basebackup;
+use crate::config::{PageServerConf, TIMELINE_SETTINGS_FILE_NAME};
 use crate::keyspace::{KeyPartitioning, KeySpace};
 use crate::metrics::TimelineMetrics;
 use crate::pgdatadir_mapping::BlockNumber;
 use crate::pgdatadir_mapping::LsnForTimestamp;
 use crate::pgdatadir_mapping::{is_rel_fsm_block_key, is_rel_vm_block_key};
 use crate::reltag::RelTag;
-use crate::tenant_config::TenantConfOpt;
+use crate::tenant_config::{GcCutoffPolicy, TenantConfOpt};
 
 use postgres_ffi::to_pg_timestamp;
 use utils::{
@@ -48,11 +55,12 @@ use utils::{
     simple_rcu::{Rcu, RcuReadGuard},
 };
 
-use crate::repository::GcResult;
+use crate::repository::{CompactionResult, GcResult};
 use crate::repository::{Key, Value};
 use crate::task_mgr;
 use crate::task_mgr::TaskKind;
 use crate::walreceiver::{is_etcd_client_initialized, spawn_connection_manager_task};
+use crate::walrecord::NeonWalRecord;
 use crate::walredo::WalRedoManager;
 use crate::CheckpointConfig;
 use crate::ZERO_PAGE;
@@ -124,6 +132,13 @@ pub struct Timeline {
     /// Used to ensure that there is only task performing flushing at a time
     layer_flush_lock: Mutex<()>,
 
+    /// Serializes the metadata file writes, which share the same temporary file
+    /// and replace the previous generation, see [`Timeline::update_metadata_file`].
+    metadata_write_lock: Mutex<()>,
+
+    /// Serializes the settings file writes, see [`Timeline::persist_settings`].
+    settings_write_lock: Mutex<()>,
+
     /// Layer removal lock.
     /// A lock to ensure that no layer of the timeline is removed concurrently by other tasks.
     /// This lock is acquired in [`Timeline::gc`], [`Timeline::compact`],
@@ -155,6 +170,9 @@ pub struct Timeline {
     current_logical_size: LogicalSize,
     initial_size_computation_started: AtomicBool,
 
+    /// Set while a GC iteration is collecting the timeline, see [`Timeline::try_start_gc`].
+    gc_in_progress: AtomicBool,
+
     /// Information about the last processed message by the WAL receiver,
     /// or None if WAL receiver has not received anything for this timeline
     /// yet.
@@ -163,7 +181,30 @@ pub struct Timeline {
     /// Relation size cache
     pub rel_size_cache: RwLock<HashMap<RelTag, (Lsn, BlockNumber)>>,
 
+    /// Optional human-readable label, persisted in the timeline settings.
+    label: RwLock<Option<String>>,
+
+    /// Named LSNs to branch the timeline at, persisted in the timeline settings.
+    savepoints: RwLock<BTreeMap<String, Lsn>>,
+
+    /// When and by whom the timeline was created, persisted in the timeline settings.
+    created_at: SystemTime,
+    created_by: Option<String>,
+
+    /// Writes through [`TimelineWriter`] are rejected when set, persisted in the timeline settings.
+    /// Only changed while holding `write_lock`, so it stays the same during a writer's lifetime.
+    read_only: AtomicBool,
+
+    /// Overrides the tenant's `image_creation_threshold` for this timeline,
+    /// persisted in the timeline settings.
+    image_creation_threshold: RwLock<Option<usize>>,
+
+    /// The read that found the WAL redo unavailable, `None` if it works.
+    /// The page service rejects the page reads while it is set, see [`Timeline::request_redo`].
+    walredo_outage: Mutex<Option<WalRedoOutage>>,
+
     state: watch::Sender<TimelineState>,
+    state_transitions: broadcast::Sender<StateTransition<TimelineState>>,
 }
 
 /// Internal structure to hold all data needed for logical size calculation.
@@ -284,6 +325,7 @@ pub struct WalReceiverInfo {
 /// Information about how much history needs to be retained, needed by
 /// Garbage Collection.
 ///
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GcInfo {
     /// Specific LSNs that are needed.
     ///
@@ -307,10 +349,21 @@ pub struct GcInfo {
     /// This is calculated by finding a number such that a record is needed for PITR
     /// if only if its LSN is larger than 'pitr_cutoff'.
     pub pitr_cutoff: Lsn,
+
+    /// How 'horizon_cutoff' and 'pitr_cutoff' combine: with [`GcCutoffPolicy::Min`]
+    /// everything newer than either of them is kept, with [`GcCutoffPolicy::Max`]
+    /// only what is newer than both.
+    pub cutoff_policy: GcCutoffPolicy,
 }
 
 /// Public interface functions
 impl Timeline {
+    /// Returns a copy of the current GC information, e.g. to see why a branch start LSN got
+    /// rejected as being below the GC cutoffs.
+    pub fn gc_info_snapshot(&self) -> GcInfo {
+        self.gc_info.read().unwrap().clone()
+    }
+
     /// Get the LSN where this branch was created
     pub fn get_ancestor_lsn(&self) -> Lsn {
         self.ancestor_lsn
@@ -328,6 +381,49 @@ impl Timeline {
         self.latest_gc_cutoff_lsn.read()
     }
 
+    /// Get the timeline's human-readable label, if any
+    pub fn get_label(&self) -> Option<String> {
+        self.label.read().unwrap().clone()
+    }
+
+    /// Get the timeline's savepoints, by name
+    pub fn get_savepoints(&self) -> BTreeMap<String, Lsn> {
+        self.savepoints.read().unwrap().clone()
+    }
+
+    /// Get the wall-clock time of the timeline creation,
+    /// `UNIX_EPOCH` if the timeline was created before it was recorded.
+    pub fn get_created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// Get the free-form description of the timeline creator, if known
+    pub fn get_created_by(&self) -> Option<String> {
+        self.created_by.clone()
+    }
+
+    /// Whether writes to the timeline are rejected
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(AtomicOrdering::Relaxed)
+    }
+
+    /// Get the timeline's override of the tenant's `image_creation_threshold`, if any
+    pub fn get_image_creation_threshold_override(&self) -> Option<usize> {
+        *self.image_creation_threshold.read().unwrap()
+    }
+
+    /// Get the LSN of the named savepoint.
+    /// Fails if there's no such savepoint, or if GC has already removed the data at its LSN.
+    pub fn get_savepoint_lsn(&self, name: &str) -> anyhow::Result<Lsn> {
+        let lsn =
+            *self.savepoints.read().unwrap().get(name).with_context(|| {
+                format!("Timeline {} has no savepoint '{name}'", self.timeline_id)
+            })?;
+        self.check_lsn_is_in_scope(lsn, &self.get_latest_gc_cutoff_lsn())
+            .with_context(|| format!("Savepoint '{name}' at {lsn} was removed by GC"))?;
+        Ok(lsn)
+    }
+
     /// Look up given page version.
     ///
     /// NOTE: It is considered an error to 'get' a key that doesn't exist. The abstraction
@@ -336,6 +432,9 @@ impl Timeline {
     /// the Repository implementation may incorrectly return a value from an ancestor
     /// branch, for example, or waste a lot of cycles chasing the non-existing key.
     ///
+    /// Fails with [`ReadTooExpensive`] if the value can't be reconstructed from at most
+    /// `max_layers_per_get` layers.
+    ///
     pub fn get(&self, key: Key, lsn: Lsn) -> anyhow::Result<Bytes> {
         anyhow::ensure!(lsn.is_valid(), "Invalid LSN");
 
@@ -387,6 +486,13 @@ impl Timeline {
         self.disk_consistent_lsn.load()
     }
 
+    /// Amount of WAL, in bytes, that was ingested but is not flushed to disk yet.
+    pub fn flush_lag(&self) -> u64 {
+        self.get_last_record_lsn()
+            .0
+            .saturating_sub(self.get_disk_consistent_lsn().0)
+    }
+
     /// Get the physical size of the timeline at the latest LSN
     pub fn get_physical_size(&self) -> u64 {
         self.metrics.current_physical_size_gauge.get()
@@ -413,6 +519,11 @@ impl Timeline {
         Ok(total_physical_size)
     }
 
+    /// Statistics of the timeline's layers, to help explain slow `get` calls.
+    pub fn layer_map_stats(&self) -> LayerMapStats {
+        self.layers.read().unwrap().stats()
+    }
+
     ///
     /// Wait until WAL has been received and processed up to this LSN.
     ///
@@ -457,11 +568,40 @@ impl Timeline {
         Ok(())
     }
 
+    /// Write a full basebackup tarball of the timeline at `lsn` into `writer`.
+    ///
+    /// Unlike the basebackup sent to compute nodes, this includes all relation
+    /// pages, so the result can be unpacked into a standalone PostgreSQL data
+    /// directory.
+    pub fn export_basebackup_to_tar<W: std::io::Write>(
+        self: &Arc<Self>,
+        writer: W,
+        lsn: Lsn,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            lsn >= self.get_ancestor_lsn(),
+            "LSN {lsn} is earlier than the timeline's ancestor LSN {}",
+            self.get_ancestor_lsn()
+        );
+        let last_record_lsn = self.get_last_record_lsn();
+        ensure!(
+            lsn <= last_record_lsn,
+            "LSN {lsn} is later than the timeline's last record LSN {last_record_lsn}"
+        );
+        let latest_gc_cutoff_lsn = self.get_latest_gc_cutoff_lsn();
+        self.check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)?;
+
+        let basebackup = Basebackup::new(writer, self, Some(lsn), None, true)?;
+        basebackup.send_tarball()
+    }
+
     /// Flush to disk all data that was written with the put_* functions
     ///
     /// NOTE: This has nothing to do with checkpoint in PostgreSQL. We don't
     /// know anything about them here in the repository.
-    pub fn checkpoint(&self, cconf: CheckpointConfig) -> anyhow::Result<()> {
+    ///
+    /// Returns the number of bytes written to disk by flushing the in-memory layers.
+    pub fn checkpoint(&self, cconf: CheckpointConfig) -> anyhow::Result<u64> {
         match cconf {
             CheckpointConfig::Flush => {
                 self.freeze_inmem_layer(false);
@@ -469,19 +609,22 @@ impl Timeline {
             }
             CheckpointConfig::Forced => {
                 self.freeze_inmem_layer(false);
-                self.flush_frozen_layers(true)?;
-                self.compact()
+                let flushed_bytes = self.flush_frozen_layers(true)?;
+                self.compact()?;
+                Ok(flushed_bytes)
             }
         }
     }
 
-    pub fn compact(&self) -> anyhow::Result<()> {
+    /// Returns the number of the layers created and removed, see [`CompactionResult`].
+    pub fn compact(&self) -> anyhow::Result<CompactionResult> {
+        let mut result = CompactionResult::default();
         let last_record_lsn = self.get_last_record_lsn();
 
         // Last record Lsn could be zero in case the timelie was just created
         if !last_record_lsn.is_valid() {
             warn!("Skipping compaction for potentially just initialized timeline, it has invalid last record lsn: {last_record_lsn}");
-            return Ok(());
+            return Ok(result);
         }
 
         //
@@ -521,6 +664,7 @@ impl Timeline {
         let _layer_removal_cs = self.layer_removal_cs.lock().unwrap();
 
         let target_file_size = self.get_checkpoint_distance();
+        let max_file_size = self.get_compaction_max_size();
 
         // Define partitioning schema if needed
 
@@ -532,6 +676,11 @@ impl Timeline {
                 // 2. Create new image layers for partitions that have been modified
                 // "enough".
                 let layer_paths_to_upload = self.create_image_layers(&partitioning, lsn, false)?;
+                result.image_layers_created = layer_paths_to_upload.len() as u64;
+                result.bytes_written = layer_paths_to_upload
+                    .values()
+                    .filter_map(LayerFileMetadata::file_size)
+                    .sum();
                 if !layer_paths_to_upload.is_empty()
                     && self.upload_layers.load(atomic::Ordering::Relaxed)
                 {
@@ -545,7 +694,7 @@ impl Timeline {
 
                 // 3. Compact
                 let timer = self.metrics.compact_time_histo.start_timer();
-                self.compact_level0(target_file_size)?;
+                result += self.compact_level0(target_file_size, max_file_size)?;
                 timer.stop_and_record();
             }
             Err(err) => {
@@ -557,14 +706,65 @@ impl Timeline {
             }
         };
 
+        Ok(result)
+    }
+
+    /// Creates image layers for the keys in `key_range` at `lsn`, regardless of the image
+    /// creation threshold, e.g. to speed up the reads of a hot part of the keyspace right away.
+    pub fn force_create_image_layers(&self, key_range: Range<Key>, lsn: Lsn) -> anyhow::Result<()> {
+        let last_record_lsn = self.get_last_record_lsn();
+        ensure!(
+            lsn <= last_record_lsn,
+            "LSN {lsn} is later than the timeline's last record LSN {last_record_lsn}"
+        );
+        // Same as compaction, to not race with GC removing the layers
+        let _layer_removal_cs = self.layer_removal_guard()?;
+        self.check_lsn_is_in_scope(lsn, &self.get_latest_gc_cutoff_lsn())?;
+
+        let keyspace = KeySpace {
+            ranges: self
+                .collect_keyspace(lsn)?
+                .ranges
+                .into_iter()
+                .filter_map(|range| {
+                    let start = max(range.start, key_range.start);
+                    let end = min(range.end, key_range.end);
+                    if start < end {
+                        Some(start..end)
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        };
+        let partitioning = keyspace.partition(self.get_compaction_target_size());
+        let layer_paths_to_upload = self.create_image_layers(&partitioning, lsn, true)?;
+        info!(
+            "created {} image layers for key range {}..{} at {lsn}",
+            layer_paths_to_upload.len(),
+            key_range.start,
+            key_range.end
+        );
+        if !layer_paths_to_upload.is_empty() && self.upload_layers.load(atomic::Ordering::Relaxed) {
+            storage_sync::schedule_layer_upload(
+                self.tenant_id,
+                self.timeline_id,
+                layer_paths_to_upload,
+                None,
+            );
+        }
         Ok(())
     }
 
     /// Mutate the timeline with a [`TimelineWriter`].
+    ///
+    /// The writes of the writer fail if the timeline is read-only, see [`Timeline::set_read_only`].
     pub fn writer(&self) -> TimelineWriter<'_> {
+        let write_guard = self.write_lock.lock().unwrap();
         TimelineWriter {
             tl: self,
-            _write_guard: self.write_lock.lock().unwrap(),
+            read_only: self.is_read_only(),
+            _write_guard: write_guard,
         }
     }
 
@@ -590,7 +790,9 @@ impl Timeline {
     /// the in-memory layer, and initiate flushing it if so.
     ///
     /// Also flush after a period of time without new data -- it helps
-    /// safekeepers to regard pageserver as caught up and suspend activity.
+    /// safekeepers to regard pageserver as caught up and suspend activity,
+    /// and when the in-memory layer grows beyond 'checkpoint_max_inmem_bytes',
+    /// however small the LSN distance.
     pub fn check_checkpoint_distance(self: &Arc<Timeline>) -> anyhow::Result<()> {
         let last_lsn = self.get_last_record_lsn();
         let layers = self.layers.read().unwrap();
@@ -603,9 +805,12 @@ impl Timeline {
             // Checkpointing the open layer can be triggered by layer size or LSN range.
             // S3 has a 5 GB limit on the size of one upload (without multi-part upload), and
             // we want to stay below that with a big margin.  The LSN distance determines how
-            // much WAL the safekeepers need to store.
+            // much WAL the safekeepers need to store. With large records, the layer size
+            // can grow much faster than the LSN distance, so its memory use is bounded
+            // separately.
             if distance >= self.get_checkpoint_distance().into()
                 || open_layer_size > self.get_checkpoint_distance()
+                || open_layer_size > self.get_checkpoint_max_inmem_bytes()
                 || (distance > 0 && last_freeze_ts.elapsed() >= self.get_checkpoint_timeout())
             {
                 info!(
@@ -634,7 +839,7 @@ impl Timeline {
                         Some(self.timeline_id),
                         "layer flush task",
                         false,
-                        async move { self_clone.flush_frozen_layers(false) },
+                        async move { self_clone.flush_frozen_layers(false).map(|_| ()) },
                     );
                 }
             }
@@ -654,7 +859,13 @@ impl Timeline {
                 debug!("Not activating a paused timeline");
             }
             (_, new_state) => {
-                self.state.send_replace(new_state);
+                let old_state = self.state.send_replace(new_state);
+                // Fails only if there are no subscribers at the moment
+                let _ = self.state_transitions.send(StateTransition {
+                    from: old_state,
+                    to: new_state,
+                    at: Instant::now(),
+                });
             }
         }
     }
@@ -670,6 +881,49 @@ impl Timeline {
     pub fn subscribe_for_state_updates(&self) -> watch::Receiver<TimelineState> {
         self.state.subscribe()
     }
+
+    /// Waits until the timeline gets into the `target` state.
+    /// Fails if that doesn't happen within `timeout`, or if the timeline becomes broken.
+    pub async fn wait_for_state(
+        &self,
+        target: TimelineState,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let mut state_updates = self.subscribe_for_state_updates();
+        let wait = async {
+            loop {
+                let current_state = *state_updates.borrow_and_update();
+                if current_state == target {
+                    return Ok(());
+                }
+                ensure!(
+                    current_state != TimelineState::Broken,
+                    "Timeline {}/{} became broken while waiting for state {target:?}",
+                    self.tenant_id,
+                    self.timeline_id,
+                );
+                state_updates
+                    .changed()
+                    .await
+                    .context("Timeline state sender dropped")?;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.with_context(|| {
+            format!(
+                "Timed out waiting for timeline {}/{} to become {target:?}, current state: {:?}",
+                self.tenant_id,
+                self.timeline_id,
+                self.current_state()
+            )
+        })?
+    }
+
+    /// Subscribes for every state transition of the timeline, happening after the call.
+    pub fn subscribe_for_state_transitions(
+        &self,
+    ) -> broadcast::Receiver<StateTransition<TimelineState>> {
+        self.state_transitions.subscribe()
+    }
 }
 
 // Private functions
@@ -688,6 +942,13 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.checkpoint_timeout)
     }
 
+    fn get_checkpoint_max_inmem_bytes(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .checkpoint_max_inmem_bytes
+            .unwrap_or(self.conf.default_tenant_conf.checkpoint_max_inmem_bytes)
+    }
+
     fn get_compaction_target_size(&self) -> u64 {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -695,6 +956,13 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.compaction_target_size)
     }
 
+    fn get_compaction_max_size(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .merge(self.conf.default_tenant_conf)
+            .effective_compaction_max_size()
+    }
+
     fn get_compaction_threshold(&self) -> usize {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -702,7 +970,18 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.compaction_threshold)
     }
 
-    fn get_image_creation_threshold(&self) -> usize {
+    fn get_max_layers_per_get(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .max_layers_per_get
+            .unwrap_or(self.conf.default_tenant_conf.max_layers_per_get)
+    }
+
+    /// The timeline's override takes precedence over the tenant config.
+    pub(super) fn get_image_creation_threshold(&self) -> usize {
+        if let Some(threshold) = self.get_image_creation_threshold_override() {
+            return threshold;
+        }
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
             .image_creation_threshold
@@ -711,12 +990,13 @@ impl Timeline {
 
     /// Open a Timeline handle.
     ///
-    /// Loads the metadata for the timeline into memory, but not the layer map.
+    /// Loads the metadata and the settings for the timeline into memory, but not the layer map.
     #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         conf: &'static PageServerConf,
         tenant_conf: Arc<RwLock<TenantConfOpt>>,
         metadata: TimelineMetadata,
+        settings: TimelineSettings,
         ancestor: Option<Arc<Timeline>>,
         timeline_id: TimelineId,
         tenant_id: TenantId,
@@ -726,6 +1006,7 @@ impl Timeline {
     ) -> Self {
         let disk_consistent_lsn = metadata.disk_consistent_lsn();
         let (state, _) = watch::channel(TimelineState::Suspended);
+        let (state_transitions, _) = broadcast::channel(STATE_TRANSITIONS_CAPACITY);
 
         let mut result = Timeline {
             conf,
@@ -756,12 +1037,15 @@ impl Timeline {
 
             write_lock: Mutex::new(()),
             layer_flush_lock: Mutex::new(()),
+            metadata_write_lock: Mutex::new(()),
+            settings_write_lock: Mutex::new(()),
             layer_removal_cs: Mutex::new(()),
 
             gc_info: RwLock::new(GcInfo {
                 retain_lsns: Vec::new(),
                 horizon_cutoff: Lsn(0),
                 pitr_cutoff: Lsn(0),
+                cutoff_policy: GcCutoffPolicy::default(),
             }),
 
             latest_gc_cutoff_lsn: Rcu::new(metadata.latest_gc_cutoff_lsn()),
@@ -777,12 +1061,21 @@ impl Timeline {
                 LogicalSize::empty_initial()
             },
             initial_size_computation_started: AtomicBool::new(false),
+            gc_in_progress: AtomicBool::new(false),
             partitioning: Mutex::new((KeyPartitioning::new(), Lsn(0))),
             repartition_threshold: 0,
 
             last_received_wal: Mutex::new(None),
             rel_size_cache: RwLock::new(HashMap::new()),
+            label: RwLock::new(settings.label),
+            savepoints: RwLock::new(settings.savepoints),
+            created_at: settings.created_at,
+            created_by: settings.created_by,
+            read_only: AtomicBool::new(settings.read_only),
+            image_creation_threshold: RwLock::new(settings.image_creation_threshold),
+            walredo_outage: Mutex::new(None),
             state,
+            state_transitions,
         };
         result.repartition_threshold = result.get_checkpoint_distance() / 10;
         result
@@ -887,7 +1180,10 @@ impl Timeline {
                 total_physical_size += layer.path().metadata()?.len();
                 layers.insert_historic(Arc::new(layer));
                 num_layers += 1;
-            } else if fname == METADATA_FILE_NAME || fname.ends_with(".old") {
+            } else if is_metadata_file(&fname)
+                || fname == TIMELINE_SETTINGS_FILE_NAME
+                || fname.ends_with(".old")
+            {
                 // ignore these
             } else if is_ephemeral_file(&fname) {
                 // Delete any old ephemeral files
@@ -919,6 +1215,18 @@ impl Timeline {
             .map_err(|e| anyhow!("cannot lock compaction critical section {e}"))
     }
 
+    /// Like [`Self::layer_removal_guard`], but only a poisoned lock is an error,
+    /// `None` means that compaction or GC is currently running.
+    pub(super) fn try_layer_removal_guard(&self) -> anyhow::Result<Option<MutexGuard<()>>> {
+        match self.layer_removal_cs.try_lock() {
+            Ok(guard) => Ok(Some(guard)),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Poisoned(e)) => {
+                bail!("compaction critical section is poisoned: {e}")
+            }
+        }
+    }
+
     fn try_spawn_size_init_task(self: &Arc<Self>, init_lsn: Lsn) {
         // Atomically check if the timeline size calculation had already started.
         // If the flag was not already set, this sets it.
@@ -1043,6 +1351,8 @@ impl Timeline {
         let mut result = ValueReconstructResult::Continue;
         let mut cont_lsn = Lsn(request_lsn.0 + 1);
 
+        let max_layers = self.get_max_layers_per_get();
+
         'outer: loop {
             // The function should have updated 'state'
             //info!("CALLED for {} at {}: {:?} with {} records, cached {}", key, cont_lsn, result, reconstruct_state.records.len(), cached_lsn);
@@ -1092,6 +1402,16 @@ impl Timeline {
                 continue;
             }
 
+            // The value is still incomplete, so at least one more layer is needed
+            if max_layers != 0 && traversal_path.len() >= max_layers {
+                return Err(ReadTooExpensive {
+                    key,
+                    lsn: request_lsn,
+                    max_layers,
+                }
+                .into());
+            }
+
             let layers = timeline.layers.read().unwrap();
 
             // Check the open and frozen in-memory layers first, in order from newest
@@ -1151,6 +1471,95 @@ impl Timeline {
         }
     }
 
+    ///
+    /// Returns every stored version of the key visible from this timeline, ordered by LSN:
+    /// the versions in this timeline's layers and in the ancestors' layers up to the branch
+    /// points. Versions are looked up in the layers directly, without WAL redo and caching.
+    ///
+    /// Meant for debugging, it reads all the layers containing the key.
+    ///
+    pub fn key_history(&self, key: Key) -> anyhow::Result<Vec<KeyVersion>> {
+        let mut history = Vec::new();
+
+        let mut timeline_owned;
+        let mut timeline = self;
+        // Versions of an ancestor are only visible up to the branch point
+        let mut end_lsn = Lsn::MAX;
+        loop {
+            let layers = {
+                let layers = timeline.layers.read().unwrap();
+                layers
+                    .open_layer
+                    .iter()
+                    .chain(layers.frozen_layers.iter())
+                    .map(|layer| Arc::clone(layer) as Arc<dyn Layer>)
+                    .chain(layers.iter_historic_layers())
+                    .filter(|layer| layer.get_key_range().contains(&key))
+                    .collect::<Vec<_>>()
+            };
+
+            for layer in layers {
+                let lsn_range = layer.get_lsn_range();
+                let mut cont_lsn = min(lsn_range.end, end_lsn);
+                // A layer returns the versions back to the first image or will_init record,
+                // continue below it to collect the older ones too
+                while cont_lsn > lsn_range.start {
+                    let mut reconstruct_state = ValueReconstructState {
+                        records: Vec::new(),
+                        img: None,
+                    };
+                    let result = layer
+                        .get_value_reconstruct_data(
+                            key,
+                            lsn_range.start..cont_lsn,
+                            &mut reconstruct_state,
+                        )
+                        .with_context(|| {
+                            format!("failed to read layer {}", layer.filename().display())
+                        })?;
+
+                    let versions = reconstruct_state
+                        .records
+                        .iter()
+                        .map(|(lsn, _)| (*lsn, ValueKind::WalRecord))
+                        .chain(
+                            reconstruct_state
+                                .img
+                                .iter()
+                                .map(|(lsn, _)| (*lsn, ValueKind::Image)),
+                        );
+                    let mut oldest_lsn = cont_lsn;
+                    for (lsn, kind) in versions {
+                        oldest_lsn = min(oldest_lsn, lsn);
+                        history.push(KeyVersion {
+                            lsn,
+                            kind,
+                            timeline_id: timeline.timeline_id,
+                            layer: layer.filename(),
+                        });
+                    }
+
+                    match result {
+                        ValueReconstructResult::Complete if oldest_lsn < cont_lsn => {
+                            cont_lsn = oldest_lsn
+                        }
+                        _ => break,
+                    }
+                }
+            }
+
+            if timeline.ancestor_timeline.is_none() {
+                break;
+            }
+            end_lsn = Lsn(timeline.ancestor_lsn.0 + 1);
+            timeline_owned = timeline.get_ancestor_timeline()?;
+            timeline = &*timeline_owned;
+        }
+
+        history.sort_by_key(|version| version.lsn);
+        Ok(history)
+    }
+
     fn lookup_cached_page(&self, key: &Key, lsn: Lsn) -> Option<(Lsn, Bytes)> {
         let cache = page_cache::get();
 
@@ -1235,11 +1644,23 @@ impl Timeline {
         Ok(())
     }
 
-    fn finish_write(&self, new_lsn: Lsn) {
+    fn finish_write(&self, new_lsn: Lsn) -> anyhow::Result<()> {
         assert!(new_lsn.is_aligned());
 
+        // Several modifications may be committed at the same LSN, e.g. when importing
+        // the initial data at the timeline start LSN, but the LSN must never go back.
+        let last_record_lsn = self.get_last_record_lsn();
+        ensure!(
+            new_lsn >= last_record_lsn,
+            "Cannot finish write at LSN {new_lsn} of timeline {}/{}, it is behind the last record LSN {last_record_lsn}",
+            self.tenant_id,
+            self.timeline_id,
+        );
+
         self.metrics.last_record_gauge.set(new_lsn.0 as i64);
         self.last_record_lsn.advance(new_lsn);
+        self.metrics.flush_lag_gauge.set(self.flush_lag());
+        Ok(())
     }
 
     fn freeze_inmem_layer(&self, write_lock_held: bool) {
@@ -1274,25 +1695,28 @@ impl Timeline {
     /// currently doing the flushing, this function will wait for it
     /// to finish. If 'wait' is false, this function will return
     /// immediately instead.
-    fn flush_frozen_layers(&self, wait: bool) -> anyhow::Result<()> {
+    ///
+    /// Returns the total size of the layer files written.
+    fn flush_frozen_layers(&self, wait: bool) -> anyhow::Result<u64> {
         let flush_lock_guard = if wait {
             self.layer_flush_lock.lock().unwrap()
         } else {
             match self.layer_flush_lock.try_lock() {
                 Ok(guard) => guard,
-                Err(TryLockError::WouldBlock) => return Ok(()),
+                Err(TryLockError::WouldBlock) => return Ok(0),
                 Err(TryLockError::Poisoned(err)) => panic!("{:?}", err),
             }
         };
 
         let timer = self.metrics.flush_time_histo.start_timer();
 
+        let mut flushed_bytes = 0;
         loop {
             let layers = self.layers.read().unwrap();
             if let Some(frozen_layer) = layers.frozen_layers.front() {
                 let frozen_layer = Arc::clone(frozen_layer);
                 drop(layers); // to allow concurrent reads and writes
-                self.flush_frozen_layer(frozen_layer)?;
+                flushed_bytes += self.flush_frozen_layer(frozen_layer)?;
             } else {
                 // Drop the 'layer_flush_lock' *before* 'layers'. That
                 // way, if you freeze a layer, and then call
@@ -1309,11 +1733,12 @@ impl Timeline {
 
         timer.stop_and_record();
 
-        Ok(())
+        Ok(flushed_bytes)
     }
 
     /// Flush one frozen in-memory layer to disk, as a new delta layer.
-    fn flush_frozen_layer(&self, frozen_layer: Arc<InMemoryLayer>) -> anyhow::Result<()> {
+    /// Returns the total size of the layer files created.
+    fn flush_frozen_layer(&self, frozen_layer: Arc<InMemoryLayer>) -> anyhow::Result<u64> {
         // As a special case, when we have just imported an image into the repository,
         // instead of writing out a L0 delta layer, we directly write out image layer
         // files instead. This is possible as long as *all* the data imported into the
@@ -1330,6 +1755,11 @@ impl Timeline {
                 HashMap::from([(delta_path, metadata)])
             };
 
+        let flushed_bytes = layer_paths_to_upload
+            .values()
+            .filter_map(LayerFileMetadata::file_size)
+            .sum::<u64>();
+
         fail_point!("flush-frozen-before-sync");
 
         // The new on-disk layers are now in the layer map. We can remove the
@@ -1362,7 +1792,111 @@ impl Timeline {
             self.update_metadata_file(disk_consistent_lsn, layer_paths_to_upload)?;
             // Also update the in-memory copy
             self.disk_consistent_lsn.store(disk_consistent_lsn);
+            self.metrics.flush_lag_gauge.set(self.flush_lag());
+        }
+        Ok(flushed_bytes)
+    }
+
+    /// Replaces the timeline's label and persists it in the settings file.
+    pub(super) fn set_label(&self, label: Option<String>) -> anyhow::Result<()> {
+        *self.label.write().unwrap() = label;
+        self.persist_settings()
+    }
+
+    /// The current timeline settings, as persisted in the settings file.
+    pub fn settings(&self) -> TimelineSettings {
+        TimelineSettings {
+            label: self.get_label(),
+            savepoints: self.get_savepoints(),
+            created_at: self.created_at,
+            created_by: self.created_by.clone(),
+            read_only: self.is_read_only(),
+            image_creation_threshold: self.get_image_creation_threshold_override(),
+        }
+    }
+
+    /// Rewrites the settings file with the current timeline settings.
+    fn persist_settings(&self) -> anyhow::Result<()> {
+        // Take the lock before collecting the settings, so that the last write has the latest values
+        let _settings_write_guard = self.settings_write_lock.lock().unwrap();
+        save_timeline_settings(
+            self.conf,
+            self.timeline_id,
+            self.tenant_id,
+            &self.settings(),
+        )
+    }
+
+    /// Records the current last record LSN under the given name, so that new timelines can be
+    /// branched at it later, see [`crate::tenant::AncestorStart::Savepoint`].
+    /// The savepoint is persisted in the settings file.
+    pub fn create_savepoint(&self, name: String) -> anyhow::Result<Lsn> {
+        ensure!(
+            !name.is_empty() && name.len() <= TIMELINE_SAVEPOINT_NAME_MAX_LEN,
+            "Savepoint name is {} bytes long, it should be 1 to {TIMELINE_SAVEPOINT_NAME_MAX_LEN} bytes",
+            name.len()
+        );
+
+        let lsn = self.get_last_record_lsn();
+        {
+            let mut savepoints = self.savepoints.write().unwrap();
+            ensure!(
+                !savepoints.contains_key(&name),
+                "Savepoint '{name}' already exists on timeline {}",
+                self.timeline_id
+            );
+            ensure!(
+                savepoints.len() < TIMELINE_SAVEPOINTS_MAX,
+                "Timeline {} already has the maximum of {TIMELINE_SAVEPOINTS_MAX} savepoints",
+                self.timeline_id
+            );
+            savepoints.insert(name.clone(), lsn);
+        }
+
+        if let Err(e) = self.persist_settings() {
+            self.savepoints.write().unwrap().remove(&name);
+            return Err(e.context(format!("Failed to persist savepoint '{name}'")));
+        }
+        info!("created savepoint '{name}' at {lsn}");
+        Ok(lsn)
+    }
+
+    /// Makes the timeline read-only or writable again, and persists the flag in the settings file.
+    /// Waits for the current writer to finish. The flag is not changed if it cannot be persisted.
+    pub(super) fn set_read_only(&self, read_only: bool) -> anyhow::Result<()> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        let was_read_only = self.read_only.swap(read_only, AtomicOrdering::Relaxed);
+        if let Err(e) = self.persist_settings() {
+            self.read_only.store(was_read_only, AtomicOrdering::Relaxed);
+            return Err(e);
         }
+        info!("timeline read-only flag set to {read_only}");
+        Ok(())
+    }
+
+    /// Replaces the timeline's compaction overrides of the tenant config and persists them
+    /// in the settings file. `None` falls back to the tenant's value.
+    /// The overrides are not changed if they cannot be persisted.
+    pub(super) fn set_compaction_overrides(
+        &self,
+        image_creation_threshold: Option<usize>,
+    ) -> anyhow::Result<()> {
+        if let Some(threshold) = image_creation_threshold {
+            ensure!(
+                threshold > 0,
+                "image_creation_threshold must be positive, got {threshold}"
+            );
+        }
+
+        let old_threshold = std::mem::replace(
+            &mut *self.image_creation_threshold.write().unwrap(),
+            image_creation_threshold,
+        );
+        if let Err(e) = self.persist_settings() {
+            *self.image_creation_threshold.write().unwrap() = old_threshold;
+            return Err(e);
+        }
+        info!("timeline image_creation_threshold override set to {image_creation_threshold:?}");
         Ok(())
     }
 
@@ -1372,6 +1906,10 @@ impl Timeline {
         disk_consistent_lsn: Lsn,
         layer_paths_to_upload: HashMap<PathBuf, LayerFileMetadata>,
     ) -> anyhow::Result<()> {
+        // Flush and GC both write the metadata, take the lock before collecting
+        // its fields so that the last write has the latest values too
+        let _metadata_write_guard = self.metadata_write_lock.lock().unwrap();
+
         // We can only save a valid 'prev_record_lsn' value on disk if we
         // flushed *all* in-memory changes to disk. We only track
         // 'prev_record_lsn' in memory for the latest processed record, so we
@@ -1402,7 +1940,6 @@ impl Timeline {
             self.initdb_lsn,
             self.pg_version,
         );
-
         fail_point!("checkpoint-before-saving-metadata", |x| bail!(
             "{}",
             x.unwrap()
@@ -1448,7 +1985,8 @@ impl Timeline {
         par_fsync::par_fsync(&[
             new_delta_path.clone(),
             self.conf.timeline_path(&self.timeline_id, &self.tenant_id),
-        ])?;
+        ])
+        .map_err(par_fsync::fsync_failures_error)?;
 
         // Add it to the layer map
         {
@@ -1595,7 +2133,7 @@ impl Timeline {
                 self.conf.timeline_path(&self.timeline_id, &self.tenant_id),
             ))
             .collect::<Vec<_>>();
-        par_fsync::par_fsync(&all_paths)?;
+        par_fsync::par_fsync(&all_paths).map_err(par_fsync::fsync_failures_error)?;
 
         let mut layer_paths_to_upload = HashMap::with_capacity(image_layers.len());
 
@@ -1619,14 +2157,22 @@ impl Timeline {
     /// Collect a bunch of Level 0 layer files, and compact and reshuffle them as
     /// as Level 1 files.
     ///
-    fn compact_level0(&self, target_file_size: u64) -> anyhow::Result<()> {
+    /// New layers are cut at key boundaries once they grow past `target_file_size`, but no
+    /// layer may grow past `max_file_size`: when the target is above it, the ceiling is used
+    /// for splitting instead, including splitting the history of a single key.
+    fn compact_level0(
+        &self,
+        target_file_size: u64,
+        max_file_size: u64,
+    ) -> anyhow::Result<CompactionResult> {
+        let target_file_size = target_file_size.min(max_file_size);
         let layers = self.layers.read().unwrap();
         let mut level0_deltas = layers.get_level0_deltas()?;
         drop(layers);
 
         // Only compact if enough layers have accumulated.
         if level0_deltas.is_empty() || level0_deltas.len() < self.get_compaction_threshold() {
-            return Ok(());
+            return Ok(CompactionResult::default());
         }
 
         // Gather the files to compact in this iteration.
@@ -1851,11 +2397,16 @@ impl Timeline {
 
             // Fsync all the layer files and directory using multiple threads to
             // minimize latency.
-            par_fsync::par_fsync(&layer_paths)?;
+            par_fsync::par_fsync(&layer_paths).map_err(par_fsync::fsync_failures_error)?;
 
             layer_paths.pop().unwrap();
         }
 
+        let mut result = CompactionResult {
+            delta_layers_created: new_layers.len() as u64,
+            layers_removed: deltas_to_compact.len() as u64,
+            ..CompactionResult::default()
+        };
         let mut layers = self.layers.write().unwrap();
         let mut new_layer_paths = HashMap::with_capacity(new_layers.len());
         for l in new_layers {
@@ -1865,6 +2416,7 @@ impl Timeline {
 
             // update the timeline's physical size
             self.metrics.current_physical_size_gauge.add(metadata.len());
+            result.bytes_written += metadata.len();
 
             new_layer_paths.insert(new_delta_path, LayerFileMetadata::new(metadata.len()));
             layers.insert_historic(Arc::new(l));
@@ -1900,7 +2452,7 @@ impl Timeline {
             );
         }
 
-        Ok(())
+        Ok(result)
     }
 
     /// Update information about which layer files need to be retained on
@@ -1929,17 +2481,30 @@ impl Timeline {
     ///
     /// The 'pitr' duration is used to calculate a 'pitr_cutoff', which can be used to determine
     /// whether a record is needed for PITR.
+    ///
+    /// The 'cutoff_policy' determines how the two cutoffs combine into the GC cutoff.
     pub(super) fn update_gc_info(
         &self,
         retain_lsns: Vec<Lsn>,
         cutoff_horizon: Lsn,
         pitr: Duration,
+        cutoff_policy: GcCutoffPolicy,
     ) -> anyhow::Result<()> {
-        let mut gc_info = self.gc_info.write().unwrap();
-
-        gc_info.horizon_cutoff = cutoff_horizon;
-        gc_info.retain_lsns = retain_lsns;
+        let new_gc_info =
+            self.calculate_gc_info(retain_lsns, cutoff_horizon, pitr, cutoff_policy)?;
+        *self.gc_info.write().unwrap() = new_gc_info;
+        Ok(())
+    }
 
+    /// Calculates the GC information the same way [`Timeline::update_gc_info`] does,
+    /// without storing it in the timeline.
+    pub(super) fn calculate_gc_info(
+        &self,
+        retain_lsns: Vec<Lsn>,
+        cutoff_horizon: Lsn,
+        pitr: Duration,
+        cutoff_policy: GcCutoffPolicy,
+    ) -> anyhow::Result<GcInfo> {
         // Calculate pitr cutoff point.
         // If we cannot determine a cutoff LSN, be conservative and don't GC anything.
         let mut pitr_cutoff_lsn: Lsn;
@@ -1960,7 +2525,7 @@ impl Timeline {
                     LsnForTimestamp::Present(lsn) => pitr_cutoff_lsn = lsn,
                     LsnForTimestamp::Future(lsn) => {
                         debug!("future({})", lsn);
-                        pitr_cutoff_lsn = gc_info.horizon_cutoff;
+                        pitr_cutoff_lsn = cutoff_horizon;
                     }
                     LsnForTimestamp::Past(lsn) => {
                         debug!("past({})", lsn);
@@ -1975,11 +2540,25 @@ impl Timeline {
             // No time-based retention. (Some unit tests depend on garbage-collection
             // working even when CLOG data is missing, so that find_lsn_for_timestamp()
             // above doesn't work.)
-            pitr_cutoff_lsn = gc_info.horizon_cutoff;
+            pitr_cutoff_lsn = cutoff_horizon;
         }
-        gc_info.pitr_cutoff = pitr_cutoff_lsn;
 
-        Ok(())
+        Ok(GcInfo {
+            retain_lsns,
+            horizon_cutoff: cutoff_horizon,
+            pitr_cutoff: pitr_cutoff_lsn,
+            cutoff_policy,
+        })
+    }
+
+    /// Marks the timeline as being garbage collected, until the returned guard is dropped.
+    /// Returns `None` if another GC iteration is collecting the timeline already.
+    pub(super) fn try_start_gc(&self) -> Option<GcInProgressGuard<'_>> {
+        if self.gc_in_progress.swap(true, AtomicOrdering::SeqCst) {
+            None
+        } else {
+            Some(GcInProgressGuard { timeline: self })
+        }
     }
 
     ///
@@ -1989,7 +2568,10 @@ impl Timeline {
     /// within a layer file. We can only remove the whole file if it's fully
     /// obsolete.
     ///
-    pub(super) fn gc(&self) -> anyhow::Result<GcResult> {
+    /// Cancellation is checked before every layer removal, the layers removed so far
+    /// are reported in the returned result.
+    ///
+    pub(super) fn gc(&self, cancel: &CancellationToken) -> anyhow::Result<GcResult> {
         let mut result: GcResult = GcResult::default();
         let now = SystemTime::now();
 
@@ -2000,10 +2582,10 @@ impl Timeline {
         let gc_info = self.gc_info.read().unwrap();
 
         let horizon_cutoff = min(gc_info.horizon_cutoff, self.get_disk_consistent_lsn());
-        let pitr_cutoff = gc_info.pitr_cutoff;
+        let pitr_cutoff = min(gc_info.pitr_cutoff, self.get_disk_consistent_lsn());
         let retain_lsns = &gc_info.retain_lsns;
 
-        let new_gc_cutoff = Lsn::min(horizon_cutoff, pitr_cutoff);
+        let new_gc_cutoff = gc_info.cutoff_policy.combine(horizon_cutoff, pitr_cutoff);
 
         let _enter =
             info_span!("gc_timeline", timeline = %self.timeline_id, cutoff = %new_gc_cutoff)
@@ -2041,17 +2623,117 @@ impl Timeline {
 
         debug!("retain_lsns: {:?}", retain_lsns);
 
+        let mut layers = self.layers.write().unwrap();
+        let layers_to_remove = Self::find_layers_to_gc(
+            &layers,
+            horizon_cutoff,
+            pitr_cutoff,
+            gc_info.cutoff_policy,
+            retain_lsns,
+            new_gc_cutoff,
+            &mut result,
+        )?;
+
+        // Actually delete the layers from disk and remove them from the map.
+        // (couldn't do this in the loop above, because you cannot modify a collection
+        // while iterating it. BTreeMap::retain() would be another option)
+        let mut layer_paths_to_delete = HashSet::with_capacity(layers_to_remove.len());
+        for doomed_layer in layers_to_remove {
+            if cancel.is_cancelled() {
+                info!(
+                    "GC cancelled after removing {} layers",
+                    result.layers_removed
+                );
+                break;
+            }
+            if let Some(path) = doomed_layer.local_path() {
+                let layer_size = path.metadata()?.len();
+                self.metrics.current_physical_size_gauge.sub(layer_size);
+                result.bytes_removed += layer_size;
+                layer_paths_to_delete.insert(path);
+            }
+            doomed_layer.delete()?;
+            layers.remove_historic(doomed_layer);
+            result.layers_removed += 1;
+            fail_point!("timeline-gc-after-layer-removal");
+        }
+
+        info!(
+            "GC completed removing {} layers, cutoff {}",
+            result.layers_removed, new_gc_cutoff
+        );
+
+        if result.layers_removed != 0 {
+            fail_point!("after-timeline-gc-removed-layers");
+        }
+
+        if self.can_upload_layers() {
+            storage_sync::schedule_layer_delete(
+                self.tenant_id,
+                self.timeline_id,
+                layer_paths_to_delete,
+            );
+        }
+
+        result.elapsed = now.elapsed()?;
+        Ok(result)
+    }
+
+    /// Estimates what [`Timeline::gc`] would remove with the given GC information,
+    /// without deleting any layers or moving the GC cutoff.
+    pub(super) fn gc_dry_run(&self, gc_info: &GcInfo) -> anyhow::Result<GcResult> {
+        let mut result: GcResult = GcResult::default();
+        let now = SystemTime::now();
+
+        let horizon_cutoff = min(gc_info.horizon_cutoff, self.get_disk_consistent_lsn());
+        let pitr_cutoff = min(gc_info.pitr_cutoff, self.get_disk_consistent_lsn());
+        let new_gc_cutoff = gc_info.cutoff_policy.combine(horizon_cutoff, pitr_cutoff);
+
+        let latest_gc_cutoff = *self.get_latest_gc_cutoff_lsn();
+        if latest_gc_cutoff < new_gc_cutoff {
+            let layers = self.layers.read().unwrap();
+            let layers_to_remove = Self::find_layers_to_gc(
+                &layers,
+                horizon_cutoff,
+                pitr_cutoff,
+                gc_info.cutoff_policy,
+                &gc_info.retain_lsns,
+                new_gc_cutoff,
+                &mut result,
+            )?;
+            for doomed_layer in layers_to_remove {
+                if let Some(path) = doomed_layer.local_path() {
+                    result.bytes_removed += path.metadata()?.len();
+                }
+                result.layers_removed += 1;
+            }
+        }
+
+        result.elapsed = now.elapsed()?;
+        Ok(result)
+    }
+
+    /// Scans all on-disk layers in the timeline and returns the ones that are not needed anymore,
+    /// accounting the reasons to keep the rest in `result`.
+    fn find_layers_to_gc(
+        layers: &LayerMap,
+        horizon_cutoff: Lsn,
+        pitr_cutoff: Lsn,
+        cutoff_policy: GcCutoffPolicy,
+        retain_lsns: &[Lsn],
+        new_gc_cutoff: Lsn,
+        result: &mut GcResult,
+    ) -> anyhow::Result<Vec<Arc<dyn Layer>>> {
         let mut layers_to_remove = Vec::new();
 
         // Scan all on-disk layers in the timeline.
         //
         // Garbage collect the layer if all conditions are satisfied:
-        // 1. it is older than cutoff LSN;
-        // 2. it is older than PITR interval;
+        // 1-2. it is older than the cutoff LSN and the PITR interval, or, with
+        //      `GcCutoffPolicy::Max`, older than any of them;
         // 3. it doesn't need to be retained for 'retain_lsns';
         // 4. newer on-disk image layers cover the layer's whole key range
         //
-        let mut layers = self.layers.write().unwrap();
         'outer: for l in layers.iter_historic_layers() {
             // This layer is in the process of being flushed to disk.
             // It will be swapped out of the layer map, replaced with
@@ -2065,25 +2747,29 @@ impl Timeline {
 
             result.layers_total += 1;
 
-            // 1. Is it newer than GC horizon cutoff point?
-            if l.get_lsn_range().end > horizon_cutoff {
-                debug!(
-                    "keeping {} because it's newer than horizon_cutoff {}",
-                    l.filename().display(),
-                    horizon_cutoff
-                );
-                result.layers_needed_by_cutoff += 1;
-                continue 'outer;
-            }
-
-            // 2. It is newer than PiTR cutoff point?
-            if l.get_lsn_range().end > pitr_cutoff {
-                debug!(
-                    "keeping {} because it's newer than pitr_cutoff {}",
-                    l.filename().display(),
-                    pitr_cutoff
-                );
-                result.layers_needed_by_pitr += 1;
+            // 1-2. Is it newer than GC horizon cutoff point and/or PiTR cutoff point?
+            let newer_than_horizon = l.get_lsn_range().end > horizon_cutoff;
+            let newer_than_pitr = l.get_lsn_range().end > pitr_cutoff;
+            let needed_by_cutoffs = match cutoff_policy {
+                GcCutoffPolicy::Min => newer_than_horizon || newer_than_pitr,
+                GcCutoffPolicy::Max => newer_than_horizon && newer_than_pitr,
+            };
+            if needed_by_cutoffs {
+                if newer_than_horizon {
+                    debug!(
+                        "keeping {} because it's newer than horizon_cutoff {}",
+                        l.filename().display(),
+                        horizon_cutoff
+                    );
+                    result.layers_needed_by_cutoff += 1;
+                } else {
+                    debug!(
+                        "keeping {} because it's newer than pitr_cutoff {}",
+                        l.filename().display(),
+                        pitr_cutoff
+                    );
+                    result.layers_needed_by_pitr += 1;
+                }
                 continue 'outer;
             }
 
@@ -2145,41 +2831,7 @@ impl Timeline {
             layers_to_remove.push(Arc::clone(&l));
         }
 
-        // Actually delete the layers from disk and remove them from the map.
-        // (couldn't do this in the loop above, because you cannot modify a collection
-        // while iterating it. BTreeMap::retain() would be another option)
-        let mut layer_paths_to_delete = HashSet::with_capacity(layers_to_remove.len());
-        for doomed_layer in layers_to_remove {
-            if let Some(path) = doomed_layer.local_path() {
-                self.metrics
-                    .current_physical_size_gauge
-                    .sub(path.metadata()?.len());
-                layer_paths_to_delete.insert(path);
-            }
-            doomed_layer.delete()?;
-            layers.remove_historic(doomed_layer);
-            result.layers_removed += 1;
-        }
-
-        info!(
-            "GC completed removing {} layers, cutoff {}",
-            result.layers_removed, new_gc_cutoff
-        );
-
-        if result.layers_removed != 0 {
-            fail_point!("after-timeline-gc-removed-layers");
-        }
-
-        if self.can_upload_layers() {
-            storage_sync::schedule_layer_delete(
-                self.tenant_id,
-                self.timeline_id,
-                layer_paths_to_delete,
-            );
-        }
-
-        result.elapsed = now.elapsed()?;
-        Ok(result)
+        Ok(layers_to_remove)
     }
 
     ///
@@ -2234,13 +2886,7 @@ impl Timeline {
 
                 let last_rec_lsn = data.records.last().unwrap().0;
 
-                let img = self.walredo_mgr.request_redo(
-                    key,
-                    request_lsn,
-                    base_img,
-                    data.records,
-                    self.pg_version,
-                )?;
+                let img = self.request_redo(key, request_lsn, base_img, data.records)?;
 
                 if img.len() == page_cache::PAGE_SZ {
                     let cache = page_cache::get();
@@ -2260,12 +2906,202 @@ impl Timeline {
         }
     }
 
+    /// Applies the WAL records with the WAL redo manager.
+    ///
+    /// If the WAL redo is unavailable, fails with [`WalRedoUnavailable`] and records the outage,
+    /// so that the page service rejects the page reads instead of waiting for the WAL redo
+    /// to fail each of them, see [`Timeline::check_walredo_available`].
+    /// The first successful WAL redo ends the outage.
+    /// Invalid requests or records fail with [`crate::walredo::WalRedoError`] without
+    /// recording an outage.
+    fn request_redo(
+        &self,
+        key: Key,
+        lsn: Lsn,
+        base_img: Option<Bytes>,
+        records: Vec<(Lsn, NeonWalRecord)>,
+    ) -> anyhow::Result<Bytes> {
+        match self
+            .walredo_mgr
+            .request_redo(key, lsn, base_img, records, self.pg_version)
+        {
+            Ok(img) => {
+                if self.walredo_outage.lock().unwrap().take().is_some() {
+                    info!("WAL redo is available again");
+                }
+                Ok(img)
+            }
+            Err(e) if e.is_retriable() => {
+                let mut outage = self.walredo_outage.lock().unwrap();
+                if outage.is_none() {
+                    warn!("WAL redo is unavailable, rejecting the page reads: {e}");
+                    *outage = Some(WalRedoOutage {
+                        key,
+                        lsn,
+                        probing: false,
+                    });
+                }
+                drop(outage);
+                Err(anyhow::Error::new(e).context(WalRedoUnavailable { key, lsn }))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fails with [`WalRedoUnavailable`] while the WAL redo is unavailable for the timeline.
+    /// Starts a background task that retries the failed read every [`WALREDO_PROBE_INTERVAL`]
+    /// meanwhile, as the rejected reads don't try the WAL redo.
+    pub fn check_walredo_available(self: &Arc<Self>) -> Result<(), WalRedoUnavailable> {
+        let mut outage = self.walredo_outage.lock().unwrap();
+        match outage.as_mut() {
+            Some(outage) => {
+                if !outage.probing {
+                    outage.probing = true;
+                    self.spawn_walredo_probe_task();
+                }
+                Err(WalRedoUnavailable {
+                    key: outage.key,
+                    lsn: outage.lsn,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn spawn_walredo_probe_task(self: &Arc<Self>) {
+        let (tenant_id, timeline_id) = (self.tenant_id, self.timeline_id);
+        let weak_timeline = Arc::downgrade(self);
+        task_mgr::spawn(
+            task_mgr::BACKGROUND_RUNTIME.handle(),
+            TaskKind::WalRedoProbe,
+            Some(tenant_id),
+            Some(timeline_id),
+            "WAL redo probe",
+            false,
+            async move {
+                // Let the next rejected read start a new probe, however this one ends
+                let exiting_timeline = weak_timeline.clone();
+                scopeguard::defer! {
+                    if let Some(timeline) = exiting_timeline.upgrade() {
+                        if let Some(outage) = timeline.walredo_outage.lock().unwrap().as_mut() {
+                            outage.probing = false;
+                        }
+                    }
+                }
+                loop {
+                    tokio::select! {
+                        _ = task_mgr::shutdown_watcher() => return Ok(()),
+                        _ = tokio::time::sleep(WALREDO_PROBE_INTERVAL) => {},
+                    }
+                    let timeline = match weak_timeline.upgrade() {
+                        Some(timeline) => timeline,
+                        // The timeline was removed, nothing to probe anymore
+                        None => return Ok(()),
+                    };
+                    match spawn_blocking(move || timeline.probe_walredo()).await {
+                        Ok(true) => return Ok(()),
+                        Ok(false) => {}
+                        Err(e) => error!("Failed to run the WAL redo probe: {e}"),
+                    }
+                }
+            }
+            .instrument(info_span!("walredo_probe", tenant = %tenant_id, timeline = %timeline_id)),
+        );
+    }
+
+    /// Retries the read that found the WAL redo unavailable.
+    /// Returns `true` if the WAL redo works again.
+    ///
+    /// Only a read failing with [`WalRedoUnavailable`] keeps the outage: the read might not need
+    /// the WAL redo anymore, e.g. after compaction, or fail for other reasons, and the page
+    /// service would reject the reads forever otherwise.
+    pub(super) fn probe_walredo(&self) -> bool {
+        let (key, lsn) = match self.walredo_outage.lock().unwrap().as_ref() {
+            Some(outage) => (outage.key, outage.lsn),
+            None => return true,
+        };
+        match self.get(key, lsn) {
+            Err(e) if e.downcast_ref::<WalRedoUnavailable>().is_some() => {
+                debug!("WAL redo probe failed: {e:#}");
+                return false;
+            }
+            Err(e) => debug!("WAL redo probe failed for a reason other than the WAL redo: {e:#}"),
+            Ok(_) => {}
+        }
+        if self.walredo_outage.lock().unwrap().take().is_some() {
+            info!("WAL redo is not reported unavailable anymore, accepting the page reads");
+        }
+        true
+    }
+
     fn can_upload_layers(&self) -> bool {
         self.upload_layers.load(atomic::Ordering::Relaxed)
             && self.current_state() != TimelineState::Broken
     }
 }
 
+/// Reconstructing a value needs more layers than the `max_layers_per_get` tenant setting
+/// allows. Creating image layers for the key makes the following reads of it cheaper.
+#[derive(Debug, thiserror::Error)]
+#[error("reading key {key} at LSN {lsn} needs more than {max_layers} layers")]
+pub struct ReadTooExpensive {
+    pub key: Key,
+    pub lsn: Lsn,
+    pub max_layers: usize,
+}
+
+/// How often a timeline with the unavailable WAL redo retries it.
+pub const WALREDO_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The read that found the WAL redo unavailable, see [`Timeline::request_redo`].
+struct WalRedoOutage {
+    key: Key,
+    lsn: Lsn,
+    /// Whether the task retrying the read was started, see [`Timeline::check_walredo_available`].
+    probing: bool,
+}
+
+/// The WAL redo failed with a retriable error, see [`crate::walredo::WalRedoError::is_retriable`].
+/// The page reads of the timeline are rejected until the WAL redo works again.
+#[derive(Debug, thiserror::Error)]
+#[error("WAL redo for key {key} at LSN {lsn} is temporarily unavailable")]
+pub struct WalRedoUnavailable {
+    pub key: Key,
+    pub lsn: Lsn,
+}
+
+/// Whether a stored version of a key is a full image or a WAL record to apply on
+/// top of the previous version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Image,
+    WalRecord,
+}
+
+/// A stored version of a key, see [`Timeline::key_history`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyVersion {
+    pub lsn: Lsn,
+    pub kind: ValueKind,
+    /// The timeline, either the requested one or its ancestor, that holds the version.
+    pub timeline_id: TimelineId,
+    /// Name of the layer that holds the version.
+    pub layer: PathBuf,
+}
+
+/// Held by the GC iteration collecting the timeline, see [`Timeline::try_start_gc`].
+pub(super) struct GcInProgressGuard<'a> {
+    timeline: &'a Timeline,
+}
+
+impl Drop for GcInProgressGuard<'_> {
+    fn drop(&mut self) {
+        self.timeline
+            .gc_in_progress
+            .store(false, AtomicOrdering::SeqCst);
+    }
+}
+
 /// Helper function for get_reconstruct_data() to add the path of layers traversed
 /// to an error, as anyhow context information.
 fn layer_traversal_error(
@@ -2298,6 +3134,7 @@ fn layer_traversal_error(
 // but will cause large code changes.
 pub struct TimelineWriter<'a> {
     tl: &'a Timeline,
+    read_only: bool,
     _write_guard: MutexGuard<'a, ()>,
 }
 
@@ -2315,10 +3152,12 @@ impl<'a> TimelineWriter<'a> {
     /// This will implicitly extend the relation, if the page is beyond the
     /// current end-of-file.
     pub fn put(&self, key: Key, lsn: Lsn, value: &Value) -> anyhow::Result<()> {
+        self.ensure_writable()?;
         self.tl.put_value(key, lsn, value)
     }
 
     pub fn delete(&self, key_range: Range<Key>, lsn: Lsn) -> anyhow::Result<()> {
+        self.ensure_writable()?;
         self.tl.put_tombstone(key_range, lsn)
     }
 
@@ -2330,13 +3169,25 @@ impl<'a> TimelineWriter<'a> {
     /// 'lsn' must be aligned. This wakes up any wait_lsn() callers waiting for
     /// the 'lsn' or anything older. The previous last record LSN is stored alongside
     /// the latest and can be read.
-    pub fn finish_write(&self, new_lsn: Lsn) {
-        self.tl.finish_write(new_lsn);
+    ///
+    /// Fails if 'lsn' is behind the last record LSN of the timeline.
+    pub fn finish_write(&self, new_lsn: Lsn) -> anyhow::Result<()> {
+        self.ensure_writable()?;
+        self.tl.finish_write(new_lsn)
     }
 
     pub fn update_current_logical_size(&self, delta: i64) {
         self.tl.update_current_logical_size(delta)
     }
+
+    fn ensure_writable(&self) -> anyhow::Result<()> {
+        ensure!(
+            !self.read_only,
+            "Timeline {} is read-only",
+            self.tl.timeline_id
+        );
+        Ok(())
+    }
 }
 
 /// Add a suffix to a layer file's name: .{num}.old
@@ -0,0 +1,217 @@
+//! Settings of a timeline that are set through the management API rather than by the timeline's
+//! own processing, like its label, savepoints or the read-only flag.
+//!
+//! The settings are stored in a JSON file next to the timeline [`metadata`](super::metadata),
+//! which stays a fixed-size file with the LSNs needed to load the timeline.
+//! Unlike the metadata, the settings file is not uploaded to the remote storage, same as
+//! the tenant config: a timeline attached from the remote storage starts with the default settings.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::info_span;
+use utils::{
+    crashsafe::{self, path_with_suffix_extension},
+    id::{TenantId, TimelineId},
+    lsn::Lsn,
+};
+
+use crate::config::PageServerConf;
+use crate::virtual_file::VirtualFile;
+use crate::TEMP_FILE_SUFFIX;
+
+/// Maximum length of the timeline label, in bytes.
+pub const TIMELINE_LABEL_MAX_LEN: usize = 256;
+
+/// Maximum length of a savepoint name and the maximum number of savepoints of a timeline.
+pub const TIMELINE_SAVEPOINT_NAME_MAX_LEN: usize = 20;
+pub const TIMELINE_SAVEPOINTS_MAX: usize = 4;
+
+/// Maximum length of the timeline creator, in bytes.
+pub const TIMELINE_CREATOR_MAX_LEN: usize = 256;
+
+/// Settings stored on disk for each timeline.
+///
+/// Every field has a default, for the settings files written before the field was added
+/// and for the timelines without a settings file.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimelineSettings {
+    /// Optional human-readable label, not used by the pageserver itself.
+    pub label: Option<String>,
+    /// Named LSNs to branch the timeline at.
+    #[serde_as(as = "BTreeMap<_, DisplayFromStr>")]
+    pub savepoints: BTreeMap<String, Lsn>,
+    /// Wall-clock time of the timeline creation, `UNIX_EPOCH` if unknown.
+    #[serde(with = "humantime_serde")]
+    pub created_at: SystemTime,
+    /// Optional free-form description of who created the timeline.
+    pub created_by: Option<String>,
+    /// Writes to a read-only timeline are rejected, branching from it is still allowed.
+    pub read_only: bool,
+    /// Overrides the tenant's `image_creation_threshold` for the timeline.
+    pub image_creation_threshold: Option<usize>,
+}
+
+impl Default for TimelineSettings {
+    fn default() -> Self {
+        Self {
+            label: None,
+            savepoints: BTreeMap::new(),
+            created_at: SystemTime::UNIX_EPOCH,
+            created_by: None,
+            read_only: false,
+            image_creation_threshold: None,
+        }
+    }
+}
+
+/// Save timeline settings to file
+///
+/// The new settings are written into a temporary file first, and then atomically renamed
+/// over the current ones, so a crash leaves either the old or the new settings behind.
+pub fn save_timeline_settings(
+    conf: &'static PageServerConf,
+    timeline_id: TimelineId,
+    tenant_id: TenantId,
+    settings: &TimelineSettings,
+) -> anyhow::Result<()> {
+    let _enter = info_span!("saving timeline settings").entered();
+    let path = conf.timeline_settings_path(timeline_id, tenant_id);
+    let settings_bytes =
+        serde_json::to_vec_pretty(settings).context("Failed to serialize timeline settings")?;
+
+    let temp_path = path_with_suffix_extension(&path, TEMP_FILE_SUFFIX);
+    let mut file = VirtualFile::open_with_options(
+        &temp_path,
+        OpenOptions::new().write(true).create(true).truncate(true),
+    )?;
+    file.write_all(&settings_bytes)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, &path).with_context(|| {
+        format!(
+            "Failed to move new timeline settings file to {}",
+            path.display()
+        )
+    })?;
+    // fsync the parent directory to ensure the directory entry is durable
+    crashsafe::fsync(
+        path.parent()
+            .expect("Timeline settings should always have a parent dir"),
+    )?;
+
+    Ok(())
+}
+
+/// Loads the timeline settings, or the default ones if the timeline has no settings file.
+pub fn load_timeline_settings(
+    conf: &'static PageServerConf,
+    timeline_id: TimelineId,
+    tenant_id: TenantId,
+) -> anyhow::Result<TimelineSettings> {
+    let path = conf.timeline_settings_path(timeline_id, tenant_id);
+    let settings_bytes = match fs::read(&path) {
+        Ok(settings_bytes) => settings_bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(TimelineSettings::default()),
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!(
+                    "Failed to read timeline settings from path {}",
+                    path.display()
+                )
+            })
+        }
+    };
+    serde_json::from_slice(&settings_bytes).with_context(|| {
+        format!(
+            "Failed to parse timeline settings from path {}",
+            path.display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::harness::{TenantHarness, TIMELINE_ID};
+
+    #[test]
+    fn timeline_settings_round_trip() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("timeline_settings_round_trip")?;
+        let (conf, tenant_id) = (harness.conf, harness.tenant_id);
+        fs::create_dir_all(harness.timeline_path(&TIMELINE_ID))?;
+
+        // No settings file
+        assert_eq!(
+            load_timeline_settings(conf, TIMELINE_ID, tenant_id)?,
+            TimelineSettings::default()
+        );
+
+        let settings = TimelineSettings {
+            label: Some("main branch".to_string()),
+            savepoints: BTreeMap::from([
+                ("before migration".to_string(), Lsn(0x100)),
+                ("release".to_string(), Lsn(0x208)),
+            ]),
+            created_at: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+            created_by: Some("console".to_string()),
+            read_only: true,
+            image_creation_threshold: Some(5),
+        };
+        save_timeline_settings(conf, TIMELINE_ID, tenant_id, &settings)?;
+        assert_eq!(
+            load_timeline_settings(conf, TIMELINE_ID, tenant_id)?,
+            settings
+        );
+
+        let settings = TimelineSettings {
+            label: None,
+            ..settings
+        };
+        save_timeline_settings(conf, TIMELINE_ID, tenant_id, &settings)?;
+        assert_eq!(
+            load_timeline_settings(conf, TIMELINE_ID, tenant_id)?,
+            settings
+        );
+        assert!(!path_with_suffix_extension(
+            conf.timeline_settings_path(TIMELINE_ID, tenant_id),
+            TEMP_FILE_SUFFIX
+        )
+        .exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_settings_missing_fields_default() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("timeline_settings_missing_fields_default")?;
+        let (conf, tenant_id) = (harness.conf, harness.tenant_id);
+        fs::create_dir_all(harness.timeline_path(&TIMELINE_ID))?;
+
+        fs::write(
+            conf.timeline_settings_path(TIMELINE_ID, tenant_id),
+            r#"{ "label": "main branch", "savepoints": { "release": "0/208" } }"#,
+        )?;
+        assert_eq!(
+            load_timeline_settings(conf, TIMELINE_ID, tenant_id)?,
+            TimelineSettings {
+                label: Some("main branch".to_string()),
+                savepoints: BTreeMap::from([("release".to_string(), Lsn(0x208))]),
+                ..TimelineSettings::default()
+            }
+        );
+
+        fs::write(conf.timeline_settings_path(TIMELINE_ID, tenant_id), "{")?;
+        assert!(load_timeline_settings(conf, TIMELINE_ID, tenant_id).is_err());
+
+        Ok(())
+    }
+}
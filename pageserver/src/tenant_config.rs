@@ -8,9 +8,13 @@
 //! We cannot use global or default config instead, because wrong settings
 //! may lead to a data loss.
 //!
+use anyhow::ensure;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::num::NonZeroU64;
+use std::str::FromStr;
 use std::time::Duration;
+use utils::lsn::Lsn;
 
 pub mod defaults {
     // FIXME: This current value is very low. I would imagine something like 1 GB or 10 GB
@@ -23,6 +27,9 @@ pub mod defaults {
     // Target file size, when creating image and delta layers.
     // This parameter determines L1 layer file size.
     pub const DEFAULT_COMPACTION_TARGET_SIZE: u64 = 128 * 1024 * 1024;
+    // Hard ceiling for the layer file size, 0 means a multiple of the target size.
+    pub const DEFAULT_COMPACTION_MAX_SIZE: u64 = 0;
+    pub const COMPACTION_MAX_SIZE_TARGET_MULTIPLIER: u64 = 4;
 
     pub const DEFAULT_COMPACTION_PERIOD: &str = "20 s";
     pub const DEFAULT_COMPACTION_THRESHOLD: usize = 10;
@@ -31,9 +38,67 @@ pub mod defaults {
     pub const DEFAULT_GC_PERIOD: &str = "100 s";
     pub const DEFAULT_IMAGE_CREATION_THRESHOLD: usize = 3;
     pub const DEFAULT_PITR_INTERVAL: &str = "30 days";
+    pub const DEFAULT_GC_CUTOFF_POLICY: &str = "min";
     pub const DEFAULT_WALRECEIVER_CONNECT_TIMEOUT: &str = "2 seconds";
     pub const DEFAULT_WALRECEIVER_LAGGING_WAL_TIMEOUT: &str = "3 seconds";
     pub const DEFAULT_MAX_WALRECEIVER_LSN_WAL_LAG: u64 = 10 * 1024 * 1024;
+    pub const DEFAULT_COMPACTION_CONCURRENCY: usize = 1;
+    pub const DEFAULT_MAX_ANCESTOR_DEPTH: usize = 0;
+    pub const DEFAULT_COMPACTION_BATCH_SIZE: usize = 0;
+    pub const DEFAULT_MAX_LAYERS_PER_GET: usize = 0;
+    pub const DEFAULT_MAX_UPLOAD_BYTES_PER_SEC: u64 = 0;
+    pub const DEFAULT_GC_RESPECT_REMOTE_BRANCHPOINTS: bool = true;
+    pub const DEFAULT_CHECKPOINT_MAX_INMEM_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+}
+
+/// How the GC horizon and PITR cutoffs of a timeline combine into its GC cutoff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GcCutoffPolicy {
+    /// The earlier of the two cutoffs: the data is kept as long as any of them needs it.
+    /// This retains the most data and is the safe default.
+    Min,
+    /// The later of the two cutoffs: the data is kept only as long as both of them need it.
+    /// This is the more aggressive mode, GC removes the data that either cutoff alone allows
+    /// to remove, e.g. the data within the PITR interval but behind the horizon.
+    Max,
+}
+
+impl GcCutoffPolicy {
+    /// Returns the GC cutoff for the given horizon and PITR cutoffs.
+    pub fn combine(self, horizon_cutoff: Lsn, pitr_cutoff: Lsn) -> Lsn {
+        match self {
+            GcCutoffPolicy::Min => Lsn::min(horizon_cutoff, pitr_cutoff),
+            GcCutoffPolicy::Max => Lsn::max(horizon_cutoff, pitr_cutoff),
+        }
+    }
+}
+
+impl Default for GcCutoffPolicy {
+    fn default() -> Self {
+        GcCutoffPolicy::Min
+    }
+}
+
+impl FromStr for GcCutoffPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "min" => Ok(GcCutoffPolicy::Min),
+            "max" => Ok(GcCutoffPolicy::Max),
+            _ => anyhow::bail!("unknown gc cutoff policy '{s}', expected 'min' or 'max'"),
+        }
+    }
+}
+
+impl fmt::Display for GcCutoffPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GcCutoffPolicy::Min => write!(f, "min"),
+            GcCutoffPolicy::Max => write!(f, "max"),
+        }
+    }
 }
 
 /// Per-tenant configuration options
@@ -50,6 +115,10 @@ pub struct TenantConf {
     // Target file size, when creating image and delta layers.
     // This parameter determines L1 layer file size.
     pub compaction_target_size: u64,
+    // Size that layer files created by compaction must never exceed, to bound the memory
+    // needed to process them. Unlike the target size, it forces a split even in the middle
+    // of building a layer. 0 means `COMPACTION_MAX_SIZE_TARGET_MULTIPLIER` times the target size.
+    pub compaction_max_size: u64,
     // How often to check if there's compaction work to be done.
     #[serde(with = "humantime_serde")]
     pub compaction_period: Duration,
@@ -71,6 +140,8 @@ pub struct TenantConf {
     // Page versions older than this are garbage collected away.
     #[serde(with = "humantime_serde")]
     pub pitr_interval: Duration,
+    // How the cutoffs derived from gc_horizon and pitr_interval combine into the GC cutoff.
+    pub gc_cutoff_policy: GcCutoffPolicy,
     /// Maximum amount of time to wait while opening a connection to receive wal, before erroring.
     #[serde(with = "humantime_serde")]
     pub walreceiver_connect_timeout: Duration,
@@ -82,6 +153,26 @@ pub struct TenantConf {
     /// A lagging safekeeper will be changed after `lagging_wal_timeout` time elapses since the last WAL update,
     /// to avoid eager reconnects.
     pub max_lsn_wal_lag: NonZeroU64,
+    // Maximum number of timelines compacted in parallel during one compaction iteration.
+    pub compaction_concurrency: usize,
+    // Maximum length of the ancestor chain of a timeline, branching deeper is rejected.
+    // 0 means the depth is unlimited.
+    pub max_ancestor_depth: usize,
+    // Maximum number of timelines compacted in one compaction iteration, the rest
+    // are compacted in the next iterations. 0 means all timelines are compacted.
+    pub compaction_batch_size: usize,
+    // Maximum number of layers a single read may visit to reconstruct a value, reads
+    // needing more fail instead. 0 means the number of layers is unlimited.
+    pub max_layers_per_get: usize,
+    // Maximum rate of the layer uploads to the remote storage, in bytes per second,
+    // shared by all timelines of the tenant. 0 means the rate is unlimited.
+    pub max_upload_bytes_per_sec: u64,
+    // Whether GC keeps the branchpoints of the timelines that are only present in the
+    // remote storage, so that their data is not collected before they are downloaded.
+    pub gc_respect_remote_branchpoints: bool,
+    // Size of the in-memory layer that forces a checkpoint, regardless of the LSN distance.
+    // Protects from running out of memory when a few huge records take a lot of space.
+    pub checkpoint_max_inmem_bytes: u64,
 }
 
 /// Same as TenantConf, but this struct preserves the information about
@@ -91,6 +182,7 @@ pub struct TenantConfOpt {
     pub checkpoint_distance: Option<u64>,
     pub checkpoint_timeout: Option<Duration>,
     pub compaction_target_size: Option<u64>,
+    pub compaction_max_size: Option<u64>,
     #[serde(with = "humantime_serde")]
     pub compaction_period: Option<Duration>,
     pub compaction_threshold: Option<usize>,
@@ -100,11 +192,19 @@ pub struct TenantConfOpt {
     pub image_creation_threshold: Option<usize>,
     #[serde(with = "humantime_serde")]
     pub pitr_interval: Option<Duration>,
+    pub gc_cutoff_policy: Option<GcCutoffPolicy>,
     #[serde(with = "humantime_serde")]
     pub walreceiver_connect_timeout: Option<Duration>,
     #[serde(with = "humantime_serde")]
     pub lagging_wal_timeout: Option<Duration>,
     pub max_lsn_wal_lag: Option<NonZeroU64>,
+    pub compaction_concurrency: Option<usize>,
+    pub max_ancestor_depth: Option<usize>,
+    pub compaction_batch_size: Option<usize>,
+    pub max_layers_per_get: Option<usize>,
+    pub max_upload_bytes_per_sec: Option<u64>,
+    pub gc_respect_remote_branchpoints: Option<bool>,
+    pub checkpoint_max_inmem_bytes: Option<u64>,
 }
 
 impl TenantConfOpt {
@@ -119,6 +219,9 @@ impl TenantConfOpt {
             compaction_target_size: self
                 .compaction_target_size
                 .unwrap_or(global_conf.compaction_target_size),
+            compaction_max_size: self
+                .compaction_max_size
+                .unwrap_or(global_conf.compaction_max_size),
             compaction_period: self
                 .compaction_period
                 .unwrap_or(global_conf.compaction_period),
@@ -131,6 +234,9 @@ impl TenantConfOpt {
                 .image_creation_threshold
                 .unwrap_or(global_conf.image_creation_threshold),
             pitr_interval: self.pitr_interval.unwrap_or(global_conf.pitr_interval),
+            gc_cutoff_policy: self
+                .gc_cutoff_policy
+                .unwrap_or(global_conf.gc_cutoff_policy),
             walreceiver_connect_timeout: self
                 .walreceiver_connect_timeout
                 .unwrap_or(global_conf.walreceiver_connect_timeout),
@@ -138,9 +244,107 @@ impl TenantConfOpt {
                 .lagging_wal_timeout
                 .unwrap_or(global_conf.lagging_wal_timeout),
             max_lsn_wal_lag: self.max_lsn_wal_lag.unwrap_or(global_conf.max_lsn_wal_lag),
+            compaction_concurrency: self
+                .compaction_concurrency
+                .unwrap_or(global_conf.compaction_concurrency),
+            max_ancestor_depth: self
+                .max_ancestor_depth
+                .unwrap_or(global_conf.max_ancestor_depth),
+            compaction_batch_size: self
+                .compaction_batch_size
+                .unwrap_or(global_conf.compaction_batch_size),
+            max_layers_per_get: self
+                .max_layers_per_get
+                .unwrap_or(global_conf.max_layers_per_get),
+            max_upload_bytes_per_sec: self
+                .max_upload_bytes_per_sec
+                .unwrap_or(global_conf.max_upload_bytes_per_sec),
+            gc_respect_remote_branchpoints: self
+                .gc_respect_remote_branchpoints
+                .unwrap_or(global_conf.gc_respect_remote_branchpoints),
+            checkpoint_max_inmem_bytes: self
+                .checkpoint_max_inmem_bytes
+                .unwrap_or(global_conf.checkpoint_max_inmem_bytes),
         }
     }
 
+    /// Checks that the config, merged with the global defaults, has no values
+    /// that would wedge the tenant's background tasks.
+    pub fn validate(&self, global_conf: TenantConf) -> anyhow::Result<()> {
+        TenantConfOpt::default().validate_update(self, global_conf)
+    }
+
+    /// Same as [`TenantConfOpt::validate`], for the config with the `update` applied.
+    /// The checks spanning several fields only concern the fields that the `update` sets.
+    pub fn validate_update(
+        &self,
+        update: &TenantConfOpt,
+        global_conf: TenantConf,
+    ) -> anyhow::Result<()> {
+        let mut updated_conf = *self;
+        updated_conf.update(update);
+        let conf = updated_conf.merge(global_conf);
+        ensure!(
+            conf.checkpoint_distance > 0,
+            "checkpoint_distance must be greater than zero"
+        );
+        ensure!(
+            !conf.checkpoint_timeout.is_zero(),
+            "checkpoint_timeout must be greater than zero"
+        );
+        ensure!(
+            conf.checkpoint_max_inmem_bytes > 0,
+            "checkpoint_max_inmem_bytes must be greater than zero"
+        );
+        ensure!(
+            conf.compaction_target_size > 0,
+            "compaction_target_size must be greater than zero"
+        );
+        ensure!(
+            conf.compaction_max_size == 0
+                || conf.compaction_max_size >= conf.compaction_target_size,
+            "compaction_max_size {} must not be less than compaction_target_size {}",
+            conf.compaction_max_size,
+            conf.compaction_target_size
+        );
+        ensure!(
+            !conf.compaction_period.is_zero(),
+            "compaction_period must be greater than zero"
+        );
+        ensure!(
+            conf.compaction_threshold > 0,
+            "compaction_threshold must be greater than zero"
+        );
+        ensure!(
+            conf.image_creation_threshold > 0,
+            "image_creation_threshold must be greater than zero"
+        );
+        // gc_horizon = 0 disables the GC loop, so the period doesn't matter then.
+        ensure!(
+            conf.gc_horizon == 0 || !conf.gc_period.is_zero(),
+            "gc_period must be greater than zero when gc_horizon is {}, set gc_horizon to 0 to disable GC",
+            conf.gc_horizon
+        );
+        ensure!(
+            !conf.walreceiver_connect_timeout.is_zero(),
+            "walreceiver_connect_timeout must be greater than zero"
+        );
+        ensure!(
+            conf.compaction_concurrency > 0,
+            "compaction_concurrency must be greater than zero"
+        );
+
+        // Disabling GC with gc_horizon = 0 alone is fine, the default pitr_interval is ignored then.
+        if update.pitr_interval.is_some() {
+            ensure!(
+                conf.gc_horizon > 0 || conf.pitr_interval.is_zero(),
+                "pitr_interval {:?} has no effect with gc_horizon 0, which disables GC, set pitr_interval to 0 too",
+                conf.pitr_interval
+            );
+        }
+        Ok(())
+    }
+
     pub fn update(&mut self, other: &TenantConfOpt) {
         if let Some(checkpoint_distance) = other.checkpoint_distance {
             self.checkpoint_distance = Some(checkpoint_distance);
@@ -151,6 +355,9 @@ impl TenantConfOpt {
         if let Some(compaction_target_size) = other.compaction_target_size {
             self.compaction_target_size = Some(compaction_target_size);
         }
+        if let Some(compaction_max_size) = other.compaction_max_size {
+            self.compaction_max_size = Some(compaction_max_size);
+        }
         if let Some(compaction_period) = other.compaction_period {
             self.compaction_period = Some(compaction_period);
         }
@@ -169,6 +376,9 @@ impl TenantConfOpt {
         if let Some(pitr_interval) = other.pitr_interval {
             self.pitr_interval = Some(pitr_interval);
         }
+        if let Some(gc_cutoff_policy) = other.gc_cutoff_policy {
+            self.gc_cutoff_policy = Some(gc_cutoff_policy);
+        }
         if let Some(walreceiver_connect_timeout) = other.walreceiver_connect_timeout {
             self.walreceiver_connect_timeout = Some(walreceiver_connect_timeout);
         }
@@ -178,10 +388,40 @@ impl TenantConfOpt {
         if let Some(max_lsn_wal_lag) = other.max_lsn_wal_lag {
             self.max_lsn_wal_lag = Some(max_lsn_wal_lag);
         }
+        if let Some(compaction_concurrency) = other.compaction_concurrency {
+            self.compaction_concurrency = Some(compaction_concurrency);
+        }
+        if let Some(max_ancestor_depth) = other.max_ancestor_depth {
+            self.max_ancestor_depth = Some(max_ancestor_depth);
+        }
+        if let Some(compaction_batch_size) = other.compaction_batch_size {
+            self.compaction_batch_size = Some(compaction_batch_size);
+        }
+        if let Some(max_layers_per_get) = other.max_layers_per_get {
+            self.max_layers_per_get = Some(max_layers_per_get);
+        }
+        if let Some(max_upload_bytes_per_sec) = other.max_upload_bytes_per_sec {
+            self.max_upload_bytes_per_sec = Some(max_upload_bytes_per_sec);
+        }
+        if let Some(gc_respect_remote_branchpoints) = other.gc_respect_remote_branchpoints {
+            self.gc_respect_remote_branchpoints = Some(gc_respect_remote_branchpoints);
+        }
+        if let Some(checkpoint_max_inmem_bytes) = other.checkpoint_max_inmem_bytes {
+            self.checkpoint_max_inmem_bytes = Some(checkpoint_max_inmem_bytes);
+        }
     }
 }
 
 impl TenantConf {
+    /// The hard ceiling for compacted layer sizes, with the `0` default resolved.
+    pub fn effective_compaction_max_size(&self) -> u64 {
+        if self.compaction_max_size == 0 {
+            self.compaction_target_size * defaults::COMPACTION_MAX_SIZE_TARGET_MULTIPLIER
+        } else {
+            self.compaction_max_size
+        }
+    }
+
     pub fn default() -> TenantConf {
         use defaults::*;
 
@@ -190,6 +430,7 @@ impl TenantConf {
             checkpoint_timeout: humantime::parse_duration(DEFAULT_CHECKPOINT_TIMEOUT)
                 .expect("cannot parse default checkpoint timeout"),
             compaction_target_size: DEFAULT_COMPACTION_TARGET_SIZE,
+            compaction_max_size: DEFAULT_COMPACTION_MAX_SIZE,
             compaction_period: humantime::parse_duration(DEFAULT_COMPACTION_PERIOD)
                 .expect("cannot parse default compaction period"),
             compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
@@ -199,6 +440,9 @@ impl TenantConf {
             image_creation_threshold: DEFAULT_IMAGE_CREATION_THRESHOLD,
             pitr_interval: humantime::parse_duration(DEFAULT_PITR_INTERVAL)
                 .expect("cannot parse default PITR interval"),
+            gc_cutoff_policy: DEFAULT_GC_CUTOFF_POLICY
+                .parse()
+                .expect("cannot parse default gc cutoff policy"),
             walreceiver_connect_timeout: humantime::parse_duration(
                 DEFAULT_WALRECEIVER_CONNECT_TIMEOUT,
             )
@@ -207,6 +451,13 @@ impl TenantConf {
                 .expect("cannot parse default walreceiver lagging wal timeout"),
             max_lsn_wal_lag: NonZeroU64::new(DEFAULT_MAX_WALRECEIVER_LSN_WAL_LAG)
                 .expect("cannot parse default max walreceiver Lsn wal lag"),
+            compaction_concurrency: DEFAULT_COMPACTION_CONCURRENCY,
+            max_ancestor_depth: DEFAULT_MAX_ANCESTOR_DEPTH,
+            compaction_batch_size: DEFAULT_COMPACTION_BATCH_SIZE,
+            max_layers_per_get: DEFAULT_MAX_LAYERS_PER_GET,
+            max_upload_bytes_per_sec: DEFAULT_MAX_UPLOAD_BYTES_PER_SEC,
+            gc_respect_remote_branchpoints: DEFAULT_GC_RESPECT_REMOTE_BRANCHPOINTS,
+            checkpoint_max_inmem_bytes: DEFAULT_CHECKPOINT_MAX_INMEM_BYTES,
         }
     }
 
@@ -216,12 +467,14 @@ impl TenantConf {
             checkpoint_distance: defaults::DEFAULT_CHECKPOINT_DISTANCE,
             checkpoint_timeout: Duration::from_secs(600),
             compaction_target_size: 4 * 1024 * 1024,
+            compaction_max_size: defaults::DEFAULT_COMPACTION_MAX_SIZE,
             compaction_period: Duration::from_secs(10),
             compaction_threshold: defaults::DEFAULT_COMPACTION_THRESHOLD,
             gc_horizon: defaults::DEFAULT_GC_HORIZON,
             gc_period: Duration::from_secs(10),
             image_creation_threshold: defaults::DEFAULT_IMAGE_CREATION_THRESHOLD,
             pitr_interval: Duration::from_secs(60 * 60),
+            gc_cutoff_policy: GcCutoffPolicy::Min,
             walreceiver_connect_timeout: humantime::parse_duration(
                 defaults::DEFAULT_WALRECEIVER_CONNECT_TIMEOUT,
             )
@@ -232,6 +485,196 @@ impl TenantConf {
             .unwrap(),
             max_lsn_wal_lag: NonZeroU64::new(defaults::DEFAULT_MAX_WALRECEIVER_LSN_WAL_LAG)
                 .unwrap(),
+            compaction_concurrency: defaults::DEFAULT_COMPACTION_CONCURRENCY,
+            max_ancestor_depth: defaults::DEFAULT_MAX_ANCESTOR_DEPTH,
+            compaction_batch_size: defaults::DEFAULT_COMPACTION_BATCH_SIZE,
+            max_layers_per_get: defaults::DEFAULT_MAX_LAYERS_PER_GET,
+            max_upload_bytes_per_sec: defaults::DEFAULT_MAX_UPLOAD_BYTES_PER_SEC,
+            gc_respect_remote_branchpoints: defaults::DEFAULT_GC_RESPECT_REMOTE_BRANCHPOINTS,
+            checkpoint_max_inmem_bytes: defaults::DEFAULT_CHECKPOINT_MAX_INMEM_BYTES,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid(conf: TenantConfOpt, expected_error: &str) {
+        let err = conf
+            .validate(TenantConf::default())
+            .expect_err("config should be rejected");
+        assert!(
+            err.to_string().contains(expected_error),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        TenantConfOpt::default()
+            .validate(TenantConf::default())
+            .unwrap();
+        TenantConfOpt::default()
+            .validate(TenantConf::dummy_conf())
+            .unwrap();
+    }
+
+    #[test]
+    fn zero_thresholds_are_rejected() {
+        assert_invalid(
+            TenantConfOpt {
+                checkpoint_distance: Some(0),
+                ..TenantConfOpt::default()
+            },
+            "checkpoint_distance",
+        );
+        assert_invalid(
+            TenantConfOpt {
+                compaction_target_size: Some(0),
+                ..TenantConfOpt::default()
+            },
+            "compaction_target_size",
+        );
+        assert_invalid(
+            TenantConfOpt {
+                compaction_threshold: Some(0),
+                ..TenantConfOpt::default()
+            },
+            "compaction_threshold",
+        );
+        assert_invalid(
+            TenantConfOpt {
+                image_creation_threshold: Some(0),
+                ..TenantConfOpt::default()
+            },
+            "image_creation_threshold",
+        );
+        assert_invalid(
+            TenantConfOpt {
+                compaction_concurrency: Some(0),
+                ..TenantConfOpt::default()
+            },
+            "compaction_concurrency",
+        );
+    }
+
+    #[test]
+    fn compaction_max_size_is_not_below_target() {
+        assert_invalid(
+            TenantConfOpt {
+                compaction_target_size: Some(1024),
+                compaction_max_size: Some(1023),
+                ..TenantConfOpt::default()
+            },
+            "compaction_max_size",
+        );
+        let conf = TenantConfOpt {
+            compaction_target_size: Some(1024),
+            compaction_max_size: Some(1024),
+            ..TenantConfOpt::default()
+        };
+        conf.validate(TenantConf::default()).unwrap();
+        assert_eq!(
+            conf.merge(TenantConf::default())
+                .effective_compaction_max_size(),
+            1024
+        );
+
+        let derived = TenantConfOpt {
+            compaction_target_size: Some(1024),
+            ..TenantConfOpt::default()
+        }
+        .merge(TenantConf::default());
+        assert_eq!(
+            derived.effective_compaction_max_size(),
+            1024 * defaults::COMPACTION_MAX_SIZE_TARGET_MULTIPLIER
+        );
+    }
+
+    #[test]
+    fn zero_periods_are_rejected() {
+        assert_invalid(
+            TenantConfOpt {
+                checkpoint_timeout: Some(Duration::ZERO),
+                ..TenantConfOpt::default()
+            },
+            "checkpoint_timeout",
+        );
+        assert_invalid(
+            TenantConfOpt {
+                compaction_period: Some(Duration::ZERO),
+                ..TenantConfOpt::default()
+            },
+            "compaction_period",
+        );
+        assert_invalid(
+            TenantConfOpt {
+                walreceiver_connect_timeout: Some(Duration::ZERO),
+                ..TenantConfOpt::default()
+            },
+            "walreceiver_connect_timeout",
+        );
+    }
+
+    #[test]
+    fn zero_gc_period_requires_disabled_gc() {
+        assert_invalid(
+            TenantConfOpt {
+                gc_period: Some(Duration::ZERO),
+                ..TenantConfOpt::default()
+            },
+            "gc_period",
+        );
+        TenantConfOpt {
+            gc_period: Some(Duration::ZERO),
+            gc_horizon: Some(0),
+            pitr_interval: Some(Duration::ZERO),
+            ..TenantConfOpt::default()
+        }
+        .validate(TenantConf::default())
+        .unwrap();
+    }
+
+    #[test]
+    fn pitr_interval_requires_enabled_gc() {
+        TenantConfOpt {
+            gc_horizon: Some(0),
+            ..TenantConfOpt::default()
+        }
+        .validate(TenantConf::default())
+        .unwrap();
+        assert_invalid(
+            TenantConfOpt {
+                gc_horizon: Some(0),
+                pitr_interval: Some(Duration::from_secs(60)),
+                ..TenantConfOpt::default()
+            },
+            "pitr_interval",
+        );
+        TenantConfOpt {
+            gc_horizon: Some(0),
+            pitr_interval: Some(Duration::ZERO),
+            ..TenantConfOpt::default()
+        }
+        .validate(TenantConf::default())
+        .unwrap();
+
+        // Only the pitr_interval set by the update is checked against the disabled GC
+        let tenant_conf = TenantConfOpt {
+            pitr_interval: Some(Duration::from_secs(60)),
+            ..TenantConfOpt::default()
+        };
+        let disable_gc = TenantConfOpt {
+            gc_horizon: Some(0),
+            ..TenantConfOpt::default()
+        };
+        tenant_conf
+            .validate_update(&disable_gc, TenantConf::default())
+            .unwrap();
+        let err = disable_gc
+            .validate_update(&tenant_conf, TenantConf::default())
+            .expect_err("config should be rejected");
+        assert!(err.to_string().contains("pitr_interval"), "{err}");
+    }
+}
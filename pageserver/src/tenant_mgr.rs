@@ -12,13 +12,15 @@ use tracing::*;
 
 use remote_storage::GenericRemoteStorage;
 
-use crate::config::{PageServerConf, METADATA_FILE_NAME, TIMELINE_UNINIT_MARK_SUFFIX};
+use crate::config::{PageServerConf, TIMELINE_SETTINGS_FILE_NAME, TIMELINE_UNINIT_MARK_SUFFIX};
 use crate::http::models::TenantInfo;
 use crate::storage_sync::index::{LayerFileMetadata, RemoteIndex, RemoteTimelineIndex};
 use crate::storage_sync::{self, LocalTimelineInitStatus, SyncStartupData, TimelineLocalFiles};
 use crate::task_mgr::{self, TaskKind};
 use crate::tenant::{
-    ephemeral_file::is_ephemeral_file, metadata::TimelineMetadata, Tenant, TenantState,
+    ephemeral_file::is_ephemeral_file,
+    metadata::{is_metadata_file, load_metadata_from_dir, TimelineMetadata},
+    Tenant, TenantState, UninitMarkReconciliation,
 };
 use crate::tenant_config::TenantConfOpt;
 use crate::walredo::PostgresRedoManager;
@@ -164,9 +166,24 @@ pub fn attach_local_tenants(
                         tenant.set_state(TenantState::Broken);
                     }
                     TenantAttachData::Ready(_) => {
-                        match Tenant::load_tenant_config(conf, tenant_id) {
+                        match tenant.reconcile_uninit_marks() {
+                            Ok(reconciliation)
+                                if reconciliation == UninitMarkReconciliation::default() => {}
+                            Ok(reconciliation) => info!(
+                                "Reconciled uninit marks of tenant {tenant_id}: {reconciliation:?}"
+                            ),
+                            Err(e) => error!(
+                                "Failed to reconcile uninit marks of tenant {tenant_id}: {e:?}"
+                            ),
+                        }
+                        match tenant.cleanup_stale_temp_files() {
+                            Ok(0) => {}
+                            Ok(removed) => info!("Removed {removed} stale temporary files of tenant {tenant_id}"),
+                            Err(e) => error!("Failed to remove stale temporary files of tenant {tenant_id}: {e:?}"),
+                        }
+                        match Tenant::load_tenant_config(conf, tenant_id, false) {
                             Ok(tenant_conf) => {
-                                tenant.update_tenant_config(tenant_conf);
+                                tenant.apply_loaded_tenant_config(tenant_conf);
                                 tenant.activate(false);
                             }
                             Err(e) => {
@@ -241,7 +258,10 @@ pub async fn shutdown_all_tenants() {
         let tenant_id = tenant.tenant_id();
         debug!("shutdown tenant {tenant_id}");
 
-        if let Err(err) = tenant.checkpoint() {
+        let checkpoint_progress = |timeline_id, flushed_bytes| {
+            info!("checkpointed timeline {tenant_id}/{timeline_id}, flushed {flushed_bytes} bytes")
+        };
+        if let Err(err) = tenant.checkpoint(checkpoint_progress) {
             error!("Could not checkpoint tenant {tenant_id} during shutdown: {err:?}");
         }
     }
@@ -384,6 +404,9 @@ pub fn create_tenant(
             Ok(None)
         }
         hash_map::Entry::Vacant(v) => {
+            tenant_conf
+                .validate(conf.default_tenant_conf)
+                .context("Invalid tenant config")?;
             let wal_redo_manager = Arc::new(PostgresRedoManager::new(conf, tenant_id));
             create_tenant_files(conf, tenant_conf, tenant_id)?;
             let tenant = Arc::new(Tenant::new(
@@ -407,7 +430,7 @@ pub fn update_tenant_config(
     tenant_id: TenantId,
 ) -> anyhow::Result<()> {
     info!("configuring tenant {tenant_id}");
-    get_tenant(tenant_id, true)?.update_tenant_config(tenant_conf);
+    get_tenant(tenant_id, true)?.update_tenant_config(tenant_conf)?;
     Tenant::persist_tenant_config(&conf.tenant_config_path(tenant_id), tenant_conf, false)?;
     Ok(())
 }
@@ -476,6 +499,7 @@ pub async fn detach_tenant(
     tenant.set_state(TenantState::Paused);
     // shutdown all tenant and timeline tasks: gc, compaction, page service)
     task_mgr::shutdown_tasks(None, Some(tenant_id), None).await;
+    storage_sync::set_upload_rate_limit(tenant_id, 0);
 
     // If removal fails there will be no way to successfully retry detach,
     // because the tenant no longer exists in the in-memory map. And it needs to be removed from it
@@ -694,28 +718,8 @@ fn collect_timelines_for_tenant(
                         );
                     }
                 } else if is_uninit_mark(&timeline_dir) {
-                    let timeline_uninit_mark_file = &timeline_dir;
-                    info!(
-                        "Found an uninit mark file {}, removing the timeline and its uninit mark",
-                        timeline_uninit_mark_file.display()
-                    );
-                    let timeline_id = timeline_uninit_mark_file
-                        .file_stem()
-                        .and_then(OsStr::to_str)
-                        .unwrap_or_default()
-                        .parse::<TimelineId>()
-                        .with_context(|| {
-                            format!(
-                                "Could not parse timeline id out of the timeline uninit mark name {}",
-                                timeline_uninit_mark_file.display()
-                            )
-                        })?;
-                    let timeline_dir = config.timeline_path(&timeline_id, &tenant_id);
-                    if let Err(e) =
-                        remove_timeline_and_uninit_mark(&timeline_dir, timeline_uninit_mark_file)
-                    {
-                        error!("Failed to clean up uninit marked timeline: {e:?}");
-                    }
+                    // removed by Tenant::reconcile_uninit_marks when the tenant is loaded
+                    debug!("Skipping uninit mark file {}", timeline_dir.display());
                 } else {
                     let timeline_id = timeline_dir
                         .file_name()
@@ -731,13 +735,7 @@ fn collect_timelines_for_tenant(
                     let timeline_uninit_mark_file =
                         config.timeline_uninit_mark_file_path(tenant_id, timeline_id);
                     if timeline_uninit_mark_file.exists() {
-                        info!("Found an uninit mark file for timeline {tenant_id}/{timeline_id}, removing the timeline and its uninit mark");
-                        if let Err(e) = remove_timeline_and_uninit_mark(
-                            &timeline_dir,
-                            &timeline_uninit_mark_file,
-                        ) {
-                            error!("Failed to clean up uninit marked timeline: {e:?}");
-                        }
+                        info!("Found an uninit mark file for timeline {tenant_id}/{timeline_id}, skipping the partially created timeline");
                     } else {
                         match collect_timeline_files(&timeline_dir) {
                             Ok((metadata, timeline_files)) => {
@@ -783,40 +781,13 @@ fn collect_timelines_for_tenant(
     Ok((tenant_id, TenantAttachData::Ready(tenant_timelines)))
 }
 
-fn remove_timeline_and_uninit_mark(timeline_dir: &Path, uninit_mark: &Path) -> anyhow::Result<()> {
-    fs::remove_dir_all(&timeline_dir)
-        .or_else(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                // we can leave the uninit mark without a timeline dir,
-                // just remove the mark then
-                Ok(())
-            } else {
-                Err(e)
-            }
-        })
-        .with_context(|| {
-            format!(
-                "Failed to remove unit marked timeline directory {}",
-                timeline_dir.display()
-            )
-        })?;
-    fs::remove_file(&uninit_mark).with_context(|| {
-        format!(
-            "Failed to remove timeline uninit mark file {}",
-            uninit_mark.display()
-        )
-    })?;
-
-    Ok(())
-}
-
 // discover timeline files and extract timeline metadata
 //  NOTE: ephemeral files are excluded from the list
 fn collect_timeline_files(
     timeline_dir: &Path,
 ) -> anyhow::Result<(TimelineMetadata, HashMap<PathBuf, LayerFileMetadata>)> {
     let mut timeline_files = HashMap::new();
-    let mut timeline_metadata_path = None;
+    let mut has_metadata = false;
 
     let timeline_dir_entries =
         fs::read_dir(&timeline_dir).context("Failed to list timeline dir contents")?;
@@ -825,8 +796,11 @@ fn collect_timeline_files(
         let metadata = entry_path.metadata()?;
 
         if metadata.is_file() {
-            if entry_path.file_name().and_then(OsStr::to_str) == Some(METADATA_FILE_NAME) {
-                timeline_metadata_path = Some(entry_path);
+            let file_name = entry_path.file_name().and_then(OsStr::to_str);
+            if file_name.map_or(false, is_metadata_file) {
+                has_metadata = true;
+            } else if file_name == Some(TIMELINE_SETTINGS_FILE_NAME) {
+                continue;
             } else if is_ephemeral_file(&entry_path.file_name().unwrap().to_string_lossy()) {
                 debug!("skipping ephemeral file {}", entry_path.display());
                 continue;
@@ -852,14 +826,11 @@ fn collect_timeline_files(
     //   be aware of that and retry attach if awaits_download for timeline switched from true to false
     //   but timelinne didn't appear locally.
     //   Check what happens with remote index in that case.
-    let timeline_metadata_path = match timeline_metadata_path {
-        Some(path) => path,
-        None => anyhow::bail!("No metadata file found in the timeline directory"),
-    };
-    let metadata = TimelineMetadata::from_bytes(
-        &fs::read(&timeline_metadata_path).context("Failed to read timeline metadata file")?,
-    )
-    .context("Failed to parse timeline metadata file bytes")?;
+    anyhow::ensure!(
+        has_metadata,
+        "No metadata file found in the timeline directory"
+    );
+    let metadata = load_metadata_from_dir(timeline_dir)?;
 
     anyhow::ensure!(
         metadata.ancestor_timeline().is_some() || !timeline_files.is_empty(),
@@ -7,12 +7,12 @@ use std::time::Duration;
 
 use crate::metrics::TENANT_TASK_EVENTS;
 use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
-use crate::tenant::{Tenant, TenantState};
+use crate::tenant::{GcRequest, Tenant};
 use crate::tenant_mgr;
 use tracing::*;
 use utils::id::TenantId;
 
-pub fn start_background_loops(tenant_id: TenantId) {
+pub fn start_compaction_loop(tenant_id: TenantId) {
     task_mgr::spawn(
         BACKGROUND_RUNTIME.handle(),
         TaskKind::Compaction,
@@ -27,6 +27,9 @@ pub fn start_background_loops(tenant_id: TenantId) {
             Ok(())
         },
     );
+}
+
+pub fn start_gc_loop(tenant_id: TenantId) {
     task_mgr::spawn(
         BACKGROUND_RUNTIME.handle(),
         TaskKind::GarbageCollector,
@@ -59,7 +62,7 @@ async fn compaction_loop(tenant_id: TenantId) {
                     info!("received cancellation request");
                     return;
                 },
-                tenant_wait_result = wait_for_active_tenant(tenant_id, wait_duration) => match tenant_wait_result {
+                tenant_wait_result = wait_for_active_tenant(tenant_id, wait_duration, Tenant::should_run_compaction) => match tenant_wait_result {
                     ControlFlow::Break(()) => return,
                     ControlFlow::Continue(tenant) => tenant,
                 },
@@ -75,6 +78,10 @@ async fn compaction_loop(tenant_id: TenantId) {
                 #[cfg(feature = "testing")]
                 std::process::abort();
             }
+            // Refresh the tenant size metric
+            if let Err(e) = tenant.total_local_size() {
+                error!("Failed to calculate tenant local size: {e:#}");
+            }
 
             // Sleep
             tokio::select! {
@@ -99,6 +106,9 @@ async fn gc_loop(tenant_id: TenantId) {
     let wait_duration = Duration::from_secs(2);
     info!("starting");
     TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
+    // Lets the GC iteration notice the shutdown request in the middle of a timeline
+    let cancel = task_mgr::shutdown_token();
+    let _cancel_on_exit = cancel.clone().drop_guard();
     async {
         loop {
             trace!("waking up");
@@ -108,7 +118,7 @@ async fn gc_loop(tenant_id: TenantId) {
                     info!("received cancellation request");
                     return;
                 },
-                tenant_wait_result = wait_for_active_tenant(tenant_id, wait_duration) => match tenant_wait_result {
+                tenant_wait_result = wait_for_active_tenant(tenant_id, wait_duration, Tenant::should_run_gc) => match tenant_wait_result {
                     ControlFlow::Break(()) => return,
                     ControlFlow::Continue(tenant) => tenant,
                 },
@@ -116,11 +126,11 @@ async fn gc_loop(tenant_id: TenantId) {
 
             // Run gc
             let gc_period = tenant.get_gc_period();
-            let gc_horizon = tenant.get_gc_horizon();
+            let mut gc_request = GcRequest::from_tenant_conf(&tenant, None);
+            gc_request.remote_branchpoints = tenant.remote_branchpoints().await;
             let mut sleep_duration = gc_period;
-            if gc_horizon > 0 {
-                if let Err(e) = tenant.gc_iteration(None, gc_horizon, tenant.get_pitr_interval(), false)
-                {
+            if gc_request.horizon > 0 {
+                if let Err(e) = tenant.gc_iteration(&gc_request, &cancel) {
                     sleep_duration = wait_duration;
                     error!("Gc failed, retrying in {:?}: {e:#}", sleep_duration);
                     #[cfg(feature = "testing")]
@@ -143,9 +153,11 @@ async fn gc_loop(tenant_id: TenantId) {
     trace!("GC loop stopped.");
 }
 
+/// Waits until the tenant is active and `should_run` allows the task to proceed.
 async fn wait_for_active_tenant(
     tenant_id: TenantId,
     wait: Duration,
+    should_run: fn(&Tenant) -> bool,
 ) -> ControlFlow<(), Arc<Tenant>> {
     let tenant = loop {
         match tenant_mgr::get_tenant(tenant_id, false) {
@@ -156,27 +168,29 @@ async fn wait_for_active_tenant(
             }
         }
     };
+    wait_for_task_enabled(tenant, should_run).await
+}
 
+/// Waits until `should_run` allows the task to proceed, which changes with the tenant state.
+async fn wait_for_task_enabled(
+    tenant: Arc<Tenant>,
+    should_run: fn(&Tenant) -> bool,
+) -> ControlFlow<(), Arc<Tenant>> {
     // if the tenant has a proper status already, no need to wait for anything
-    if tenant.should_run_tasks() {
+    if should_run(&tenant) {
         ControlFlow::Continue(tenant)
     } else {
         let mut tenant_state_updates = tenant.subscribe_for_state_updates();
         loop {
             match tenant_state_updates.changed().await {
                 Ok(()) => {
-                    let new_state = *tenant_state_updates.borrow();
-                    match new_state {
-                        TenantState::Active {
-                            background_jobs_running: true,
-                        } => {
-                            debug!("Tenant state changed to active with background jobs enabled, continuing the task loop");
-                            return ControlFlow::Continue(tenant);
-                        }
-                        state => {
-                            debug!("Not running the task loop, tenant is not active with background jobs enabled: {state:?}");
-                            continue;
-                        }
+                    if should_run(&tenant) {
+                        debug!("Tenant state changed to active with the task enabled, continuing the task loop");
+                        return ControlFlow::Continue(tenant);
+                    } else {
+                        let state = *tenant_state_updates.borrow();
+                        debug!("Not running the task loop, tenant is not active with the task enabled: {state:?}");
+                        continue;
                     }
                 }
                 Err(_sender_dropped_error) => {
@@ -187,3 +201,48 @@ async fn wait_for_active_tenant(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::harness::TenantHarness;
+
+    /// Whether the task loop gated by `should_run` would proceed within a short time.
+    async fn task_proceeds(tenant: &Arc<Tenant>, should_run: fn(&Tenant) -> bool) -> bool {
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            wait_for_task_enabled(Arc::clone(tenant), should_run),
+        )
+        .await
+        .is_ok()
+    }
+
+    #[tokio::test]
+    async fn gc_and_compaction_toggle_independently() -> anyhow::Result<()> {
+        let tenant =
+            Arc::new(TenantHarness::create("gc_and_compaction_toggle_independently")?.load());
+        tenant.activate(true);
+
+        tenant.set_gc_enabled(false)?;
+        assert!(!task_proceeds(&tenant, Tenant::should_run_gc).await);
+        assert!(task_proceeds(&tenant, Tenant::should_run_compaction).await);
+
+        // A GC loop waiting for its turn is not woken up by the compaction toggles
+        let gc_wait = tokio::spawn(wait_for_task_enabled(
+            Arc::clone(&tenant),
+            Tenant::should_run_gc,
+        ));
+        tenant.set_compaction_enabled(false)?;
+        tenant.set_compaction_enabled(true)?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!gc_wait.is_finished());
+
+        tenant.set_compaction_enabled(false)?;
+        tenant.set_gc_enabled(true)?;
+        assert!(matches!(gc_wait.await?, ControlFlow::Continue(_)));
+        assert!(task_proceeds(&tenant, Tenant::should_run_gc).await);
+        assert!(!task_proceeds(&tenant, Tenant::should_run_compaction).await);
+
+        Ok(())
+    }
+}
@@ -1058,11 +1058,14 @@ impl<'a> WalIngest<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::pgdatadir_mapping::create_test_timeline;
+    use crate::pgdatadir_mapping::{create_test_timeline, rel_block_to_key};
+    use crate::repository::Key;
     use crate::tenant::harness::*;
-    use crate::tenant::Timeline;
+    use crate::tenant::storage_layer::Layer;
+    use crate::tenant::{AncestorStart, InitdbParams, Timeline};
     use postgres_ffi::v14::xlog_utils::SIZEOF_CHECKPOINT;
     use postgres_ffi::RELSEG_SIZE;
+    use std::sync::Arc;
 
     use crate::DEFAULT_PG_VERSION;
 
@@ -1216,6 +1219,116 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_iter_keys() -> Result<()> {
+        let tenant = Arc::new(TenantHarness::create("test_iter_keys")?.load());
+        let tline = create_test_timeline(&tenant, TIMELINE_ID, DEFAULT_PG_VERSION)?;
+        let mut walingest = init_walingest_test(&*tline)?;
+
+        let mut m = tline.begin_modification(Lsn(0x20));
+        walingest.put_rel_page_image(&mut m, TESTREL_A, 0, TEST_IMG("foo blk 0 at 2"))?;
+        walingest.put_rel_page_image(&mut m, TESTREL_A, 1, TEST_IMG("foo blk 1 at 2"))?;
+        m.commit()?;
+        tline.checkpoint(crate::CheckpointConfig::Forced)?;
+        let mut m = tline.begin_modification(Lsn(0x30));
+        walingest.put_rel_page_image(&mut m, TESTREL_A, 1, TEST_IMG("foo blk 1 at 3"))?;
+        m.commit()?;
+
+        // Keys come in key order, one per key in use, with the values at the requested LSN
+        let keys = tline.iter_keys(Lsn(0x30)).collect::<Result<Vec<_>>>()?;
+        assert!(keys.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(
+            keys.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            tline
+                .collect_keyspace(Lsn(0x30))?
+                .ranges
+                .iter()
+                .flat_map(|range| {
+                    std::iter::successors(Some(range.start), |key| Some(key.next()))
+                        .take_while(move |key| *key < range.end)
+                })
+                .collect::<Vec<_>>()
+        );
+        let value_at = |keys: &[(Key, Bytes)], blknum| {
+            keys.iter()
+                .find(|(key, _)| *key == rel_block_to_key(TESTREL_A, blknum))
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(value_at(&keys, 0), Some(TEST_IMG("foo blk 0 at 2")));
+        assert_eq!(value_at(&keys, 1), Some(TEST_IMG("foo blk 1 at 3")));
+
+        // The relation does not exist yet at an earlier LSN
+        let keys = tline.iter_keys(Lsn(0x10)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(value_at(&keys, 0), None);
+
+        // Keys of the branch include the ones inherited from the ancestor
+        let branch = tenant
+            .create_timeline(
+                Some(NEW_TIMELINE_ID),
+                Some(TIMELINE_ID),
+                Some(AncestorStart::Lsn(Lsn(0x20))),
+                DEFAULT_PG_VERSION,
+                InitdbParams::default(),
+                None,
+                None,
+            )
+            .await?;
+        let keys = branch.iter_keys(Lsn(0x20)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(value_at(&keys, 1), Some(TEST_IMG("foo blk 1 at 2")));
+
+        // Reading behind the GC cutoff fails
+        tline
+            .latest_gc_cutoff_lsn
+            .lock_for_write()
+            .store_and_unlock(Lsn(0x30))
+            .wait();
+        let mut iter = tline.iter_keys(Lsn(0x20));
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_result() -> Result<()> {
+        let mut harness = TenantHarness::create("test_compaction_result")?;
+        harness.tenant_conf.compaction_threshold = 3;
+        let tenant = harness.load();
+        let tline = create_test_timeline(&tenant, TIMELINE_ID, DEFAULT_PG_VERSION)?;
+        let mut walingest = init_walingest_test(&*tline)?;
+
+        // Not enough level 0 layers to compact yet
+        let mut lsn = Lsn(0x20);
+        for _ in 0..2 {
+            let mut m = tline.begin_modification(lsn);
+            let img = TEST_IMG(&format!("foo blk 0 at {lsn}"));
+            walingest.put_rel_page_image(&mut m, TESTREL_A, 0, img)?;
+            m.commit()?;
+            tline.checkpoint(crate::CheckpointConfig::Flush)?;
+            lsn += 0x10;
+        }
+        let result = tline.compact()?;
+        assert_eq!(result.delta_layers_created, 0);
+        assert_eq!(result.layers_removed, 0);
+
+        let mut m = tline.begin_modification(lsn);
+        walingest.put_rel_page_image(&mut m, TESTREL_A, 0, TEST_IMG("foo blk 0 at last"))?;
+        m.commit()?;
+        tline.checkpoint(crate::CheckpointConfig::Flush)?;
+
+        // The level 0 layers get replaced with the new delta layers
+        let result = tline.compact()?;
+        assert_eq!(result.layers_removed, 3);
+        assert!(result.delta_layers_created > 0);
+        assert!(result.bytes_written > 0);
+        assert_eq!(
+            tline.get_rel_page_at_lsn(TESTREL_A, 0, lsn, false)?,
+            TEST_IMG("foo blk 0 at last")
+        );
+
+        Ok(())
+    }
+
     // Test what happens if we dropped a relation
     // and then created it again within the same layer.
     #[test]
@@ -1406,4 +1519,61 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_force_create_image_layers() -> Result<()> {
+        let tenant = TenantHarness::create("test_force_create_image_layers")?.load();
+        let tline = create_test_timeline(&tenant, TIMELINE_ID, DEFAULT_PG_VERSION)?;
+        let mut walingest = init_walingest_test(&*tline)?;
+
+        let mut lsn = Lsn(0x20);
+        let mut m = tline.begin_modification(lsn);
+        walingest.put_rel_creation(&mut m, TESTREL_A)?;
+        walingest.put_rel_page_image(&mut m, TESTREL_A, 0, TEST_IMG(&format!("foo at {lsn}")))?;
+        m.commit()?;
+        for _ in 0..3 {
+            lsn += 0x10;
+            let mut m = tline.begin_modification(lsn);
+            walingest.put_rel_page_image(
+                &mut m,
+                TESTREL_A,
+                0,
+                TEST_IMG(&format!("foo at {lsn}")),
+            )?;
+            m.commit()?;
+        }
+        tline.checkpoint(crate::CheckpointConfig::Forced)?;
+
+        let key = rel_block_to_key(TESTREL_A, 0);
+        let layer_for_read = |lsn: Lsn| {
+            tline
+                .layers
+                .read()
+                .unwrap()
+                .search(key, lsn + 1)
+                .unwrap()
+                .expect("the key should be in some layer")
+                .layer
+        };
+        assert!(layer_for_read(lsn).is_incremental());
+
+        tline.force_create_image_layers(Key::MIN..Key::MAX, lsn)?;
+        let layer = layer_for_read(lsn);
+        assert!(
+            !layer.is_incremental(),
+            "read should hit the new image layer"
+        );
+        assert_eq!(layer.get_lsn_range().start, lsn);
+        assert_eq!(
+            tline.get_rel_page_at_lsn(TESTREL_A, 0, lsn, false)?,
+            TEST_IMG(&format!("foo at {lsn}"))
+        );
+
+        // Nothing to materialize past the last record LSN
+        assert!(tline
+            .force_create_image_layers(Key::MIN..Key::MAX, lsn + 0x10)
+            .is_err());
+
+        Ok(())
+    }
 }
@@ -606,7 +606,7 @@ impl WalreceiverState {
                 if let Some(current_commit_lsn) = existing_wal_connection.status.commit_lsn {
                     let new_commit_lsn = new_safekeeper_etcd_data.commit_lsn.unwrap_or(Lsn(0));
                     // Check if the new candidate has much more WAL than the current one.
-                    match new_commit_lsn.0.checked_sub(current_commit_lsn.0) {
+                    match current_commit_lsn.distance_to(new_commit_lsn) {
                         Some(new_sk_lsn_advantage) => {
                             if new_sk_lsn_advantage >= self.max_lsn_wal_lag.get() {
                                 return Some(NewWalConnectionCandidate {
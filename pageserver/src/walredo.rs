@@ -78,6 +78,9 @@ pub trait WalRedoManager: Send + Sync {
     /// The caller passes an old page image, and WAL records that should be
     /// applied over it. The return value is a new page image, after applying
     /// the reords.
+    ///
+    /// Implementations that can stall should fail with [`WalRedoError::Timeout`]
+    /// after `wal_redo_timeout`, instead of blocking the read forever.
     fn request_redo(
         &self,
         key: Key,
@@ -129,6 +132,21 @@ pub enum WalRedoError {
     InvalidRequest,
     #[error("cannot perform WAL redo for this record")]
     InvalidRecord,
+    #[error("WAL redo did not complete in {0:?}")]
+    Timeout(Duration),
+}
+
+impl WalRedoError {
+    /// Whether the WAL redo itself is unavailable and the same request may succeed later,
+    /// as opposed to the request or its records being invalid.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            WalRedoError::IoError(_) | WalRedoError::InvalidState | WalRedoError::Timeout(_) => {
+                true
+            }
+            WalRedoError::InvalidRequest | WalRedoError::InvalidRecord => false,
+        }
+    }
 }
 
 ///
@@ -240,7 +258,10 @@ impl PostgresRedoManager {
         let buf_tag = BufferTag { rel, blknum };
         let result = process
             .apply_wal_records(buf_tag, base_img, records, wal_redo_timeout)
-            .map_err(WalRedoError::IoError);
+            .map_err(|e| match e.kind() {
+                ErrorKind::TimedOut => WalRedoError::Timeout(wal_redo_timeout),
+                _ => WalRedoError::IoError(e),
+            });
 
         let end_time = Instant::now();
         let duration = end_time.duration_since(lock_time);
@@ -655,41 +676,50 @@ impl PostgresRedoProcess {
         }
 
         // Start postgres itself
-        let mut child = Command::new(pg_bin_dir_path.join("postgres"))
-            .arg("--wal-redo")
-            .stdin(Stdio::piped())
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .env_clear()
-            .env("LD_LIBRARY_PATH", &pg_lib_dir_path)
-            .env("DYLD_LIBRARY_PATH", &pg_lib_dir_path)
-            .env("PGDATA", &datadir)
-            // The redo process is not trusted, so it runs in seccomp mode
-            // (see seccomp in zenith_wal_redo.c). We have to make sure it doesn't
-            // inherit any file descriptors from the pageserver that would allow
-            // an attacker to do bad things.
-            //
-            // The Rust standard library makes sure to mark any file descriptors with
-            // as close-on-exec by default, but that's not enough, since we use
-            // libraries that directly call libc open without setting that flag.
-            //
-            // One example is the pidfile of the daemonize library, which doesn't
-            // currently mark file descriptors as close-on-exec. Either way, we
-            // want to be on the safe side and prevent accidental regression.
-            .close_fds()
-            .spawn()
-            .map_err(|e| {
-                Error::new(
-                    e.kind(),
-                    format!("postgres --wal-redo command failed to start: {}", e),
-                )
-            })?;
+        let process = Self::spawn(
+            Command::new(pg_bin_dir_path.join("postgres"))
+                .arg("--wal-redo")
+                .env_clear()
+                .env("LD_LIBRARY_PATH", &pg_lib_dir_path)
+                .env("DYLD_LIBRARY_PATH", &pg_lib_dir_path)
+                .env("PGDATA", &datadir)
+                // The redo process is not trusted, so it runs in seccomp mode
+                // (see seccomp in zenith_wal_redo.c). We have to make sure it doesn't
+                // inherit any file descriptors from the pageserver that would allow
+                // an attacker to do bad things.
+                //
+                // The Rust standard library makes sure to mark any file descriptors with
+                // as close-on-exec by default, but that's not enough, since we use
+                // libraries that directly call libc open without setting that flag.
+                //
+                // One example is the pidfile of the daemonize library, which doesn't
+                // currently mark file descriptors as close-on-exec. Either way, we
+                // want to be on the safe side and prevent accidental regression.
+                .close_fds(),
+        )
+        .map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("postgres --wal-redo command failed to start: {}", e),
+            )
+        })?;
 
         info!(
             "launched WAL redo postgres process on {}",
             datadir.display()
         );
 
+        Ok(process)
+    }
+
+    /// Spawns the `command` with piped standard streams, to talk to it as to the WAL redo process.
+    fn spawn(command: &mut Command) -> Result<PostgresRedoProcess, Error> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
         let stdin = child.stdin.take().unwrap();
         let stdout = child.stdout.take().unwrap();
         let stderr = child.stderr.take().unwrap();
@@ -718,6 +748,9 @@ impl PostgresRedoProcess {
     // Apply given WAL records ('records') over an old page image. Returns
     // new page image.
     //
+    // Fails with `ErrorKind::TimedOut` if the page is not returned in 'wal_redo_timeout',
+    // even if the process keeps logging or answers slowly.
+    //
     fn apply_wal_records(
         &mut self,
         tag: BufferTag,
@@ -763,6 +796,8 @@ impl PostgresRedoProcess {
         let mut resultbuf = vec![0; BLCKSZ.into()];
         let mut nresult: usize = 0; // # of bytes read into 'resultbuf' so far
 
+        let deadline = Instant::now() + wal_redo_timeout;
+
         // Prepare for calling poll()
         let mut pollfds = [
             PollFd::new(self.stdout.as_raw_fd(), PollFlags::POLLIN),
@@ -778,14 +813,15 @@ impl PostgresRedoProcess {
             // we have data to read. Otherwise only wake up if there's data to read.
             let nfds = if nwrite < writebuf.len() { 3 } else { 2 };
             let n = loop {
-                match nix::poll::poll(&mut pollfds[0..nfds], wal_redo_timeout.as_millis() as i32) {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match nix::poll::poll(&mut pollfds[0..nfds], remaining.as_millis() as i32) {
                     Err(e) if e == nix::errno::Errno::EINTR => continue,
                     res => break res,
                 }
             }?;
 
-            if n == 0 {
-                return Err(Error::new(ErrorKind::Other, "WAL redo timed out"));
+            if n == 0 || Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, "WAL redo timed out"));
             }
 
             // If we have some messages in stderr, forward them to the log.
@@ -886,3 +922,63 @@ fn build_get_page_msg(tag: BufferTag, buf: &mut Vec<u8>) {
     tag.ser_into(buf)
         .expect("serialize BufferTag should always succeed");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgdatadir_mapping::rel_block_to_key;
+    use crate::DEFAULT_PG_VERSION;
+
+    #[test]
+    fn stalled_redo_process_times_out() -> anyhow::Result<()> {
+        let repo_dir = tempfile::tempdir()?;
+        let conf: &'static PageServerConf = Box::leak(Box::new(PageServerConf::dummy_conf(
+            repo_dir.path().to_path_buf(),
+        )));
+        let manager = PostgresRedoManager::new(conf, TenantId::generate());
+
+        // Keeps logging, but never returns the page
+        let process = PostgresRedoProcess::spawn(
+            Command::new("sh")
+                .arg("-c")
+                .arg("while true; do echo redoing >&2; sleep 0.05; done"),
+        )?;
+        *manager.process.lock().unwrap() = Some(process);
+
+        let rel = RelTag {
+            forknum: 0,
+            spcnode: 1663,
+            dbnode: 1,
+            relnode: 2,
+        };
+        let records = vec![(
+            Lsn(0x10),
+            NeonWalRecord::Postgres {
+                will_init: true,
+                rec: Bytes::from_static(b"record"),
+            },
+        )];
+        let timeout = Duration::from_millis(300);
+        let started = Instant::now();
+        let err = manager
+            .apply_batch_postgres(
+                rel_block_to_key(rel, 0),
+                Lsn(0x10),
+                None,
+                &records,
+                timeout,
+                DEFAULT_PG_VERSION,
+            )
+            .unwrap_err();
+        assert!(
+            matches!(err, WalRedoError::Timeout(t) if t == timeout),
+            "unexpected error: {err}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // The stalled process is killed, the next request launches a new one
+        assert!(manager.process.lock().unwrap().is_none());
+
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 //
 // Main entry point for the safekeeper executable
 //
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use clap::{value_parser, Arg, ArgAction, Command};
 use const_format::formatcp;
 use daemonize::Daemonize;
@@ -22,7 +22,10 @@ use safekeeper::broker;
 use safekeeper::control_file;
 use safekeeper::defaults::{
     DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_HTTP_LISTEN_ADDR, DEFAULT_MAX_OFFLOADER_LAG_BYTES,
-    DEFAULT_PG_LISTEN_ADDR, DEFAULT_WAL_BACKUP_RUNTIME_THREADS,
+    DEFAULT_PG_LISTEN_ADDR, DEFAULT_PROPOSER_HANDSHAKE_TIMEOUT, DEFAULT_PROPOSER_IDLE_TIMEOUT,
+    DEFAULT_PROPOSER_QUEUE_SIZE, DEFAULT_PROPOSER_TIMELINE_CREATE_BACKOFF,
+    DEFAULT_PROPOSER_TIMELINE_CREATE_RETRIES, DEFAULT_PROPOSER_WRITE_TIMEOUT,
+    DEFAULT_TCP_KEEPALIVE_IDLE, DEFAULT_WAL_BACKUP_RUNTIME_THREADS, DEFAULT_WAL_FLUSH_BATCH_BYTES,
 };
 use safekeeper::http;
 use safekeeper::remove_wal;
@@ -124,6 +127,71 @@ fn main() -> anyhow::Result<()> {
             )
         })?;
     }
+    if let Some(proposer_queue_size_str) = arg_matches.get_one::<String>("proposer-queue-size") {
+        conf.proposer_queue_size = proposer_queue_size_str.parse().with_context(|| {
+            format!(
+                "failed to parse proposer queue size {}",
+                proposer_queue_size_str
+            )
+        })?;
+        ensure!(
+            conf.proposer_queue_size > 0,
+            "proposer queue size must be greater than zero"
+        );
+    }
+    if let Some(idle_timeout_str) = arg_matches.get_one::<String>("proposer-idle-timeout") {
+        conf.proposer_idle_timeout =
+            humantime::parse_duration(idle_timeout_str).with_context(|| {
+                format!("failed to parse proposer-idle-timeout {}", idle_timeout_str)
+            })?;
+    }
+    if let Some(handshake_timeout_str) = arg_matches.get_one::<String>("proposer-handshake-timeout")
+    {
+        conf.proposer_handshake_timeout = humantime::parse_duration(handshake_timeout_str)
+            .with_context(|| {
+                format!(
+                    "failed to parse proposer-handshake-timeout {}",
+                    handshake_timeout_str
+                )
+            })?;
+    }
+    if let Some(write_timeout_str) = arg_matches.get_one::<String>("proposer-write-timeout") {
+        conf.proposer_write_timeout =
+            humantime::parse_duration(write_timeout_str).with_context(|| {
+                format!(
+                    "failed to parse proposer-write-timeout {}",
+                    write_timeout_str
+                )
+            })?;
+    }
+    if let Some(keepalive_idle_str) = arg_matches.get_one::<String>("tcp-keepalive-idle") {
+        conf.tcp_keepalive_idle =
+            humantime::parse_duration(keepalive_idle_str).with_context(|| {
+                format!("failed to parse tcp-keepalive-idle {}", keepalive_idle_str)
+            })?;
+    }
+    if let Some(retries_str) = arg_matches.get_one::<String>("proposer-timeline-create-retries") {
+        conf.proposer_timeline_create_retries = retries_str.parse().with_context(|| {
+            format!(
+                "failed to parse proposer-timeline-create-retries {}",
+                retries_str
+            )
+        })?;
+    }
+    if let Some(backoff_str) = arg_matches.get_one::<String>("proposer-timeline-create-backoff") {
+        conf.proposer_timeline_create_backoff = humantime::parse_duration(backoff_str)
+            .with_context(|| {
+                format!(
+                    "failed to parse proposer-timeline-create-backoff {}",
+                    backoff_str
+                )
+            })?;
+    }
+    if let Some(flush_batch_str) = arg_matches.get_one::<String>("wal-flush-batch-bytes") {
+        conf.wal_flush_batch_bytes = flush_batch_str.parse().with_context(|| {
+            format!("failed to parse wal flush batch bytes {}", flush_batch_str)
+        })?;
+    }
     // Seems like there is no better way to accept bool values explicitly in clap.
     conf.wal_backup_enabled = arg_matches
         .get_one::<String>("enable-wal-backup")
@@ -138,12 +206,18 @@ fn main() -> anyhow::Result<()> {
     if let Some(log_format) = arg_matches.get_one::<String>("log-format") {
         conf.log_format = LogFormat::from_config(log_format)?;
     }
+    conf.log_pretty_json = arg_matches.get_flag("log-pretty-json");
 
     start_safekeeper(conf, given_id, arg_matches.get_flag("init"))
 }
 
 fn start_safekeeper(mut conf: SafeKeeperConf, given_id: Option<NodeId>, init: bool) -> Result<()> {
-    let log_file = logging::init("safekeeper.log", conf.daemonize, conf.log_format)?;
+    let log_file = logging::init(
+        "safekeeper.log",
+        conf.daemonize,
+        conf.log_format,
+        conf.log_pretty_json,
+    )?;
 
     info!("version: {GIT_VERSION}");
 
@@ -432,6 +506,46 @@ fn cli() -> Command {
                 .long("max-offloader-lag")
                 .help(formatcp!("Safekeeper won't be elected for WAL offloading if it is lagging for more than this value (default {}MB) in bytes", DEFAULT_MAX_OFFLOADER_LAG_BYTES / (1 << 20)))
         )
+        .arg(
+            Arg::new("proposer-queue-size")
+                .long("proposer-queue-size")
+                .help(formatcp!("Maximum number of received WAL proposer messages waiting to be processed, reading from the proposer pauses when it's reached (default {DEFAULT_PROPOSER_QUEUE_SIZE})"))
+        )
+        .arg(
+            Arg::new("proposer-idle-timeout")
+                .long("proposer-idle-timeout")
+                .help(formatcp!("WAL proposer connection is dropped after receiving no messages from it during this period (default {}s), passed as a human readable duration.", DEFAULT_PROPOSER_IDLE_TIMEOUT.as_secs()))
+        )
+        .arg(
+            Arg::new("proposer-handshake-timeout")
+                .long("proposer-handshake-timeout")
+                .help(formatcp!("WAL proposer connection is dropped if it does not send the greeting during this period (default {}s), passed as a human readable duration.", DEFAULT_PROPOSER_HANDSHAKE_TIMEOUT.as_secs()))
+        )
+        .arg(
+            Arg::new("proposer-write-timeout")
+                .long("proposer-write-timeout")
+                .help(formatcp!("WAL proposer connection is dropped if a reply to it can't be written during this period (default {}s), passed as a human readable duration.", DEFAULT_PROPOSER_WRITE_TIMEOUT.as_secs()))
+        )
+        .arg(
+            Arg::new("tcp-keepalive-idle")
+                .long("tcp-keepalive-idle")
+                .help(formatcp!("TCP keepalive probes are sent on a connection idle for this period, to detect vanished peers (default {}s), passed as a human readable duration.", DEFAULT_TCP_KEEPALIVE_IDLE.as_secs()))
+        )
+        .arg(
+            Arg::new("proposer-timeline-create-retries")
+                .long("proposer-timeline-create-retries")
+                .help(formatcp!("Number of times the timeline creation is retried when handling the WAL proposer greeting, before the connection is dropped (default {DEFAULT_PROPOSER_TIMELINE_CREATE_RETRIES})"))
+        )
+        .arg(
+            Arg::new("proposer-timeline-create-backoff")
+                .long("proposer-timeline-create-backoff")
+                .help(formatcp!("Delay before the first retry of the timeline creation for the WAL proposer, doubled on each next one (default {}ms), passed as a human readable duration.", DEFAULT_PROPOSER_TIMELINE_CREATE_BACKOFF.as_millis()))
+        )
+        .arg(
+            Arg::new("wal-flush-batch-bytes")
+                .long("wal-flush-batch-bytes")
+                .help(formatcp!("Received WAL is flushed after at most this many bytes, even if more WAL is readily available (default {}MB)", DEFAULT_WAL_FLUSH_BATCH_BYTES / (1 << 20)))
+        )
         .arg(
             Arg::new("enable-wal-backup")
                 .long("enable-wal-backup")
@@ -447,7 +561,13 @@ fn cli() -> Command {
         .arg(
             Arg::new("log-format")
                 .long("log-format")
-                .help("Format for logging, either 'plain' or 'json'")
+                .help("Format for logging, one of 'plain', 'json', 'pretty' or 'logfmt'")
+        )
+        .arg(
+            Arg::new("log-pretty-json")
+                .long("log-pretty-json")
+                .action(ArgAction::SetTrue)
+                .help("Pretty-print the 'json' log format over multiple lines, for local use")
         )
 }
 
@@ -1,5 +1,9 @@
 use defaults::{
-    DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_MAX_OFFLOADER_LAG_BYTES, DEFAULT_WAL_BACKUP_RUNTIME_THREADS,
+    DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_MAX_OFFLOADER_LAG_BYTES, DEFAULT_PROPOSER_HANDSHAKE_TIMEOUT,
+    DEFAULT_PROPOSER_IDLE_TIMEOUT, DEFAULT_PROPOSER_QUEUE_SIZE,
+    DEFAULT_PROPOSER_TIMELINE_CREATE_BACKOFF, DEFAULT_PROPOSER_TIMELINE_CREATE_RETRIES,
+    DEFAULT_PROPOSER_WRITE_TIMEOUT, DEFAULT_TCP_KEEPALIVE_IDLE, DEFAULT_WAL_BACKUP_RUNTIME_THREADS,
+    DEFAULT_WAL_FLUSH_BATCH_BYTES,
 };
 //
 use remote_storage::RemoteStorageConfig;
@@ -42,6 +46,14 @@ pub mod defaults {
     pub const DEFAULT_WAL_BACKUP_RUNTIME_THREADS: usize = 8;
     pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
     pub const DEFAULT_MAX_OFFLOADER_LAG_BYTES: u64 = 128 * (1 << 20);
+    pub const DEFAULT_PROPOSER_QUEUE_SIZE: usize = 256;
+    pub const DEFAULT_PROPOSER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+    pub const DEFAULT_PROPOSER_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+    pub const DEFAULT_PROPOSER_WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+    pub const DEFAULT_PROPOSER_TIMELINE_CREATE_RETRIES: u32 = 3;
+    pub const DEFAULT_PROPOSER_TIMELINE_CREATE_BACKOFF: Duration = Duration::from_millis(100);
+    pub const DEFAULT_TCP_KEEPALIVE_IDLE: Duration = Duration::from_secs(30);
+    pub const DEFAULT_WAL_FLUSH_BATCH_BYTES: usize = 8 * (1 << 20);
 }
 
 #[derive(Debug, Clone)]
@@ -67,7 +79,29 @@ pub struct SafeKeeperConf {
     pub auth_validation_public_key_path: Option<PathBuf>,
     pub heartbeat_timeout: Duration,
     pub max_offloader_lag_bytes: u64,
+    // Number of received proposer messages that may wait to be processed,
+    // reading from the proposer connection pauses when it's reached.
+    pub proposer_queue_size: usize,
+    // Proposer connection is dropped if no messages arrive from it during this period.
+    pub proposer_idle_timeout: Duration,
+    // Same as above, but for the greeting starting the connection.
+    pub proposer_handshake_timeout: Duration,
+    // Proposer connection is dropped if a reply to it can't be written during this period.
+    pub proposer_write_timeout: Duration,
+    // Creating (or loading) the timeline during the proposer greeting is retried
+    // this many times before the connection is dropped.
+    pub proposer_timeline_create_retries: u32,
+    // Delay before the first such retry, doubled on each next one.
+    pub proposer_timeline_create_backoff: Duration,
+    // TCP keepalive probes are sent on a connection once it's idle for this period,
+    // so that a peer which vanished without closing it is detected.
+    pub tcp_keepalive_idle: Duration,
+    // Received WAL is flushed after at most this many bytes, even if more of it
+    // is readily available.
+    pub wal_flush_batch_bytes: usize,
     pub log_format: LogFormat,
+    // Pretty-print the JSON logs over multiple lines, for reading them locally.
+    pub log_pretty_json: bool,
 }
 
 impl SafeKeeperConf {
@@ -101,7 +135,16 @@ impl Default for SafeKeeperConf {
             auth_validation_public_key_path: None,
             heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
             max_offloader_lag_bytes: DEFAULT_MAX_OFFLOADER_LAG_BYTES,
+            proposer_queue_size: DEFAULT_PROPOSER_QUEUE_SIZE,
+            proposer_idle_timeout: DEFAULT_PROPOSER_IDLE_TIMEOUT,
+            proposer_handshake_timeout: DEFAULT_PROPOSER_HANDSHAKE_TIMEOUT,
+            proposer_write_timeout: DEFAULT_PROPOSER_WRITE_TIMEOUT,
+            proposer_timeline_create_retries: DEFAULT_PROPOSER_TIMELINE_CREATE_RETRIES,
+            proposer_timeline_create_backoff: DEFAULT_PROPOSER_TIMELINE_CREATE_BACKOFF,
+            tcp_keepalive_idle: DEFAULT_TCP_KEEPALIVE_IDLE,
+            wal_flush_batch_bytes: DEFAULT_WAL_FLUSH_BATCH_BYTES,
             log_format: LogFormat::Plain,
+            log_pretty_json: false,
         }
     }
 }
@@ -2,7 +2,10 @@
 
 use std::time::{Instant, SystemTime};
 
-use ::metrics::{register_histogram, GaugeVec, Histogram, IntGauge, DISK_WRITE_SECONDS_BUCKETS};
+use ::metrics::{
+    register_histogram, register_histogram_vec, register_int_counter_vec, GaugeVec, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, DISK_WRITE_SECONDS_BUCKETS,
+};
 use anyhow::Result;
 use metrics::{
     core::{AtomicU64, Collector, Desc, GenericGaugeVec, Opts},
@@ -15,7 +18,7 @@ use utils::{id::TenantTimelineId, lsn::Lsn};
 
 use crate::{
     safekeeper::{SafeKeeperState, SafekeeperMemState},
-    timeline::ReplicaState,
+    timeline::{ReceivedVsFlushed, ReplicaState},
     GlobalTimelines,
 };
 
@@ -62,6 +65,68 @@ pub static PERSIST_CONTROL_FILE_SECONDS: Lazy<Histogram> = Lazy::new(|| {
     .expect("Failed to register safekeeper_persist_control_file_seconds histogram vec")
 });
 
+// Metrics of the WAL received from the proposers, per timeline.
+static RECEIVED_WAL_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "safekeeper_received_wal_bytes_total",
+        "Bytes of WAL received from the proposer",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("Failed to register safekeeper_received_wal_bytes_total counter vec")
+});
+static RECEIVED_APPEND_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "safekeeper_received_append_requests_total",
+        "Number of append requests received from the proposer",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("Failed to register safekeeper_received_append_requests_total counter vec")
+});
+static RECEIVED_WAL_FLUSH_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "safekeeper_received_wal_flush_seconds",
+        "Seconds spent flushing the WAL received from the proposer, counts the flushes too",
+        &["tenant_id", "timeline_id"],
+        DISK_WRITE_SECONDS_BUCKETS.to_vec()
+    )
+    .expect("Failed to register safekeeper_received_wal_flush_seconds histogram vec")
+});
+
+/// Metrics of the WAL received from the proposer, for a single timeline.
+pub struct ReceiveWalMetrics {
+    pub received_wal_bytes: IntCounter,
+    pub received_append_requests: IntCounter,
+    pub flush_seconds: Histogram,
+}
+
+impl ReceiveWalMetrics {
+    pub fn new(ttid: &TenantTimelineId) -> Self {
+        let tenant_id = ttid.tenant_id.to_string();
+        let timeline_id = ttid.timeline_id.to_string();
+        let labels = &[tenant_id.as_str(), timeline_id.as_str()];
+        Self {
+            received_wal_bytes: RECEIVED_WAL_BYTES.with_label_values(labels),
+            received_append_requests: RECEIVED_APPEND_REQUESTS.with_label_values(labels),
+            flush_seconds: RECEIVED_WAL_FLUSH_SECONDS.with_label_values(labels),
+        }
+    }
+
+    pub fn observe_append_request(&self, wal_bytes: usize) {
+        self.received_append_requests.inc();
+        self.received_wal_bytes.inc_by(wal_bytes as u64);
+    }
+}
+
+/// Removes the received WAL metrics of the deleted timeline.
+pub fn remove_receive_wal_metrics(ttid: &TenantTimelineId) {
+    let tenant_id = ttid.tenant_id.to_string();
+    let timeline_id = ttid.timeline_id.to_string();
+    let labels = &[tenant_id.as_str(), timeline_id.as_str()];
+    let _ = RECEIVED_WAL_BYTES.remove_label_values(labels);
+    let _ = RECEIVED_APPEND_REQUESTS.remove_label_values(labels);
+    let _ = RECEIVED_WAL_FLUSH_SECONDS.remove_label_values(labels);
+}
+
 /// Metrics for WalStorage in a single timeline.
 #[derive(Clone, Default)]
 pub struct WalStorageMetrics {
@@ -111,6 +176,7 @@ pub struct FullTimelineInfo {
     pub persisted_state: SafeKeeperState,
 
     pub flush_lsn: Lsn,
+    pub received_vs_flushed: ReceivedVsFlushed,
 
     pub wal_storage: WalStorageMetrics,
 }
@@ -121,6 +187,7 @@ pub struct TimelineCollector {
     commit_lsn: GenericGaugeVec<AtomicU64>,
     backup_lsn: GenericGaugeVec<AtomicU64>,
     flush_lsn: GenericGaugeVec<AtomicU64>,
+    received_lsn: GenericGaugeVec<AtomicU64>,
     epoch_start_lsn: GenericGaugeVec<AtomicU64>,
     peer_horizon_lsn: GenericGaugeVec<AtomicU64>,
     remote_consistent_lsn: GenericGaugeVec<AtomicU64>,
@@ -178,6 +245,16 @@ impl TimelineCollector {
         .unwrap();
         descs.extend(flush_lsn.desc().into_iter().cloned());
 
+        let received_lsn = GenericGaugeVec::new(
+            Opts::new(
+                "safekeeper_received_lsn",
+                "End of WAL received from the proposer (not necessarily flushed to disk), grouped by timeline",
+            ),
+            &["tenant_id", "timeline_id"],
+        )
+        .unwrap();
+        descs.extend(received_lsn.desc().into_iter().cloned());
+
         let epoch_start_lsn = GenericGaugeVec::new(
             Opts::new(
                 "safekeeper_epoch_start_lsn",
@@ -324,6 +401,7 @@ impl TimelineCollector {
             commit_lsn,
             backup_lsn,
             flush_lsn,
+            received_lsn,
             epoch_start_lsn,
             peer_horizon_lsn,
             remote_consistent_lsn,
@@ -355,6 +433,7 @@ impl Collector for TimelineCollector {
         self.commit_lsn.reset();
         self.backup_lsn.reset();
         self.flush_lsn.reset();
+        self.received_lsn.reset();
         self.epoch_start_lsn.reset();
         self.peer_horizon_lsn.reset();
         self.remote_consistent_lsn.reset();
@@ -405,6 +484,9 @@ impl Collector for TimelineCollector {
             self.flush_lsn
                 .with_label_values(labels)
                 .set(tli.flush_lsn.into());
+            self.received_lsn
+                .with_label_values(labels)
+                .set(tli.received_vs_flushed.received_lsn.into());
             self.epoch_start_lsn
                 .with_label_values(labels)
                 .set(tli.epoch_start_lsn.into());
@@ -465,6 +547,7 @@ impl Collector for TimelineCollector {
         mfs.extend(self.commit_lsn.collect());
         mfs.extend(self.backup_lsn.collect());
         mfs.extend(self.flush_lsn.collect());
+        mfs.extend(self.received_lsn.collect());
         mfs.extend(self.epoch_start_lsn.collect());
         mfs.extend(self.peer_horizon_lsn.collect());
         mfs.extend(self.remote_consistent_lsn.collect());
@@ -2,31 +2,37 @@
 //! Gets messages from the network, passes them down to consensus module and
 //! sends replies back.
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use bytes::BytesMut;
 use tracing::*;
 use utils::lsn::Lsn;
 
+use crate::metrics::ReceiveWalMetrics;
 use crate::safekeeper::ServerInfo;
 use crate::timeline::Timeline;
 use crate::GlobalTimelines;
 
-use std::net::SocketAddr;
-use std::sync::mpsc::channel;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::sync_channel;
 use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 
 use std::sync::Arc;
 use std::thread;
 
 use crate::safekeeper::AcceptorProposerMessage;
+use crate::safekeeper::AppendRequest;
 use crate::safekeeper::ProposerAcceptorMessage;
+use crate::safekeeper::Term;
 
 use crate::handler::SafekeeperPostgresHandler;
 use utils::{
     postgres_backend::PostgresBackend,
     pq_proto::{BeMessage, FeMessage},
-    sock_split::ReadStream,
 };
 
 pub struct ReceiveWalConn<'pg> {
@@ -57,18 +63,38 @@ impl<'pg> ReceiveWalConn<'pg> {
     pub fn run(&mut self, spg: &mut SafekeeperPostgresHandler) -> Result<()> {
         let _enter = info_span!("WAL acceptor", timeline = %spg.timeline_id.unwrap()).entered();
 
+        // The errors are propagated out of the span, mention the connection in them.
+        // That covers the errors of the read thread too, they are returned by `recv_msg`.
+        let peer_addr = self.peer_addr;
+        let ttid = spg.ttid;
+        self.run_impl(spg).with_context(|| {
+            format!("WAL proposer {peer_addr} connection for timeline {ttid} failed")
+        })
+    }
+
+    fn run_impl(&mut self, spg: &mut SafekeeperPostgresHandler) -> Result<()> {
         // Notify the libpq client that it's allowed to send `CopyData` messages
         self.pg_backend
             .write_message(&BeMessage::CopyBothResponse)?;
 
+        // A proposer that stopped reading must not block the replies forever
+        self.pg_backend
+            .set_write_timeout(Some(spg.conf.proposer_write_timeout))?;
+
+        let socket = self.pg_backend.try_clone_socket()?;
         let r = self
             .pg_backend
             .take_stream_in()
             .ok_or_else(|| anyhow!("failed to take read stream from pgbackend"))?;
-        let mut poll_reader = ProposerPollStream::new(r)?;
+        let mut poll_reader = ProposerPollStream::new(
+            r,
+            Some(socket),
+            spg.conf.proposer_queue_size,
+            MAX_PROPOSER_MESSAGE_SIZE,
+        )?;
 
         // Receive information about server
-        let next_msg = poll_reader.recv_msg()?;
+        let next_msg = poll_reader.recv_msg(spg.conf.proposer_handshake_timeout)?;
         let tli = match next_msg {
             ProposerAcceptorMessage::Greeting(ref greeting) => {
                 info!(
@@ -80,75 +106,205 @@ impl<'pg> ReceiveWalConn<'pg> {
                     system_id: greeting.system_id,
                     wal_seg_size: greeting.wal_seg_size,
                 };
-                GlobalTimelines::create(spg.ttid, server_info, Lsn::INVALID, Lsn::INVALID)?
+                // A bad greeting won't get any better, only the storage errors are retried
+                server_info.validate()?;
+                let tli = retry_timeline_create(
+                    spg.conf.proposer_timeline_create_retries,
+                    spg.conf.proposer_timeline_create_backoff,
+                    || {
+                        GlobalTimelines::create(
+                            spg.ttid,
+                            server_info.clone(),
+                            Lsn::INVALID,
+                            Lsn::INVALID,
+                        )
+                    },
+                )?;
+                // An existing timeline is returned as is, the greeting is checked against
+                // its server info when processed
+                tli
             }
             _ => bail!("unexpected message {:?} instead of greeting", next_msg),
         };
 
-        let mut next_msg = Some(next_msg);
+        let metrics = ReceiveWalMetrics::new(&spg.ttid);
+        if let Some(reply) = tli.process_msg(&next_msg)? {
+            self.write_msg(&reply)?;
+        }
 
-        let mut first_time_through = true;
-        let mut _guard: Option<ComputeConnectionGuard> = None;
+        // Register the connection and defer unregister. Do that only
+        // after processing the greeting, as it sets wal_seg_size,
+        // wanted by many. The guard also releases the proposer slot.
+        tli.on_compute_connect()?;
+        let _guard = ComputeConnectionGuard {
+            timeline: Arc::clone(&tli),
+            peer_addr: self.peer_addr,
+        };
+
+        // The proposer term is fixed for the connection, so the slot is claimed once,
+        // with the first message carrying the term: VoteRequest for a new proposer,
+        // ProposerElected or AppendRequest for a reconnecting one.
+        let peer_addr = self.peer_addr;
+        let mut proposer_slot_claimed = false;
+        let mut claim_proposer_slot = |term: Option<Term>| -> Result<()> {
+            if !proposer_slot_claimed {
+                if let Some(term) = term {
+                    tli.claim_proposer_slot(peer_addr, term)?;
+                    proposer_slot_claimed = true;
+                }
+            }
+            Ok(())
+        };
+
+        let mut next_msg = None;
         loop {
-            if matches!(next_msg, Some(ProposerAcceptorMessage::AppendRequest(_))) {
-                // poll AppendRequest's without blocking and write WAL to disk without flushing,
-                // while it's readily available
-                while let Some(ProposerAcceptorMessage::AppendRequest(append_request)) = next_msg {
-                    let msg = ProposerAcceptorMessage::NoFlushAppendRequest(append_request);
+            match next_msg.take() {
+                Some(ProposerAcceptorMessage::AppendRequest(append_request)) => {
+                    claim_proposer_slot(Some(append_request.h.term))?;
+                    // poll AppendRequest's without blocking and write WAL to disk without flushing,
+                    // while it's readily available
+                    next_msg = write_append_batch(
+                        append_request,
+                        &mut poll_reader,
+                        spg.conf.wal_flush_batch_bytes,
+                        |msg| {
+                            if let ProposerAcceptorMessage::NoFlushAppendRequest(append_request) =
+                                msg
+                            {
+                                metrics.observe_append_request(append_request.wal_data.len());
+                            }
+                            if let Some(reply) = tli.process_msg(msg)? {
+                                self.write_msg(&reply)?;
+                            }
+                            Ok(())
+                        },
+                    )?;
 
-                    let reply = tli.process_msg(&msg)?;
+                    // flush all written WAL to the disk
+                    let flush_timer = metrics.flush_seconds.start_timer();
+                    let reply = tli.process_msg(&ProposerAcceptorMessage::FlushWAL)?;
+                    flush_timer.observe_duration();
                     if let Some(reply) = reply {
                         self.write_msg(&reply)?;
                     }
-
-                    next_msg = poll_reader.poll_msg();
-                }
-
-                // flush all written WAL to the disk
-                let reply = tli.process_msg(&ProposerAcceptorMessage::FlushWAL)?;
-                if let Some(reply) = reply {
-                    self.write_msg(&reply)?;
                 }
-            } else if let Some(msg) = next_msg.take() {
-                // process other message
-                let reply = tli.process_msg(&msg)?;
-                if let Some(reply) = reply {
-                    self.write_msg(&reply)?;
+                Some(msg) => {
+                    // process other message
+                    claim_proposer_slot(msg.term())?;
+                    let reply = tli.process_msg(&msg)?;
+                    if let Some(reply) = reply {
+                        self.write_msg(&reply)?;
+                    }
                 }
-            }
-            if first_time_through {
-                // Register the connection and defer unregister. Do that only
-                // after processing first message, as it sets wal_seg_size,
-                // wanted by many.
-                tli.on_compute_connect()?;
-                _guard = Some(ComputeConnectionGuard {
-                    timeline: Arc::clone(&tli),
-                });
-                first_time_through = false;
+                None => {}
             }
 
             // blocking wait for the next message
             if next_msg.is_none() {
-                next_msg = Some(poll_reader.recv_msg()?);
+                next_msg = Some(poll_reader.recv_msg(spg.conf.proposer_idle_timeout)?);
+            }
+        }
+    }
+}
+
+/// Upper bound for the delay between the timeline creation retries.
+const MAX_TIMELINE_CREATE_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Runs `create` until it succeeds, retrying it at most `max_retries` times, so that
+/// transient storage failures don't drop the proposer connection. The delay starts at
+/// `backoff` and doubles after each failure. The last error is returned if all the
+/// attempts fail.
+fn retry_timeline_create<T>(
+    max_retries: u32,
+    backoff: Duration,
+    mut create: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match create() {
+            Ok(res) => return Ok(res),
+            Err(e) if attempt < max_retries => {
+                let delay = backoff
+                    .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+                    .min(MAX_TIMELINE_CREATE_BACKOFF);
+                attempt += 1;
+                warn!(
+                    "failed to create timeline, retry {attempt}/{max_retries} in {delay:?}: {e:#}"
+                );
+                thread::sleep(delay);
             }
+            Err(e) => {
+                return Err(e.context(format!(
+                    "failed to create timeline after {} attempts",
+                    attempt + 1
+                )))
+            }
+        }
+    }
+}
+
+/// Writes the readily available AppendRequests, starting with `append_request`, without
+/// flushing. Stops after `max_batch_bytes` of WAL, so that a steady stream of WAL does not
+/// delay the flush and the replies to the proposer indefinitely.
+///
+/// Returns the polled message that is not an AppendRequest, if any.
+fn write_append_batch(
+    append_request: AppendRequest,
+    poll_reader: &mut ProposerPollStream,
+    max_batch_bytes: usize,
+    mut process_msg: impl FnMut(&ProposerAcceptorMessage) -> Result<()>,
+) -> Result<Option<ProposerAcceptorMessage>> {
+    let mut batch_bytes = 0;
+    let mut next_msg = Some(ProposerAcceptorMessage::AppendRequest(append_request));
+    while let Some(ProposerAcceptorMessage::AppendRequest(append_request)) = next_msg {
+        batch_bytes += append_request.wal_data.len();
+        process_msg(&ProposerAcceptorMessage::NoFlushAppendRequest(
+            append_request,
+        ))?;
+        if batch_bytes >= max_batch_bytes {
+            // The rest stays queued until the next batch
+            return Ok(None);
         }
+        next_msg = poll_reader.poll_msg();
     }
+    Ok(next_msg)
 }
 
+/// The proposer sends WAL in chunks of at most `MAX_SEND_SIZE` (128 KiB),
+/// a much larger message means a broken proposer.
+const MAX_PROPOSER_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
 struct ProposerPollStream {
     msg_rx: Receiver<ProposerAcceptorMessage>,
     read_thread: Option<thread::JoinHandle<Result<()>>>,
+    /// Tells the read thread to exit, checked before reading every message.
+    stop: Arc<AtomicBool>,
+    /// Socket the messages are read from, shut down to interrupt a blocked read.
+    socket: Option<TcpStream>,
 }
 
 impl ProposerPollStream {
-    fn new(mut r: ReadStream) -> Result<Self> {
-        let (msg_tx, msg_rx) = channel();
+    /// Reads the messages in a separate thread. At most `queue_size` of them are
+    /// buffered, then the thread waits for them to be received.
+    ///
+    /// The thread is stopped and joined on drop, `socket` should be the one `r` reads from.
+    /// Messages larger than `max_message_size` fail the read thread.
+    fn new(
+        mut r: impl io::Read + Unpin + Send + 'static,
+        socket: Option<TcpStream>,
+        queue_size: usize,
+        max_message_size: usize,
+    ) -> Result<Self> {
+        // With no room in the queue, a blocked send could not be interrupted on drop
+        let (msg_tx, msg_rx) = sync_channel(queue_size.max(1));
+        let stop = Arc::new(AtomicBool::new(false));
 
+        let thread_stop = Arc::clone(&stop);
         let read_thread = thread::Builder::new()
             .name("Read WAL thread".into())
             .spawn(move || -> Result<()> {
-                loop {
-                    let copy_data = match FeMessage::read(&mut r)? {
+                while !thread_stop.load(Ordering::Relaxed) {
+                    let copy_data = match FeMessage::read_with_max_size(&mut r, max_message_size)? {
                         Some(FeMessage::CopyData(bytes)) => bytes,
                         Some(msg) => bail!("expected `CopyData` message, found {:?}", msg),
                         None => bail!("connection closed unexpectedly"),
@@ -157,17 +313,25 @@ impl ProposerPollStream {
                     let msg = ProposerAcceptorMessage::parse(copy_data)?;
                     msg_tx.send(msg)?;
                 }
+                Ok(())
                 // msg_tx will be dropped here, this will also close msg_rx
             })?;
 
         Ok(Self {
             msg_rx,
             read_thread: Some(read_thread),
+            stop,
+            socket,
         })
     }
 
-    fn recv_msg(&mut self) -> Result<ProposerAcceptorMessage> {
-        self.msg_rx.recv().map_err(|_| {
+    /// Waits for the next message, failing if none arrives within `timeout`.
+    fn recv_msg(&mut self, timeout: Duration) -> Result<ProposerAcceptorMessage> {
+        self.msg_rx.recv_timeout(timeout).map_err(|e| {
+            if let RecvTimeoutError::Timeout = e {
+                return anyhow!("no messages from the proposer in {:?}", timeout);
+            }
+
             // return error from the read thread
             let res = match self.read_thread.take() {
                 Some(thread) => thread.join(),
@@ -192,14 +356,270 @@ impl ProposerPollStream {
     }
 }
 
+impl Drop for ProposerPollStream {
+    fn drop(&mut self) {
+        // Already joined by `recv_msg` otherwise
+        let read_thread = match self.read_thread.take() {
+            Some(read_thread) => read_thread,
+            None => return,
+        };
+
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(socket) = &self.socket {
+            // Interrupts the read waiting for the proposer, the thread exits with an error
+            let _ = socket.shutdown(Shutdown::Read);
+        }
+        // Makes room for the message the thread might be blocked on sending
+        while self.msg_rx.try_recv().is_ok() {}
+
+        if let Err(err) = read_thread.join() {
+            error!("read thread panicked: {:?}", err);
+        }
+    }
+}
+
 struct ComputeConnectionGuard {
     timeline: Arc<Timeline>,
+    peer_addr: SocketAddr,
 }
 
 impl Drop for ComputeConnectionGuard {
     fn drop(&mut self) {
+        self.timeline.release_proposer_slot(self.peer_addr);
         if let Err(e) = self.timeline.on_compute_disconnect() {
             error!("failed to unregister compute connection: {}", e);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// `VoteRequest` wrapped into `CopyData`: tag, length, message tag and term.
+    const VOTE_REQUEST_FRAME_LEN: usize = 1 + 4 + 8 + 8;
+
+    fn vote_request_frame() -> Vec<u8> {
+        let mut frame = Vec::with_capacity(VOTE_REQUEST_FRAME_LEN);
+        frame.push(b'd');
+        frame.extend_from_slice(&(VOTE_REQUEST_FRAME_LEN as i32 - 1).to_be_bytes());
+        frame.extend_from_slice(&(b'v' as u64).to_le_bytes());
+        frame.extend_from_slice(&1u64.to_le_bytes());
+        frame
+    }
+
+    /// `AppendRequest` with `wal_len` bytes of WAL, wrapped into `CopyData`.
+    fn append_request_frame(wal_len: usize) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&(b'a' as u64).to_le_bytes());
+        // term, epoch_start_lsn, begin_lsn, end_lsn, commit_lsn, truncate_lsn
+        for value in [1, 0, 0, wal_len as u64, 0, 0] {
+            msg.extend_from_slice(&value.to_le_bytes());
+        }
+        // proposer_uuid
+        msg.extend_from_slice(&[0; 16]);
+        msg.resize(msg.len() + wal_len, 0);
+
+        let mut frame = vec![b'd'];
+        frame.extend_from_slice(&(msg.len() as i32 + 4).to_be_bytes());
+        frame.extend_from_slice(&msg);
+        frame
+    }
+
+    /// An endless proposer repeating the same message, counts the bytes read from it.
+    struct EndlessProposer {
+        frame: Vec<u8>,
+        pos: usize,
+        bytes_read: Arc<AtomicUsize>,
+    }
+
+    impl EndlessProposer {
+        fn new(frame: Vec<u8>, bytes_read: Arc<AtomicUsize>) -> Self {
+            Self {
+                frame,
+                pos: 0,
+                bytes_read,
+            }
+        }
+    }
+
+    impl io::Read for EndlessProposer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.frame.len() - self.pos);
+            buf[..n].copy_from_slice(&self.frame[self.pos..self.pos + n]);
+            self.pos = (self.pos + n) % self.frame.len();
+            self.bytes_read.fetch_add(n, Ordering::SeqCst);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn slow_consumer_bounds_queued_messages() -> Result<()> {
+        let queue_size = 4;
+        // The queued messages, one more blocked on sending and one being read
+        let max_bytes_read = (queue_size + 2) * VOTE_REQUEST_FRAME_LEN;
+
+        let bytes_read = Arc::new(AtomicUsize::new(0));
+        let mut poll_reader = ProposerPollStream::new(
+            EndlessProposer::new(vote_request_frame(), Arc::clone(&bytes_read)),
+            None,
+            queue_size,
+            MAX_PROPOSER_MESSAGE_SIZE,
+        )?;
+
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(100));
+            assert!(
+                bytes_read.load(Ordering::SeqCst) <= max_bytes_read,
+                "read {} bytes with only {queue_size} messages allowed in the queue",
+                bytes_read.load(Ordering::SeqCst)
+            );
+
+            // Drain the queue, the reader fills it again
+            for _ in 0..queue_size {
+                let msg = poll_reader.recv_msg(Duration::from_secs(10))?;
+                assert!(matches!(msg, ProposerAcceptorMessage::VoteRequest(_)));
+            }
+            bytes_read.fetch_sub(queue_size * VOTE_REQUEST_FRAME_LEN, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the proposer and the safekeeper sides of a local connection.
+    fn proposer_connection() -> Result<(TcpStream, TcpStream)> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let proposer = TcpStream::connect(listener.local_addr()?)?;
+        let (safekeeper, _) = listener.accept()?;
+        Ok((proposer, safekeeper))
+    }
+
+    #[test]
+    fn silent_proposer_times_out() -> Result<()> {
+        let (mut proposer, safekeeper) = proposer_connection()?;
+        proposer.write_all(&vote_request_frame())?;
+        let socket = safekeeper.try_clone()?;
+        let mut poll_reader =
+            ProposerPollStream::new(safekeeper, Some(socket), 4, MAX_PROPOSER_MESSAGE_SIZE)?;
+
+        let msg = poll_reader.recv_msg(Duration::from_secs(10))?;
+        assert!(matches!(msg, ProposerAcceptorMessage::VoteRequest(_)));
+        let err = poll_reader
+            .recv_msg(Duration::from_millis(100))
+            .expect_err("silent proposer should time out");
+        assert!(err.to_string().contains("no messages"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_message_is_rejected() -> Result<()> {
+        let (mut proposer, safekeeper) = proposer_connection()?;
+        // Only the header, the body must never be allocated
+        proposer.write_all(&[b'd'])?;
+        proposer.write_all(&(MAX_PROPOSER_MESSAGE_SIZE as u32 + 5).to_be_bytes())?;
+        let socket = safekeeper.try_clone()?;
+        let mut poll_reader =
+            ProposerPollStream::new(safekeeper, Some(socket), 4, MAX_PROPOSER_MESSAGE_SIZE)?;
+
+        let err = poll_reader
+            .recv_msg(Duration::from_secs(10))
+            .expect_err("oversized message should fail the read thread");
+        assert!(err.to_string().contains("exceeds the maximum"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn drop_stops_read_thread() -> Result<()> {
+        // Blocked on reading from a silent proposer
+        let (_proposer, safekeeper) = proposer_connection()?;
+        let socket = safekeeper.try_clone()?;
+        let poll_reader =
+            ProposerPollStream::new(safekeeper, Some(socket), 4, MAX_PROPOSER_MESSAGE_SIZE)?;
+        thread::sleep(Duration::from_millis(50));
+        drop(poll_reader);
+
+        // Blocked on sending to the full queue
+        let poll_reader = ProposerPollStream::new(
+            EndlessProposer::new(vote_request_frame(), Arc::new(AtomicUsize::new(0))),
+            None,
+            4,
+            MAX_PROPOSER_MESSAGE_SIZE,
+        )?;
+        thread::sleep(Duration::from_millis(50));
+        drop(poll_reader);
+
+        Ok(())
+    }
+
+    #[test]
+    fn continuous_appends_are_flushed_in_batches() -> Result<()> {
+        let wal_len = 100;
+        let max_batch_bytes = 10 * wal_len;
+        let mut poll_reader = ProposerPollStream::new(
+            EndlessProposer::new(append_request_frame(wal_len), Arc::new(AtomicUsize::new(0))),
+            None,
+            32,
+            MAX_PROPOSER_MESSAGE_SIZE,
+        )?;
+        // Let the reader fill the queue, so that an AppendRequest is always available
+        thread::sleep(Duration::from_millis(100));
+
+        for _ in 0..5 {
+            let append_request = match poll_reader.recv_msg(Duration::from_secs(10))? {
+                ProposerAcceptorMessage::AppendRequest(append_request) => append_request,
+                msg => panic!("unexpected message {msg:?}"),
+            };
+            let mut written = 0;
+            let next_msg =
+                write_append_batch(append_request, &mut poll_reader, max_batch_bytes, |msg| {
+                    assert!(matches!(
+                        msg,
+                        ProposerAcceptorMessage::NoFlushAppendRequest(_)
+                    ));
+                    written += 1;
+                    Ok(())
+                })?;
+            assert!(next_msg.is_none());
+            assert!(
+                (1..=10).contains(&written),
+                "{written} requests written without a flush"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_create_retries() {
+        // Storage that fails the first two times, e.g. because the disk is busy
+        let attempts = AtomicUsize::new(0);
+        let flaky_storage = || {
+            if attempts.fetch_add(1, Ordering::Relaxed) < 2 {
+                bail!("disk busy");
+            }
+            Ok("timeline")
+        };
+        let tli = retry_timeline_create(3, Duration::from_millis(1), flaky_storage)
+            .expect("creation should succeed on the third attempt");
+        assert_eq!(tli, "timeline");
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        // Too few retries to get past the failures
+        attempts.store(0, Ordering::Relaxed);
+        let err = retry_timeline_create(1, Duration::from_millis(1), flaky_storage)
+            .expect_err("creation should fail after a single retry");
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+        let err = format!("{err:#}");
+        assert!(err.contains("after 2 attempts"), "{err}");
+        assert!(err.contains("disk busy"), "{err}");
+
+        // No retries at all
+        attempts.store(0, Ordering::Relaxed);
+        assert!(retry_timeline_create(0, Duration::from_millis(1), flaky_storage).is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+}
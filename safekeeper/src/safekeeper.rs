@@ -133,6 +133,27 @@ pub struct ServerInfo {
     pub wal_seg_size: u32,
 }
 
+/// Range of the WAL segment sizes PostgreSQL allows, see `IsValidWalSegSize`.
+const MIN_WAL_SEG_SIZE: u32 = 1024 * 1024;
+const MAX_WAL_SEG_SIZE: u32 = 1024 * 1024 * 1024;
+
+impl ServerInfo {
+    /// Checks the server info received from the proposer, before it's used for the timeline.
+    pub fn validate(&self) -> Result<()> {
+        if !self.wal_seg_size.is_power_of_two()
+            || !(MIN_WAL_SEG_SIZE..=MAX_WAL_SEG_SIZE).contains(&self.wal_seg_size)
+        {
+            bail!(
+                "invalid wal_seg_size {}, it must be a power of two between {} and {} bytes",
+                self.wal_seg_size,
+                MIN_WAL_SEG_SIZE,
+                MAX_WAL_SEG_SIZE
+            );
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PersistedPeerInfo {
     /// LSN up to which safekeeper offloaded WAL to s3.
@@ -266,7 +287,7 @@ impl SafeKeeperState {
 // protocol messages
 
 /// Initial Proposer -> Acceptor message
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ProposerGreeting {
     /// proposer-acceptor protocol version
     pub protocol_version: u32,
@@ -385,6 +406,17 @@ pub enum ProposerAcceptorMessage {
 }
 
 impl ProposerAcceptorMessage {
+    /// Term of the proposer sending the message, if the message carries it.
+    pub fn term(&self) -> Option<Term> {
+        match self {
+            ProposerAcceptorMessage::VoteRequest(msg) => Some(msg.term),
+            ProposerAcceptorMessage::Elected(msg) => Some(msg.term),
+            ProposerAcceptorMessage::AppendRequest(msg)
+            | ProposerAcceptorMessage::NoFlushAppendRequest(msg) => Some(msg.h.term),
+            ProposerAcceptorMessage::Greeting(_) | ProposerAcceptorMessage::FlushWAL => None,
+        }
+    }
+
     /// Parse proposer message.
     pub fn parse(msg_bytes: Bytes) -> Result<ProposerAcceptorMessage> {
         // xxx using Reader is inefficient but easy to work with bincode
@@ -601,20 +633,6 @@ where
                 SK_PROTOCOL_VERSION
             );
         }
-        /* Postgres major version mismatch is treated as fatal error
-         * because safekeepers parse WAL headers and the format
-         * may change between versions.
-         */
-        if msg.pg_version / 10000 != self.state.server.pg_version / 10000
-            && self.state.server.pg_version != UNKNOWN_SERVER_VERSION
-        {
-            bail!(
-                "incompatible server version {}, expected {}",
-                msg.pg_version,
-                self.state.server.pg_version
-            );
-        }
-
         if msg.tenant_id != self.state.tenant_id {
             bail!(
                 "invalid tenant ID, got {}, expected {}",
@@ -629,21 +647,47 @@ where
                 self.state.timeline_id
             );
         }
-        if self.state.server.wal_seg_size != msg.wal_seg_size {
+
+        // The zero values of the stored server info are unknown, e.g. the timeline was created
+        // over HTTP without them. They match anything and get filled in from the greeting.
+        let server = &self.state.server;
+        /* Postgres major version mismatch is treated as fatal error
+         * because safekeepers parse WAL headers and the format
+         * may change between versions.
+         */
+        if server.pg_version != UNKNOWN_SERVER_VERSION
+            && msg.pg_version / 10000 != server.pg_version / 10000
+        {
             bail!(
-                "invalid wal_seg_size, got {}, expected {}",
+                "proposer pg_version {} does not match pg_version {} of the timeline",
+                msg.pg_version,
+                server.pg_version
+            );
+        }
+        // Another system_id means another cluster, its WAL must not get mixed with this one
+        if server.system_id != 0 && server.system_id != msg.system_id {
+            bail!(
+                "proposer system_id {} does not match system_id {} of the timeline",
+                msg.system_id,
+                server.system_id
+            );
+        }
+        if server.wal_seg_size != msg.wal_seg_size {
+            bail!(
+                "proposer wal_seg_size {} does not match wal_seg_size {} of the timeline",
                 msg.wal_seg_size,
-                self.state.server.wal_seg_size
+                server.wal_seg_size
             );
         }
 
-        // system_id will be updated on mismatch
-        if self.state.server.system_id != msg.system_id {
-            warn!(
-                "unexpected system ID arrived, got {}, expected {}",
-                msg.system_id, self.state.server.system_id
+        if (server.system_id == 0 && msg.system_id != 0)
+            || (server.pg_version == UNKNOWN_SERVER_VERSION
+                && msg.pg_version != UNKNOWN_SERVER_VERSION)
+        {
+            info!(
+                "filling in the unknown server info from the greeting, system_id {}, pg_version {}",
+                msg.system_id, msg.pg_version
             );
-
             let mut state = self.state.clone();
             state.server.system_id = msg.system_id;
             if msg.pg_version != UNKNOWN_SERVER_VERSION {
@@ -997,6 +1041,10 @@ mod tests {
             self.lsn
         }
 
+        fn write_lsn(&self) -> Lsn {
+            self.lsn
+        }
+
         fn write_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<()> {
             self.lsn = startpos + buf.len() as u64;
             Ok(())
@@ -1020,6 +1068,69 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_greeting_server_info() {
+        let mut state = test_sk_state();
+        state.server.pg_version = 140005;
+        let greeting = ProposerGreeting {
+            protocol_version: SK_PROTOCOL_VERSION,
+            pg_version: 140005,
+            proposer_id: [0; 16],
+            system_id: 42,
+            timeline_id: state.timeline_id,
+            tenant_id: state.tenant_id,
+            tli: 1,
+            wal_seg_size: WAL_SEGMENT_SIZE as u32,
+        };
+        let storage = InMemoryState {
+            persisted_state: state,
+        };
+        let wal_store = DummyWalStore { lsn: Lsn(0) };
+        let mut sk = SafeKeeper::new(storage, wal_store, NodeId(0)).unwrap();
+
+        // The unknown system_id is taken from the first greeting
+        sk.process_msg(&ProposerAcceptorMessage::Greeting(greeting.clone()))
+            .unwrap();
+        assert_eq!(sk.state.server.system_id, 42);
+        // Minor version upgrade
+        sk.process_msg(&ProposerAcceptorMessage::Greeting(ProposerGreeting {
+            pg_version: 140006,
+            ..greeting.clone()
+        }))
+        .unwrap();
+
+        for (mismatching_greeting, field) in [
+            (
+                ProposerGreeting {
+                    pg_version: 150000,
+                    ..greeting.clone()
+                },
+                "pg_version",
+            ),
+            (
+                ProposerGreeting {
+                    system_id: 43,
+                    ..greeting.clone()
+                },
+                "system_id",
+            ),
+            (
+                ProposerGreeting {
+                    wal_seg_size: 1024 * 1024,
+                    ..greeting.clone()
+                },
+                "wal_seg_size",
+            ),
+        ] {
+            let err = sk
+                .process_msg(&ProposerAcceptorMessage::Greeting(mismatching_greeting))
+                .expect_err("mismatching greeting should be rejected")
+                .to_string();
+            assert!(err.contains(field), "{field} mismatch, error: {err}");
+        }
+        assert_eq!(sk.state.server.system_id, 42);
+    }
+
     #[test]
     fn test_voting() {
         let storage = InMemoryState {
@@ -1104,4 +1215,28 @@ mod tests {
         sk.wal_store.truncate_wal(Lsn(3)).unwrap(); // imitate the complete record at 3 %)
         assert_eq!(sk.get_epoch(), 1);
     }
+
+    #[test]
+    fn test_server_info_wal_seg_size() {
+        let server_info = |wal_seg_size| ServerInfo {
+            pg_version: 140005,
+            system_id: 42,
+            wal_seg_size,
+        };
+        for valid in [MIN_WAL_SEG_SIZE, WAL_SEGMENT_SIZE as u32, MAX_WAL_SEG_SIZE] {
+            assert!(server_info(valid).validate().is_ok(), "{valid} is valid");
+        }
+
+        // Not a power of two
+        let err = server_info(16 * 1024 * 1024 + 1).validate().unwrap_err();
+        assert!(err.to_string().contains("invalid wal_seg_size"), "{err}");
+        let err = server_info(3 * 1024 * 1024).validate().unwrap_err();
+        assert!(err.to_string().contains("invalid wal_seg_size"), "{err}");
+
+        // Out of the allowed range
+        for invalid in [0, MIN_WAL_SEG_SIZE / 2, MAX_WAL_SEG_SIZE * 2] {
+            let err = server_info(invalid).validate().unwrap_err();
+            assert!(err.to_string().contains("invalid wal_seg_size"), "{err}");
+        }
+    }
 }
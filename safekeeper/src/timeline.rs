@@ -13,6 +13,7 @@ use std::cmp::{max, min};
 
 use parking_lot::{Mutex, MutexGuard};
 
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use tokio::sync::mpsc::Sender;
@@ -117,6 +118,30 @@ impl ReplicaState {
     }
 }
 
+/// Snapshot of how far the WAL received from the proposer is durably stored,
+/// see [`Timeline::received_vs_flushed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedVsFlushed {
+    /// End of the WAL received from the proposer and written, possibly not yet flushed.
+    pub received_lsn: Lsn,
+    /// End of the last WAL record flushed to disk.
+    pub flushed_lsn: Lsn,
+}
+
+impl ReceivedVsFlushed {
+    fn observe(wal_store: &impl wal_storage::Storage) -> Self {
+        ReceivedVsFlushed {
+            received_lsn: wal_store.write_lsn(),
+            flushed_lsn: wal_store.flush_lsn(),
+        }
+    }
+
+    /// Number of received WAL bytes not flushed yet.
+    pub fn flush_lag(&self) -> u64 {
+        self.received_lsn.0.saturating_sub(self.flushed_lsn.0)
+    }
+}
+
 /// Shared state associated with database instance
 pub struct SharedState {
     /// Safekeeper object
@@ -138,7 +163,42 @@ pub struct SharedState {
     /// when tli is inactive instead of having this flag.
     active: bool,
     num_computes: u32,
+    /// Proposer streaming WAL to the timeline, see [`Timeline::claim_proposer_slot`].
+    active_proposer: Option<ActiveProposer>,
     last_removed_segno: XLogSegNo,
+    /// Updated on every message processed by [`Timeline::process_msg`].
+    received_vs_flushed: ReceivedVsFlushed,
+}
+
+/// Proposer connection that streams WAL to the timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ActiveProposer {
+    peer_addr: SocketAddr,
+    term: Term,
+}
+
+/// Makes the proposer at `peer_addr` the active one. Another active proposer is only
+/// replaced by a proposer with the same or a higher term: with Paxos, the one with the lower
+/// term can't make progress anyway, while a term has a single proposer, so the same term
+/// means that the proposer has reconnected and its old connection is stale.
+fn claim_proposer_slot(
+    slot: &mut Option<ActiveProposer>,
+    peer_addr: SocketAddr,
+    term: Term,
+) -> Result<()> {
+    if let Some(active) = slot {
+        if active.peer_addr != peer_addr && active.term > term {
+            bail!(
+                "proposer {} with term {} is already active, rejecting proposer {} with term {}",
+                active.peer_addr,
+                active.term,
+                peer_addr,
+                term
+            );
+        }
+    }
+    *slot = Some(ActiveProposer { peer_addr, term });
+    Ok(())
 }
 
 impl SharedState {
@@ -168,6 +228,7 @@ impl SharedState {
         // These functions should not change anything on disk.
         let control_store = control_file::FileStorage::create_new(ttid, conf, state)?;
         let wal_store = wal_storage::PhysicalStorage::new(ttid, conf, &control_store)?;
+        let received_vs_flushed = ReceivedVsFlushed::observe(&wal_store);
         let sk = SafeKeeper::new(control_store, wal_store, conf.my_id)?;
 
         Ok(Self {
@@ -177,7 +238,9 @@ impl SharedState {
             wal_backup_active: false,
             active: false,
             num_computes: 0,
+            active_proposer: None,
             last_removed_segno: 0,
+            received_vs_flushed,
         })
     }
 
@@ -189,6 +252,7 @@ impl SharedState {
         }
 
         let wal_store = wal_storage::PhysicalStorage::new(ttid, conf, &control_store)?;
+        let received_vs_flushed = ReceivedVsFlushed::observe(&wal_store);
 
         Ok(Self {
             sk: SafeKeeper::new(control_store, wal_store, conf.my_id)?,
@@ -197,7 +261,9 @@ impl SharedState {
             wal_backup_active: false,
             active: false,
             num_computes: 0,
+            active_proposer: None,
             last_removed_segno: 0,
+            received_vs_flushed,
         })
     }
 
@@ -528,6 +594,32 @@ impl Timeline {
         Ok(())
     }
 
+    /// Makes the proposer at `peer_addr` with the given term the one streaming WAL to the
+    /// timeline, failing if another proposer with a higher term is connected.
+    /// Called once per proposer connection, the slot is released with
+    /// [`Timeline::release_proposer_slot`] when the connection is closed.
+    pub fn claim_proposer_slot(&self, peer_addr: SocketAddr, term: Term) -> Result<()> {
+        let mut shared_state = self.write_shared_state();
+        let replaced = shared_state.active_proposer;
+        claim_proposer_slot(&mut shared_state.active_proposer, peer_addr, term)
+            .map_err(|e| e.context(format!("timeline {}", self.ttid)))?;
+        if let Some(replaced) = replaced.filter(|replaced| replaced.peer_addr != peer_addr) {
+            info!(
+                "proposer {} with term {} replaced proposer {} with term {}",
+                peer_addr, term, replaced.peer_addr, replaced.term
+            );
+        }
+        Ok(())
+    }
+
+    /// Frees the active proposer slot, if it's taken by the proposer at `peer_addr`.
+    pub fn release_proposer_slot(&self, peer_addr: SocketAddr) {
+        let mut shared_state = self.write_shared_state();
+        if matches!(shared_state.active_proposer, Some(active) if active.peer_addr == peer_addr) {
+            shared_state.active_proposer = None;
+        }
+    }
+
     /// De-register compute connection, shutting down timeline activity if
     /// pageserver doesn't need catchup.
     pub fn on_compute_disconnect(&self) -> Result<()> {
@@ -605,6 +697,7 @@ impl Timeline {
                 mem_state: state.sk.inmem.clone(),
                 persisted_state: state.sk.state.clone(),
                 flush_lsn: state.sk.wal_store.flush_lsn(),
+                received_vs_flushed: state.received_vs_flushed,
                 wal_storage: state.sk.wal_store.get_metrics(),
             })
         } else {
@@ -631,6 +724,8 @@ impl Timeline {
         {
             let mut shared_state = self.write_shared_state();
             rmsg = shared_state.sk.process_msg(msg)?;
+            shared_state.received_vs_flushed =
+                ReceivedVsFlushed::observe(&shared_state.sk.wal_store);
 
             // if this is AppendResponse, fill in proper hot standby feedback and disk consistent lsn
             if let Some(AcceptorProposerMessage::AppendResponse(ref mut resp)) = rmsg {
@@ -647,6 +742,12 @@ impl Timeline {
         Ok(rmsg)
     }
 
+    /// Returns the received and flushed WAL positions as of the last processed proposer message,
+    /// e.g. after `FlushWAL`.
+    pub fn received_vs_flushed(&self) -> ReceivedVsFlushed {
+        self.write_shared_state().received_vs_flushed
+    }
+
     /// Returns wal_seg_size.
     pub fn get_wal_seg_size(&self) -> usize {
         self.write_shared_state().get_wal_seg_size()
@@ -790,3 +891,61 @@ fn delete_dir(path: &PathBuf) -> Result<bool> {
         Err(e) => Err(e.into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proposer_slot() {
+        let first: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let second: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let mut slot = None;
+
+        claim_proposer_slot(&mut slot, first, 1).unwrap();
+        // The same proposer advances its term
+        claim_proposer_slot(&mut slot, first, 2).unwrap();
+
+        // A concurrent proposer with a lower term is rejected
+        let err = claim_proposer_slot(&mut slot, second, 1).unwrap_err();
+        assert!(err.to_string().contains("already active"), "{err}");
+        assert_eq!(
+            slot,
+            Some(ActiveProposer {
+                peer_addr: first,
+                term: 2
+            })
+        );
+
+        // The proposer reconnects from another port, replacing its stale connection
+        claim_proposer_slot(&mut slot, second, 2).unwrap();
+        assert_eq!(
+            slot,
+            Some(ActiveProposer {
+                peer_addr: second,
+                term: 2
+            })
+        );
+
+        // A higher term takes over, the old proposer gets rejected then
+        let third: SocketAddr = "127.0.0.1:3000".parse().unwrap();
+        claim_proposer_slot(&mut slot, third, 3).unwrap();
+        assert!(claim_proposer_slot(&mut slot, second, 2).is_err());
+    }
+
+    #[test]
+    fn received_vs_flushed_lag() {
+        let snapshot = ReceivedVsFlushed {
+            received_lsn: Lsn(0x1000),
+            flushed_lsn: Lsn(0x800),
+        };
+        assert_eq!(snapshot.flush_lag(), 0x800);
+
+        // Never underflows, even for an inconsistent snapshot
+        let snapshot = ReceivedVsFlushed {
+            received_lsn: Lsn(0x800),
+            flushed_lsn: Lsn(0x1000),
+        };
+        assert_eq!(snapshot.flush_lag(), 0);
+    }
+}
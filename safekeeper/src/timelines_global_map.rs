@@ -2,6 +2,7 @@
 //! All timelines should always be present in this map, this is done by loading them
 //! all from the disk on startup and keeping them in memory.
 
+use crate::metrics::remove_receive_wal_metrics;
 use crate::safekeeper::ServerInfo;
 use crate::timeline::{Timeline, TimelineError};
 use crate::SafeKeeperConf;
@@ -273,6 +274,7 @@ impl GlobalTimelines {
 
                 // Remove timeline from the map.
                 TIMELINES_STATE.lock().unwrap().timelines.remove(ttid);
+                remove_receive_wal_metrics(ttid);
 
                 Ok(TimelineDeleteForceResult {
                     dir_existed,
@@ -49,8 +49,10 @@ fn handle_socket(socket: TcpStream, conf: SafeKeeperConf) -> Result<()> {
 
     socket.set_nodelay(true)?;
 
+    let keepalive_idle = conf.tcp_keepalive_idle;
     let mut conn_handler = SafekeeperPostgresHandler::new(conf);
-    let pgbackend = PostgresBackend::new(socket, AuthType::Trust, None, false)?;
+    let mut pgbackend = PostgresBackend::new(socket, AuthType::Trust, None, false)?;
+    pgbackend.set_keepalive(Some(keepalive_idle))?;
     // libpq replication protocol between safekeeper and replicas/pagers
     pgbackend.run(&mut conn_handler)?;
 
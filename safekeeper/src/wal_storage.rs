@@ -41,6 +41,9 @@ pub trait Storage {
     /// LSN of last durably stored WAL record.
     fn flush_lsn(&self) -> Lsn;
 
+    /// LSN up to which WAL is written, possibly not yet durably and in the middle of a record.
+    fn write_lsn(&self) -> Lsn;
+
     /// Write piece of WAL from buf to disk, but not necessarily sync it.
     fn write_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<()>;
 
@@ -273,6 +276,10 @@ impl Storage for PhysicalStorage {
         self.flush_record_lsn
     }
 
+    fn write_lsn(&self) -> Lsn {
+        self.write_lsn
+    }
+
     /// Write WAL to disk.
     fn write_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<()> {
         // Disallow any non-sequential writes, which can result in gaps or overwrites.
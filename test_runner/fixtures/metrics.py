@@ -42,6 +42,7 @@ def parse_metrics(text: str, name: str = ""):
 PAGESERVER_PER_TENANT_METRICS = [
     "pageserver_current_logical_size",
     "pageserver_current_physical_size",
+    "pageserver_flush_lag_bytes",
     "pageserver_getpage_reconstruct_seconds_bucket",
     "pageserver_getpage_reconstruct_seconds_count",
     "pageserver_getpage_reconstruct_seconds_sum",
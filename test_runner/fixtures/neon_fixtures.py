@@ -1110,16 +1110,20 @@ class NeonPageserverHttpClient(requests.Session):
         return res_json
 
     def timeline_gc(
-        self, tenant_id: TenantId, timeline_id: TimelineId, gc_horizon: Optional[int]
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        gc_horizon: Optional[int],
+        pitr_interval: Optional[str] = None,
     ) -> dict[str, Any]:
         self.is_testing_enabled_or_skip()
 
         log.info(
-            f"Requesting GC: tenant {tenant_id}, timeline {timeline_id}, gc_horizon {repr(gc_horizon)}"
+            f"Requesting GC: tenant {tenant_id}, timeline {timeline_id}, gc_horizon {repr(gc_horizon)}, pitr_interval {repr(pitr_interval)}"
         )
         res = self.put(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/do_gc",
-            json={"gc_horizon": gc_horizon},
+            json={"gc_horizon": gc_horizon, "pitr_interval": pitr_interval},
         )
         log.info(f"Got GC request response code: {res.status_code}")
         self.verbose_error(res)
@@ -201,8 +201,8 @@ def switch_pg_to_new_pageserver(
         env.repo_dir / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
     )
     files_before_detach = os.listdir(timeline_to_detach_local_path)
-    assert (
-        "metadata" in files_before_detach
+    assert any(
+        filename.startswith("metadata") for filename in files_before_detach
     ), f"Regular timeline {timeline_to_detach_local_path} should have the metadata file,\
             but got: {files_before_detach}"
     assert (
@@ -41,14 +41,14 @@ def test_tenant_tasks(neon_env_builder: NeonEnvBuilder):
             client.timeline_delete(tenant, t)
 
     def assert_active_without_jobs(tenant):
-        assert get_state(tenant) == {"Active": {"background_jobs_running": False}}
+        assert get_state(tenant) == {"Active": {"gc_enabled": False, "compaction_enabled": False}}
 
     # Create tenant, start compute
     tenant, _ = env.neon_cli.create_tenant()
     env.neon_cli.create_timeline(name, tenant_id=tenant)
     pg = env.postgres.create_start(name, tenant_id=tenant)
     assert get_state(tenant) == {
-        "Active": {"background_jobs_running": True}
+        "Active": {"gc_enabled": True, "compaction_enabled": True}
     }, "Pageserver should activate a tenant and start background jobs if timelines are loaded"
 
     # Stop compute
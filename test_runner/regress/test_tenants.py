@@ -258,5 +258,5 @@ def test_pageserver_with_empty_tenants(
         loaded_tenant
     ), f"Tenant {tenant_with_empty_timelines_dir} should be loaded as the only one with tenants/ directory"
     assert loaded_tenant["state"] == {
-        "Active": {"background_jobs_running": False}
+        "Active": {"gc_enabled": False, "compaction_enabled": False}
     }, "Empty tenant should be loaded and ready for timeline creation"
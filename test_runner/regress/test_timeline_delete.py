@@ -11,7 +11,10 @@ def test_timeline_delete(neon_simple_env: NeonEnv):
     # first try to delete non existing timeline
     # for existing tenant:
     invalid_timeline_id = TimelineId.generate()
-    with pytest.raises(NeonPageserverApiException, match="timeline not found"):
+    with pytest.raises(
+        NeonPageserverApiException,
+        match=f"Timeline {env.initial_tenant}/{invalid_timeline_id} was not found",
+    ):
         ps_http.timeline_delete(tenant_id=env.initial_tenant, timeline_id=invalid_timeline_id)
 
     # for non existing tenant:
@@ -48,6 +51,13 @@ def test_timeline_delete(neon_simple_env: NeonEnv):
 
         assert not timeline_path.exists()
 
+    # the error body lists the children blocking the deletion
+    res = ps_http.delete(
+        f"http://localhost:{ps_http.port}/v1/tenant/{env.initial_tenant}/timeline/{parent_timeline_id}"
+    )
+    assert res.status_code == 412
+    assert res.json()["child_timeline_ids"] == [str(leaf_timeline_id)]
+
     timeline_path = (
         env.repo_dir / "tenants" / str(env.initial_tenant) / "timelines" / str(leaf_timeline_id)
     )